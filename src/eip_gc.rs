@@ -0,0 +1,383 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use tokio::time::{sleep, Duration, Instant};
+
+use crate::market::JobId;
+use crate::reconcile::{ReconcileMode, TaggedAddress, TaggedResources};
+
+// Launches in progress per job, from before the instance is launched until its elastic ip
+// is associated, the elastic ip of the job is expected to be unassociated in between
+#[derive(Clone, Default)]
+pub struct PendingLaunches(Arc<Mutex<HashMap<JobId, usize>>>);
+
+impl PendingLaunches {
+    pub fn start(&self, job: &JobId) -> PendingLaunch {
+        *self.0.lock().unwrap().entry(job.clone()).or_default() += 1;
+        PendingLaunch {
+            launches: self.clone(),
+            job: job.clone(),
+        }
+    }
+
+    pub fn contains(&self, job: &JobId) -> bool {
+        self.0.lock().unwrap().contains_key(job)
+    }
+}
+
+// Ends the launch when dropped, so launches bailing out early are not left pending
+pub struct PendingLaunch {
+    launches: PendingLaunches,
+    job: JobId,
+}
+
+impl Drop for PendingLaunch {
+    fn drop(&mut self) {
+        let mut launches = self.launches.0.lock().unwrap();
+        if let Some(count) = launches.get_mut(&self.job) {
+            *count -= 1;
+            if *count == 0 {
+                launches.remove(&self.job);
+            }
+        }
+    }
+}
+
+// Elastic ips found leaked and released in a single pass
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CollectReport {
+    // unassociated for longer than the grace period
+    pub leaked: Vec<String>,
+    // only in enforce mode
+    pub released: Vec<String>,
+}
+
+// Releases elastic ips left behind when a launch fails or the control plane dies between
+// allocating and associating them, or when a spin down fails halfway
+//
+// Unlike the reconciler this also covers jobs that are still live, an elastic ip is leaked
+// once it is unassociated while its job has no running instance and no launch in progress
+pub struct EipCollector<R> {
+    resources: R,
+    regions: &'static [String],
+    deployments: Vec<JobId>,
+    mode: ReconcileMode,
+    grace: Duration,
+    // allocation id to when it was first seen leaked
+    leaked_since: HashMap<String, Instant>,
+}
+
+impl<R: TaggedResources> EipCollector<R> {
+    pub fn new(
+        resources: R,
+        regions: &'static [String],
+        deployments: Vec<JobId>,
+        mode: ReconcileMode,
+        grace: Duration,
+    ) -> EipCollector<R> {
+        EipCollector {
+            resources,
+            regions,
+            deployments,
+            mode,
+            grace,
+            leaked_since: HashMap::new(),
+        }
+    }
+
+    pub async fn run(mut self, interval: Duration) {
+        loop {
+            let report = self.collect_once().await;
+            println!(
+                "eip gc: {} leaked, {} released",
+                report.leaked.len(),
+                report.released.len()
+            );
+            sleep(interval).await;
+        }
+    }
+
+    pub async fn collect_once(&mut self) -> CollectReport {
+        let mut report = CollectReport::default();
+        let mut leaked = Vec::new();
+
+        for deployment in self.deployments.clone() {
+            for region in self.regions {
+                // without the instances it is unknown which jobs are running, try again later
+                let instances = match self.resources.tagged_instances(&deployment, region).await {
+                    Ok(instances) => instances,
+                    Err(err) => {
+                        println!("eip gc: failed to list instances in {region}: {err:?}");
+                        continue;
+                    }
+                };
+                let running: HashSet<JobId> = instances
+                    .into_iter()
+                    .filter(|instance| instance.state == "pending" || instance.state == "running")
+                    .map(|instance| instance.job)
+                    .collect();
+
+                let addresses = match self.resources.tagged_addresses(&deployment, region).await {
+                    Ok(addresses) => addresses,
+                    Err(err) => {
+                        println!("eip gc: failed to list elastic ips in {region}: {err:?}");
+                        continue;
+                    }
+                };
+
+                for address in addresses {
+                    if !self.is_leaked(&address, &running, &mut leaked) {
+                        continue;
+                    }
+                    report.leaked.push(address.allocation_id.clone());
+                    self.release(&address, region, &mut report).await;
+                }
+            }
+        }
+
+        // elastic ips that are gone or in use again start over
+        self.leaked_since.retain(|id, _| leaked.contains(id));
+
+        report
+    }
+
+    // true once the elastic ip has been leaked for longer than the grace period
+    fn is_leaked(
+        &mut self,
+        address: &TaggedAddress,
+        running: &HashSet<JobId>,
+        leaked: &mut Vec<String>,
+    ) -> bool {
+        if address.association_id.is_some()
+            || running.contains(&address.job)
+            || self.resources.launch_pending(&address.job)
+        {
+            return false;
+        }
+
+        let id = &address.allocation_id;
+        leaked.push(id.clone());
+        let since = *self.leaked_since.entry(id.clone()).or_insert_with(|| {
+            println!(
+                "eip gc: job {}: {id} ({}) is not associated",
+                address.job.id, address.public_ip
+            );
+            Instant::now()
+        });
+
+        since.elapsed() >= self.grace
+    }
+
+    async fn release(&self, address: &TaggedAddress, region: &str, report: &mut CollectReport) {
+        let job = &address.job.id;
+        let id = &address.allocation_id;
+        if self.mode != ReconcileMode::Enforce {
+            println!(
+                "eip gc: job {job}: would release {id} ({}) in {region}",
+                address.public_ip
+            );
+            return;
+        }
+
+        // launches reuse the elastic ip of the job, one might have started since the listing
+        if self.resources.launch_pending(&address.job) {
+            println!("eip gc: job {job}: launch started, keeping {id}");
+            return;
+        }
+
+        match self.resources.release_orphan(address, region).await {
+            Ok(()) => {
+                println!(
+                    "eip gc: job {job}: released {id} ({}) in {region}",
+                    address.public_ip
+                );
+                report.released.push(id.clone());
+            }
+            Err(err) => println!("eip gc: job {job}: failed to release {id}: {err:?}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use anyhow::Result;
+    use tokio::time::{sleep, Duration};
+
+    use super::{CollectReport, EipCollector, PendingLaunches};
+    use crate::market::JobId;
+    use crate::reconcile::{ReconcileMode, TaggedAddress, TaggedInstance, TaggedResources};
+
+    #[derive(Clone, Default)]
+    struct TestResources {
+        instances: Arc<Mutex<Vec<TaggedInstance>>>,
+        addresses: Arc<Mutex<Vec<TaggedAddress>>>,
+        launches: PendingLaunches,
+    }
+
+    impl TaggedResources for TestResources {
+        async fn tagged_instances(
+            &self,
+            _deployment: &JobId,
+            _region: &str,
+        ) -> Result<Vec<TaggedInstance>> {
+            Ok(self.instances.lock().unwrap().clone())
+        }
+
+        async fn tagged_addresses(
+            &self,
+            _deployment: &JobId,
+            _region: &str,
+        ) -> Result<Vec<TaggedAddress>> {
+            Ok(self.addresses.lock().unwrap().clone())
+        }
+
+        async fn terminate_orphan(&self, instance: &TaggedInstance, _region: &str) -> Result<()> {
+            self.instances
+                .lock()
+                .unwrap()
+                .retain(|i| i.instance_id != instance.instance_id);
+            Ok(())
+        }
+
+        async fn release_orphan(&self, address: &TaggedAddress, _region: &str) -> Result<()> {
+            self.addresses
+                .lock()
+                .unwrap()
+                .retain(|a| a.allocation_id != address.allocation_id);
+            Ok(())
+        }
+
+        fn launch_pending(&self, job: &JobId) -> bool {
+            self.launches.contains(job)
+        }
+    }
+
+    fn job(id: &str) -> JobId {
+        JobId {
+            id: id.to_owned(),
+            operator: "abc".into(),
+            contract: "xyz".into(),
+            chain: "123".into(),
+        }
+    }
+
+    fn address(id: &str, job_id: &str, associated: bool) -> TaggedAddress {
+        TaggedAddress {
+            allocation_id: id.to_owned(),
+            public_ip: "1.1.1.1".into(),
+            association_id: associated.then(|| format!("eipassoc-{job_id}")),
+            job: job(job_id),
+        }
+    }
+
+    fn resources() -> TestResources {
+        let resources = TestResources::default();
+        *resources.instances.lock().unwrap() = vec![
+            TaggedInstance {
+                instance_id: "i-running".into(),
+                job: job("0x01"),
+                state: "running".into(),
+            },
+            TaggedInstance {
+                instance_id: "i-stopped".into(),
+                job: job("0x03"),
+                state: "stopped".into(),
+            },
+        ];
+        *resources.addresses.lock().unwrap() = vec![
+            // in use
+            address("eipalloc-associated", "0x01", true),
+            // instance still running, about to be associated or spun down
+            address("eipalloc-running", "0x01", false),
+            // launch failed after allocating
+            address("eipalloc-leaked", "0x02", false),
+            // stopped instances do not count as running
+            address("eipalloc-stopped", "0x03", false),
+            // allocated, waiting for the instance to be associated
+            address("eipalloc-launching", "0x04", false),
+        ];
+        resources
+    }
+
+    fn collector(resources: TestResources, mode: ReconcileMode) -> EipCollector<TestResources> {
+        let regions: &'static [String] = Box::leak(vec!["ap-south-1".into()].into_boxed_slice());
+        EipCollector::new(
+            resources,
+            regions,
+            vec![job("")],
+            mode,
+            Duration::from_secs(600),
+        )
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_release_after_grace() {
+        let resources = resources();
+        let _launch = resources.launches.start(&job("0x04"));
+        let mut collector = collector(resources.clone(), ReconcileMode::Enforce);
+
+        assert_eq!(collector.collect_once().await, CollectReport::default());
+        assert_eq!(resources.addresses.lock().unwrap().len(), 5);
+
+        sleep(Duration::from_secs(600)).await;
+        let report = collector.collect_once().await;
+        assert_eq!(report.leaked, vec!["eipalloc-leaked", "eipalloc-stopped"]);
+        assert_eq!(report.released, vec!["eipalloc-leaked", "eipalloc-stopped"]);
+
+        let remaining: Vec<String> = resources
+            .addresses
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|address| address.allocation_id.clone())
+            .collect();
+        assert_eq!(
+            remaining,
+            vec![
+                "eipalloc-associated",
+                "eipalloc-running",
+                "eipalloc-launching"
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_report_only() {
+        let resources = resources();
+        let mut collector = collector(resources.clone(), ReconcileMode::Report);
+
+        collector.collect_once().await;
+        sleep(Duration::from_secs(600)).await;
+        let report = collector.collect_once().await;
+
+        assert_eq!(report.leaked.len(), 3);
+        assert!(report.released.is_empty());
+        assert_eq!(resources.addresses.lock().unwrap().len(), 5);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_launch_restarts_grace() {
+        let resources = resources();
+        let mut collector = collector(resources.clone(), ReconcileMode::Enforce);
+
+        collector.collect_once().await;
+        sleep(Duration::from_secs(300)).await;
+
+        // a relaunch of the job picks the elastic ip up again
+        let launch = resources.launches.start(&job("0x02"));
+        assert!(resources.launches.contains(&job("0x02")));
+        collector.collect_once().await;
+        drop(launch);
+        assert!(!resources.launches.contains(&job("0x02")));
+
+        sleep(Duration::from_secs(300)).await;
+        let report = collector.collect_once().await;
+        assert!(!report.released.contains(&"eipalloc-leaked".to_owned()));
+
+        sleep(Duration::from_secs(600)).await;
+        let report = collector.collect_once().await;
+        assert_eq!(report.released, vec!["eipalloc-leaked"]);
+    }
+}
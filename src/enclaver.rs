@@ -1,4 +1,5 @@
 use cp::aws;
+use cp::family;
 
 use anyhow::Context;
 use anyhow::Result;
@@ -28,6 +29,10 @@ struct Cli {
     #[clap(long, value_parser, default_value = "salmon")]
     family: String,
 
+    /// Enclave family definitions location, built-in families if empty
+    #[clap(long, value_parser, default_value = "")]
+    families: String,
+
     /// Enclave image URL
     #[clap(long, value_parser)]
     url: String,
@@ -43,8 +48,11 @@ pub async fn main() -> Result<()> {
         cli.key_name,
         String::new(),
         String::new(),
+        family::Families::load(&cli.families).context("failed to load families")?,
+        Default::default(),
     )
-    .await;
+    .await
+    .context("failed to set up aws client")?;
     aws.run_enclave_impl(
         "0x01020304",
         &cli.family,
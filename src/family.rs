@@ -0,0 +1,322 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+
+use crate::command::RemoteCommand;
+
+// families shipped with the control plane, used when no families file is given
+const DEFAULT_FAMILIES: &str = include_str!("../families.json");
+
+// Everything that differs between AMI families when setting up an instance to run an enclave
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Family {
+    pub name: String,
+    // image name pattern, {arch} is replaced by amd64 or arm64
+    pub ami_name: String,
+    #[serde(default)]
+    pub allocator: Allocator,
+    #[serde(default)]
+    pub sysctls: Vec<Sysctl>,
+    pub firewall: Firewall,
+    // run in order after the firewall is set up and before the enclave is started
+    #[serde(default)]
+    pub init_hooks: Vec<InitHook>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Allocator {
+    pub config: String,
+    pub service: String,
+}
+
+impl Default for Allocator {
+    fn default() -> Self {
+        Allocator {
+            config: "/etc/nitro_enclaves/allocator.yaml".to_owned(),
+            service: "nitro-enclaves-allocator.service".to_owned(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Sysctl {
+    pub key: String,
+    pub value: String,
+}
+
+// Rules are written the way `iptables -S` prints them without the leading `-A <chain>`,
+// so rules already present on the instance are recognised and not added twice
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Firewall {
+    #[serde(default)]
+    pub table: Option<String>,
+    pub chain: String,
+    pub rules: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct InitHook {
+    pub description: String,
+    // program and arguments, {job_id} is replaced by the id of the job
+    pub command: Vec<String>,
+}
+
+impl Family {
+    pub fn ami_name(&self, architecture: &str) -> String {
+        self.ami_name.replace("{arch}", architecture)
+    }
+
+    pub fn allocator_command(&self, req_vcpu: i32, req_mem: i64) -> RemoteCommand {
+        RemoteCommand::new("echo")
+            .arg("-e")
+            .arg(format!(
+                "---\\nmemory_mib: {req_mem}\\ncpu_count: {req_vcpu}"
+            ))
+            .pipe(RemoteCommand::new("sudo tee").arg(&self.allocator.config))
+    }
+
+    pub fn restart_allocator_command(&self) -> RemoteCommand {
+        RemoteCommand::new("sudo systemctl restart").arg(&self.allocator.service)
+    }
+
+    pub fn sysctl_commands(&self) -> Vec<RemoteCommand> {
+        self.sysctls
+            .iter()
+            .map(|sysctl| {
+                RemoteCommand::new("sudo sysctl -w").arg(format!("{}={}", sysctl.key, sysctl.value))
+            })
+            .collect()
+    }
+
+    fn iptables(&self) -> RemoteCommand {
+        let command = RemoteCommand::new("sudo iptables");
+        match &self.firewall.table {
+            Some(table) => command.arg("-t").arg(table),
+            None => command,
+        }
+    }
+
+    pub fn list_firewall_command(&self) -> RemoteCommand {
+        self.iptables().arg("-S").arg(&self.firewall.chain)
+    }
+
+    // checks the chain accepts by default and returns the rules that still have to be added
+    pub fn missing_firewall_rules(&self, listed: &str) -> Result<Vec<&str>> {
+        let listed: Vec<&str> = listed.trim().split('\n').map(|s| s.trim()).collect();
+
+        let policy = format!("-P {} ACCEPT", self.firewall.chain);
+        if listed[0] != policy {
+            println!("Got '{}' instead of '{}'", listed[0], policy);
+            return Err(anyhow!(
+                "Failed to get {} ACCEPT rules",
+                self.firewall.chain
+            ));
+        }
+
+        Ok(self
+            .firewall
+            .rules
+            .iter()
+            .map(String::as_str)
+            .filter(|rule| !listed.contains(&format!("-A {} {rule}", self.firewall.chain).as_str()))
+            .collect())
+    }
+
+    pub fn add_firewall_rule_command(&self, rule: &str) -> RemoteCommand {
+        self.iptables()
+            .arg("-A")
+            .arg(&self.firewall.chain)
+            .args(rule.split_whitespace())
+    }
+
+    pub fn init_hook_commands(&self, job_id: &str) -> Vec<(&str, RemoteCommand)> {
+        self.init_hooks
+            .iter()
+            .map(|hook| {
+                (
+                    hook.description.as_str(),
+                    RemoteCommand::from_argv(
+                        hook.command
+                            .iter()
+                            .map(|arg| arg.replace("{job_id}", job_id)),
+                    ),
+                )
+            })
+            .collect()
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.name.is_empty() {
+            return Err(anyhow!("family name is empty"));
+        }
+        if !self.ami_name.contains("{arch}") {
+            return Err(anyhow!("ami name of {} has no {{arch}}", self.name));
+        }
+        if self.firewall.chain.is_empty() {
+            return Err(anyhow!("firewall chain of {} is empty", self.name));
+        }
+        if self.init_hooks.iter().any(|hook| hook.command.is_empty()) {
+            return Err(anyhow!("init hook of {} has no command", self.name));
+        }
+
+        Ok(())
+    }
+}
+
+// Configured families, in the order they were defined
+#[derive(Debug, Clone, PartialEq)]
+pub struct Families {
+    families: Vec<Family>,
+}
+
+impl Default for Families {
+    fn default() -> Self {
+        Families::parse(DEFAULT_FAMILIES).expect("default families are valid")
+    }
+}
+
+impl Families {
+    // empty location uses the default families
+    pub fn load(location: &str) -> Result<Families> {
+        if location.is_empty() {
+            return Ok(Families::default());
+        }
+
+        let contents = fs::read_to_string(location).context("Error reading file")?;
+        Families::parse(&contents).context("failed to parse families file")
+    }
+
+    pub fn parse(contents: &str) -> Result<Families> {
+        let families: Vec<Family> = serde_json::from_str(contents)?;
+        if families.is_empty() {
+            return Err(anyhow!("no families configured"));
+        }
+
+        let mut names = HashSet::new();
+        for family in &families {
+            family.validate()?;
+            if !names.insert(family.name.as_str()) {
+                return Err(anyhow!("family {} is defined twice", family.name));
+            }
+        }
+
+        Ok(Families { families })
+    }
+
+    pub fn get(&self, name: &str) -> Result<&Family> {
+        self.families
+            .iter()
+            .find(|family| family.name == name)
+            .ok_or_else(|| {
+                anyhow!(
+                    "unsupported image family: {name}, configured families: {}",
+                    self.names().join(", ")
+                )
+            })
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.families
+            .iter()
+            .map(|family| family.name.as_str())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Families;
+
+    #[test]
+    fn test_default_families() {
+        let families = Families::default();
+        assert_eq!(families.names(), vec!["salmon", "tuna"]);
+
+        let salmon = families.get("salmon").unwrap();
+        assert_eq!(
+            salmon.ami_name("arm64"),
+            "marlin/oyster/worker-salmon-arm64-????????"
+        );
+        assert!(salmon.sysctl_commands().is_empty());
+        assert!(salmon.init_hook_commands("0x01").is_empty());
+        assert_eq!(
+            salmon.list_firewall_command().as_str(),
+            "sudo iptables '-t' 'nat' '-S' 'PREROUTING'"
+        );
+
+        let tuna = families.get("tuna").unwrap();
+        assert_eq!(
+            tuna.sysctl_commands()[0].as_str(),
+            "sudo sysctl -w 'net.ipv4.ip_local_port_range=61440 65535'"
+        );
+        assert_eq!(
+            tuna.restart_allocator_command().as_str(),
+            "sudo systemctl restart 'nitro-enclaves-allocator.service'"
+        );
+
+        let err = families.get("trout").unwrap_err().to_string();
+        assert!(err.contains("salmon, tuna"));
+    }
+
+    #[test]
+    fn test_missing_firewall_rules() {
+        let families = Families::default();
+        let tuna = families.get("tuna").unwrap();
+
+        let listed = "-P INPUT ACCEPT\n\
+            -A INPUT -i ens5 -p tcp -m tcp --dport 443 -j NFQUEUE --queue-num 0\n";
+        assert_eq!(
+            tuna.missing_firewall_rules(listed).unwrap(),
+            vec![
+                "-i ens5 -p tcp -m tcp --dport 80 -j NFQUEUE --queue-num 0",
+                "-i ens5 -p tcp -m tcp --dport 1024:61439 -j NFQUEUE --queue-num 0",
+            ]
+        );
+        assert_eq!(
+            tuna.add_firewall_rule_command("-i ens5 -p tcp -m tcp --dport 80 -j NFQUEUE --queue-num 0")
+                .as_str(),
+            "sudo iptables '-A' 'INPUT' '-i' 'ens5' '-p' 'tcp' '-m' 'tcp' '--dport' '80' '-j' 'NFQUEUE' '--queue-num' '0'"
+        );
+
+        assert!(tuna.missing_firewall_rules("-P INPUT DROP\n").is_err());
+    }
+
+    #[test]
+    fn test_init_hooks() {
+        let families = Families::default();
+        let hooks = families
+            .get("tuna")
+            .unwrap()
+            .init_hook_commands("0x01020304");
+
+        assert_eq!(hooks[0].0, "set job id for init server");
+        assert_eq!(
+            hooks[0].1.as_str(),
+            "'sudo' 'sed' '-i' '-e' 's/placeholder_job_id/0x01020304/g' '/etc/supervisor/conf.d/oyster-init-server.conf'"
+        );
+        assert_eq!(hooks[1].1.as_str(), "'sudo' 'supervisorctl' 'update'");
+    }
+
+    #[test]
+    fn test_invalid_families() {
+        assert!(Families::parse("[]").is_err());
+
+        // no {arch} in the ami name
+        assert!(Families::parse(
+            r#"[{"name": "trout", "ami_name": "trout", "firewall": {"chain": "INPUT", "rules": []}}]"#
+        )
+        .is_err());
+
+        // duplicate names
+        let trout = r#"{"name": "trout", "ami_name": "trout-{arch}", "firewall": {"chain": "INPUT", "rules": []}}"#;
+        assert!(Families::parse(&format!("[{trout}]")).is_ok());
+        assert!(Families::parse(&format!("[{trout}, {trout}]")).is_err());
+
+        // hooks need a command
+        assert!(Families::parse(
+            r#"[{"name": "trout", "ami_name": "trout-{arch}", "firewall": {"chain": "INPUT", "rules": []}, "init_hooks": [{"description": "nothing", "command": []}]}]"#
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,229 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+// Host key of an instance did not match the one it was pinned to
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostKeyMismatch {
+    pub instance_id: String,
+    pub expected: Vec<String>,
+    pub presented: String,
+}
+
+impl fmt::Display for HostKeyMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "host key mismatch for {}: expected one of {:?}, got {}",
+            self.instance_id, self.expected, self.presented
+        )
+    }
+}
+
+impl std::error::Error for HostKeyMismatch {}
+
+// Pinned SHA256 host key fingerprints per instance id, persisted as a json file
+// Instance ids are never reused, so a pin is only ever set once for an instance
+#[derive(Clone)]
+pub struct HostKeyStore {
+    path: String,
+    keys: Arc<Mutex<HashMap<String, String>>>,
+    // orders the writes of the file, the keys are not locked while writing
+    writes: Arc<tokio::sync::Mutex<()>>,
+    // pins are only kept in memory, the file is left to another process
+    read_only: bool,
+}
+
+impl HostKeyStore {
+    pub fn new(path: String) -> Result<HostKeyStore> {
+        let keys = if Path::new(&path).exists() {
+            let contents = fs::read_to_string(&path).context("failed to read host keys file")?;
+            serde_json::from_str(&contents).context("failed to parse host keys file")?
+        } else {
+            HashMap::new()
+        };
+
+        Ok(HostKeyStore {
+            path,
+            keys: Arc::new(Mutex::new(keys)),
+            writes: Arc::new(tokio::sync::Mutex::new(())),
+            read_only: false,
+        })
+    }
+
+    // pins from the file are still checked, new pins and removals are not written back
+    pub fn read_only(self) -> HostKeyStore {
+        HostKeyStore {
+            read_only: true,
+            ..self
+        }
+    }
+
+    pub fn get(&self, instance_id: &str) -> Option<String> {
+        self.keys.lock().unwrap().get(instance_id).cloned()
+    }
+
+    pub async fn pin(&self, instance_id: &str, fingerprint: &str) -> Result<()> {
+        self.keys
+            .lock()
+            .unwrap()
+            .insert(instance_id.to_owned(), fingerprint.to_owned());
+        self.persist().await
+    }
+
+    pub async fn forget(&self, instance_id: &str) -> Result<()> {
+        let removed = self.keys.lock().unwrap().remove(instance_id);
+        if removed.is_none() {
+            return Ok(());
+        }
+        self.persist().await
+    }
+
+    async fn persist(&self) -> Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+
+        // the keys are read once the previous write is done, so a later write never loses pins
+        let _writes = self.writes.lock().await;
+        let contents = serde_json::to_string(&*self.keys.lock().unwrap())
+            .context("failed to serialize host keys")?;
+
+        // write to a temporary file and rename so a crash never loses the pins
+        let tmp_path = self.path.clone() + ".tmp";
+        tokio::fs::write(&tmp_path, contents)
+            .await
+            .context("failed to write host keys file")?;
+        tokio::fs::rename(&tmp_path, &self.path)
+            .await
+            .context("failed to rename host keys file")?;
+
+        Ok(())
+    }
+}
+
+// cloud-init prints the host key fingerprints to the console on first boot
+//
+// -----BEGIN SSH HOST KEY FINGERPRINTS-----
+// 256 SHA256:1D2Hb3... root@ip-172-31-0-1 (ED25519)
+// -----END SSH HOST KEY FINGERPRINTS-----
+pub fn parse_console_fingerprints(console_output: &str) -> Vec<String> {
+    console_output
+        .lines()
+        .skip_while(|line| !line.contains("-----BEGIN SSH HOST KEY FINGERPRINTS-----"))
+        .skip(1)
+        .take_while(|line| !line.contains("-----END SSH HOST KEY FINGERPRINTS-----"))
+        .filter_map(|line| {
+            line.split_whitespace()
+                .find(|field| field.starts_with("SHA256:"))
+                .map(str::to_owned)
+        })
+        .collect()
+}
+
+// pinned keys win, console fingerprints are used for the first connection if available
+// with neither, the presented key is trusted and should be pinned by the caller
+pub fn check_host_key(
+    instance_id: &str,
+    presented: &str,
+    pinned: Option<&str>,
+    console_fingerprints: &[String],
+) -> Result<(), HostKeyMismatch> {
+    let expected = match pinned {
+        Some(pinned) => vec![pinned.to_owned()],
+        None => console_fingerprints.to_vec(),
+    };
+
+    if expected.is_empty() || expected.iter().any(|key| key == presented) {
+        return Ok(());
+    }
+
+    Err(HostKeyMismatch {
+        instance_id: instance_id.to_owned(),
+        expected,
+        presented: presented.to_owned(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_host_key, parse_console_fingerprints, HostKeyStore};
+
+    #[test]
+    fn test_parse_console_fingerprints() {
+        let output = "[   10.1] cloud-init[1234]: Generating public/private ed25519 key pair.\n\
+            <14>Jan  1 00:00:00 ec2: \n\
+            <14>Jan  1 00:00:00 ec2: #############################################################\n\
+            <14>Jan  1 00:00:00 ec2: -----BEGIN SSH HOST KEY FINGERPRINTS-----\n\
+            <14>Jan  1 00:00:00 ec2: 256 SHA256:ecdsaFingerprint root@ip-172-31-0-1 (ECDSA)\n\
+            <14>Jan  1 00:00:00 ec2: 256 SHA256:ed25519Fingerprint root@ip-172-31-0-1 (ED25519)\n\
+            <14>Jan  1 00:00:00 ec2: -----END SSH HOST KEY FINGERPRINTS-----\n\
+            <14>Jan  1 00:00:00 ec2: 256 SHA256:notAFingerprint root@ip-172-31-0-1 (ED25519)\n";
+
+        assert_eq!(
+            parse_console_fingerprints(output),
+            vec!["SHA256:ecdsaFingerprint", "SHA256:ed25519Fingerprint"]
+        );
+
+        // console output is empty until the instance has booted
+        assert!(parse_console_fingerprints("").is_empty());
+    }
+
+    #[test]
+    fn test_check_host_key() {
+        let console = vec!["SHA256:a".to_owned(), "SHA256:b".to_owned()];
+
+        // trust on first use
+        assert!(check_host_key("i-1", "SHA256:x", None, &[]).is_ok());
+
+        // first use checked against the console
+        assert!(check_host_key("i-1", "SHA256:b", None, &console).is_ok());
+        let err = check_host_key("i-1", "SHA256:x", None, &console).unwrap_err();
+        assert_eq!(err.expected, console);
+        assert_eq!(err.presented, "SHA256:x");
+
+        // pins win over the console
+        assert!(check_host_key("i-1", "SHA256:x", Some("SHA256:x"), &console).is_ok());
+        assert!(check_host_key("i-1", "SHA256:a", Some("SHA256:x"), &console).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_host_key_store_persistence() -> anyhow::Result<()> {
+        let path = std::env::temp_dir()
+            .join(format!("cp-host-keys-{}.json", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+
+        let store = HostKeyStore::new(path.clone())?;
+        assert_eq!(store.get("i-1"), None);
+        store.pin("i-1", "SHA256:a").await?;
+        store.pin("i-2", "SHA256:b").await?;
+        store.forget("i-2").await?;
+
+        let store = HostKeyStore::new(path.clone())?;
+        assert_eq!(store.get("i-1"), Some("SHA256:a".to_owned()));
+        assert_eq!(store.get("i-2"), None);
+
+        std::fs::remove_file(path.clone())?;
+
+        // read only stores check the pins of the file without changing it
+        let store = HostKeyStore::new(path.clone())?;
+        store.pin("i-1", "SHA256:a").await?;
+        let store = HostKeyStore::new(path.clone())?.read_only();
+        assert_eq!(store.get("i-1"), Some("SHA256:a".to_owned()));
+        store.pin("i-2", "SHA256:b").await?;
+        store.forget("i-1").await?;
+        assert_eq!(store.get("i-2"), Some("SHA256:b".to_owned()));
+
+        let store = HostKeyStore::new(path.clone())?;
+        assert_eq!(store.get("i-1"), Some("SHA256:a".to_owned()));
+        assert_eq!(store.get("i-2"), None);
+
+        std::fs::remove_file(path)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,256 @@
+use std::collections::HashSet;
+use std::fs;
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::admission::AdmissionPolicy;
+use crate::market::{GBRateCard, RegionalRates};
+use crate::reload::{Reloadable, Snapshot};
+
+// Rate cards of a deployment and the policy jobs are admitted by
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MarketInputs {
+    pub rates: Vec<RegionalRates>,
+    pub gb_rates: Vec<GBRateCard>,
+    // includes the address lists
+    pub admission: AdmissionPolicy,
+}
+
+impl MarketInputs {
+    pub fn validate(&self) -> Result<()> {
+        let mut regions = HashSet::new();
+        for entry in &self.rates {
+            if !regions.insert(entry.region.as_str()) {
+                return Err(anyhow!("rates of {} are defined twice", entry.region));
+            }
+
+            let mut instances = HashSet::new();
+            for card in &entry.rate_cards {
+                if !instances.insert(card.instance.as_str()) {
+                    return Err(anyhow!(
+                        "rate of {} in {} is defined twice",
+                        card.instance,
+                        entry.region
+                    ));
+                }
+            }
+        }
+
+        let mut regions = HashSet::new();
+        for entry in &self.gb_rates {
+            if !regions.insert(entry.region_code.as_str()) {
+                return Err(anyhow!(
+                    "bandwidth rate of {} is defined twice",
+                    entry.region_code
+                ));
+            }
+            // bandwidth is derived by dividing by the rate
+            if entry.rate.is_zero() {
+                return Err(anyhow!("bandwidth rate of {} is zero", entry.region_code));
+            }
+        }
+
+        self.admission
+            .validate()
+            .context("invalid admission policy")?;
+
+        Ok(())
+    }
+}
+
+// inputs of a deployment, read by job managers and the http server and swapped on reloads
+pub type SharedInputs = Snapshot<MarketInputs>;
+
+// Locations the inputs of a deployment are loaded from, empty locations leave them empty
+#[derive(Debug, Clone, Default)]
+pub struct InputFiles {
+    pub rates: String,
+    pub bandwidth: String,
+    pub address_whitelist: String,
+    pub address_blacklist: String,
+    pub admission_policy: String,
+}
+
+impl InputFiles {
+    pub fn load(&self) -> Result<MarketInputs> {
+        let inputs = MarketInputs {
+            rates: parse_json_file(&self.rates).context("failed to parse rates file")?,
+            gb_rates: parse_json_file(&self.bandwidth)
+                .context("failed to parse bandwidth rates file")?,
+            admission: parse_policy_file(&self.admission_policy)
+                .context("failed to parse admission policy")?
+                .with_address_lists(
+                    parse_lines_file(&self.address_whitelist)
+                        .context("Failed to parse address whitelist")?,
+                    parse_lines_file(&self.address_blacklist)
+                        .context("Failed to parse address blacklist")?,
+                ),
+        };
+        inputs.validate()?;
+
+        Ok(inputs)
+    }
+}
+
+fn parse_lines_file(location: &str) -> Result<Vec<String>> {
+    if location.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let contents = fs::read_to_string(location).context("Error reading file")?;
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_owned)
+        .collect())
+}
+
+fn parse_json_file<T: serde::de::DeserializeOwned>(location: &str) -> Result<Vec<T>> {
+    if location.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let contents = fs::read_to_string(location).context("Error reading file")?;
+    Ok(serde_json::from_str(&contents)?)
+}
+
+fn parse_policy_file(location: &str) -> Result<AdmissionPolicy> {
+    if location.is_empty() {
+        return Ok(AdmissionPolicy::default());
+    }
+
+    let contents = fs::read_to_string(location).context("Error reading file")?;
+    Ok(serde_json::from_str(&contents)?)
+}
+
+// Inputs of a deployment along with the files they are reloaded from
+pub struct DeploymentInputs {
+    // contract the inputs belong to, for logs
+    pub name: String,
+    pub files: InputFiles,
+    pub inputs: SharedInputs,
+}
+
+impl Reloadable for DeploymentInputs {
+    fn name(&self) -> String {
+        format!("inputs of {}", self.name)
+    }
+
+    fn files(&self) -> Vec<String> {
+        vec![
+            self.files.rates.clone(),
+            self.files.bandwidth.clone(),
+            self.files.address_whitelist.clone(),
+            self.files.address_blacklist.clone(),
+            self.files.admission_policy.clone(),
+        ]
+    }
+
+    fn reload(&self) -> Result<()> {
+        let inputs = self.files.load()?;
+        if *self.inputs.load() != inputs {
+            println!("reload: {}: {inputs:?}", self.name());
+        }
+        self.inputs.store(inputs);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use ethers::types::U256;
+
+    use super::{DeploymentInputs, InputFiles, MarketInputs, SharedInputs};
+    use crate::admission::AdmissionPolicy;
+    use crate::reload::Reloadable;
+    use crate::test;
+
+    const OWNER: &str = "0x000000000000000000000000000000000000000000000000f020b3e5fc7a49ec";
+
+    #[test]
+    fn test_validate() {
+        let inputs = MarketInputs {
+            rates: test::get_rates(),
+            gb_rates: test::get_gb_rates(),
+            admission: AdmissionPolicy::default()
+                .with_address_lists(vec![OWNER.to_owned()], Vec::new()),
+        };
+        assert!(inputs.validate().is_ok());
+
+        let mut duplicate = inputs.clone();
+        duplicate.rates.push(duplicate.rates[0].clone());
+        assert!(duplicate.validate().is_err());
+
+        let mut free_bandwidth = inputs.clone();
+        free_bandwidth.gb_rates[0].rate = U256::zero();
+        assert!(free_bandwidth.validate().is_err());
+
+        let mut checksummed = inputs.clone();
+        checksummed.admission = AdmissionPolicy::default()
+            .with_address_lists(Vec::new(), vec![OWNER.to_uppercase().replace("0X", "0x")]);
+        assert!(checksummed.validate().is_err());
+
+        let mut short = inputs;
+        short.admission = AdmissionPolicy::default()
+            .with_address_lists(vec!["0xf020b3e5fc7a49ec".to_owned()], Vec::new());
+        assert!(short.validate().is_err());
+    }
+
+    #[test]
+    fn test_reload() {
+        let dir = std::env::temp_dir().join(format!("cp-inputs-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let whitelist = dir.join("whitelist.txt");
+        fs::write(&whitelist, format!("{OWNER}\n\n")).unwrap();
+        let policy = dir.join("policy.json");
+        fs::write(
+            &policy,
+            r#"{"rules": [{"name": "mumbai", "effect": "allow", "when": {"regions": ["ap-south-1"]}}]}"#,
+        )
+        .unwrap();
+
+        let files = InputFiles {
+            address_whitelist: whitelist.to_str().unwrap().to_owned(),
+            admission_policy: policy.to_str().unwrap().to_owned(),
+            ..Default::default()
+        };
+        let deployment = DeploymentInputs {
+            name: "xyz".to_owned(),
+            inputs: SharedInputs::new(files.load().unwrap()),
+            files,
+        };
+        let rules = |deployment: &DeploymentInputs| -> Vec<String> {
+            let inputs = deployment.inputs.load();
+            inputs
+                .admission
+                .rules
+                .iter()
+                .map(|rule| rule.name.clone())
+                .collect()
+        };
+        // address lists go before the rules of the policy file
+        assert_eq!(rules(&deployment), vec!["address-whitelist", "mumbai"]);
+
+        fs::write(&whitelist, "").unwrap();
+        deployment.reload().unwrap();
+        assert_eq!(rules(&deployment), vec!["mumbai"]);
+
+        // invalid files keep the current inputs
+        fs::write(&whitelist, "not an address\n").unwrap();
+        assert!(deployment.reload().is_err());
+        fs::write(&whitelist, "").unwrap();
+        fs::write(
+            &policy,
+            r#"{"rules": [{"name": "default", "effect": "deny"}]}"#,
+        )
+        .unwrap();
+        assert!(deployment.reload().is_err());
+        assert_eq!(rules(&deployment), vec!["mumbai"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,5 +1,25 @@
+pub mod admission;
 pub mod aws;
+pub mod capacity;
+pub mod command;
+pub mod eip_gc;
+pub mod family;
+pub mod host_keys;
+pub mod inputs;
 pub mod market;
+pub mod metadata;
+pub mod pcr;
+pub mod placement;
+pub mod polling;
+pub mod quorum;
+pub mod readiness;
+pub mod reconcile;
+pub mod reload;
+pub mod retry;
 pub mod server;
+pub mod shadow;
+pub mod simulated;
+pub mod ssh;
+pub mod store;
 #[cfg(test)]
 mod test;
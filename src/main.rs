@@ -1,28 +1,63 @@
+use cp::admission;
 use cp::aws;
+use cp::capacity;
+use cp::eip_gc;
+use cp::family;
+use cp::inputs;
 use cp::market;
+use cp::polling;
+use cp::readiness;
+use cp::reconcile;
+use cp::reload;
 use cp::server;
+use cp::shadow;
+use cp::simulated;
+use cp::store;
 
+use anyhow::anyhow;
 use anyhow::Context;
 use anyhow::Result;
 use clap::Parser;
 use ethers::abi::AbiEncode;
 use ethers::prelude::*;
-use ethers::providers::{Provider, Ws};
+use ethers::providers::{Http, Provider, Ws};
+use serde::Deserialize;
 use std::fs;
 use std::net::SocketAddr;
+use std::time::Duration;
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
 /// Control plane for Oyster
 struct Cli {
-    /// AWS profile
-    #[clap(long, value_parser)]
+    /// Infrastructure to run jobs on, aws or simulated
+    #[clap(long, value_parser, default_value = "aws")]
+    infra: String,
+
+    /// Only record the infrastructure changes that would be made, served at /shadow/actions
+    #[clap(long)]
+    shadow: bool,
+
+    /// AWS profile, required for aws infra
+    #[clap(long, value_parser, default_value = "")]
     profile: String,
 
-    /// AWS keypair name
-    #[clap(long, value_parser)]
+    /// AWS keypair name, required for aws infra
+    #[clap(long, value_parser, default_value = "")]
     key_name: String,
 
+    /// Seconds simulated instances take to boot
+    #[clap(long, value_parser, default_value = "30")]
+    sim_boot_latency: u64,
+
+    /// Probability of simulated instance launches failing
+    #[clap(long, value_parser, default_value = "0")]
+    sim_launch_failure_rate: f64,
+
+    /// Probability of simulated enclaves failing to start
+    #[clap(long, value_parser, default_value = "0")]
+    sim_enclave_failure_rate: f64,
+
     /// AWS regions
     #[clap(
         long,
@@ -31,33 +66,89 @@ struct Cli {
     )]
     regions: String,
 
-    /// RPC url
-    #[clap(long, value_parser)]
+    /// Deployments config location, overrides rpc, contract, provider and rate flags
+    #[clap(long, value_parser, default_value = "")]
+    deployments: String,
+
+    /// RPC urls, comma separated
+    #[clap(long, value_parser, default_value = "")]
     rpc: String,
 
+    /// How multiple RPC urls are used, failover or quorum
+    #[clap(long, value_parser, default_value = "failover")]
+    rpc_mode: String,
+
+    /// Number of RPC urls that have to agree on logs in quorum mode, majority if 0
+    #[clap(long, value_parser, default_value = "0")]
+    quorum: usize,
+
+    /// Block to start discovering jobs from, jobs opened before it are not managed
+    #[clap(long, value_parser, default_value = "0")]
+    start_block: u64,
+
+    /// Seconds without new heads before switching RPC urls
+    #[clap(long, value_parser, default_value = "60")]
+    stall_timeout: u64,
+
     /// Rates location
-    #[clap(long, value_parser)]
+    #[clap(long, value_parser, default_value = "")]
     rates: String,
 
     /// Bandwidth Rates location
-    #[clap(long, value_parser)]
+    #[clap(long, value_parser, default_value = "")]
     bandwidth: String,
 
     /// Contract address
-    #[clap(long, value_parser)]
+    #[clap(long, value_parser, default_value = "")]
     contract: String,
 
     /// Provider address
-    #[clap(long, value_parser)]
+    #[clap(long, value_parser, default_value = "")]
     provider: String,
 
-    /// Blacklist location
+    /// Enclave image PCR denylist location, json list of PCR rules
+    #[clap(long, value_parser, default_value = "")]
+    pcr_denylist: String,
+
+    /// Enclave image PCR allowlist location, json list of PCR rules
     #[clap(long, value_parser, default_value = "")]
-    blacklist: String,
+    pcr_allowlist: String,
 
-    /// Whitelist location
+    /// Enclave family definitions location, built-in salmon and tuna families if empty
     #[clap(long, value_parser, default_value = "")]
-    whitelist: String,
+    families: String,
+
+    /// Seconds to wait for launched instances to become reachable over ssh
+    #[clap(long, value_parser, default_value = "600")]
+    boot_timeout: u64,
+
+    /// What to do with instances and elastic ips of closed or unknown jobs, off, report or enforce
+    #[clap(long, value_parser, default_value = "report")]
+    reconcile: String,
+
+    /// Seconds between reconciliations of tagged resources with live jobs
+    #[clap(long, value_parser, default_value = "600")]
+    reconcile_interval: u64,
+
+    /// Seconds resources have to stay orphaned before they are cleaned up
+    #[clap(long, value_parser, default_value = "1800")]
+    reconcile_grace: u64,
+
+    /// What to do with elastic ips left unassociated by failed launches, off, report or enforce
+    #[clap(long, value_parser, default_value = "report")]
+    eip_gc: String,
+
+    /// Seconds between elastic ip garbage collections
+    #[clap(long, value_parser, default_value = "300")]
+    eip_gc_interval: u64,
+
+    /// Seconds elastic ips have to stay unassociated before they are released
+    #[clap(long, value_parser, default_value = "900")]
+    eip_gc_grace: u64,
+
+    /// Seconds between checks for modified rates, address list, admission policy and pcr list files, they are also reloaded on SIGHUP
+    #[clap(long, value_parser, default_value = "10")]
+    reload_interval: u64,
 
     /// Address Blacklist location
     #[clap(long, value_parser, default_value = "")]
@@ -66,150 +157,546 @@ struct Cli {
     /// Address Whitelist location
     #[clap(long, value_parser, default_value = "")]
     address_whitelist: String,
+
+    /// Admission policy location, rules evaluated after the address lists when jobs are opened
+    #[clap(long, value_parser, default_value = "")]
+    admission_policy: String,
+
+    /// Max vcpus of all launched instances together, launches beyond it wait in a queue, 0 for no limit
+    #[clap(long, value_parser, default_value = "0")]
+    max_vcpus: u64,
+
+    /// Max instances per region, launches beyond it wait in a queue, 0 for no limit
+    #[clap(long, value_parser, default_value = "0")]
+    max_instances_per_region: usize,
+
+    /// Max instances per instance type, launches beyond it wait in a queue, 0 for no limit
+    #[clap(long, value_parser, default_value = "0")]
+    max_instances_per_type: usize,
+
+    /// Job and job discovery checkpoint directory, checkpointing is disabled if empty
+    #[clap(long, value_parser, default_value = "")]
+    state_dir: String,
+
+    /// Number of blocks to wait for before applying job events, required for http rpc urls
+    #[clap(long, value_parser, default_value = "0")]
+    confirmations: u64,
+
+    /// Poll interval in seconds for http rpc urls
+    #[clap(long, value_parser, default_value = "5")]
+    poll_interval: u64,
+}
+
+// A marketplace deployment to serve, several can share the same process
+#[derive(Deserialize)]
+struct DeploymentConfig {
+    // fetched from the rpc if not set, checked against it otherwise
+    #[serde(default)]
+    chain: Option<String>,
+    // comma separated
+    rpc: String,
+    #[serde(default = "default_rpc_mode")]
+    rpc_mode: String,
+    #[serde(default)]
+    quorum: usize,
+    // block to start discovering jobs from
+    #[serde(default)]
+    start_block: u64,
+    contract: String,
+    provider: String,
+    // rates and bandwidth rates locations
+    rates: String,
+    bandwidth: String,
+}
+
+fn default_rpc_mode() -> String {
+    "failover".to_owned()
 }
 
-async fn parse_file(filepath: String) -> Result<Vec<String>> {
-    if filepath.is_empty() {
-        return Ok(Vec::new());
+async fn parse_deployments_file(cli: &Cli) -> Result<Vec<DeploymentConfig>> {
+    if cli.deployments.is_empty() {
+        // single deployment from flags
+        return Ok(vec![DeploymentConfig {
+            chain: None,
+            rpc: cli.rpc.clone(),
+            rpc_mode: cli.rpc_mode.clone(),
+            quorum: cli.quorum,
+            start_block: cli.start_block,
+            contract: cli.contract.clone(),
+            provider: cli.provider.clone(),
+            rates: cli.rates.clone(),
+            bandwidth: cli.bandwidth.clone(),
+        }]);
     }
 
-    let contents = fs::read_to_string(filepath).context("Error reading file")?;
-    let lines: Vec<String> = contents.lines().map(|s| s.to_string()).collect();
+    let contents = fs::read_to_string(&cli.deployments).context("Error reading file")?;
+    let deployments: Vec<DeploymentConfig> =
+        serde_json::from_str(&contents).context("failed to parse deployments file")?;
+    if deployments.is_empty() {
+        return Err(anyhow!("no deployments configured"));
+    }
+
+    Ok(deployments)
+}
 
-    Ok(lines)
+// websocket urls get subscriptions, http urls get polled
+fn is_http_rpc_url(url: &str) -> Result<bool> {
+    match url.split_once("://").map(|(scheme, _)| scheme) {
+        Some("ws" | "wss") => Ok(false),
+        Some("http" | "https") => Ok(true),
+        _ => Err(anyhow!("unsupported rpc url scheme: {url}")),
+    }
 }
 
-async fn parse_compute_rates_file(filepath: String) -> Result<Vec<market::RegionalRates>> {
-    if filepath.is_empty() {
-        return Ok(Vec::new());
+fn parse_rpc_config(
+    rpc: &str,
+    rpc_mode: &str,
+    quorum: usize,
+    stall_timeout: u64,
+) -> Result<market::RpcConfig> {
+    let urls: Vec<String> = rpc.split(',').map(|url| url.trim().into()).collect();
+
+    // a single logs provider is used for all urls
+    let http = is_http_rpc_url(&urls[0])?;
+    for url in &urls {
+        if is_http_rpc_url(url)? != http {
+            return Err(anyhow!("rpc urls have to be all http or all websocket"));
+        }
     }
 
-    let contents = fs::read_to_string(filepath).context("Error reading file")?;
-    let rates: Vec<market::RegionalRates> =
-        serde_json::from_str(&contents).context("failed to parse rates file")?;
+    let mode = match rpc_mode {
+        "failover" => market::RpcMode::Failover,
+        "quorum" => {
+            let threshold = if quorum == 0 {
+                urls.len() / 2 + 1
+            } else {
+                quorum
+            };
+            if threshold > urls.len() {
+                return Err(anyhow!(
+                    "quorum of {threshold} needs at least as many rpc urls"
+                ));
+            }
+            market::RpcMode::Quorum { threshold }
+        }
+        _ => return Err(anyhow!("unsupported rpc mode: {rpc_mode}")),
+    };
 
-    Ok(rates)
+    Ok(market::RpcConfig {
+        urls,
+        mode,
+        stall_timeout: Duration::from_secs(stall_timeout),
+    })
 }
 
-async fn parse_bandwidth_rates_file(filepath: String) -> Result<Vec<market::GBRateCard>> {
-    if filepath.is_empty() {
-        return Ok(Vec::new());
+// any reachable url will do, the chain id is only used to identify jobs
+async fn get_chain_id_from_rpc_urls(urls: &[String]) -> Result<String> {
+    for url in urls {
+        match get_chain_id_from_rpc_url(url.clone()).await {
+            Ok(chain_id) => return Ok(chain_id),
+            Err(err) => println!("Failed to fetch chain_id from {url}: {err:?}"),
+        }
     }
 
-    let contents = fs::read_to_string(filepath).context("Error reading file")?;
-    let rates: Vec<market::GBRateCard> =
-        serde_json::from_str(&contents).context("failed to parse rates file")?;
-
-    Ok(rates)
+    Err(anyhow!("no rpc url returned a chain_id"))
 }
 
 async fn get_chain_id_from_rpc_url(url: String) -> Result<String> {
-    let provider = Provider::<Ws>::connect(url)
-        .await
-        .context("Failed to connect to rpc to fetch chain_id")?;
-    let hex_chain_id: String = provider
-        .request("eth_chainId", ())
-        .await
-        .context("Failed to fetch chain_id")?;
+    let hex_chain_id: String = if is_http_rpc_url(&url)? {
+        Provider::<Http>::try_from(url)
+            .context("Failed to parse rpc url to fetch chain_id")?
+            .request("eth_chainId", ())
+            .await
+            .context("Failed to fetch chain_id")?
+    } else {
+        Provider::<Ws>::connect(url)
+            .await
+            .context("Failed to connect to rpc to fetch chain_id")?
+            .request("eth_chainId", ())
+            .await
+            .context("Failed to fetch chain_id")?
+    };
     let chain_id =
         u64::from_str_radix(&hex_chain_id[2..], 16).context("Failed to convert chain_id to u64")?;
 
     Ok(chain_id.to_string())
 }
 
-#[tokio::main]
-pub async fn main() -> Result<()> {
-    let cli = Cli::parse();
+// settings shared by all deployments
+struct DeploymentSettings {
+    stall_timeout: u64,
+    poll_interval: u64,
+    confirmations: u64,
+    shadow: bool,
+    reconcile: reconcile::ReconcileMode,
+    reconcile_interval: u64,
+    reconcile_grace: u64,
+    eip_gc: reconcile::ReconcileMode,
+    eip_gc_interval: u64,
+    eip_gc_grace: u64,
+    // shared by all deployments
+    address_whitelist: String,
+    address_blacklist: String,
+    admission_policy: String,
+    reload_interval: u64,
+    capacity: capacity::CapacityLimits,
+}
 
-    let regions: Vec<String> = cli.regions.split(',').map(|r| (r.into())).collect();
-    println!("Supported regions: {regions:?}");
+// wraps the infra in shadow mode so nothing is changed
+async fn run_with_infra(
+    infra: impl market::InfraProvider + reconcile::TaggedResources + Send + Sync + Clone + 'static,
+    store: store::FileStore,
+    deployment_configs: Vec<DeploymentConfig>,
+    regions: &'static [String],
+    // reloaded along with the inputs of the deployments
+    reloadables: Vec<Box<dyn reload::Reloadable>>,
+    mut settings: DeploymentSettings,
+) -> Result<()> {
+    if !settings.shadow {
+        return run_deployments(
+            infra.clone(),
+            infra,
+            None,
+            store,
+            deployment_configs,
+            regions,
+            reloadables,
+            settings,
+        )
+        .await;
+    }
 
-    let aws = aws::Aws::new(
-        cli.profile,
-        &regions,
-        cli.key_name,
-        cli.whitelist,
-        cli.blacklist,
+    println!("main: Running in shadow mode, infrastructure changes are only recorded");
+    if settings.reconcile == reconcile::ReconcileMode::Enforce {
+        settings.reconcile = reconcile::ReconcileMode::Report;
+    }
+    if settings.eip_gc == reconcile::ReconcileMode::Enforce {
+        settings.eip_gc = reconcile::ReconcileMode::Report;
+    }
+    let actions = shadow::ShadowActions::default();
+    run_deployments(
+        shadow::ShadowInfra::new(infra.clone(), actions.clone()),
+        infra,
+        Some(actions),
+        store,
+        deployment_configs,
+        regions,
+        reloadables,
+        settings,
     )
-    .await;
-
-    aws.generate_key_pair()
-        .await
-        .context("Failed to generate key pair")?;
+    .await
+}
 
-    for region in regions.clone() {
-        aws.key_setup(region)
+// all deployments share the infra client, the job store and the http server
+async fn run_deployments(
+    infra: impl market::InfraProvider + Send + Sync + Clone + 'static,
+    // resources are reconciled with the real infra even in shadow mode
+    resources: impl reconcile::TaggedResources + Send + Sync + Clone + 'static,
+    shadow: Option<shadow::ShadowActions>,
+    store: store::FileStore,
+    deployment_configs: Vec<DeploymentConfig>,
+    regions: &'static [String],
+    mut reloadables: Vec<Box<dyn reload::Reloadable>>,
+    settings: DeploymentSettings,
+) -> Result<()> {
+    let outcomes = market::JobOutcomes::default();
+    let live_jobs = market::LiveJobs::default();
+    let admissions = admission::Admissions::default();
+    // limits apply to the instances of all deployments together
+    let capacity = capacity::Capacity::new(settings.capacity);
+
+    let mut deployments: Vec<server::Deployment> = Vec::new();
+    let mut tasks = Vec::new();
+    for config in deployment_configs {
+        let rpc = parse_rpc_config(
+            &config.rpc,
+            &config.rpc_mode,
+            config.quorum,
+            settings.stall_timeout,
+        )
+        .context("Failed to parse rpc config")?;
+        let chain = get_chain_id_from_rpc_urls(&rpc.urls)
             .await
-            .context("Failed to setup key pair in {region}")?;
+            .context("Failed to fetch chain_id")?;
+        if config
+            .chain
+            .as_ref()
+            .is_some_and(|expected| *expected != chain)
+        {
+            return Err(anyhow!(
+                "chain_id {chain} of {} does not match the configured one",
+                config.rpc
+            ));
+        }
+
+        if deployments.iter().any(|deployment| {
+            deployment.job_id.chain == chain
+                && deployment
+                    .job_id
+                    .contract
+                    .eq_ignore_ascii_case(&config.contract)
+        }) {
+            return Err(anyhow!(
+                "contract {} on chain {chain} configured more than once",
+                config.contract
+            ));
+        }
+
+        let files = inputs::InputFiles {
+            rates: config.rates,
+            bandwidth: config.bandwidth,
+            address_whitelist: settings.address_whitelist.clone(),
+            address_blacklist: settings.address_blacklist.clone(),
+            admission_policy: settings.admission_policy.clone(),
+        };
+        let shared_inputs = inputs::SharedInputs::new(
+            files
+                .load()
+                .with_context(|| format!("failed to load inputs of {}", config.contract))?,
+        );
+        reloadables.push(Box::new(inputs::DeploymentInputs {
+            name: config.contract.clone(),
+            files,
+            inputs: shared_inputs.clone(),
+        }));
+
+        let job_id = market::JobId {
+            id: H256::zero().encode_hex(),
+            operator: config.provider.clone(),
+            contract: config.contract.clone(),
+            chain,
+        };
+        deployments.push(server::Deployment {
+            job_id: job_id.clone(),
+            inputs: shared_inputs.clone(),
+        });
+
+        let contract = config
+            .contract
+            .parse::<Address>()
+            .context("failed to parse contract address")?;
+        let provider = config
+            .provider
+            .parse::<Address>()
+            .context("failed to parse provider address")?;
+
+        if is_http_rpc_url(&rpc.urls[0])? {
+            // polled logs are never reported as removed, they are only safe to apply once confirmed
+            if settings.confirmations == 0 {
+                return Err(anyhow!(
+                    "http rpc urls never report logs removed by reorgs, they need --confirmations"
+                ));
+            }
+            let polling = polling::PollingProvider {
+                contract,
+                provider,
+                poll_interval: Duration::from_secs(settings.poll_interval),
+                confirmations: settings.confirmations,
+            };
+
+            // logs are confirmed by the time they are polled, job managers apply them right away
+            tasks.push(tokio::spawn(market::run(
+                infra.clone(),
+                polling,
+                store.clone(),
+                rpc,
+                config.start_block,
+                regions,
+                shared_inputs.clone(),
+                job_id,
+                0,
+                outcomes.clone(),
+                live_jobs.clone(),
+                admissions.clone(),
+                capacity.clone(),
+            )));
+        } else {
+            let ethers = market::EthersProvider { contract, provider };
+
+            tasks.push(tokio::spawn(market::run(
+                infra.clone(),
+                ethers,
+                store.clone(),
+                rpc,
+                config.start_block,
+                regions,
+                shared_inputs.clone(),
+                job_id,
+                settings.confirmations,
+                outcomes.clone(),
+                live_jobs.clone(),
+                admissions.clone(),
+                capacity.clone(),
+            )));
+        }
     }
 
-    let compute_rates = parse_compute_rates_file(cli.rates)
-        .await
-        .context("failed to parse computes rates file")?;
-    let bandwidth_rates = parse_bandwidth_rates_file(cli.bandwidth)
-        .await
-        .context("failed to parse bandwidth rates file")?;
+    let deployments: &'static [server::Deployment] = Box::leak(deployments.into_boxed_slice());
+
+    let reload_interval = Duration::from_secs(settings.reload_interval);
+    tokio::spawn(async move {
+        if let Err(err) = reload::run(reloadables, reload_interval).await {
+            println!("main: Inputs will not be reloaded: {err:?}");
+        }
+    });
+
+    let deployment_ids: Vec<market::JobId> = deployments
+        .iter()
+        .map(|deployment| deployment.job_id.clone())
+        .collect();
+
+    if settings.eip_gc != reconcile::ReconcileMode::Off {
+        let collector = eip_gc::EipCollector::new(
+            resources.clone(),
+            regions,
+            deployment_ids.clone(),
+            settings.eip_gc,
+            Duration::from_secs(settings.eip_gc_grace),
+        );
+        tokio::spawn(collector.run(Duration::from_secs(settings.eip_gc_interval)));
+    }
 
-    let address_whitelist_vec: Vec<String> = parse_file(cli.address_whitelist)
-        .await
-        .context("Failed to parse address whitelist")?;
-    let address_blacklist_vec: Vec<String> = parse_file(cli.address_blacklist)
+    if settings.reconcile != reconcile::ReconcileMode::Off {
+        let reconciler = reconcile::Reconciler::new(
+            resources,
+            regions,
+            deployment_ids,
+            live_jobs,
+            settings.reconcile,
+            Duration::from_secs(settings.reconcile_grace),
+        );
+        tokio::spawn(reconciler.run(Duration::from_secs(settings.reconcile_interval)));
+    }
+
+    tokio::spawn(server::serve(
+        infra,
+        regions,
+        deployments,
+        SocketAddr::from(([0, 0, 0, 0], 8080)),
+        outcomes,
+        admissions,
+        capacity,
+        shadow,
+    ));
+
+    // deployments run forever
+    for task in tasks {
+        task.await.context("deployment task failed")?;
+    }
+
+    Ok(())
+}
+
+#[tokio::main]
+pub async fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    let deployment_configs = parse_deployments_file(&cli)
         .await
-        .context("Failed to parse address blacklist")?;
+        .context("Failed to parse deployments")?;
+
+    let regions: Vec<String> = cli.regions.split(',').map(|r| (r.into())).collect();
+    println!("Supported regions: {regions:?}");
 
     // leak memory to get static references
     // will be cleaned up once program exits
     // alternative to OnceCell equivalents
-    let compute_rates: &'static [market::RegionalRates] =
-        Box::leak(compute_rates.into_boxed_slice());
-    let bandwidth_rates: &'static [market::GBRateCard] =
-        Box::leak(bandwidth_rates.into_boxed_slice());
-    let address_whitelist: &'static [String] = Box::leak(address_whitelist_vec.into_boxed_slice());
-    let address_blacklist: &'static [String] = Box::leak(address_blacklist_vec.into_boxed_slice());
     let regions: &'static [String] = Box::leak(regions.into_boxed_slice());
-    let chain = get_chain_id_from_rpc_url(cli.rpc.clone())
-        .await
-        .context("Failed to fetch chain_id")?;
-
-    let job_id = market::JobId {
-        id: H256::zero().encode_hex(),
-        operator: cli.provider.clone(),
-        contract: cli.contract.clone(),
-        chain,
-    };
-
-    tokio::spawn(server::serve(
-        aws.clone(),
-        regions,
-        compute_rates,
-        bandwidth_rates,
-        SocketAddr::from(([0, 0, 0, 0], 8080)),
-        job_id.clone(),
-    ));
 
-    let ethers = market::EthersProvider {
-        contract: cli
-            .contract
-            .parse::<Address>()
-            .context("failed to parse contract address")?,
-        provider: cli
-            .provider
-            .parse::<Address>()
-            .context("failed to parse provider address")?,
+    if cli.state_dir.is_empty() {
+        println!(
+            "main: No --state-dir set, checkpointing is disabled and every restart replays all jobs from the start block"
+        );
+    }
+    let store = store::FileStore::new(cli.state_dir).context("failed to set up job store")?;
+    let settings = DeploymentSettings {
+        stall_timeout: cli.stall_timeout,
+        poll_interval: cli.poll_interval,
+        confirmations: cli.confirmations,
+        shadow: cli.shadow,
+        reconcile: reconcile::ReconcileMode::parse(&cli.reconcile)?,
+        reconcile_interval: cli.reconcile_interval,
+        reconcile_grace: cli.reconcile_grace,
+        eip_gc: reconcile::ReconcileMode::parse(&cli.eip_gc)?,
+        eip_gc_interval: cli.eip_gc_interval,
+        eip_gc_grace: cli.eip_gc_grace,
+        address_whitelist: cli.address_whitelist,
+        address_blacklist: cli.address_blacklist,
+        admission_policy: cli.admission_policy,
+        reload_interval: cli.reload_interval,
+        capacity: capacity::CapacityLimits {
+            max_vcpus: (cli.max_vcpus > 0).then_some(cli.max_vcpus),
+            max_instances_per_region: (cli.max_instances_per_region > 0)
+                .then_some(cli.max_instances_per_region),
+            max_instances_per_type: (cli.max_instances_per_type > 0)
+                .then_some(cli.max_instances_per_type),
+        },
     };
 
-    market::run(
-        aws,
-        ethers,
-        cli.rpc,
-        regions,
-        compute_rates,
-        bandwidth_rates,
-        address_whitelist,
-        address_blacklist,
-        job_id,
-    )
-    .await;
-
-    Ok(())
+    match cli.infra.as_str() {
+        "aws" => {
+            if cli.profile.is_empty() || cli.key_name.is_empty() {
+                return Err(anyhow!("aws infra needs --profile and --key-name"));
+            }
+
+            let mut aws = aws::Aws::new(
+                cli.profile,
+                regions,
+                cli.key_name,
+                cli.pcr_allowlist,
+                cli.pcr_denylist,
+                family::Families::load(&cli.families).context("failed to load families")?,
+                readiness::ReadinessConfig {
+                    deadline: Duration::from_secs(cli.boot_timeout),
+                    ..Default::default()
+                },
+            )
+            .await
+            .context("failed to set up aws client")?;
+            if cli.shadow {
+                aws.read_only_host_keys();
+            }
+
+            aws.generate_key_pair()
+                .await
+                .context("Failed to generate key pair")?;
+
+            for region in regions {
+                aws.key_setup(region.clone())
+                    .await
+                    .context("Failed to setup key pair in {region}")?;
+            }
+
+            let reloadables: Vec<Box<dyn reload::Reloadable>> = vec![Box::new(aws.pcr_policy())];
+            run_with_infra(
+                aws,
+                store,
+                deployment_configs,
+                regions,
+                reloadables,
+                settings,
+            )
+            .await
+        }
+        "simulated" => {
+            println!("main: Running on simulated infra, no instances will be launched");
+            let simulated = simulated::SimulatedInfra::new(simulated::SimulatedConfig {
+                boot_latency: Duration::from_secs(cli.sim_boot_latency),
+                launch_failure_rate: cli.sim_launch_failure_rate,
+                enclave_failure_rate: cli.sim_enclave_failure_rate,
+                ..Default::default()
+            });
+
+            run_with_infra(
+                simulated,
+                store,
+                deployment_configs,
+                regions,
+                Vec::new(),
+                settings,
+            )
+            .await
+        }
+        _ => Err(anyhow!("unsupported infra: {}", cli.infra)),
+    }
 }
@@ -1,28 +1,52 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
 use std::future::Future;
+use std::sync::{Arc, Mutex};
 
-use ethers::abi::{AbiDecode, AbiEncode};
+use ethers::abi::AbiEncode;
 use ethers::prelude::*;
 use ethers::types::serde_helpers::deserialize_stringified_numeric;
-use ethers::utils::keccak256;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
 
 use anyhow::{Context, Result};
-use tokio::time::sleep;
+use tokio::sync::{mpsc, watch};
+use tokio::time::{sleep, sleep_until};
 use tokio::time::{Duration, Instant};
+use tokio_stream::wrappers::{UnboundedReceiverStream, WatchStream};
 use tokio_stream::Stream;
 
 use ethers::types::Log;
 
+use crate::admission::{AdmissionDecision, AdmissionPolicy, AdmissionRequest, Admissions};
+use crate::capacity::{Capacity, Slot};
+use crate::host_keys::HostKeyMismatch;
+use crate::inputs::SharedInputs;
+use crate::metadata::{format_problems, JobMetadata, JobSpec};
+use crate::pcr::Pcrs;
+use crate::quorum::{quorum_heads, quorum_logs};
+use crate::store::{DispatcherCheckpoint, JobCheckpoint, JobStore};
+
 // IMPORTANT: do not import SystemTime, use the now_timestamp helper
 
 // Basic architecture:
 // One future listening to new jobs
 // Each job has its own future managing its lifetime
 
+// Bindings generated from the MarketV1 ABI
+// Both event decoding and subscription filters are derived from them so the two cannot disagree
+abigen!(MarketV1, "abi/MarketV1.json");
+
+// topic0 of every event tracked per job
+fn job_events() -> Vec<H256> {
+    MARKETV1_ABI
+        .events()
+        .map(|event| event.signature())
+        .collect()
+}
+
 // Identify jobs not only by the id, but also by the operator, contract and the chain
 // This is needed to cleanly support multiple operators/contracts/chains at the infra level
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize)]
 pub struct JobId {
     pub id: String,
     pub operator: String,
@@ -58,6 +82,12 @@ pub trait InfraProvider {
 
     fn get_job_ip(&self, job: &JobId, region: &str) -> impl Future<Output = Result<String>> + Send;
 
+    // measurements of the enclave image approved for the job
+    fn get_job_pcrs(&self, job: &JobId, region: &str) -> impl Future<Output = Result<Pcrs>> + Send;
+
+    // measurements approved before a restart, served by get_job_pcrs again afterwards
+    fn restore_job_pcrs(&mut self, job: &JobId, instance_id: &str, pcrs: Pcrs);
+
     fn check_instance_running(
         &mut self,
         instance_id: &str,
@@ -133,6 +163,14 @@ where
         (**self).get_job_ip(job, region).await
     }
 
+    async fn get_job_pcrs(&self, job: &JobId, region: &str) -> Result<Pcrs> {
+        (**self).get_job_pcrs(job, region).await
+    }
+
+    fn restore_job_pcrs(&mut self, job: &JobId, instance_id: &str, pcrs: Pcrs) {
+        (**self).restore_job_pcrs(job, instance_id, pcrs)
+    }
+
     async fn check_instance_running(&mut self, instance_id: &str, region: &str) -> Result<bool> {
         (**self).check_instance_running(instance_id, region).await
     }
@@ -181,16 +219,24 @@ where
 }
 
 pub trait LogsProvider {
-    fn new_jobs<'a>(
-        &'a self,
-        client: &'a Provider<Ws>,
-    ) -> impl Future<Output = Result<impl Stream<Item = (H256, bool)> + 'a>>;
+    // connection to the rpc endpoint, streams end when it breaks
+    type Client: Send + Sync;
+
+    fn connect<'a>(&'a self, url: &'a str) -> impl Future<Output = Result<Self::Client>> + Send;
 
-    fn job_logs<'a>(
+    // logs of every job event of the market, jobs of other providers are never opened
+    // caught_up is set once every log up to the head at subscription time was yielded
+    fn market_logs<'a>(
         &'a self,
-        client: &'a Provider<Ws>,
-        job: H256,
+        client: &'a Self::Client,
+        from_block: u64,
+        caught_up: watch::Sender<bool>,
     ) -> impl Future<Output = Result<impl Stream<Item = Log> + Send + 'a>> + Send;
+
+    fn new_heads<'a>(
+        &'a self,
+        client: &'a Self::Client,
+    ) -> impl Future<Output = Result<impl Stream<Item = u64> + Send + 'a>> + Send;
 }
 
 #[derive(Clone)]
@@ -200,19 +246,28 @@ pub struct EthersProvider {
 }
 
 impl LogsProvider for EthersProvider {
-    async fn new_jobs<'a>(
+    type Client = Provider<Ws>;
+
+    async fn connect<'a>(&'a self, url: &'a str) -> Result<Provider<Ws>> {
+        Provider::<Ws>::connect(url)
+            .await
+            .context("failed to connect to rpc endpoint")
+    }
+
+    async fn market_logs<'a>(
         &'a self,
         client: &'a Provider<Ws>,
-    ) -> Result<impl Stream<Item = (H256, bool)> + 'a> {
-        new_jobs(client, self.contract, self.provider).await
+        from_block: u64,
+        caught_up: watch::Sender<bool>,
+    ) -> Result<impl Stream<Item = Log> + Send + 'a> {
+        market_logs(client, self.contract, self.provider, from_block, caught_up).await
     }
 
-    async fn job_logs<'a>(
+    async fn new_heads<'a>(
         &'a self,
         client: &'a Provider<Ws>,
-        job: H256,
-    ) -> Result<impl Stream<Item = Log> + Send + 'a> {
-        job_logs(client, self.contract, job).await
+    ) -> Result<impl Stream<Item = u64> + Send + 'a> {
+        new_heads(client).await
     }
 }
 
@@ -240,17 +295,41 @@ pub struct GBRateCard {
     pub rate: U256,
 }
 
+// RPC endpoints used for event ingestion
+#[derive(Clone, Debug)]
+pub struct RpcConfig {
+    pub urls: Vec<String>,
+    pub mode: RpcMode,
+    // endpoints are considered stalled if there are no new heads for this long
+    pub stall_timeout: Duration,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RpcMode {
+    // one endpoint at a time, rotating to the next one on errors or stalls
+    Failover,
+    // all endpoints at once, logs and heads are only applied once threshold endpoints agree
+    // guards against a single endpoint serving bad logs
+    Quorum { threshold: usize },
+}
+
 pub async fn run(
     infra_provider: impl InfraProvider + Send + Sync + Clone + 'static,
     logs_provider: impl LogsProvider + Send + Sync + Clone + 'static,
-    url: String,
+    store: impl JobStore + Send + Sync + Clone + 'static,
+    rpc: RpcConfig,
+    // jobs opened before it are never discovered
+    start_block: u64,
     regions: &'static [String],
-    rates: &'static [RegionalRates],
-    gb_rates: &'static [GBRateCard],
-    address_whitelist: &'static [String],
-    address_blacklist: &'static [String],
+    // rate cards and admission policy, swapped on reloads
+    inputs: SharedInputs,
     // without job_id.id set
     job_id: JobId,
+    confirmations: u64,
+    outcomes: JobOutcomes,
+    live_jobs: LiveJobs,
+    admissions: Admissions,
+    capacity: Capacity,
 ) {
     let mut backoff = 1;
 
@@ -259,13 +338,57 @@ pub async fn run(
     // trying to implicitly resume connections or event streams can cause issues
     // since subscriptions are stateful
 
-    let mut job_count = 0;
+    // a single subscription is shared by all jobs, job managers receive their logs from the dispatcher
+    // the dispatcher outlives connections so job managers are unaffected by reconnections
+    let mut dispatcher = LogDispatcher {
+        start_block,
+        ..Default::default()
+    };
+    // retried until it works, jobs opened before the watermark would never be managed otherwise
+    let checkpoint = loop {
+        match store.load_dispatcher(&job_id).await {
+            Ok(checkpoint) => break checkpoint,
+            Err(err) => {
+                println!("main: Failed to load dispatcher checkpoint: {err:?}");
+                sleep(Duration::from_secs(backoff)).await;
+                backoff *= 2;
+                if backoff > 128 {
+                    backoff = 128;
+                }
+            }
+        }
+    };
+    backoff = 1;
+    if let Some(checkpoint) = checkpoint {
+        println!(
+            "main: Resuming from dispatcher checkpoint: {:?}, {} jobs",
+            checkpoint.watermark,
+            checkpoint.jobs.len()
+        );
+        dispatcher.restore(checkpoint, confirmations + REORG_DEPTH);
+    }
+    let (heads, _) = watch::channel(0);
+    // endpoint used next in failover mode
+    let mut current = 0;
     loop {
-        println!("main: Connecting to RPC endpoint...");
-        let res = Provider::<Ws>::connect(url.clone()).await;
-        if let Err(err) = res {
+        // failover is a quorum of one over the current endpoint
+        let (urls, threshold) = match rpc.mode {
+            RpcMode::Failover => (&rpc.urls[current..=current], 1),
+            RpcMode::Quorum { threshold } => (&rpc.urls[..], threshold),
+        };
+        // every reconnection moves on to the next endpoint
+        current = (current + 1) % rpc.urls.len();
+
+        println!("main: Connecting to RPC endpoints: {urls:?}");
+        let mut clients = Vec::new();
+        for url in urls {
+            match logs_provider.connect(url).await {
+                Ok(client) => clients.push(client),
+                Err(err) => println!("main: Connection error for {url}: {err:?}"),
+            }
+        }
+        if clients.len() < threshold {
             // exponential backoff on connection errors
-            println!("main: Connection error: {err:?}");
             sleep(Duration::from_secs(backoff)).await;
             backoff *= 2;
             if backoff > 128 {
@@ -274,206 +397,501 @@ pub async fn run(
             continue;
         }
         backoff = 1;
-        println!("main: Connected to RPC endpoint");
-
-        let client = res.unwrap();
-        let res = logs_provider.new_jobs(&client).await;
-        if let Err(err) = res {
-            println!("main: Subscribe error: {err:?}");
+        println!("main: Connected to RPC endpoints");
+
+        let mut log_streams = Vec::new();
+        let mut head_streams = Vec::new();
+        let mut caught_up = Vec::new();
+        for client in &clients {
+            let (caught_up_tx, caught_up_rx) = watch::channel(false);
+            let logs = logs_provider
+                .market_logs(client, dispatcher.from_block(), caught_up_tx)
+                .await;
+            let new_heads = logs_provider.new_heads(client).await;
+            match (logs, new_heads) {
+                (Ok(logs), Ok(new_heads)) => {
+                    log_streams.push(logs);
+                    head_streams.push(new_heads);
+                    caught_up.push(caught_up_rx);
+                }
+                (Err(err), _) | (_, Err(err)) => println!("main: Subscribe error: {err:?}"),
+            }
+        }
+        if log_streams.len() < threshold {
             sleep(Duration::from_secs(1)).await;
             continue;
         }
 
-        let job_stream = std::pin::pin!(res.unwrap());
-        job_count += run_once(
-            // we need to keep track of jobs for whom tasks have already been spawned
-            // and not spawn duplicate tasks
-            job_stream.skip(job_count),
+        let log_stream = std::pin::pin!(quorum_logs(log_streams, threshold));
+        let head_stream = std::pin::pin!(quorum_heads(head_streams, threshold));
+        run_once(
+            log_stream,
+            head_stream,
+            caught_up,
+            &mut dispatcher,
+            &heads,
+            rpc.stall_timeout,
             infra_provider.clone(),
-            logs_provider.clone(),
-            url.clone(),
+            store.clone(),
             regions,
-            rates,
-            gb_rates,
-            address_whitelist,
-            address_blacklist,
+            inputs.clone(),
             job_id.clone(),
+            confirmations,
+            outcomes.clone(),
+            live_jobs.clone(),
+            admissions.clone(),
+            capacity.clone(),
         )
         .await;
     }
 }
 
 async fn run_once(
-    mut job_stream: impl Stream<Item = (H256, bool)> + Unpin,
+    mut log_stream: impl Stream<Item = Log> + Unpin,
+    mut head_stream: impl Stream<Item = u64> + Unpin,
+    // one per log stream, set once its backfill was consumed
+    caught_up: Vec<watch::Receiver<bool>>,
+    dispatcher: &mut LogDispatcher,
+    heads: &watch::Sender<u64>,
+    stall_timeout: Duration,
     infra_provider: impl InfraProvider + Send + Sync + Clone + 'static,
-    logs_provider: impl LogsProvider + Send + Sync + Clone + 'static,
-    url: String,
+    store: impl JobStore + Send + Sync + Clone + 'static,
     regions: &'static [String],
-    rates: &'static [RegionalRates],
-    gb_rates: &'static [GBRateCard],
-    address_whitelist: &'static [String],
-    address_blacklist: &'static [String],
+    // rate cards and admission policy, swapped on reloads
+    inputs: SharedInputs,
     // without job_id.id set
     job_id: JobId,
-) -> usize {
-    let mut job_count = 0;
-    while let Some((job, removed)) = job_stream.next().await {
-        println!("main: New job: {job}, {removed}");
-
+    confirmations: u64,
+    outcomes: JobOutcomes,
+    live_jobs: LiveJobs,
+    admissions: Admissions,
+    capacity: Capacity,
+) {
+    let spawn = |job: H256, job_logs: mpsc::UnboundedReceiver<Log>| {
         // prepare with correct job id
         let mut job_id = job_id.clone();
         job_id.id = job.encode_hex();
 
         tokio::spawn(job_manager(
             infra_provider.clone(),
-            logs_provider.clone(),
-            url.clone(),
+            store.clone(),
             job_id,
+            UnboundedReceiverStream::new(job_logs),
+            WatchStream::new(heads.subscribe()),
             regions,
             3,
-            rates,
-            gb_rates,
-            address_whitelist,
-            address_blacklist,
+            confirmations,
+            inputs.clone(),
+            outcomes.clone(),
+            live_jobs.clone(),
+            admissions.clone(),
+            capacity.clone(),
         ));
-        job_count += 1;
+    };
+
+    // jobs known before the restart are managed again, ended ones exit once they load their checkpoint
+    for (job, job_logs) in std::mem::take(&mut dispatcher.restored) {
+        println!("main: Restored job: {job}");
+        spawn(job, job_logs);
+    }
+
+    let mut stall_deadline = Instant::now() + stall_timeout;
+    loop {
+        let log = tokio::select! {
+            log = log_stream.next() => log,
+            head = head_stream.next() => {
+                let Some(head) = head else {
+                    break;
+                };
+                heads.send_replace(head);
+                stall_deadline = Instant::now() + stall_timeout;
+                // the watermark is saved once per head instead of once per log to bound writes
+                if dispatcher.watermark > dispatcher.saved {
+                    dispatcher.save(&store, &job_id).await;
+                }
+                // every job opened up to the head has a job manager, missing jobs are orphaned from now on
+                if !live_jobs.is_synced(&job_id) && caught_up.iter().all(|rx| *rx.borrow()) {
+                    println!("main: Job discovery caught up with head {head}");
+                    live_jobs.set_synced(&job_id);
+                }
+                continue;
+            }
+            () = sleep_until(stall_deadline) => {
+                println!("main: No new heads for {}s, reconnecting", stall_timeout.as_secs());
+                break;
+            }
+        };
+
+        let Some(log) = log else {
+            break;
+        };
+        let Some((job, job_logs)) = dispatcher.dispatch(log) else {
+            continue;
+        };
+        println!("main: New job: {job}");
+
+        // saved before the job manager runs so the job is respawned if the process stops
+        dispatcher.save(&store, &job_id).await;
+        spawn(job, job_logs);
+    }
+
+    println!("main: Market stream ended");
+}
+
+// Routes logs from the shared market subscription to their job managers by job id (topic1)
+// Keeps the number of rpc connections independent of the number of jobs
+// Relying on the resubscribed stream returning the same events in the same order is not safe,
+// providers can page logs differently and reorgs can add or remove events
+#[derive(Default)]
+struct LogDispatcher {
+    // block the first connection backfills from
+    start_block: u64,
+    // (block number, log index) of the last dispatched log
+    watermark: Option<(u64, u64)>,
+    // senders are kept after the job manager exits so that the job is never spawned again
+    jobs: HashMap<H256, mpsc::UnboundedSender<Log>>,
+    // log streams of jobs restored from the checkpoint whose managers are yet to be spawned
+    restored: Vec<(H256, mpsc::UnboundedReceiver<Log>)>,
+    // watermark of the last saved checkpoint
+    saved: Option<(u64, u64)>,
+}
+
+impl LogDispatcher {
+    // discovery resumes replay blocks before the saved watermark since logs waiting for
+    // confirmations or on their way to job managers when the process stopped were never applied
+    // job managers skip the replayed logs they did apply
+    fn restore(&mut self, checkpoint: DispatcherCheckpoint, replay: u64) {
+        if let Some((block, _)) = checkpoint.watermark {
+            self.start_block = self.start_block.max(block.saturating_sub(replay));
+        }
+        self.saved = checkpoint.watermark;
+
+        for job in checkpoint.jobs {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            self.jobs.insert(job, sender);
+            self.restored.push((job, receiver));
+        }
+    }
+
+    fn checkpoint(&self) -> DispatcherCheckpoint {
+        let mut jobs: Vec<H256> = self.jobs.keys().copied().collect();
+        jobs.sort_unstable();
+
+        DispatcherCheckpoint {
+            // replayed logs are behind the restored watermark, it is kept until they are passed
+            watermark: self.watermark.max(self.saved),
+            jobs,
+        }
+    }
+
+    async fn save(&mut self, store: &impl JobStore, deployment: &JobId) {
+        let checkpoint = self.checkpoint();
+        if let Err(err) = store.save_dispatcher(deployment, &checkpoint).await {
+            // not fatal, discovery resumes from an older watermark on restart
+            println!("main: Failed to save dispatcher checkpoint: {err:?}");
+            return;
+        }
+        self.saved = checkpoint.watermark;
+    }
+
+    // block to resume from on reconnection
+    // the watermark block is refetched since it might not have been fully processed
+    fn from_block(&self) -> u64 {
+        self.watermark
+            .map(|(block, _)| block)
+            .unwrap_or(self.start_block)
+    }
+
+    // forwards the log to the manager of its job
+    // returns the log stream of the job if the log opens a job that has not been seen before
+    fn dispatch(&mut self, log: Log) -> Option<(H256, mpsc::UnboundedReceiver<Log>)> {
+        let job = *log.topics.get(1)?;
+
+        if log.removed.unwrap_or(false) {
+            // removed logs are at or before the watermark, job managers rebuild their state on them
+            println!("main: Removed log for job: {job}");
+            // the log can be included again by the new canonical chain at the same or an earlier
+            // position, everything after it is removed by the reorg as well
+            if let Some(position) = log_position(&log) {
+                if self
+                    .watermark
+                    .is_some_and(|watermark| position <= watermark)
+                {
+                    self.watermark = before(position);
+                }
+            }
+            if let Some(sender) = self.jobs.get(&job) {
+                let _ = sender.send(log);
+            }
+            return None;
+        }
+
+        if let Some(position) = log_position(&log) {
+            if self
+                .watermark
+                .is_some_and(|watermark| position <= watermark)
+            {
+                return None;
+            }
+            self.watermark = Some(position);
+        }
+
+        if let Some(sender) = self.jobs.get(&job) {
+            // send only fails if the job manager has already exited
+            let _ = sender.send(log);
+            return None;
+        }
+
+        if log.topics[0] != JobOpenedFilter::signature() {
+            // job of another provider
+            return None;
+        }
+
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let _ = sender.send(log);
+        self.jobs.insert(job, sender);
+
+        Some((job, receiver))
     }
+}
 
-    println!("main: Job stream ended");
+// max number of blocks fetched in a single eth_getLogs call while backfilling
+pub const LOGS_PAGE_SIZE: u64 = 2000;
 
-    job_count
+// every job event of the market contract
+pub fn market_filter(contract: Address) -> Filter {
+    Filter::new().address(contract).topic0(job_events())
 }
 
-async fn new_jobs(
+// provider is only indexed in JobOpened, so only jobs of other providers are filtered out
+// logs of those jobs are dropped by the dispatcher since the jobs are never opened
+pub fn is_provider_log(log: &Log, provider: Address) -> bool {
+    let opened = log.topics.first() == Some(&JobOpenedFilter::signature());
+    !opened || log.topics.get(3) == Some(&H256::from(provider))
+}
+
+async fn market_logs(
     client: &Provider<Ws>,
     address: Address,
     provider: Address,
-) -> Result<impl Stream<Item = (H256, bool)> + '_> {
-    let event_filter = Filter::new()
-        .address(address)
-        .select(0..)
-        .topic0(vec![keccak256(
-            "JobOpened(bytes32,string,address,address,uint256,uint256,uint256)",
-        )])
-        .topic3(provider);
+    from_block: u64,
+    caught_up: watch::Sender<bool>,
+) -> Result<impl Stream<Item = Log> + Send + '_> {
+    let event_filter = market_filter(address);
 
-    // register subscription
+    // register subscription before backfilling so no logs are missed in between
+    // overlapping logs are filtered out by the watermark during dispatch
     let stream = client
         .subscribe_logs(&event_filter)
         .await
-        .context("failed to subscribe to new jobs")?;
+        .context("failed to subscribe to market logs")?;
 
-    Ok(stream.map(|item| (item.topics[1], item.removed.unwrap_or(false))))
+    let head = client
+        .get_block_number()
+        .await
+        .context("failed to fetch head block")?
+        .as_u64();
+
+    // pages are only fetched as the stream is consumed, long backfills are never held in memory
+    let pages = (from_block..=head)
+        .step_by(LOGS_PAGE_SIZE as usize)
+        .map(move |start| (start, head.min(start + LOGS_PAGE_SIZE - 1)));
+    let backfill = tokio_stream::iter(pages)
+        .then(move |(start, end)| {
+            let page_filter = event_filter.clone().from_block(start).to_block(end);
+            async move { client.get_logs(&page_filter).await }
+        })
+        .flat_map(|page| {
+            let logs = match page {
+                Ok(logs) => logs.into_iter().map(Some).collect(),
+                Err(err) => {
+                    println!("main: Failed to backfill market logs: {err:?}");
+                    // ends the stream, skipping the page would lose its logs
+                    vec![None]
+                }
+            };
+            tokio_stream::iter(logs)
+        });
+
+    // polled once the last backfilled log was taken, failed backfills end the stream before it
+    let backfilled = tokio_stream::once(()).filter_map(move |()| {
+        caught_up.send_replace(true);
+        std::future::ready(None::<Option<Log>>)
+    });
+
+    Ok(backfill
+        .chain(backfilled)
+        .chain(stream.map(Some))
+        .take_while(|log| std::future::ready(log.is_some()))
+        .filter_map(move |log| {
+            std::future::ready(log.filter(|log| is_provider_log(log, provider)))
+        }))
 }
 
 // manage the complete lifecycle of a job
 async fn job_manager(
     infra_provider: impl InfraProvider + Send + Sync + Clone,
-    logs_provider: impl LogsProvider + Send + Sync,
-    url: String,
+    store: impl JobStore + Send + Sync,
     job_id: JobId,
+    job_stream: impl Stream<Item = Log> + Unpin + Send,
+    head_stream: impl Stream<Item = u64> + Unpin + Send,
     allowed_regions: &[String],
     aws_delay_duration: u64,
-    rates: &[RegionalRates],
-    gb_rates: &[GBRateCard],
-    address_whitelist: &[String],
-    address_blacklist: &[String],
+    confirmations: u64,
+    inputs: SharedInputs,
+    outcomes: JobOutcomes,
+    live_jobs: LiveJobs,
+    admissions: Admissions,
+    capacity: Capacity,
 ) {
-    let mut backoff = 1;
-    let job = job_id.id.clone();
-
-    // connection level loop
-    // start from scratch in case of connection errors
-    // trying to implicitly resume connections or event streams can cause issues
-    // since subscriptions are stateful
-    loop {
-        println!("job {job}: Connecting to RPC endpoint...");
-        let res = Provider::<Ws>::connect(url.clone()).await;
-        if let Err(err) = res {
-            // exponential backoff on connection errors
-            println!("job {job}: Connection error: {err:?}");
-            sleep(Duration::from_secs(backoff)).await;
-            backoff *= 2;
-            if backoff > 128 {
-                backoff = 128;
-            }
-            continue;
-        }
-        backoff = 1;
-        println!("job {job}: Connected to RPC endpoint");
+    live_jobs.insert(&job_id);
+
+    // the streams are shared across connections and logs taken from them are never delivered
+    // again, so the job manager is not restarted on errors, it would lose the state of the job
+    let outcome = job_manager_once(
+        job_stream,
+        head_stream,
+        infra_provider,
+        &store,
+        job_id.clone(),
+        allowed_regions,
+        aws_delay_duration,
+        confirmations,
+        &inputs,
+        &admissions,
+        &capacity,
+    )
+    .await;
+
+    outcomes.record(&job_id, outcome);
+    live_jobs.remove(&job_id);
+    admissions.release(&job_id);
+    capacity.release(&job_id);
+}
 
-        let client = res.unwrap();
-        let res = logs_provider
-            // TODO: Bad unwrap?
-            .job_logs(&client, job.parse().unwrap())
-            .await;
-        if let Err(err) = res {
-            println!("job {job}: Subscribe error: {err:?}");
-            sleep(Duration::from_secs(1)).await;
-            continue;
-        }
+// Outcome of a job manager, or the reason processing a log failed
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "outcome", content = "reason", rename_all = "snake_case")]
+pub enum JobOutcome {
+    // job ended and its instance is terminated
+    Terminated,
+    // transient errors like the log streams ending, the job resumes from its checkpoint on restart
+    Recoverable(String),
+    // job can never be served as requested, no point retrying
+    Unrecoverable(String),
+    // job was rejected by the operator policy, its instance gets terminated
+    PolicyRejected(String),
+}
 
-        let job_stream = std::pin::pin!(res.unwrap());
-        let res = job_manager_once(
-            job_stream,
-            infra_provider.clone(),
-            job_id.clone(),
-            allowed_regions,
-            aws_delay_duration,
-            rates,
-            gb_rates,
-            address_whitelist,
-            address_blacklist,
+impl JobOutcome {
+    // whether event processing has to stop
+    fn is_fatal(&self) -> bool {
+        matches!(
+            self,
+            JobOutcome::Recoverable(_) | JobOutcome::Unrecoverable(_)
         )
-        .await;
+    }
+}
 
-        if res == -2 || res == 0 {
-            // full exit
-            break;
+impl fmt::Display for JobOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobOutcome::Terminated => write!(f, "terminated"),
+            JobOutcome::Recoverable(reason) => write!(f, "recoverable error: {reason}"),
+            JobOutcome::Unrecoverable(reason) => write!(f, "unrecoverable error: {reason}"),
+            JobOutcome::PolicyRejected(reason) => write!(f, "rejected by policy: {reason}"),
         }
     }
 }
 
-fn whitelist_blacklist_check(
-    log: Log,
-    address_whitelist: &[String],
-    address_blacklist: &[String],
-) -> bool {
-    // check whitelist
-    if !address_whitelist.is_empty() {
-        println!("Checking address whitelist...");
-        if address_whitelist
-            .iter()
-            .any(|s| s == &log.topics[2].encode_hex())
-        {
-            println!("ADDRESS ALLOWED!");
-        } else {
-            println!("ADDRESS NOT ALLOWED!");
-            return false;
+// Number of job managers that exited with each outcome
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct OutcomeCounts {
+    pub terminated: u64,
+    pub recoverable: u64,
+    pub unrecoverable: u64,
+    pub policy_rejected: u64,
+}
+
+#[derive(Default)]
+struct OutcomeRecords {
+    latest: HashMap<JobId, JobOutcome>,
+    counts: OutcomeCounts,
+}
+
+// Latest outcome of every job manager and counts of all outcomes, shared with the http server
+#[derive(Clone, Default)]
+pub struct JobOutcomes(Arc<Mutex<OutcomeRecords>>);
+
+impl JobOutcomes {
+    pub fn record(&self, job: &JobId, outcome: JobOutcome) {
+        let mut records = self.0.lock().unwrap();
+        let counts = &mut records.counts;
+        match outcome {
+            JobOutcome::Terminated => counts.terminated += 1,
+            JobOutcome::Recoverable(_) => counts.recoverable += 1,
+            JobOutcome::Unrecoverable(_) => counts.unrecoverable += 1,
+            JobOutcome::PolicyRejected(_) => counts.policy_rejected += 1,
         }
+        println!("outcome metrics: {:?}", records.counts);
+        records.latest.insert(job.clone(), outcome);
     }
 
-    // check blacklist
-    if !address_blacklist.is_empty() {
-        println!("Checking address blacklist...");
-        if address_blacklist
-            .iter()
-            .any(|s| s == &log.topics[2].encode_hex())
-        {
-            println!("ADDRESS NOT ALLOWED!");
-            return false;
-        } else {
-            println!("ADDRESS ALLOWED!");
-        }
+    pub fn get(&self, job: &JobId) -> Option<JobOutcome> {
+        self.0.lock().unwrap().latest.get(job).cloned()
+    }
+
+    pub fn counts(&self) -> OutcomeCounts {
+        self.0.lock().unwrap().counts
+    }
+}
+
+// Jobs whose job manager is still running, infra resources of any other job are orphaned
+// only once job discovery of their deployment has caught up with the chain head
+#[derive(Clone, Default)]
+pub struct LiveJobs {
+    jobs: Arc<Mutex<HashSet<JobId>>>,
+    // deployment job ids as given to run, job managers of every job opened up to the head were spawned
+    synced: Arc<Mutex<HashSet<JobId>>>,
+}
+
+impl LiveJobs {
+    pub fn insert(&self, job: &JobId) {
+        self.jobs.lock().unwrap().insert(job.clone());
+    }
+
+    pub fn remove(&self, job: &JobId) {
+        self.jobs.lock().unwrap().remove(job);
+    }
+
+    pub fn contains(&self, job: &JobId) -> bool {
+        self.jobs.lock().unwrap().contains(job)
+    }
+
+    pub fn len(&self) -> usize {
+        self.jobs.lock().unwrap().len()
+    }
+
+    // never reset, jobs of later blocks are spawned as soon as their logs arrive
+    pub fn set_synced(&self, deployment: &JobId) {
+        self.synced.lock().unwrap().insert(deployment.clone());
+    }
+
+    pub fn is_synced(&self, deployment: &JobId) -> bool {
+        self.synced.lock().unwrap().contains(deployment)
     }
 
-    true
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 }
 
 struct JobState<'a> {
     job_id: JobId,
     launch_delay: u64,
     allowed_regions: &'a [String],
+    admissions: &'a Admissions,
+    capacity: &'a Capacity,
 
     balance: U256,
     last_settled: Duration,
@@ -488,6 +906,8 @@ struct JobState<'a> {
     region: String,
     req_vcpus: i32,
     req_mem: i64,
+    // vcpus of the instance type from the rate card
+    instance_vcpus: u32,
 
     // whether instance should exist or not
     infra_state: bool,
@@ -497,16 +917,40 @@ struct JobState<'a> {
     infra_change_scheduled: bool,
     // whether to just update the eif
     eif_update: bool,
+    // seconds to wait before retrying a failed infra change, doubles on every failure
+    infra_backoff: u64,
+
+    // (block number, log index) of the last processed log
+    watermark: Option<(u64, u64)>,
+
+    // reason the job was rejected by policy, if any
+    rejection: Option<String>,
+    // reason the job can never be served, nothing is managed after it is set
+    failure: Option<String>,
+    // decision of the admission policy once the job is opened
+    admission: Option<AdmissionDecision>,
+    // measurements approved for the enclave running on the instance
+    pcrs: Option<Pcrs>,
+    // position in the launch queue while waiting for capacity
+    queue_position: Option<usize>,
 }
 
 impl<'a> JobState<'a> {
-    fn new(job_id: JobId, launch_delay: u64, allowed_regions: &[String]) -> JobState {
+    fn new(
+        job_id: JobId,
+        launch_delay: u64,
+        allowed_regions: &'a [String],
+        admissions: &'a Admissions,
+        capacity: &'a Capacity,
+    ) -> JobState<'a> {
         // solvency metrics
         // default of 60s
         JobState {
             job_id,
             launch_delay,
             allowed_regions,
+            admissions,
+            capacity,
             balance: U256::from(360),
             last_settled: now_timestamp(),
             rate: U256::one(),
@@ -521,10 +965,121 @@ impl<'a> JobState<'a> {
             region: "ap-south-1".to_string(),
             req_vcpus: 2,
             req_mem: 4096,
+            instance_vcpus: 0,
             infra_state: false,
             infra_change_time: Instant::now(),
             infra_change_scheduled: false,
             eif_update: false,
+            infra_backoff: 2,
+            watermark: None,
+            rejection: None,
+            failure: None,
+            admission: None,
+            pcrs: None,
+            queue_position: None,
+        }
+    }
+
+    fn checkpoint(&self) -> JobCheckpoint {
+        JobCheckpoint {
+            watermark: self.watermark,
+            balance: self.balance,
+            last_settled: self.last_settled.as_secs(),
+            rate: self.rate,
+            original_rate: self.original_rate,
+            instance_id: self.instance_id.clone(),
+            family: self.family.clone(),
+            min_rate: self.min_rate,
+            bandwidth: self.bandwidth,
+            eif_url: self.eif_url.clone(),
+            instance_type: self.instance_type.clone(),
+            region: self.region.clone(),
+            req_vcpus: self.req_vcpus,
+            req_mem: self.req_mem,
+            instance_vcpus: self.instance_vcpus,
+            infra_state: self.infra_state,
+            infra_change_scheduled: self.infra_change_scheduled,
+            eif_update: self.eif_update,
+            rejection: self.rejection.clone(),
+            failure: self.failure.clone(),
+            admission: self.admission.clone(),
+            pcrs: self.pcrs.clone(),
+            applied: Vec::new(),
+            base: None,
+        }
+    }
+
+    fn restore(&mut self, checkpoint: JobCheckpoint) {
+        self.watermark = checkpoint.watermark;
+        self.balance = checkpoint.balance;
+        self.last_settled = Duration::from_secs(checkpoint.last_settled);
+        self.rate = checkpoint.rate;
+        self.original_rate = checkpoint.original_rate;
+        self.instance_id = checkpoint.instance_id;
+        self.family = checkpoint.family;
+        self.min_rate = checkpoint.min_rate;
+        self.bandwidth = checkpoint.bandwidth;
+        self.eif_url = checkpoint.eif_url;
+        self.instance_type = checkpoint.instance_type;
+        self.region = checkpoint.region;
+        self.req_vcpus = checkpoint.req_vcpus;
+        self.req_mem = checkpoint.req_mem;
+        self.instance_vcpus = checkpoint.instance_vcpus;
+        self.infra_state = checkpoint.infra_state;
+        // pending infra changes are picked up right away after a restart
+        self.infra_change_scheduled = checkpoint.infra_change_scheduled;
+        self.infra_change_time = Instant::now();
+        self.eif_update = checkpoint.eif_update;
+        self.rejection = checkpoint.rejection;
+        self.failure = checkpoint.failure;
+        self.admission = checkpoint.admission;
+        self.pcrs = checkpoint.pcrs;
+    }
+
+    // whether the job has ended with its instance terminated or failed, nothing is left to manage then
+    // jobs are only saved without a scheduled change before the first log or once terminated
+    fn ended(&self) -> bool {
+        self.failure.is_some()
+            || (self.watermark.is_some() && !self.infra_state && !self.infra_change_scheduled)
+    }
+
+    // outcome once the job has ended with its instance terminated or failed
+    fn final_outcome(&self) -> JobOutcome {
+        if let Some(reason) = &self.failure {
+            return JobOutcome::Unrecoverable(reason.clone());
+        }
+        match &self.rejection {
+            Some(reason) => JobOutcome::PolicyRejected(reason.clone()),
+            None => JobOutcome::Terminated,
+        }
+    }
+
+    // the dispatcher respawns the job after a restart without delivering its logs again
+    // failed jobs are marked ended so they exit instead of idling, others resume from the checkpoint
+    async fn save_outcome(
+        &mut self,
+        store: &impl JobStore,
+        base: Option<&JobCheckpoint>,
+        applied: &[Log],
+        outcome: &JobOutcome,
+    ) {
+        if let JobOutcome::Unrecoverable(reason) = outcome {
+            self.failure = Some(reason.clone());
+        }
+        self.save(store, base, applied).await;
+    }
+
+    // the base and the applied logs on top of it are saved along to rebuild from after a restart
+    async fn save(&self, store: &impl JobStore, base: Option<&JobCheckpoint>, applied: &[Log]) {
+        let job = &self.job_id.id;
+        let checkpoint = JobCheckpoint {
+            applied: applied.to_vec(),
+            base: base.cloned().map(Box::new),
+            ..self.checkpoint()
+        };
+        if let Err(err) = store.save(&self.job_id, &checkpoint).await {
+            // not fatal, job gets replayed from an older watermark on restart
+            println!("job {job}: Failed to save checkpoint: {err:?}");
         }
     }
 
@@ -579,6 +1134,7 @@ impl<'a> JobState<'a> {
                                         println!(
                                             "job {job}: enclave successfully ran on the instance"
                                         );
+                                        self.record_pcrs(&infra_provider).await;
                                     }
                                     Err(err) => {
                                         println!("job {job}: failed to run enclave, {err:?}");
@@ -586,6 +1142,23 @@ impl<'a> JobState<'a> {
                                 }
                             }
                         }
+                        Err(err) if err.downcast_ref::<HostKeyMismatch>().is_some() => {
+                            // the instance can not be trusted anymore, replace it
+                            // spin down forgets its host key pin and approved pcrs
+                            println!("job {job}: {err}, replacing the instance");
+                            let res = infra_provider
+                                .spin_down(&self.instance_id, &self.job_id, &self.region)
+                                .await;
+                            match res {
+                                Ok(_) => {
+                                    self.pcrs = None;
+                                    self.schedule_launch(0);
+                                }
+                                Err(err) => {
+                                    println!("job {job}: failed to terminate instance, {err:?}");
+                                }
+                            }
+                        }
                         Err(err) => {
                             println!("job {job}: failed to retrieve enclave state, {err:?}");
                         }
@@ -624,21 +1197,77 @@ impl<'a> JobState<'a> {
         println!("job {job}: Instance termination scheduled");
     }
 
+    // capacity taken by the instance of the job
+    fn slot(&self) -> Slot {
+        Slot {
+            region: self.region.clone(),
+            instance_type: self.instance_type.clone(),
+            // checkpoints without it predate capacity limits
+            vcpus: if self.instance_vcpus > 0 {
+                self.instance_vcpus.into()
+            } else {
+                self.req_vcpus.max(0) as u64
+            },
+        }
+    }
+
+    // returns whether the launch can go ahead, otherwise the job waits in the launch queue
+    fn acquire_capacity(&mut self) -> bool {
+        let job = &self.job_id.id;
+        let priority = self
+            .admission
+            .as_ref()
+            .map_or(0, |decision| decision.priority);
+        match self.capacity.acquire(&self.job_id, &self.slot(), priority) {
+            Ok(()) => {
+                self.queue_position = None;
+                true
+            }
+            Err(position) => {
+                if self.queue_position != Some(position) {
+                    println!(
+                        "job {job}: Waiting for capacity, position {position} in launch queue"
+                    );
+                }
+                self.queue_position = Some(position);
+                false
+            }
+        }
+    }
+
     async fn change_infra(&mut self, infra_provider: impl InfraProvider) -> bool {
+        // queued launches only poll the queue until they get capacity
+        if self.infra_state && self.queue_position.is_some() && !self.acquire_capacity() {
+            self.infra_change_time = Instant::now() + Duration::from_secs(5);
+            return false;
+        }
+
         let res = self.change_infra_impl(infra_provider).await;
         if res {
             // successful
             self.infra_change_scheduled = false;
+            self.infra_backoff = 2;
+            if !self.infra_state {
+                // instance is gone or was never launched, capacity goes to the next launch
+                self.capacity.release(&self.job_id);
+                self.queue_position = None;
+            }
+        } else if self.queue_position.is_some() {
+            self.infra_change_time = Instant::now() + Duration::from_secs(5);
         } else {
-            // failed, reschedule with small delay
-            self.infra_change_time = Instant::now() + Duration::from_secs(2);
+            // failed, reschedule with exponential backoff
+            self.infra_change_time = Instant::now() + Duration::from_secs(self.infra_backoff);
+            self.infra_backoff *= 2;
+            if self.infra_backoff > 128 {
+                self.infra_backoff = 128;
+            }
         }
 
         res
     }
 
     async fn change_infra_impl(&mut self, mut infra_provider: impl InfraProvider) -> bool {
-        let job = &self.job_id.id;
+        let job = self.job_id.id.clone();
 
         let res = infra_provider
             .get_job_instance(&self.job_id, &self.region)
@@ -658,6 +1287,9 @@ impl<'a> JobState<'a> {
                     // instance exists and is already running, we are done
                     println!("job {job}: found existing healthy instance: {instance}");
                     self.instance_id = instance;
+                    // counted even if it was launched before a restart and the limits are reached
+                    self.capacity.hold(&self.job_id, &self.slot());
+                    self.queue_position = None;
                     if self.eif_update {
                         // update eif
                         let res = infra_provider
@@ -674,6 +1306,7 @@ impl<'a> JobState<'a> {
                             return false;
                         }
                         self.eif_update = false;
+                        self.record_pcrs(&infra_provider).await;
                     }
                     return true;
                 }
@@ -694,6 +1327,9 @@ impl<'a> JobState<'a> {
             }
 
             // either no old instance or old instance was not enough, launch new one
+            if !self.acquire_capacity() {
+                return false;
+            }
             println!("job {job}: launching new instance");
             let res = infra_provider
                 .spin_up(
@@ -712,6 +1348,7 @@ impl<'a> JobState<'a> {
                 return false;
             }
             self.instance_id = res.unwrap();
+            self.pcrs = None;
             println!("job {job}: Instance launched: {}", self.instance_id);
 
             // try to run the enclave, ignore errors
@@ -732,11 +1369,13 @@ impl<'a> JobState<'a> {
                 // NOTE: return true here and let heartbeat check pick up from the errors
                 return true;
             }
+            self.record_pcrs(&infra_provider).await;
         } else {
             // terminate mode
             if !exist || state == "shutting-down" || state == "terminated" {
                 // instance does not really exist anyway, we are done
                 println!("job {job}: instance does not exist or is already terminated");
+                self.pcrs = None;
                 return true;
             }
 
@@ -749,160 +1388,258 @@ impl<'a> JobState<'a> {
                 println!("job {job}: ERROR failed to terminate instance, {err:?}");
                 return false;
             }
+            self.pcrs = None;
         }
 
         true
     }
 
-    // return 0 on success
-    // -1 on recoverable errors (can retry)
-    // -2 on unrecoverable errors (no point retrying)
-    // -3 when blacklist/whitelist check fails
-    fn process_log(
+    // measurements approved for the enclave just run, saved to be served after a restart
+    async fn record_pcrs(&mut self, infra_provider: &impl InfraProvider) {
+        self.pcrs = infra_provider
+            .get_job_pcrs(&self.job_id, &self.region)
+            .await
+            .ok();
+    }
+
+    // process logs in order, stopping at the first fatal error
+    // policy rejections only schedule a termination, so processing continues past them
+    fn process_logs(
         &mut self,
-        log: Option<Log>,
+        logs: Vec<Log>,
         rates: &[RegionalRates],
         gb_rates: &[GBRateCard],
-        address_whitelist: &[String],
-        address_blacklist: &[String],
-    ) -> i8 {
+        admission: &AdmissionPolicy,
+    ) -> Result<(), JobOutcome> {
         let job = self.job_id.id.clone();
-
-        if log.is_none() {
-            // error in the stream, can retry with new conn
-            return -1;
+        for log in logs {
+            let res = self.process_log(Some(log), rates, gb_rates, admission);
+            if let Err(outcome) = res {
+                println!("job {job}: {outcome}");
+                if outcome.is_fatal() {
+                    return Err(outcome);
+                }
+            }
         }
 
-        let log = log.unwrap();
-        println!("job {}: New log: {}, {}", job, log.topics[0], log.data);
+        Ok(())
+    }
 
-        // events
-        #[allow(non_snake_case)]
-        let JOB_OPENED =
-            keccak256("JobOpened(bytes32,string,address,address,uint256,uint256,uint256)").into();
-        #[allow(non_snake_case)]
-        let JOB_SETTLED = keccak256("JobSettled(bytes32,uint256,uint256)").into();
-        #[allow(non_snake_case)]
-        let JOB_CLOSED = keccak256("JobClosed(bytes32)").into();
-        #[allow(non_snake_case)]
-        let JOB_DEPOSITED = keccak256("JobDeposited(bytes32,address,uint256)").into();
-        #[allow(non_snake_case)]
-        let JOB_WITHDREW = keccak256("JobWithdrew(bytes32,address,uint256)").into();
-        #[allow(non_snake_case)]
-        let JOB_REVISE_RATE_INITIATED = keccak256("JobReviseRateInitiated(bytes32,uint256)").into();
-        #[allow(non_snake_case)]
-        let JOB_REVISE_RATE_CANCELLED = keccak256("JobReviseRateCancelled(bytes32)").into();
-        #[allow(non_snake_case)]
-        let JOB_REVISE_RATE_FINALIZED =
-            keccak256("JobReviseRateFinalized(bytes32, uint256)").into();
-        #[allow(non_snake_case)]
-        let METADATA_UPDATED = keccak256("JobMetadataUpdated(bytes32,string)").into();
+    // recompute the state from the canonical logs after a reorg
+    // infra is not derived from logs, so the instance is carried over from the previous state
+    fn rebuild(
+        &mut self,
+        base: Option<JobCheckpoint>,
+        logs: Vec<Log>,
+        rates: &[RegionalRates],
+        gb_rates: &[GBRateCard],
+        admission: &AdmissionPolicy,
+    ) -> Result<(), JobOutcome> {
+        let job = self.job_id.id.clone();
+        println!("job {job}: Applied log removed, rebuilding job state");
+
+        let mut rebuilt = JobState::new(
+            self.job_id.clone(),
+            self.launch_delay,
+            self.allowed_regions,
+            self.admissions,
+            self.capacity,
+        );
+        if let Some(base) = base {
+            rebuilt.restore(base);
+        }
+        // the decision made when the job was opened is reused, policy changes since do not apply
+        rebuilt.admission = rebuilt.admission.or(self.admission.clone());
+        let previous = std::mem::replace(self, rebuilt);
+        self.instance_id = previous.instance_id;
+        self.pcrs = previous.pcrs;
 
-        // NOTE: jobs should be killed fully if any individual event would kill it
-        // regardless of future events
-        // helps preserve consistency on restarts where events are procesed all at once
-        // e.g. do not spin up if job goes below min_rate and then goes above min_rate
+        self.process_logs(logs, rates, gb_rates, admission)?;
 
-        if log.topics[0] == JOB_OPENED {
-            // decode
-            if let Ok((metadata, _rate, _balance, timestamp)) =
-                <(String, U256, U256, U256)>::decode(&log.data)
-            {
-                println!(
-                    "job {job}: OPENED: metadata: {metadata}, rate: {_rate}, balance: {_balance}, timestamp: {timestamp}, {}",
-                    self.last_settled.as_secs()
-                );
+        if previous.infra_state && !self.infra_state {
+            // instance is no longer backed by the canonical chain
+            self.schedule_termination(0);
+        }
 
-                // update solvency metrics
-                self.balance = _balance;
-                self.rate = _rate;
-                self.original_rate = _rate;
-                self.last_settled = Duration::from_secs(timestamp.low_u64());
+        Ok(())
+    }
 
-                let v = serde_json::from_str(&metadata);
-                if let Err(err) = v {
-                    println!("job {job}: Error reading metadata: {err:?}");
-                    return -2;
-                }
+    // base to rebuild from with the logs applied on top
+    // used once the logs are final, so they no longer have to be kept for rebuilds
+    fn finalize(
+        &self,
+        base: Option<JobCheckpoint>,
+        logs: Vec<Log>,
+        rates: &[RegionalRates],
+        gb_rates: &[GBRateCard],
+        admission: &AdmissionPolicy,
+    ) -> JobCheckpoint {
+        let mut finalized = JobState::new(
+            self.job_id.clone(),
+            self.launch_delay,
+            self.allowed_regions,
+            self.admissions,
+            self.capacity,
+        );
+        if let Some(base) = base {
+            finalized.restore(base);
+        }
+        finalized.admission = finalized.admission.or(self.admission.clone());
+        // errors were already handled when the logs were applied
+        let _ = finalized.process_logs(logs, rates, gb_rates, admission);
 
-                let v: Value = v.unwrap();
+        finalized.checkpoint()
+    }
 
-                let r = v["instance"].as_str();
-                match r {
-                    Some(t) => {
-                        self.instance_type = t.to_string();
-                        println!("job {job}: Instance type set: {}", self.instance_type);
-                    }
-                    None => {
-                        println!("job {job}: Instance type not set");
-                        return -2;
-                    }
-                }
+    // rejected jobs get their instance terminated, the reason is reported once the job ends
+    fn reject(&mut self, reason: String) -> Result<(), JobOutcome> {
+        self.schedule_termination(0);
+        self.rejection = Some(reason.clone());
+        Err(JobOutcome::PolicyRejected(reason))
+    }
 
-                let r = v["region"].as_str();
-                match r {
-                    Some(t) => {
-                        self.region = t.to_string();
-                        println!("job {job}: Job region set: {}", self.region);
-                    }
-                    None => {
-                        println!("job {job}: Job region not set");
-                        return -2;
-                    }
-                }
+    // returns the first field that differs from the job but is not allowed to change
+    fn immutable_change(&self, spec: &JobSpec) -> Option<&'static str> {
+        if self.instance_type != spec.instance_type {
+            return Some("Instance type");
+        }
+        if self.region != spec.region {
+            return Some("Region");
+        }
+        if self.req_mem != spec.req_mem {
+            return Some("Memory");
+        }
+        if self.req_vcpus != spec.req_vcpus {
+            return Some("vcpu");
+        }
+        if spec
+            .family
+            .as_ref()
+            .is_some_and(|family| family != &self.family)
+        {
+            return Some("family");
+        }
 
-                if !self.allowed_regions.contains(&self.region) {
-                    println!(
-                        "job {job}: region: {} not suppported, exiting job",
-                        self.region
-                    );
-                    return -2;
-                }
+        None
+    }
 
-                let r = v["memory"].as_i64();
-                match r {
-                    Some(t) => {
-                        self.req_mem = t;
-                        println!("job {job}: Required memory: {}", self.req_mem);
-                    }
-                    None => {
-                        println!("job {job}: memory not set");
-                        return -2;
-                    }
-                }
+    fn process_log(
+        &mut self,
+        log: Option<Log>,
+        rates: &[RegionalRates],
+        gb_rates: &[GBRateCard],
+        admission: &AdmissionPolicy,
+    ) -> Result<(), JobOutcome> {
+        let job = self.job_id.id.clone();
 
-                let r = v["vcpu"].as_i64();
-                match r {
-                    Some(t) => {
-                        self.req_vcpus = t.try_into().unwrap_or(2);
-                        println!("job {job}: Required vcpu: {}", self.req_vcpus);
-                    }
-                    None => {
-                        println!("job {job}: vcpu not set");
-                        return -2;
+        if log.is_none() {
+            // streams are never recreated, the job resumes from its checkpoint on restart
+            return Err(JobOutcome::Recoverable("job stream ended".to_owned()));
+        }
+
+        let log = log.unwrap();
+        println!("job {}: New log: {}, {}", job, log.topics[0], log.data);
+
+        if let Some(position) = log_position(&log) {
+            if self
+                .watermark
+                .is_some_and(|watermark| position <= watermark)
+            {
+                println!("job {job}: Log already processed, skipping");
+                return Ok(());
+            }
+            self.watermark = Some(position);
+        }
+
+        let event = MarketV1Events::decode_log(&log.clone().into());
+        if let Err(err) = event {
+            println!(
+                "job {job}: Unknown event or decode failure: {}, {err:?}",
+                log.topics[0]
+            );
+            return Ok(());
+        }
+
+        // NOTE: jobs should be killed fully if any individual event would kill it
+        // regardless of future events
+        // helps preserve consistency on restarts where events are procesed all at once
+        // e.g. do not spin up if job goes below min_rate and then goes above min_rate
+
+        match event.unwrap() {
+            MarketV1Events::JobOpenedFilter(JobOpenedFilter {
+                metadata,
+                rate,
+                balance,
+                timestamp,
+                ..
+            }) => {
+                println!(
+                    "job {job}: OPENED: metadata: {metadata}, rate: {rate}, balance: {balance}, timestamp: {timestamp}, {}",
+                    self.last_settled.as_secs()
+                );
+
+                // update solvency metrics
+                self.balance = balance;
+                self.rate = rate;
+                self.original_rate = rate;
+                self.last_settled = Duration::from_secs(timestamp.low_u64());
+
+                let spec = match JobMetadata::parse(&metadata) {
+                    Ok(spec) => spec,
+                    Err(problems) => {
+                        return Err(JobOutcome::Unrecoverable(format!(
+                            "invalid metadata: {}",
+                            format_problems(&problems)
+                        )));
                     }
-                }
+                };
 
-                let url = v["url"].as_str();
-                if url.is_none() {
-                    println!("job {job}: eif url not found! Exiting job");
-                    return -2;
+                self.instance_type = spec.instance_type;
+                println!("job {job}: Instance type set: {}", self.instance_type);
+                self.region = spec.region;
+                println!("job {job}: Job region set: {}", self.region);
+
+                if !self.allowed_regions.contains(&self.region) {
+                    return Err(JobOutcome::Unrecoverable(format!(
+                        "region {} not supported",
+                        self.region
+                    )));
                 }
-                self.eif_url = url.unwrap().to_string();
 
-                let family = v["family"].as_str();
+                self.req_mem = spec.req_mem;
+                println!("job {job}: Required memory: {}", self.req_mem);
+                self.req_vcpus = spec.req_vcpus;
+                println!("job {job}: Required vcpu: {}", self.req_vcpus);
+                self.eif_url = spec.eif_url;
+
                 // we leave the default family unchanged if not found for backward compatibility
-                if family.is_some() {
-                    self.family = family.unwrap().to_owned();
+                if let Some(family) = spec.family {
+                    self.family = family;
                 }
 
-                // blacklist whitelist check
-                let allowed =
-                    whitelist_blacklist_check(log.clone(), address_whitelist, address_blacklist);
-                if !allowed {
-                    // blacklisted or not whitelisted address
-                    self.schedule_termination(0);
-                    return -3;
+                let request = AdmissionRequest {
+                    owner: log.topics[2].encode_hex(),
+                    region: self.region.clone(),
+                    instance_type: self.instance_type.clone(),
+                    family: self.family.clone(),
+                    vcpus: self.req_vcpus,
+                    memory: self.req_mem,
+                    eif_url: self.eif_url.clone(),
+                    rate: self.rate,
+                };
+                // replays after reorgs reuse the decision made when the log was first applied
+                let replayed = self.admission.is_some();
+                let decision = match &self.admission {
+                    Some(decision) => decision.clone(),
+                    None => {
+                        let decision = self.admissions.admit(&self.job_id, admission, &request);
+                        println!("job {job}: Admission of {}: {decision}", request.owner);
+                        decision
+                    }
+                };
+                self.admission = Some(decision.clone());
+                if !decision.allowed {
+                    return self.reject(decision.to_string());
                 }
 
                 let mut supported = false;
@@ -911,6 +1648,7 @@ impl<'a> JobState<'a> {
                         for card in &entry.rate_cards {
                             if card.instance == self.instance_type {
                                 self.min_rate = card.min_rate;
+                                self.instance_vcpus = card.cpu;
                                 supported = true;
                                 break;
                             }
@@ -920,11 +1658,23 @@ impl<'a> JobState<'a> {
                 }
 
                 if !supported {
-                    println!(
-                        "job {job}: instance type {}, not supported",
+                    return Err(JobOutcome::Unrecoverable(format!(
+                        "instance type {} not supported",
                         self.instance_type
-                    );
-                    return -2;
+                    )));
+                }
+
+                // limits may have changed since the log was first applied
+                let checked = if replayed {
+                    Ok(())
+                } else {
+                    self.capacity.check(&self.slot())
+                };
+                if let Err(limit) = checked {
+                    return Err(JobOutcome::Unrecoverable(format!(
+                        "instance type {} is beyond the capacity limit of {limit}",
+                        self.instance_type
+                    )));
                 }
 
                 println!(
@@ -949,12 +1699,10 @@ impl<'a> JobState<'a> {
                 } else {
                     self.schedule_termination(0);
                 }
-            } else {
-                println!("job {job}: OPENED: Decode failure: {}", log.data);
             }
-        } else if log.topics[0] == JOB_SETTLED {
-            // decode
-            if let Ok((amount, timestamp)) = <(U256, U256)>::decode(&log.data) {
+            MarketV1Events::JobSettledFilter(JobSettledFilter {
+                amount, timestamp, ..
+            }) => {
                 println!(
                     "job {job}: SETTLED: amount: {amount}, rate: {}, balance: {}, timestamp: {}",
                     self.rate,
@@ -970,14 +1718,11 @@ impl<'a> JobState<'a> {
                     self.balance,
                     self.last_settled.as_secs()
                 );
-            } else {
-                println!("job {job}: SETTLED: Decode failure: {}", log.data);
             }
-        } else if log.topics[0] == JOB_CLOSED {
-            self.schedule_termination(0);
-        } else if log.topics[0] == JOB_DEPOSITED {
-            // decode
-            if let Ok(amount) = U256::decode(&log.data) {
+            MarketV1Events::JobClosedFilter(_) => {
+                self.schedule_termination(0);
+            }
+            MarketV1Events::JobDepositedFilter(JobDepositedFilter { amount, .. }) => {
                 // update solvency metrics
                 println!(
                     "job {job}: DEPOSITED: amount: {amount}, rate: {}, balance: {}, timestamp: {}",
@@ -992,12 +1737,8 @@ impl<'a> JobState<'a> {
                     self.balance,
                     self.last_settled.as_secs()
                 );
-            } else {
-                println!("job {job}: DEPOSITED: Decode failure: {}", log.data);
             }
-        } else if log.topics[0] == JOB_WITHDREW {
-            // decode
-            if let Ok(amount) = U256::decode(&log.data) {
+            MarketV1Events::JobWithdrewFilter(JobWithdrewFilter { amount, .. }) => {
                 println!(
                     "job {job}: WITHDREW: amount: {amount}, rate: {}, balance: {}, timestamp: {}",
                     self.rate,
@@ -1012,11 +1753,11 @@ impl<'a> JobState<'a> {
                     self.balance,
                     self.last_settled.as_secs()
                 );
-            } else {
-                println!("job {job}: WITHDREW: Decode failure: {}", log.data);
             }
-        } else if log.topics[0] == JOB_REVISE_RATE_INITIATED {
-            if let Ok(new_rate) = U256::decode(&log.data) {
+            MarketV1Events::JobReviseRateInitiatedFilter(JobReviseRateInitiatedFilter {
+                new_rate,
+                ..
+            }) => {
                 println!(
                     "job {job}: JOB_REVISE_RATE_INTIATED: original_rate: {}, rate: {}, balance: {}, timestamp: {}", 
                     self.original_rate,
@@ -1037,28 +1778,26 @@ impl<'a> JobState<'a> {
                     self.balance,
                     self.last_settled.as_secs()
                 );
-            } else {
+            }
+            MarketV1Events::JobReviseRateCancelledFilter(_) => {
+                println!(
+                    "job {job}: JOB_REVISED_RATE_CANCELLED: rate: {}, balance: {}, timestamp: {}",
+                    self.rate,
+                    self.balance,
+                    self.last_settled.as_secs()
+                );
+                self.rate = self.original_rate;
                 println!(
-                    "job {job}: JOB_REVISE_RATE_INITIATED: Decode failure: {}",
-                    log.data
+                    "job {job}: JOB_REVISED_RATE_CANCELLED: rate: {}, balance: {}, timestamp: {}",
+                    self.rate,
+                    self.balance,
+                    self.last_settled.as_secs()
                 );
             }
-        } else if log.topics[0] == JOB_REVISE_RATE_CANCELLED {
-            println!(
-                "job {job}: JOB_REVISED_RATE_CANCELLED: rate: {}, balance: {}, timestamp: {}",
-                self.rate,
-                self.balance,
-                self.last_settled.as_secs()
-            );
-            self.rate = self.original_rate;
-            println!(
-                "job {job}: JOB_REVISED_RATE_CANCELLED: rate: {}, balance: {}, timestamp: {}",
-                self.rate,
-                self.balance,
-                self.last_settled.as_secs()
-            );
-        } else if log.topics[0] == JOB_REVISE_RATE_FINALIZED {
-            if let Ok(new_rate) = U256::decode(&log.data) {
+            MarketV1Events::JobReviseRateFinalizedFilter(JobReviseRateFinalizedFilter {
+                new_rate,
+                ..
+            }) => {
                 println!(
                     "job {job}: JOB_REVISE_RATE_FINALIZED: original_rate: {}, rate: {}, balance: {}, timestamp: {}", 
                     self.original_rate,
@@ -1067,8 +1806,9 @@ impl<'a> JobState<'a> {
                     self.last_settled.as_secs()
                 );
                 if self.rate != new_rate {
-                    println!("Job {job}: Something went wrong, finalized rate not same as initiated rate");
-                    return -2;
+                    return Err(JobOutcome::Unrecoverable(
+                        "finalized rate not same as initiated rate".to_owned(),
+                    ));
                 }
                 self.original_rate = new_rate;
                 println!(
@@ -1078,146 +1818,111 @@ impl<'a> JobState<'a> {
                     self.balance,
                     self.last_settled.as_secs()
                 );
-            } else {
-                println!(
-                    "job {job}: JOB_REVISE_RATE_FINALIZED: Decode failure: {}",
-                    log.data
-                );
             }
-        } else if log.topics[0] == METADATA_UPDATED {
-            if let Ok((metadata, timestamp)) = <(String, U256)>::decode(&log.data) {
-                println!(
-                    "job {job}: METADATA_UPDATED: metadata: {}, timestamp: {}",
-                    metadata,
-                    self.last_settled.as_secs()
-                );
-
-                self.last_settled = Duration::from_secs(timestamp.low_u64());
-
-                let v = serde_json::from_str(&metadata);
-                if let Err(err) = v {
-                    println!("job {job}: Error reading metadata: {err:?}");
-                    self.schedule_termination(0);
-                    return -3;
-                }
-
-                let v: Value = v.unwrap();
-
-                let r = v["instance"].as_str();
-                match r {
-                    Some(t) => {
-                        if self.instance_type != t {
-                            println!("job {job}: Instance type change not allowed");
-                            self.schedule_termination(0);
-                            return -3;
-                        }
-                    }
-                    None => {
-                        println!("job {job}: Instance type not set");
-                        self.schedule_termination(0);
-                        return -3;
-                    }
-                }
-
-                let r = v["region"].as_str();
-                match r {
-                    Some(t) => {
-                        if self.region != t {
-                            println!("job {job}: Region change not allowed");
-                            self.schedule_termination(0);
-                            return -3;
-                        }
-                    }
-                    None => {
-                        println!("job {job}: Job region not set");
-                        self.schedule_termination(0);
-                        return -3;
-                    }
-                }
-
-                let r = v["memory"].as_i64();
-                match r {
-                    Some(t) => {
-                        if self.req_mem != t {
-                            println!("job {job}: Memory change not allowed");
-                            self.schedule_termination(0);
-                            return -3;
-                        }
-                    }
-                    None => {
-                        println!("job {job}: memory not set");
-                        self.schedule_termination(0);
-                        return -3;
-                    }
-                }
-
-                let r = v["vcpu"].as_i64();
-                match r {
-                    Some(t) => {
-                        if self.req_vcpus != t.try_into().unwrap_or(2) {
-                            println!("job {job}: vcpu change not allowed");
-                            self.schedule_termination(0);
-                            return -3;
-                        }
-                    }
-                    None => {
-                        println!("job {job}: vcpu not set");
-                        self.schedule_termination(0);
-                        return -3;
+            MarketV1Events::JobMetadataUpdatedFilter(JobMetadataUpdatedFilter {
+                metadata, ..
+            }) => {
+                println!("job {job}: METADATA_UPDATED: metadata: {metadata}");
+                // last_settled is left alone, the event carries no settlement timestamp
+                // what used to be decoded as one was the length word of the metadata string
+
+                let spec = match JobMetadata::parse(&metadata) {
+                    Ok(spec) => spec,
+                    Err(problems) => {
+                        return self
+                            .reject(format!("invalid metadata: {}", format_problems(&problems)));
                     }
-                }
+                };
 
-                let family = v["family"].as_str();
-                if family.is_some() && self.family != family.unwrap() {
-                    println!("job {job}: family change not allowed");
-                    self.schedule_termination(0);
-                    return -3;
+                if let Some(field) = self.immutable_change(&spec) {
+                    return self.reject(format!("{field} change not allowed"));
                 }
 
-                let url = v["url"].as_str();
-                match url {
-                    Some(t) => {
-                        if self.eif_url == t {
-                            println!("job {job}: no url change for EIF update event");
-                            self.schedule_termination(0);
-                            return -3;
-                        }
-                    }
-                    None => {
-                        println!("job {job}: url not set");
-                        self.schedule_termination(0);
-                        return -3;
-                    }
+                if self.eif_url == spec.eif_url {
+                    return self.reject("no url change for EIF update event".to_owned());
                 }
-                self.eif_url = url.unwrap().to_string();
+                self.eif_url = spec.eif_url;
                 self.eif_update = true;
                 self.schedule_launch(self.launch_delay);
-            } else {
-                println!("job {job}: METADATA_UPDATED: Decode failure: {}", log.data);
             }
-        } else {
-            println!("job {job}: Unknown event: {}", log.topics[0]);
         }
 
-        0
+        Ok(())
     }
 }
 
 // manage the complete lifecycle of a job
-// returns true if "done"
 async fn job_manager_once(
     mut job_stream: impl Stream<Item = Log> + Unpin,
+    mut head_stream: impl Stream<Item = u64> + Unpin,
     mut infra_provider: impl InfraProvider + Send + Sync,
+    store: impl JobStore + Send + Sync,
     job_id: JobId,
     allowed_regions: &[String],
     aws_delay_duration: u64,
-    rates: &[RegionalRates],
-    gb_rates: &[GBRateCard],
-    address_whitelist: &[String],
-    address_blacklist: &[String],
-) -> i8 {
+    confirmations: u64,
+    // loaded for every batch of logs so reloads apply to the logs processed after them
+    inputs: &SharedInputs,
+    admissions: &Admissions,
+    capacity: &Capacity,
+) -> JobOutcome {
     let job = job_id.id.clone();
-    let mut state = JobState::new(job_id, aws_delay_duration, allowed_regions);
+    let mut state = JobState::new(
+        job_id,
+        aws_delay_duration,
+        allowed_regions,
+        admissions,
+        capacity,
+    );
+    let mut buffer = LogBuffer::new(confirmations);
+
+    // retried until it works, no log has been taken from the stream yet so nothing is lost
+    let mut backoff = 1;
+    let checkpoint = loop {
+        match store.load(&state.job_id).await {
+            Ok(checkpoint) => break checkpoint,
+            Err(err) => {
+                println!("job {job}: Failed to load checkpoint: {err:?}");
+                sleep(Duration::from_secs(backoff)).await;
+                backoff *= 2;
+                if backoff > 128 {
+                    backoff = 128;
+                }
+            }
+        }
+    };
+
+    // state to rebuild from if applied logs get removed by a reorg
+    let mut base = None;
+    if let Some(mut checkpoint) = checkpoint {
+        println!(
+            "job {job}: Resuming from checkpoint: {:?}",
+            checkpoint.watermark
+        );
+        let applied = std::mem::take(&mut checkpoint.applied);
+        let checkpoint_base = checkpoint.base.take();
+        state.restore(checkpoint.clone());
+        // the JobOpened log is not processed again, so the decision has to count towards quotas
+        if let Some(decision) = &state.admission {
+            admissions.restore(&state.job_id, decision.clone());
+        }
+        if state.ended() {
+            // ended before the restart, the manager would otherwise idle forever
+            println!("job {job}: Job already ended at checkpoint");
+            return state.final_outcome();
+        }
+        // the image is never measured again from the pcrs endpoint
+        if let Some(pcrs) = &state.pcrs {
+            infra_provider.restore_job_pcrs(&state.job_id, &state.instance_id, pcrs.clone());
+        }
+        // applied logs that are not final yet can still be removed after the restart
+        base = if applied.is_empty() {
+            Some(checkpoint)
+        } else {
+            checkpoint_base.map(|base| *base)
+        };
+        buffer.applied = applied;
+    }
 
     let res = 'event: loop {
         // compute time to insolvency
@@ -1239,9 +1944,51 @@ async fn job_manager_once(
             biased;
 
             log = job_stream.next() => {
-                let res = state.process_log(log, rates, gb_rates, address_whitelist, address_blacklist);
-                if res == -2 || res == -1 {
-                    break 'event res;
+                let inputs = inputs.load();
+                let res = match log {
+                    // pending logs are simply dropped, applied ones need the state to be rebuilt
+                    Some(log) if log.removed.unwrap_or(false) => {
+                        if buffer.remove(&log) {
+                            state.rebuild(base.clone(), buffer.applied.clone(), &inputs.rates, &inputs.gb_rates, &inputs.admission)
+                        } else {
+                            Ok(())
+                        }
+                    }
+                    Some(log) => {
+                        let logs = buffer.push(log);
+                        state.process_logs(logs, &inputs.rates, &inputs.gb_rates, &inputs.admission)
+                    }
+                    None => state.process_log(None, &inputs.rates, &inputs.gb_rates, &inputs.admission),
+                };
+                if let Err(outcome) = res {
+                    state.save_outcome(&store, base.as_ref(), &buffer.applied, &outcome).await;
+                    break 'event outcome;
+                }
+                state.save(&store, base.as_ref(), &buffer.applied).await;
+            }
+
+            // apply logs once they have enough confirmations
+            head = head_stream.next() => {
+                let Some(head) = head else {
+                    // streams are never recreated, the job resumes from its checkpoint on restart
+                    break 'event JobOutcome::Recoverable("head stream ended".to_owned());
+                };
+
+                let logs = buffer.set_head(head);
+                let inputs = inputs.load();
+                if !logs.is_empty() {
+                    let res = state.process_logs(logs, &inputs.rates, &inputs.gb_rates, &inputs.admission);
+                    if let Err(outcome) = res {
+                        state.save_outcome(&store, base.as_ref(), &buffer.applied, &outcome).await;
+                        break 'event outcome;
+                    }
+                    state.save(&store, base.as_ref(), &buffer.applied).await;
+                }
+
+                // logs beyond the reorg depth are moved into the base instead of being kept
+                let finalized = buffer.finalize();
+                if !finalized.is_empty() {
+                    base = Some(state.finalize(base.take(), finalized, &inputs.rates, &inputs.gb_rates, &inputs.admission));
                 }
             }
 
@@ -1261,9 +2008,10 @@ async fn job_manager_once(
             // should only happen if scheduled
             () = sleep(aws_delay_timeout), if state.infra_change_scheduled => {
                 let res = state.change_infra(&mut infra_provider).await;
+                state.save(&store, base.as_ref(), &buffer.applied).await;
                 if res && !state.infra_state {
                     // successful termination, exit
-                    break 'event 0;
+                    break 'event state.final_outcome();
                 }
             }
         }
@@ -1274,33 +2022,119 @@ async fn job_manager_once(
     res
 }
 
-async fn job_logs(
-    client: &Provider<Ws>,
-    contract: Address,
-    job: H256,
-) -> Result<impl Stream<Item = Log> + Send + '_> {
-    let event_filter = Filter::new()
-        .select(0..)
-        .address(contract)
-        .topic0(vec![
-            keccak256("JobOpened(bytes32,string,address,address,uint256,uint256,uint256)"),
-            keccak256("JobSettled(bytes32,uint256,uint256)"),
-            keccak256("JobClosed(bytes32)"),
-            keccak256("JobDeposited(bytes32,address,uint256)"),
-            keccak256("JobWithdrew(bytes32,address,uint256)"),
-            keccak256("JobReviseRateInitiated(bytes32,uint256)"),
-            keccak256("JobReviseRateCancelled(bytes32)"),
-            keccak256("JobReviseRateFinalized(bytes32,uint256)"),
-        ])
-        .topic1(job);
-
+async fn new_heads(client: &Provider<Ws>) -> Result<impl Stream<Item = u64> + Send + '_> {
     // register subscription
     let stream = client
-        .subscribe_logs(&event_filter)
+        .subscribe_blocks()
         .await
-        .context("failed to subscribe to job logs")?;
+        .context("failed to subscribe to new heads")?;
+
+    Ok(stream.map(|block| block.number.unwrap_or_default().as_u64()))
+}
+
+// blocks after which applied logs are final, reorgs are not expected to remove them
+pub const REORG_DEPTH: u64 = 64;
+
+// Holds back logs until they have enough confirmations
+// Applied logs are kept around until they are final so the job state can be rebuilt if a reorg
+// removes any of them
+struct LogBuffer {
+    confirmations: u64,
+    head: u64,
+    // logs waiting for confirmations, ordered by (block number, log index)
+    pending: BTreeMap<(u64, u64), Log>,
+    // logs already handed out to be applied and not final yet, in order
+    applied: Vec<Log>,
+}
+
+impl LogBuffer {
+    fn new(confirmations: u64) -> LogBuffer {
+        LogBuffer {
+            confirmations,
+            head: 0,
+            pending: BTreeMap::new(),
+            applied: Vec::new(),
+        }
+    }
+
+    // returns the logs that can be applied now
+    fn push(&mut self, log: Log) -> Vec<Log> {
+        match log_position(&log) {
+            Some(position) if self.confirmations > 0 => {
+                self.pending.insert(position, log);
+                self.confirmed()
+            }
+            // nothing to wait for
+            _ => {
+                self.applied.push(log.clone());
+                vec![log]
+            }
+        }
+    }
+
+    // returns the logs confirmed by the new head
+    fn set_head(&mut self, head: u64) -> Vec<Log> {
+        self.head = self.head.max(head);
+        self.confirmed()
+    }
+
+    fn confirmed(&mut self) -> Vec<Log> {
+        let mut logs = Vec::new();
+        while let Some(entry) = self.pending.first_entry() {
+            if entry.key().0 + self.confirmations > self.head {
+                break;
+            }
+
+            let log = entry.remove();
+            self.applied.push(log.clone());
+            logs.push(log);
+        }
+
+        logs
+    }
+
+    // returns the applied logs that became final, in order
+    // logs without a position can never be removed, so they are final right away
+    fn finalize(&mut self) -> Vec<Log> {
+        let count = self
+            .applied
+            .iter()
+            .take_while(|log| {
+                !log_position(log).is_some_and(|(block, _)| block + REORG_DEPTH > self.head)
+            })
+            .count();
+
+        self.applied.drain(..count).collect()
+    }
+
+    // returns true if the removed log was already applied and not final yet
+    fn remove(&mut self, log: &Log) -> bool {
+        let Some(position) = log_position(log) else {
+            return false;
+        };
+
+        if self.pending.remove(&position).is_some() {
+            return false;
+        }
+
+        let len = self.applied.len();
+        self.applied
+            .retain(|applied| log_position(applied) != Some(position));
+        self.applied.len() != len
+    }
+}
+
+fn log_position(log: &Log) -> Option<(u64, u64)> {
+    Some((log.block_number?.as_u64(), log.log_index?.as_u64()))
+}
 
-    Ok(Box::new(stream))
+// position right before the given one, none if it is the very first
+fn before((block, index): (u64, u64)) -> Option<(u64, u64)> {
+    match (block, index) {
+        (0, 0) => None,
+        (block, 0) => Some((block - 1, u64::MAX)),
+        (block, index) => Some((block, index - 1)),
+    }
 }
 
 #[cfg(not(test))]
@@ -1329,10 +2163,17 @@ mod tests {
     use ethers::abi::AbiEncode;
     use ethers::prelude::*;
     use std::str::FromStr;
+    use tokio::sync::watch;
     use tokio::time::{sleep, Duration, Instant};
 
+    use crate::admission;
+    use crate::capacity;
     use crate::market;
-    use crate::test::{self, Action, TestAws, TestAwsOutcome};
+    use crate::pcr::Pcrs;
+    use crate::store::{DispatcherCheckpoint, JobCheckpoint};
+    use crate::test::{
+        self, Action, InstanceMetadata, TestAws, TestAwsOutcome, TestLogger, TestStore,
+    };
 
     #[tokio::test(start_paused = true)]
     async fn test_instance_launch_after_delay_on_spin_up() {
@@ -1356,7 +2197,9 @@ mod tests {
         let mut aws: TestAws = Default::default();
         let res = market::job_manager_once(
             job_stream,
+            tokio_stream::pending(),
             &mut aws,
+            TestStore::default(),
             market::JobId {
                 id: job_num.encode_hex(),
                 operator: "abc".into(),
@@ -1365,15 +2208,15 @@ mod tests {
             },
             &["ap-south-1".into()],
             300,
-            &test::get_rates(),
-            &test::get_gb_rates(),
-            &Vec::new(),
-            &Vec::new(),
+            0,
+            &test::get_inputs(Vec::new(), Vec::new()),
+            &admission::Admissions::default(),
+            &capacity::Capacity::default(),
         )
         .await;
 
         // job manager should have finished successfully
-        assert_eq!(res, 0);
+        assert_eq!(res, market::JobOutcome::Terminated);
         println!("{:?}", aws.outcomes);
         let spin_up_tv_sec: Instant;
         let instance_id: String;
@@ -1421,6 +2264,84 @@ mod tests {
         assert!(!aws.instances.contains_key(&job_num.to_string()))
     }
 
+    #[tokio::test(start_paused = true)]
+    async fn test_instance_replaced_on_host_key_mismatch() {
+        let _ = market::START.set(Instant::now());
+
+        let job_num = H256::from_low_u64_be(1);
+        let job_logs: Vec<(u64, Log)> = vec![
+            (0, Action::Open, ("{\"region\":\"ap-south-1\",\"url\":\"https://example.com/enclave.eif\",\"instance\":\"c6a.xlarge\",\"memory\":4096,\"vcpu\":2}".to_string(),31000000000000u64,31000u64,market::now_timestamp().as_secs()).encode()),
+            (500, Action::Close, [].into()),
+        ].into_iter().map(|x| (x.0, test::get_log(x.1, Bytes::from(x.2), job_num))).collect();
+
+        let start_time = Instant::now();
+        // pending stream appended so job stream never ends
+        let job_stream = std::pin::pin!(tokio_stream::iter(job_logs.into_iter())
+            .then(|(moment, log)| async move {
+                let delay = start_time + Duration::from_secs(moment) - Instant::now();
+                sleep(delay).await;
+                log
+            })
+            .chain(tokio_stream::pending()));
+
+        // existing instance presents a host key other than its pinned one
+        let mut aws: TestAws = Default::default();
+        let instance_metadata = InstanceMetadata::new(None, None).await;
+        aws.instances
+            .insert(job_num.encode_hex(), instance_metadata.clone());
+        aws.host_key_mismatches
+            .push(instance_metadata.instance_id.clone());
+
+        let res = market::job_manager_once(
+            job_stream,
+            tokio_stream::pending(),
+            &mut aws,
+            TestStore::default(),
+            market::JobId {
+                id: job_num.encode_hex(),
+                operator: "abc".into(),
+                contract: "xyz".into(),
+                chain: "123".into(),
+            },
+            &["ap-south-1".into()],
+            300,
+            0,
+            &test::get_inputs(Vec::new(), Vec::new()),
+            &admission::Admissions::default(),
+            &capacity::Capacity::default(),
+        )
+        .await;
+
+        assert_eq!(res, market::JobOutcome::Terminated);
+
+        // the instance is spun down and a new one launched in its place
+        if let TestAwsOutcome::SpinDown(out) = &aws.outcomes[0] {
+            assert_eq!(out.instance_id, instance_metadata.instance_id);
+        } else {
+            panic!();
+        };
+
+        let instance_id = if let TestAwsOutcome::SpinUp(out) = &aws.outcomes[1] {
+            assert_ne!(out.instance_id, instance_metadata.instance_id);
+            out.instance_id.clone()
+        } else {
+            panic!();
+        };
+
+        if let TestAwsOutcome::RunEnclave(out) = &aws.outcomes[2] {
+            assert_eq!(out.instance_id, instance_id);
+        } else {
+            panic!();
+        };
+
+        if let TestAwsOutcome::SpinDown(out) = &aws.outcomes[3] {
+            assert_eq!(out.instance_id, instance_id);
+        } else {
+            panic!();
+        };
+        assert_eq!(aws.outcomes.len(), 4);
+    }
+
     #[tokio::test(start_paused = true)]
     async fn test_instance_launch_after_delay_on_spin_up_with_specific_family() {
         let _ = market::START.set(Instant::now());
@@ -1443,7 +2364,9 @@ mod tests {
         let mut aws: TestAws = Default::default();
         let res = market::job_manager_once(
             job_stream,
+            tokio_stream::pending(),
             &mut aws,
+            TestStore::default(),
             market::JobId {
                 id: job_num.encode_hex(),
                 operator: "abc".into(),
@@ -1452,15 +2375,15 @@ mod tests {
             },
             &["ap-south-1".into()],
             300,
-            &test::get_rates(),
-            &test::get_gb_rates(),
-            &Vec::new(),
-            &Vec::new(),
+            0,
+            &test::get_inputs(Vec::new(), Vec::new()),
+            &admission::Admissions::default(),
+            &capacity::Capacity::default(),
         )
         .await;
 
         // job manager should have finished successfully
-        assert_eq!(res, 0);
+        assert_eq!(res, market::JobOutcome::Terminated);
         println!("{:?}", aws.outcomes);
         let spin_up_tv_sec: Instant;
         let instance_id: String;
@@ -1531,7 +2454,9 @@ mod tests {
         let mut aws: TestAws = Default::default();
         let res = market::job_manager_once(
             job_stream,
+            tokio_stream::pending(),
             &mut aws,
+            TestStore::default(),
             market::JobId {
                 id: job_num.encode_hex(),
                 operator: "abc".into(),
@@ -1540,15 +2465,15 @@ mod tests {
             },
             &["ap-south-1".into()],
             300,
-            &test::get_rates(),
-            &test::get_gb_rates(),
-            &Vec::new(),
-            &Vec::new(),
+            0,
+            &test::get_inputs(Vec::new(), Vec::new()),
+            &admission::Admissions::default(),
+            &capacity::Capacity::default(),
         )
         .await;
 
         // job manager should have finished successfully
-        assert_eq!(res, 0);
+        assert_eq!(res, market::JobOutcome::Terminated);
         println!("{:?}", aws.outcomes);
         let spin_up_tv_sec: Instant;
         let instance_id: String;
@@ -1622,7 +2547,9 @@ mod tests {
         let mut aws: TestAws = Default::default();
         let res = market::job_manager_once(
             job_stream,
+            tokio_stream::pending(),
             &mut aws,
+            TestStore::default(),
             market::JobId {
                 id: job_num.encode_hex(),
                 operator: "abc".into(),
@@ -1631,15 +2558,15 @@ mod tests {
             },
             &["ap-south-1".into()],
             300,
-            &test::get_rates(),
-            &test::get_gb_rates(),
-            &Vec::new(),
-            &Vec::new(),
+            0,
+            &test::get_inputs(Vec::new(), Vec::new()),
+            &admission::Admissions::default(),
+            &capacity::Capacity::default(),
         )
         .await;
 
         // job manager should have finished successfully
-        assert_eq!(res, 0);
+        assert_eq!(res, market::JobOutcome::Terminated);
         println!("{:?}", aws.outcomes);
         let spin_up_tv_sec: Instant;
         let instance_id: String;
@@ -1707,28 +2634,60 @@ mod tests {
             })
             .chain(tokio_stream::pending()));
         let mut aws: TestAws = Default::default();
+        let store = TestStore::default();
+        let job_id = market::JobId {
+            id: job_num.encode_hex(),
+            operator: "abc".into(),
+            contract: "xyz".into(),
+            chain: "123".into(),
+        };
         let res = market::job_manager_once(
             job_stream,
+            tokio_stream::pending(),
             &mut aws,
-            market::JobId {
-                id: job_num.encode_hex(),
-                operator: "abc".into(),
-                contract: "xyz".into(),
-                chain: "123".into(),
-            },
+            store.clone(),
+            job_id.clone(),
             &["ap-south-1".into()],
             300,
-            &test::get_rates(),
-            &test::get_gb_rates(),
-            &Vec::new(),
-            &Vec::new(),
+            0,
+            &test::get_inputs(Vec::new(), Vec::new()),
+            &admission::Admissions::default(),
+            &capacity::Capacity::default(),
         )
         .await;
 
         // job manager should have finished successfully
-        assert_eq!(res, -2);
+        assert_eq!(
+            res,
+            market::JobOutcome::Unrecoverable("region ap-east-1 not supported".to_owned())
+        );
         assert!(aws.outcomes.is_empty());
-        assert!(!aws.instances.contains_key(&job_num.to_string()))
+        assert!(!aws.instances.contains_key(&job_num.to_string()));
+
+        // respawned after a restart without its logs, exits right away instead of idling
+        let res = tokio::time::timeout(
+            Duration::from_secs(1),
+            market::job_manager_once(
+                tokio_stream::pending(),
+                tokio_stream::pending(),
+                &mut aws,
+                store,
+                job_id,
+                &["ap-south-1".into()],
+                300,
+                0,
+                &test::get_inputs(Vec::new(), Vec::new()),
+                &admission::Admissions::default(),
+                &capacity::Capacity::default(),
+            ),
+        )
+        .await;
+        assert_eq!(
+            res,
+            Ok(market::JobOutcome::Unrecoverable(
+                "region ap-east-1 not supported".to_owned()
+            ))
+        );
     }
 
     #[tokio::test(start_paused = true)]
@@ -1753,7 +2712,9 @@ mod tests {
         let mut aws: TestAws = Default::default();
         let res = market::job_manager_once(
             job_stream,
+            tokio_stream::pending(),
             &mut aws,
+            TestStore::default(),
             market::JobId {
                 id: job_num.encode_hex(),
                 operator: "abc".into(),
@@ -1762,15 +2723,18 @@ mod tests {
             },
             &["ap-south-1".into()],
             300,
-            &test::get_rates(),
-            &test::get_gb_rates(),
-            &Vec::new(),
-            &Vec::new(),
+            0,
+            &test::get_inputs(Vec::new(), Vec::new()),
+            &admission::Admissions::default(),
+            &capacity::Capacity::default(),
         )
         .await;
 
         // job manager should have finished successfully
-        assert_eq!(res, -2);
+        assert_eq!(
+            res,
+            market::JobOutcome::Unrecoverable("invalid metadata: region not set".to_owned())
+        );
         assert!(aws.outcomes.is_empty());
         assert!(!aws.instances.contains_key(&job_num.to_string()))
     }
@@ -1797,7 +2761,9 @@ mod tests {
         let mut aws: TestAws = Default::default();
         let res = market::job_manager_once(
             job_stream,
+            tokio_stream::pending(),
             &mut aws,
+            TestStore::default(),
             market::JobId {
                 id: job_num.encode_hex(),
                 operator: "abc".into(),
@@ -1806,15 +2772,18 @@ mod tests {
             },
             &["ap-south-1".into()],
             300,
-            &test::get_rates(),
-            &test::get_gb_rates(),
-            &Vec::new(),
-            &Vec::new(),
+            0,
+            &test::get_inputs(Vec::new(), Vec::new()),
+            &admission::Admissions::default(),
+            &capacity::Capacity::default(),
         )
         .await;
 
         // job manager should have finished successfully
-        assert_eq!(res, -2);
+        assert_eq!(
+            res,
+            market::JobOutcome::Unrecoverable("invalid metadata: instance not set".to_owned())
+        );
         assert!(aws.outcomes.is_empty());
         assert!(!aws.instances.contains_key(&job_num.to_string()))
     }
@@ -1841,7 +2810,9 @@ mod tests {
         let mut aws: TestAws = Default::default();
         let res = market::job_manager_once(
             job_stream,
+            tokio_stream::pending(),
             &mut aws,
+            TestStore::default(),
             market::JobId {
                 id: job_num.encode_hex(),
                 operator: "abc".into(),
@@ -1850,15 +2821,18 @@ mod tests {
             },
             &["ap-south-1".into()],
             300,
-            &test::get_rates(),
-            &test::get_gb_rates(),
-            &Vec::new(),
-            &Vec::new(),
+            0,
+            &test::get_inputs(Vec::new(), Vec::new()),
+            &admission::Admissions::default(),
+            &capacity::Capacity::default(),
         )
         .await;
 
         // job manager should have finished successfully
-        assert_eq!(res, -2);
+        assert_eq!(
+            res,
+            market::JobOutcome::Unrecoverable("instance type c6a.vsmall not supported".to_owned())
+        );
         assert!(aws.outcomes.is_empty());
         assert!(!aws.instances.contains_key(&job_num.to_string()))
     }
@@ -1885,7 +2859,9 @@ mod tests {
         let mut aws: TestAws = Default::default();
         let res = market::job_manager_once(
             job_stream,
+            tokio_stream::pending(),
             &mut aws,
+            TestStore::default(),
             market::JobId {
                 id: job_num.encode_hex(),
                 operator: "abc".into(),
@@ -1894,15 +2870,18 @@ mod tests {
             },
             &["ap-south-1".into()],
             300,
-            &test::get_rates(),
-            &test::get_gb_rates(),
-            &Vec::new(),
-            &Vec::new(),
+            0,
+            &test::get_inputs(Vec::new(), Vec::new()),
+            &admission::Admissions::default(),
+            &capacity::Capacity::default(),
         )
         .await;
 
         // job manager should have finished successfully
-        assert_eq!(res, -2);
+        assert_eq!(
+            res,
+            market::JobOutcome::Unrecoverable("invalid metadata: url not set".to_owned())
+        );
         assert!(aws.outcomes.is_empty());
         assert!(!aws.instances.contains_key(&job_num.to_string()))
     }
@@ -1929,7 +2908,9 @@ mod tests {
         let mut aws: TestAws = Default::default();
         let res = market::job_manager_once(
             job_stream,
+            tokio_stream::pending(),
             &mut aws,
+            TestStore::default(),
             market::JobId {
                 id: job_num.encode_hex(),
                 operator: "abc".into(),
@@ -1938,15 +2919,15 @@ mod tests {
             },
             &["ap-south-1".into()],
             300,
-            &test::get_rates(),
-            &test::get_gb_rates(),
-            &Vec::new(),
-            &Vec::new(),
+            0,
+            &test::get_inputs(Vec::new(), Vec::new()),
+            &admission::Admissions::default(),
+            &capacity::Capacity::default(),
         )
         .await;
 
         // job manager should have finished successfully
-        assert_eq!(res, 0);
+        assert_eq!(res, market::JobOutcome::Terminated);
         assert!(aws.outcomes.is_empty());
         assert!(!aws.instances.contains_key(&job_num.to_string()))
     }
@@ -1973,7 +2954,9 @@ mod tests {
         let mut aws: TestAws = Default::default();
         let res = market::job_manager_once(
             job_stream,
+            tokio_stream::pending(),
             &mut aws,
+            TestStore::default(),
             market::JobId {
                 id: job_num.encode_hex(),
                 operator: "abc".into(),
@@ -1982,15 +2965,15 @@ mod tests {
             },
             &["ap-south-1".into()],
             300,
-            &test::get_rates(),
-            &test::get_gb_rates(),
-            &Vec::new(),
-            &Vec::new(),
+            0,
+            &test::get_inputs(Vec::new(), Vec::new()),
+            &admission::Admissions::default(),
+            &capacity::Capacity::default(),
         )
         .await;
 
         // job manager should have finished successfully
-        assert_eq!(res, 0);
+        assert_eq!(res, market::JobOutcome::Terminated);
         assert!(aws.outcomes.is_empty());
         assert!(!aws.instances.contains_key(&job_num.to_string()))
     }
@@ -2018,7 +3001,9 @@ mod tests {
         let mut aws: TestAws = Default::default();
         let res = market::job_manager_once(
             job_stream,
+            tokio_stream::pending(),
             &mut aws,
+            TestStore::default(),
             market::JobId {
                 id: job_num.encode_hex(),
                 operator: "abc".into(),
@@ -2027,15 +3012,15 @@ mod tests {
             },
             &["ap-south-1".into()],
             300,
-            &test::get_rates(),
-            &test::get_gb_rates(),
-            &Vec::new(),
-            &Vec::new(),
+            0,
+            &test::get_inputs(Vec::new(), Vec::new()),
+            &admission::Admissions::default(),
+            &capacity::Capacity::default(),
         )
         .await;
 
         // job manager should have finished successfully
-        assert_eq!(res, 0);
+        assert_eq!(res, market::JobOutcome::Terminated);
         println!("{:?}", aws.outcomes);
         let spin_up_tv_sec: Instant;
         let instance_id: String;
@@ -2108,7 +3093,9 @@ mod tests {
         let mut aws: TestAws = Default::default();
         let res = market::job_manager_once(
             job_stream,
+            tokio_stream::pending(),
             &mut aws,
+            TestStore::default(),
             market::JobId {
                 id: job_num.encode_hex(),
                 operator: "abc".into(),
@@ -2117,15 +3104,15 @@ mod tests {
             },
             &["ap-south-1".into()],
             300,
-            &test::get_rates(),
-            &test::get_gb_rates(),
-            &Vec::new(),
-            &Vec::new(),
+            0,
+            &test::get_inputs(Vec::new(), Vec::new()),
+            &admission::Admissions::default(),
+            &capacity::Capacity::default(),
         )
         .await;
 
         // job manager should have finished successfully
-        assert_eq!(res, 0);
+        assert_eq!(res, market::JobOutcome::Terminated);
         println!("{:?}", aws.outcomes);
         let spin_up_tv_sec: Instant;
         let instance_id: String;
@@ -2195,7 +3182,9 @@ mod tests {
         let mut aws: TestAws = Default::default();
         let res = market::job_manager_once(
             job_stream,
+            tokio_stream::pending(),
             &mut aws,
+            TestStore::default(),
             market::JobId {
                 id: job_num.encode_hex(),
                 operator: "abc".into(),
@@ -2204,17 +3193,21 @@ mod tests {
             },
             &["ap-south-1".into()],
             300,
-            &test::get_rates(),
-            &test::get_gb_rates(),
-            &Vec::from([
-                "0x000000000000000000000000000000000000000000000000f020b3e5fc7a49ec".to_string(),
-            ]),
-            &Vec::new(),
+            0,
+            &test::get_inputs(
+                Vec::from([
+                    "0x000000000000000000000000000000000000000000000000f020b3e5fc7a49ec"
+                        .to_string(),
+                ]),
+                Vec::new(),
+            ),
+            &admission::Admissions::default(),
+            &capacity::Capacity::default(),
         )
         .await;
 
         // job manager should have finished successfully
-        assert_eq!(res, 0);
+        assert_eq!(res, market::JobOutcome::Terminated);
         println!("{:?}", aws.outcomes);
         let spin_up_tv_sec: Instant;
         let instance_id: String;
@@ -2284,7 +3277,9 @@ mod tests {
         let mut aws: TestAws = Default::default();
         let res = market::job_manager_once(
             job_stream,
+            tokio_stream::pending(),
             &mut aws,
+            TestStore::default(),
             market::JobId {
                 id: job_num.encode_hex(),
                 operator: "abc".into(),
@@ -2293,17 +3288,26 @@ mod tests {
             },
             &["ap-south-1".into()],
             300,
-            &test::get_rates(),
-            &test::get_gb_rates(),
-            &Vec::from([
-                "0x000000000000000000000000000000000000000000000000f020c4f6gc7a56ce".to_string(),
-            ]),
-            &Vec::new(),
+            0,
+            &test::get_inputs(
+                Vec::from([
+                    "0x000000000000000000000000000000000000000000000000f020c4f6gc7a56ce"
+                        .to_string(),
+                ]),
+                Vec::new(),
+            ),
+            &admission::Admissions::default(),
+            &capacity::Capacity::default(),
         )
         .await;
 
         // job manager should have finished successfully
-        assert_eq!(res, 0);
+        assert_eq!(
+            res,
+            market::JobOutcome::PolicyRejected(
+                "rule address-whitelist: owner address not allowed".to_owned()
+            )
+        );
         assert!(aws.outcomes.is_empty());
         assert!(!aws.instances.contains_key(&job_num.to_string()))
     }
@@ -2330,7 +3334,9 @@ mod tests {
         let mut aws: TestAws = Default::default();
         let res = market::job_manager_once(
             job_stream,
+            tokio_stream::pending(),
             &mut aws,
+            TestStore::default(),
             market::JobId {
                 id: job_num.encode_hex(),
                 operator: "abc".into(),
@@ -2339,17 +3345,26 @@ mod tests {
             },
             &["ap-south-1".into()],
             300,
-            &test::get_rates(),
-            &test::get_gb_rates(),
-            &Vec::new(),
-            &Vec::from([
-                "0x000000000000000000000000000000000000000000000000f020b3e5fc7a49ec".to_string(),
-            ]),
+            0,
+            &test::get_inputs(
+                Vec::new(),
+                Vec::from([
+                    "0x000000000000000000000000000000000000000000000000f020b3e5fc7a49ec"
+                        .to_string(),
+                ]),
+            ),
+            &admission::Admissions::default(),
+            &capacity::Capacity::default(),
         )
         .await;
 
         // job manager should have finished successfully
-        assert_eq!(res, 0);
+        assert_eq!(
+            res,
+            market::JobOutcome::PolicyRejected(
+                "rule address-blacklist: owner address not allowed".to_owned()
+            )
+        );
         assert!(aws.outcomes.is_empty());
         assert!(!aws.instances.contains_key(&job_num.to_string()))
     }
@@ -2376,7 +3391,9 @@ mod tests {
         let mut aws: TestAws = Default::default();
         let res = market::job_manager_once(
             job_stream,
+            tokio_stream::pending(),
             &mut aws,
+            TestStore::default(),
             market::JobId {
                 id: job_num.encode_hex(),
                 operator: "abc".into(),
@@ -2385,17 +3402,21 @@ mod tests {
             },
             &["ap-south-1".into()],
             300,
-            &test::get_rates(),
-            &test::get_gb_rates(),
-            &Vec::new(),
-            &Vec::from([
-                "0x000000000000000000000000000000000000000000000000f020b3e5fc7a49ece".to_string(),
-            ]),
+            0,
+            &test::get_inputs(
+                Vec::new(),
+                Vec::from([
+                    "0x000000000000000000000000000000000000000000000000f020b3e5fc7a49ece"
+                        .to_string(),
+                ]),
+            ),
+            &admission::Admissions::default(),
+            &capacity::Capacity::default(),
         )
         .await;
 
         // job manager should have finished successfully
-        assert_eq!(res, 0);
+        assert_eq!(res, market::JobOutcome::Terminated);
         println!("{:?}", aws.outcomes);
         let spin_up_tv_sec: Instant;
         let instance_id: String;
@@ -2443,148 +3464,176 @@ mod tests {
         assert!(!aws.instances.contains_key(&job_num.to_string()))
     }
 
-    // Tests for whitelist blacklist checks
-    #[tokio::test]
-    async fn test_whitelist_blacklist_check_no_list() {
+    #[tokio::test(start_paused = true)]
+    async fn test_owner_quota() {
         let _ = market::START.set(Instant::now());
 
-        let log = test::get_log(Action::Open,
-            Bytes::from(("{\"region\":\"ap-south-1\",\"url\":\"https://example.com/enclave.eif\",\"instance\":\"c6a.xlarge\",\"memory\":4096,\"vcpu\":2}".to_string(),31000000000000u64,31000u64,market::now_timestamp().as_secs()).encode()),
-            H256::zero());
-        let address_whitelist = vec![];
-        let address_blacklist = vec![];
-
-        assert!(market::whitelist_blacklist_check(
-            log.clone(),
-            &address_whitelist,
-            &address_blacklist
-        ));
-    }
-
-    #[tokio::test]
-    async fn test_whitelist_blacklist_check_whitelisted() {
-        let _ = market::START.set(Instant::now());
+        let inputs = test::get_inputs(Vec::new(), Vec::new());
+        let mut quota = (*inputs.load()).clone();
+        quota.admission = serde_json::from_str(
+            r#"{"rules": [{"name": "one-job", "effect": "allow", "max_jobs_per_owner": 1}]}"#,
+        )
+        .unwrap();
+        inputs.store(quota);
+        let admissions = admission::Admissions::default();
 
-        let log = test::get_log(Action::Open,
-            Bytes::from(("{\"region\":\"ap-south-1\",\"url\":\"https://example.com/enclave.eif\",\"instance\":\"c6a.xlarge\",\"memory\":4096,\"vcpu\":2}".to_string(),31000000000000u64,31000u64,market::now_timestamp().as_secs()).encode()),
-            H256::zero());
-        let address_whitelist = vec![
-            "0x000000000000000000000000000000000000000000000000f020b3e5fc7a49ec".to_string(),
-            "0x000000000000000000000000000000000000000000000000f020b3e5fd6sd76d".to_string(),
-        ];
-        let address_blacklist = vec![];
-
-        assert!(market::whitelist_blacklist_check(
-            log.clone(),
-            &address_whitelist,
-            &address_blacklist
-        ));
-    }
+        let mut aws: TestAws = Default::default();
+        let mut outcomes = Vec::new();
+        for id in 1..3 {
+            let job_num = H256::from_low_u64_be(id);
+            let job_logs: Vec<(u64, Log)> = vec![
+                (0, Action::Open, ("{\"region\":\"ap-south-1\",\"url\":\"https://example.com/enclave.eif\",\"instance\":\"c6a.xlarge\",\"memory\":4096,\"vcpu\":2}".to_string(),31000000000000u64,31000u64,market::now_timestamp().as_secs()).encode()),
+                (500, Action::Close, [].into()),
+            ].into_iter().map(|x| (x.0, test::get_log(x.1, Bytes::from(x.2), job_num))).collect();
 
-    #[tokio::test]
-    async fn test_whitelist_blacklist_check_not_whitelisted() {
-        let _ = market::START.set(Instant::now());
+            let start_time = Instant::now();
+            // pending stream appended so job stream never ends
+            let job_stream = std::pin::pin!(tokio_stream::iter(job_logs.into_iter())
+                .then(|(moment, log)| async move {
+                    let delay = start_time + Duration::from_secs(moment) - Instant::now();
+                    sleep(delay).await;
+                    log
+                })
+                .chain(tokio_stream::pending()));
+            let job_id = market::JobId {
+                id: job_num.encode_hex(),
+                operator: "abc".into(),
+                contract: "xyz".into(),
+                chain: "123".into(),
+            };
+            // the first job is not released, so it keeps counting towards the quota
+            let res = market::job_manager_once(
+                job_stream,
+                tokio_stream::pending(),
+                &mut aws,
+                TestStore::default(),
+                job_id.clone(),
+                &["ap-south-1".into()],
+                300,
+                0,
+                &inputs,
+                &admissions,
+                &capacity::Capacity::default(),
+            )
+            .await;
+            outcomes.push((res, admissions.get(&job_id).unwrap()));
+        }
 
-        let log = test::get_log(Action::Open,
-            Bytes::from(("{\"region\":\"ap-south-1\",\"url\":\"https://example.com/enclave.eif\",\"instance\":\"c6a.xlarge\",\"memory\":4096,\"vcpu\":2}".to_string(),31000000000000u64,31000u64,market::now_timestamp().as_secs()).encode()),
-            H256::zero());
-        let address_whitelist = vec![
-            "0x000000000000000000000000000000000000000000000000f020b3e5fc7a48as".to_string(),
-            "0x000000000000000000000000000000000000000000000000f020b3e5fd6sd76d".to_string(),
-        ];
-        let address_blacklist = vec![];
-
-        assert!(!market::whitelist_blacklist_check(
-            log.clone(),
-            &address_whitelist,
-            &address_blacklist
-        ));
+        assert_eq!(outcomes[0].0, market::JobOutcome::Terminated);
+        assert!(outcomes[0].1.decision.allowed);
+        assert_eq!(outcomes[0].1.decision.rule, "one-job");
+        assert_eq!(
+            outcomes[1].0,
+            market::JobOutcome::PolicyRejected(
+                "rule one-job: owner has 1 jobs admitted, quota is 1".to_owned()
+            )
+        );
+        assert!(!outcomes[1].1.decision.allowed);
+        assert_eq!(
+            outcomes[1].1.decision.owner,
+            "0x000000000000000000000000000000000000000000000000f020b3e5fc7a49ec"
+        );
+        // only the first job was launched
+        assert_eq!(aws.outcomes.len(), 3);
     }
 
-    #[tokio::test]
-    async fn test_whitelist_blacklist_check_blacklisted() {
+    #[tokio::test(start_paused = true)]
+    async fn test_launch_queue() {
         let _ = market::START.set(Instant::now());
 
-        let log = test::get_log(Action::Open,
-            Bytes::from(("{\"region\":\"ap-south-1\",\"url\":\"https://example.com/enclave.eif\",\"instance\":\"c6a.xlarge\",\"memory\":4096,\"vcpu\":2}".to_string(),31000000000000u64,31000u64,market::now_timestamp().as_secs()).encode()),
-            H256::zero());
-        let address_whitelist = vec![];
-        let address_blacklist = vec![
-            "0x000000000000000000000000000000000000000000000000f020b3e5fc7a49ec".to_string(),
-            "0x000000000000000000000000000000000000000000000000f020b3e5fd6sdsd6".to_string(),
-        ];
-
-        assert!(!market::whitelist_blacklist_check(
-            log.clone(),
-            &address_whitelist,
-            &address_blacklist
-        ));
-    }
-
-    #[tokio::test]
-    async fn test_whitelist_blacklist_check_not_blacklisted() {
-        let _ = market::START.set(Instant::now());
+        let inputs = test::get_inputs(Vec::new(), Vec::new());
+        let admissions = admission::Admissions::default();
+        let capacity = capacity::Capacity::new(capacity::CapacityLimits {
+            max_instances_per_region: Some(1),
+            ..Default::default()
+        });
 
-        let log = test::get_log(Action::Open,
-            Bytes::from(("{\"region\":\"ap-south-1\",\"url\":\"https://example.com/enclave.eif\",\"instance\":\"c6a.xlarge\",\"memory\":4096,\"vcpu\":2}".to_string(),31000000000000u64,31000u64,market::now_timestamp().as_secs()).encode()),
-            H256::zero());
-        let address_whitelist = vec![];
-        let address_blacklist = vec![
-            "0x000000000000000000000000000000000000000000000000f020b3e5fc7a49fe".to_string(),
-            "0x000000000000000000000000000000000000000000000000f020b3e5fd6sdsd6".to_string(),
-        ];
-
-        assert!(market::whitelist_blacklist_check(
-            log.clone(),
-            &address_whitelist,
-            &address_blacklist
-        ));
-    }
+        let start_time = Instant::now();
+        let job_id = |job_num: H256| market::JobId {
+            id: job_num.encode_hex(),
+            operator: "abc".into(),
+            contract: "xyz".into(),
+            chain: "123".into(),
+        };
+        // second job is opened while the first one holds the only slot of the region
+        let job_stream = |job_num: H256, open: u64, close: u64| {
+            let job_logs: Vec<(u64, Log)> = vec![
+                (open, Action::Open, ("{\"region\":\"ap-south-1\",\"url\":\"https://example.com/enclave.eif\",\"instance\":\"c6a.xlarge\",\"memory\":4096,\"vcpu\":2}".to_string(),31000000000000u64,31000u64,market::now_timestamp().as_secs()).encode()),
+                (close, Action::Close, [].into()),
+            ].into_iter().map(|x| (x.0, test::get_log(x.1, Bytes::from(x.2), job_num))).collect();
 
-    #[tokio::test]
-    async fn test_whitelist_blacklist_check_neither() {
-        let _ = market::START.set(Instant::now());
+            // pending stream appended so job stream never ends
+            Box::pin(
+                tokio_stream::iter(job_logs.into_iter())
+                    .then(move |(moment, log)| async move {
+                        let delay = start_time + Duration::from_secs(moment) - Instant::now();
+                        sleep(delay).await;
+                        log
+                    })
+                    .chain(tokio_stream::pending()),
+            )
+        };
+        let (job_1, job_2) = (H256::from_low_u64_be(1), H256::from_low_u64_be(2));
+
+        let mut aws_1: TestAws = Default::default();
+        let mut aws_2: TestAws = Default::default();
+        let (res_1, res_2, queued) = tokio::join!(
+            market::job_manager_once(
+                job_stream(job_1, 0, 500),
+                tokio_stream::pending(),
+                &mut aws_1,
+                TestStore::default(),
+                job_id(job_1),
+                &["ap-south-1".into()],
+                300,
+                0,
+                &inputs,
+                &admissions,
+                &capacity,
+            ),
+            market::job_manager_once(
+                job_stream(job_2, 100, 1000),
+                tokio_stream::pending(),
+                &mut aws_2,
+                TestStore::default(),
+                job_id(job_2),
+                &["ap-south-1".into()],
+                300,
+                0,
+                &inputs,
+                &admissions,
+                &capacity,
+            ),
+            async {
+                sleep(Duration::from_secs(450)).await;
+                capacity.status(&job_id(job_2))
+            }
+        );
 
-        let log = test::get_log(Action::Open,
-            Bytes::from(("{\"region\":\"ap-south-1\",\"url\":\"https://example.com/enclave.eif\",\"instance\":\"c6a.xlarge\",\"memory\":4096,\"vcpu\":2}".to_string(),31000000000000u64,31000u64,market::now_timestamp().as_secs()).encode()),
-            H256::zero());
-        let address_whitelist = vec![
-            "0x000000000000000000000000000000000000000000000000f020b3e5fc7a48aa".to_string(),
-            "0x000000000000000000000000000000000000000000000000f020b3e5fd6sd76d".to_string(),
-        ];
-        let address_blacklist = vec![
-            "0x000000000000000000000000000000000000000000000000f020b3e5fc7a49ed".to_string(),
-            "0x000000000000000000000000000000000000000000000000f020b3e5fd6sdsd6".to_string(),
-        ];
-
-        assert!(!market::whitelist_blacklist_check(
-            log.clone(),
-            &address_whitelist,
-            &address_blacklist
+        assert_eq!(res_1, market::JobOutcome::Terminated);
+        assert_eq!(res_2, market::JobOutcome::Terminated);
+        assert!(matches!(
+            queued,
+            Some(capacity::QueueStatus::Queued {
+                position: 1,
+                length: 1,
+                ..
+            })
         ));
-    }
-
-    #[tokio::test]
-    async fn test_whitelist_blacklist_check_both() {
-        let _ = market::START.set(Instant::now());
 
-        let log = test::get_log(Action::Open,
-            Bytes::from(("{\"region\":\"ap-south-1\",\"url\":\"https://example.com/enclave.eif\",\"instance\":\"c6a.xlarge\",\"memory\":4096,\"vcpu\":2}".to_string(),31000000000000u64,31000u64,market::now_timestamp().as_secs()).encode()),
-            H256::zero());
-        let address_whitelist = vec![
-            "0x000000000000000000000000000000000000000000000000f020b3e5fc7a49ec".to_string(),
-            "0x000000000000000000000000000000000000000000000000f020b3e5fd6sd76d".to_string(),
-        ];
-        let address_blacklist = vec![
-            "0x000000000000000000000000000000000000000000000000f020b3e5fc7a49ec".to_string(),
-            "0x000000000000000000000000000000000000000000000000f020b3e5fd6sdsd6".to_string(),
-        ];
-
-        assert!(!market::whitelist_blacklist_check(
-            log.clone(),
-            &address_whitelist,
-            &address_blacklist
-        ));
+        let TestAwsOutcome::SpinDown(spin_down) = &aws_1.outcomes[2] else {
+            panic!();
+        };
+        let TestAwsOutcome::SpinUp(spin_up) = &aws_2.outcomes[0] else {
+            panic!();
+        };
+        // launched once the first instance is terminated instead of at 400s
+        assert_eq!((spin_down.time - start_time).as_secs(), 500);
+        assert!(spin_up.time >= spin_down.time);
+        assert!((spin_up.time - spin_down.time).as_secs() <= 5);
+        // capacity is released once the job ends
+        assert_eq!(capacity.status(&job_id(job_1)), None);
+        assert_eq!(capacity.status(&job_id(job_2)), None);
     }
 
     #[test]
@@ -2664,7 +3713,9 @@ mod tests {
         let mut aws: TestAws = Default::default();
         let res = market::job_manager_once(
             job_stream,
+            tokio_stream::pending(),
             &mut aws,
+            TestStore::default(),
             market::JobId {
                 id: job_num.encode_hex(),
                 operator: "abc".into(),
@@ -2673,15 +3724,15 @@ mod tests {
             },
             &["ap-south-1".into()],
             300,
-            &test::get_rates(),
-            &test::get_gb_rates(),
-            &Vec::new(),
-            &Vec::new(),
+            0,
+            &test::get_inputs(Vec::new(), Vec::new()),
+            &admission::Admissions::default(),
+            &capacity::Capacity::default(),
         )
         .await;
 
         // job manager should have finished successfully
-        assert_eq!(res, 0);
+        assert_eq!(res, market::JobOutcome::Terminated);
         println!("{:?}", aws.outcomes);
         let spin_up_tv_sec: Instant;
         let instance_id: String;
@@ -2729,6 +3780,59 @@ mod tests {
         assert!(!aws.instances.contains_key(&job_num.to_string()))
     }
 
+    #[tokio::test(start_paused = true)]
+    async fn test_metadata_update_keeps_settlement() {
+        let _ = market::START.set(Instant::now());
+
+        let job_num = H256::from_low_u64_be(1);
+        let job_logs: Vec<(u64, Log)> = vec![
+            (0, Action::Open, ("{\"region\":\"ap-south-1\",\"url\":\"https://example.com/enclave.eif\",\"instance\":\"c6a.xlarge\",\"memory\":4096,\"vcpu\":2}".to_string(),31000000000000u64,31000u64,market::now_timestamp().as_secs()).encode()),
+            (100, Action::MetadataUpdated, ("{\"region\":\"ap-south-1\",\"url\":\"https://example.com/updated-enclave.eif\",\"instance\":\"c6a.xlarge\",\"memory\":4096,\"vcpu\":2}".to_string()).encode()),
+        ].into_iter().map(|x| (x.0, test::get_log(x.1, Bytes::from(x.2), job_num))).collect();
+
+        let start_time = Instant::now();
+        // pending stream appended so job stream never ends
+        let job_stream = std::pin::pin!(tokio_stream::iter(job_logs.into_iter())
+            .then(|(moment, log)| async move {
+                let delay = start_time + Duration::from_secs(moment) - Instant::now();
+                sleep(delay).await;
+                log
+            })
+            .chain(tokio_stream::pending()));
+        let mut aws: TestAws = Default::default();
+        let res = market::job_manager_once(
+            job_stream,
+            tokio_stream::pending(),
+            &mut aws,
+            TestStore::default(),
+            market::JobId {
+                id: job_num.encode_hex(),
+                operator: "abc".into(),
+                contract: "xyz".into(),
+                chain: "123".into(),
+            },
+            &["ap-south-1".into()],
+            300,
+            0,
+            &test::get_inputs(Vec::new(), Vec::new()),
+            &admission::Admissions::default(),
+            &capacity::Capacity::default(),
+        )
+        .await;
+
+        // job manager should have finished successfully
+        assert_eq!(res, market::JobOutcome::Terminated);
+        println!("{:?}", aws.outcomes);
+
+        // balance runs out 1000s after the open, minus the 300s margin, as if there was no update
+        if let TestAwsOutcome::SpinDown(out) = aws.outcomes.last().unwrap() {
+            assert_eq!((out.time - start_time).as_secs(), 700);
+            assert!(H256::from_str(&out.job).unwrap() == job_num && out.region == *"ap-south-1")
+        } else {
+            panic!();
+        };
+    }
+
     #[tokio::test(start_paused = true)]
     async fn test_other_metadata_update_after_spin_up() {
         let _ = market::START.set(Instant::now());
@@ -2753,7 +3857,9 @@ mod tests {
         let mut aws: TestAws = Default::default();
         let res = market::job_manager_once(
             job_stream,
+            tokio_stream::pending(),
             &mut aws,
+            TestStore::default(),
             market::JobId {
                 id: job_num.encode_hex(),
                 operator: "abc".into(),
@@ -2762,15 +3868,18 @@ mod tests {
             },
             &["ap-south-1".into()],
             300,
-            &test::get_rates(),
-            &test::get_gb_rates(),
-            &Vec::new(),
-            &Vec::new(),
+            0,
+            &test::get_inputs(Vec::new(), Vec::new()),
+            &admission::Admissions::default(),
+            &capacity::Capacity::default(),
         )
         .await;
 
         // job manager should have finished successfully
-        assert_eq!(res, 0);
+        assert_eq!(
+            res,
+            market::JobOutcome::PolicyRejected("Instance type change not allowed".to_owned())
+        );
         assert!(aws.outcomes.is_empty());
         assert!(!aws.instances.contains_key(&job_num.to_string()))
     }
@@ -2799,7 +3908,536 @@ mod tests {
         let mut aws: TestAws = Default::default();
         let res = market::job_manager_once(
             job_stream,
+            tokio_stream::pending(),
+            &mut aws,
+            TestStore::default(),
+            market::JobId {
+                id: job_num.encode_hex(),
+                operator: "abc".into(),
+                contract: "xyz".into(),
+                chain: "123".into(),
+            },
+            &["ap-south-1".into()],
+            300,
+            0,
+            &test::get_inputs(Vec::new(), Vec::new()),
+            &admission::Admissions::default(),
+            &capacity::Capacity::default(),
+        )
+        .await;
+
+        // job manager should have finished successfully
+        assert_eq!(
+            res,
+            market::JobOutcome::PolicyRejected("no url change for EIF update event".to_owned())
+        );
+        assert!(aws.outcomes.is_empty());
+        assert!(!aws.instances.contains_key(&job_num.to_string()))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_resume_from_checkpoint() {
+        let _ = market::START.set(Instant::now());
+
+        let job_num = H256::from_low_u64_be(1);
+        let job_logs: Vec<(u64, Log)> = vec![
+            // processed before the restart, should be skipped
+            (0, Action::Open, ("{\"region\":\"ap-south-1\",\"url\":\"https://example.com/enclave.eif\",\"instance\":\"c6a.xlarge\",\"memory\":4096,\"vcpu\":2}".to_string(),31000000000000u64,31000u64,market::now_timestamp().as_secs()).encode(), 10, 0),
+            (100, Action::Close, [].into(), 12, 3),
+        ].into_iter().map(|x| (x.0, test::get_log_at(x.1, Bytes::from(x.2), job_num, x.3, x.4))).collect();
+
+        let start_time = Instant::now();
+        // pending stream appended so job stream never ends
+        let job_stream = std::pin::pin!(tokio_stream::iter(job_logs.into_iter())
+            .then(|(moment, log)| async move {
+                let delay = start_time + Duration::from_secs(moment) - Instant::now();
+                sleep(delay).await;
+                log
+            })
+            .chain(tokio_stream::pending()));
+
+        // instance launched before the restart
+        let mut aws: TestAws = Default::default();
+        let instance_metadata = InstanceMetadata::new(None, None).await;
+        aws.instances
+            .insert(job_num.encode_hex(), instance_metadata.clone());
+
+        let store = TestStore::default();
+        store.checkpoints.lock().unwrap().insert(
+            job_num.encode_hex(),
+            JobCheckpoint {
+                watermark: Some((10, 0)),
+                balance: U256::from(31000u64),
+                last_settled: market::now_timestamp().as_secs(),
+                rate: U256::from(31000000000000u64),
+                original_rate: U256::from(31000000000000u64),
+                instance_id: instance_metadata.instance_id.clone(),
+                family: "salmon".to_owned(),
+                min_rate: U256::from_dec_str("29997916666666").unwrap(),
+                bandwidth: 76,
+                eif_url: "https://example.com/enclave.eif".to_owned(),
+                instance_type: "c6a.xlarge".to_owned(),
+                region: "ap-south-1".to_owned(),
+                req_vcpus: 2,
+                req_mem: 4096,
+                instance_vcpus: 4,
+                infra_state: true,
+                infra_change_scheduled: false,
+                eif_update: false,
+                rejection: None,
+                failure: None,
+                admission: None,
+                pcrs: Some(Pcrs {
+                    pcr0: "a".repeat(96),
+                    pcr1: "b".repeat(96),
+                    pcr2: "c".repeat(96),
+                }),
+                applied: Vec::new(),
+                base: None,
+            },
+        );
+
+        let res = market::job_manager_once(
+            job_stream,
+            tokio_stream::pending(),
+            &mut aws,
+            store.clone(),
+            market::JobId {
+                id: job_num.encode_hex(),
+                operator: "abc".into(),
+                contract: "xyz".into(),
+                chain: "123".into(),
+            },
+            &["ap-south-1".into()],
+            300,
+            0,
+            &test::get_inputs(Vec::new(), Vec::new()),
+            &admission::Admissions::default(),
+            &capacity::Capacity::default(),
+        )
+        .await;
+
+        // job manager should have finished successfully without relaunching
+        assert_eq!(res, market::JobOutcome::Terminated);
+        println!("{:?}", aws.outcomes);
+        assert_eq!(aws.outcomes.len(), 1);
+        if let TestAwsOutcome::SpinDown(out) = &aws.outcomes[0] {
+            assert_eq!((out.time - start_time).as_secs(), 100);
+            assert!(
+                H256::from_str(&out.job).unwrap() == job_num
+                    && out.instance_id == instance_metadata.instance_id
+                    && out.region == *"ap-south-1"
+            )
+        } else {
+            panic!();
+        };
+
+        let checkpoint = store
+            .checkpoints
+            .lock()
+            .unwrap()
+            .get(&job_num.encode_hex())
+            .cloned()
+            .unwrap();
+        assert_eq!(checkpoint.watermark, Some((12, 3)));
+        assert!(!checkpoint.infra_state && !checkpoint.infra_change_scheduled);
+        // approved pcrs are served again after the restart, until the instance is gone
+        assert_eq!(aws.pcrs[&job_num.encode_hex()].pcr0, "a".repeat(96));
+        assert_eq!(checkpoint.pcrs, None);
+
+        // ended jobs return right away when they are resumed again
+        let res = market::job_manager_once(
+            tokio_stream::pending(),
+            tokio_stream::pending(),
+            &mut aws,
+            store.clone(),
+            market::JobId {
+                id: job_num.encode_hex(),
+                operator: "abc".into(),
+                contract: "xyz".into(),
+                chain: "123".into(),
+            },
+            &["ap-south-1".into()],
+            300,
+            0,
+            &test::get_inputs(Vec::new(), Vec::new()),
+            &admission::Admissions::default(),
+            &capacity::Capacity::default(),
+        )
+        .await;
+        assert_eq!(res, market::JobOutcome::Terminated);
+        assert_eq!(aws.outcomes.len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_checkpoint_load_retry() {
+        let _ = market::START.set(Instant::now());
+
+        let job_num = H256::from_low_u64_be(1);
+        let job_logs: Vec<(u64, Log)> = vec![
+            (0, Action::Open, ("{\"region\":\"ap-south-1\",\"url\":\"https://example.com/enclave.eif\",\"instance\":\"c6a.xlarge\",\"memory\":4096,\"vcpu\":2}".to_string(),31000000000000u64,31000u64,market::now_timestamp().as_secs()).encode()),
+            (400, Action::Close, [].into()),
+        ].into_iter().map(|x| (x.0, test::get_log(x.1, Bytes::from(x.2), job_num))).collect();
+
+        let start_time = Instant::now();
+        // pending stream appended so job stream never ends
+        let job_stream = std::pin::pin!(tokio_stream::iter(job_logs.into_iter())
+            .then(|(moment, log)| async move {
+                let delay = start_time + Duration::from_secs(moment) - Instant::now();
+                sleep(delay).await;
+                log
+            })
+            .chain(tokio_stream::pending()));
+
+        // store fails twice, retried after 1s and 2s before any log is taken
+        let store = TestStore::default();
+        *store.failing_loads.lock().unwrap() = 2;
+
+        let mut aws: TestAws = Default::default();
+        let res = market::job_manager_once(
+            job_stream,
+            tokio_stream::pending(),
+            &mut aws,
+            store,
+            market::JobId {
+                id: job_num.encode_hex(),
+                operator: "abc".into(),
+                contract: "xyz".into(),
+                chain: "123".into(),
+            },
+            &["ap-south-1".into()],
+            300,
+            0,
+            &test::get_inputs(Vec::new(), Vec::new()),
+            &admission::Admissions::default(),
+            &capacity::Capacity::default(),
+        )
+        .await;
+
+        // job manager should have finished successfully with the open applied once loading worked
+        assert_eq!(res, market::JobOutcome::Terminated);
+        println!("{:?}", aws.outcomes);
+        if let TestAwsOutcome::SpinUp(out) = &aws.outcomes[0] {
+            assert_eq!((out.time - start_time).as_secs(), 303);
+        } else {
+            panic!();
+        };
+        if let TestAwsOutcome::SpinDown(out) = aws.outcomes.last().unwrap() {
+            assert_eq!((out.time - start_time).as_secs(), 400);
+        } else {
+            panic!();
+        };
+    }
+
+    #[test]
+    fn test_dispatch_dedup() {
+        let mut dispatcher = market::LogDispatcher::default();
+        assert_eq!(dispatcher.from_block(), 0);
+
+        let job_1 = H256::from_low_u64_be(1);
+        let job_2 = H256::from_low_u64_be(2);
+        let log_1 = test::get_log_at(Action::Open, Bytes::new(), job_1, 10, 0);
+        let log_2 = test::get_log_at(Action::Open, Bytes::new(), job_2, 10, 1);
+
+        let (job, mut job_1_logs) = dispatcher.dispatch(log_1.clone()).unwrap();
+        assert_eq!(job, job_1);
+        assert_eq!(job_1_logs.try_recv(), Ok(log_1.clone()));
+        let (job, _) = dispatcher.dispatch(log_2.clone()).unwrap();
+        assert_eq!(job, job_2);
+        assert_eq!(dispatcher.from_block(), 10);
+
+        // resubscription from the watermark block returns the same logs again
+        assert!(dispatcher.dispatch(log_1).is_none());
+        assert!(dispatcher.dispatch(log_2).is_none());
+        assert!(job_1_logs.try_recv().is_err());
+
+        // same job at a later position is still not spawned twice
+        let log_3 = test::get_log_at(Action::Open, Bytes::new(), job_1, 12, 0);
+        assert!(dispatcher.dispatch(log_3.clone()).is_none());
+        assert_eq!(job_1_logs.try_recv(), Ok(log_3));
+        assert_eq!(dispatcher.from_block(), 12);
+    }
+
+    #[test]
+    fn test_dispatch_routing() {
+        let mut dispatcher = market::LogDispatcher::default();
+
+        let job_1 = H256::from_low_u64_be(1);
+        let job_2 = H256::from_low_u64_be(2);
+
+        // logs of jobs that were never opened for this provider are dropped
+        let log = test::get_log_at(Action::Deposit, Bytes::new(), job_2, 9, 0);
+        assert!(dispatcher.dispatch(log).is_none());
+
+        let log = test::get_log_at(Action::Open, Bytes::new(), job_1, 10, 0);
+        let (_, mut job_1_logs) = dispatcher.dispatch(log.clone()).unwrap();
+        assert_eq!(job_1_logs.try_recv(), Ok(log));
+        let log = test::get_log_at(Action::Open, Bytes::new(), job_2, 10, 1);
+        let (_, mut job_2_logs) = dispatcher.dispatch(log.clone()).unwrap();
+        assert_eq!(job_2_logs.try_recv(), Ok(log));
+
+        let log = test::get_log_at(Action::Deposit, Bytes::new(), job_1, 11, 0);
+        assert!(dispatcher.dispatch(log.clone()).is_none());
+        assert_eq!(job_1_logs.try_recv(), Ok(log));
+        assert!(job_2_logs.try_recv().is_err());
+
+        // closed job managers do not affect other jobs
+        drop(job_1_logs);
+        let log = test::get_log_at(Action::Close, Bytes::new(), job_1, 12, 0);
+        assert!(dispatcher.dispatch(log).is_none());
+        let log = test::get_log_at(Action::Close, Bytes::new(), job_2, 12, 1);
+        assert!(dispatcher.dispatch(log.clone()).is_none());
+        assert_eq!(job_2_logs.try_recv(), Ok(log));
+    }
+
+    #[test]
+    fn test_dispatch_removed() {
+        let mut dispatcher = market::LogDispatcher::default();
+
+        let job = H256::from_low_u64_be(1);
+        let mut log = test::get_log_at(Action::Open, Bytes::new(), job, 10, 0);
+        log.removed = Some(true);
+
+        assert!(dispatcher.dispatch(log).is_none());
+        assert_eq!(dispatcher.from_block(), 0);
+
+        // job is discovered once it is included in the canonical chain
+        let log = test::get_log_at(Action::Open, Bytes::new(), job, 11, 0);
+        let (_, mut job_logs) = dispatcher.dispatch(log.clone()).unwrap();
+        assert_eq!(job_logs.try_recv(), Ok(log.clone()));
+
+        // removals of known jobs are forwarded even though they are behind the watermark
+        let mut removed = log.clone();
+        removed.removed = Some(true);
+        assert!(dispatcher.dispatch(removed.clone()).is_none());
+        assert_eq!(job_logs.try_recv(), Ok(removed));
+        assert_eq!(dispatcher.from_block(), 10);
+
+        // and the log gets through again once the new canonical chain includes it
+        assert!(dispatcher.dispatch(log.clone()).is_none());
+        assert_eq!(job_logs.try_recv(), Ok(log));
+    }
+
+    #[test]
+    fn test_dispatch_restore() {
+        let mut dispatcher = market::LogDispatcher {
+            start_block: 5,
+            ..Default::default()
+        };
+        let job_1 = H256::from_low_u64_be(1);
+        let job_2 = H256::from_low_u64_be(2);
+        dispatcher.restore(
+            DispatcherCheckpoint {
+                watermark: Some((100, 2)),
+                jobs: vec![job_1, job_2],
+            },
+            64,
+        );
+
+        // blocks that might not have been applied before the restart are fetched again
+        assert_eq!(dispatcher.from_block(), 36);
+        assert_eq!(dispatcher.restored.len(), 2);
+        let (job, mut job_1_logs) = dispatcher.restored.remove(0);
+        assert_eq!(job, job_1);
+
+        // replayed logs of restored jobs go to their managers, the jobs are not spawned again
+        let log = test::get_log_at(Action::Open, Bytes::new(), job_1, 40, 0);
+        assert!(dispatcher.dispatch(log.clone()).is_none());
+        assert_eq!(job_1_logs.try_recv(), Ok(log));
+
+        // the restored watermark is kept until the replay passes it
+        assert_eq!(
+            dispatcher.checkpoint(),
+            DispatcherCheckpoint {
+                watermark: Some((100, 2)),
+                jobs: vec![job_1, job_2],
+            }
+        );
+        let log = test::get_log_at(Action::Deposit, Bytes::new(), job_2, 101, 0);
+        assert!(dispatcher.dispatch(log).is_none());
+        assert_eq!(dispatcher.checkpoint().watermark, Some((101, 0)));
+
+        // a start block after the watermark still wins
+        let mut dispatcher = market::LogDispatcher {
+            start_block: 90,
+            ..Default::default()
+        };
+        dispatcher.restore(
+            DispatcherCheckpoint {
+                watermark: Some((100, 2)),
+                jobs: Vec::new(),
+            },
+            64,
+        );
+        assert_eq!(dispatcher.from_block(), 90);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_run_once_shared_stream() {
+        let _ = market::START.set(Instant::now());
+
+        let job_1 = H256::from_low_u64_be(1);
+        let job_2 = H256::from_low_u64_be(2);
+        let metadata = ("{\"region\":\"ap-south-1\",\"url\":\"https://example.com/enclave.eif\",\"instance\":\"c6a.xlarge\",\"memory\":4096,\"vcpu\":2}".to_string(),31000000000000u64,31000u64,market::now_timestamp().as_secs()).encode();
+        let logger = TestLogger {
+            logs: vec![
+                (0, Action::Open, metadata.clone(), job_1, 10),
+                (100, Action::Open, metadata, job_2, 11),
+                (500, Action::Close, Vec::new(), job_1, 12),
+                (600, Action::Close, Vec::new(), job_2, 13),
+            ]
+            .into_iter()
+            .map(|x| (x.0, test::get_log_at(x.1, Bytes::from(x.2), x.3, x.4, 0)))
+            .collect(),
+            heads: Vec::new(),
+        };
+
+        let regions: &'static [String] = Box::leak(vec!["ap-south-1".into()].into_boxed_slice());
+
+        let start_time = Instant::now();
+        let log_stream = std::pin::pin!(logger.market_stream(start_time));
+        let head_stream = std::pin::pin!(logger.head_stream(start_time));
+        let mut dispatcher = market::LogDispatcher::default();
+        let (heads, _) = watch::channel(0);
+        let outcomes = market::JobOutcomes::default();
+        let live_jobs = market::LiveJobs::default();
+        let store = TestStore::default();
+
+        // the shared stream never ends, job managers are given time to exit
+        let res = tokio::time::timeout(
+            Duration::from_secs(1000),
+            market::run_once(
+                log_stream,
+                head_stream,
+                Vec::new(),
+                &mut dispatcher,
+                &heads,
+                Duration::from_secs(2000),
+                TestAws::default(),
+                store.clone(),
+                regions,
+                test::get_inputs(Vec::new(), Vec::new()),
+                market::JobId {
+                    id: H256::zero().encode_hex(),
+                    operator: "abc".into(),
+                    contract: "xyz".into(),
+                    chain: "123".into(),
+                },
+                0,
+                outcomes.clone(),
+                live_jobs.clone(),
+                admission::Admissions::default(),
+                capacity::Capacity::default(),
+            ),
+        )
+        .await;
+
+        assert!(res.is_err());
+        // both jobs ended
+        assert!(live_jobs.is_empty());
+        // and are respawned on restart
+        assert_eq!(
+            store.dispatchers.lock().unwrap().get("xyz").unwrap().jobs,
+            vec![job_1, job_2]
+        );
+        assert_eq!(
+            outcomes.get(&market::JobId {
+                id: job_1.encode_hex(),
+                operator: "abc".into(),
+                contract: "xyz".into(),
+                chain: "123".into(),
+            }),
+            Some(market::JobOutcome::Terminated)
+        );
+        assert_eq!(
+            outcomes.get(&market::JobId {
+                id: job_2.encode_hex(),
+                operator: "abc".into(),
+                contract: "xyz".into(),
+                chain: "123".into(),
+            }),
+            Some(market::JobOutcome::Terminated)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_run_once_stall() {
+        let logger = TestLogger {
+            logs: Vec::new(),
+            heads: vec![(0, 10), (30, 11), (60, 12)],
+        };
+
+        let regions: &'static [String] = Box::leak(vec!["ap-south-1".into()].into_boxed_slice());
+
+        let start_time = Instant::now();
+        let log_stream = std::pin::pin!(logger.market_stream(start_time));
+        let head_stream = std::pin::pin!(logger.head_stream(start_time));
+        let mut dispatcher = market::LogDispatcher::default();
+        let (heads, _) = watch::channel(0);
+        let live_jobs = market::LiveJobs::default();
+        let (caught_up, caught_up_rx) = watch::channel(false);
+        let job_id = market::JobId {
+            id: H256::zero().encode_hex(),
+            operator: "abc".into(),
+            contract: "xyz".into(),
+            chain: "123".into(),
+        };
+
+        // the backfill is consumed between the second and third head
+        let backfill = async {
+            sleep(Duration::from_secs(40)).await;
+            assert!(!live_jobs.is_synced(&job_id));
+            caught_up.send_replace(true);
+        };
+
+        // returns once heads stop for the stall timeout
+        let run = market::run_once(
+            log_stream,
+            head_stream,
+            vec![caught_up_rx],
+            &mut dispatcher,
+            &heads,
+            Duration::from_secs(45),
+            TestAws::default(),
+            TestStore::default(),
+            regions,
+            test::get_inputs(Vec::new(), Vec::new()),
+            job_id.clone(),
+            0,
+            market::JobOutcomes::default(),
+            live_jobs.clone(),
+            admission::Admissions::default(),
+            capacity::Capacity::default(),
+        );
+        tokio::join!(run, backfill);
+
+        assert_eq!(start_time.elapsed(), Duration::from_secs(105));
+        assert_eq!(*heads.borrow(), 12);
+        assert!(live_jobs.is_synced(&job_id));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_launch_after_confirmations() {
+        let _ = market::START.set(Instant::now());
+
+        let job_num = H256::from_low_u64_be(1);
+        let logger = TestLogger {
+            logs: vec![
+                (0, Action::Open, ("{\"region\":\"ap-south-1\",\"url\":\"https://example.com/enclave.eif\",\"instance\":\"c6a.xlarge\",\"memory\":4096,\"vcpu\":2}".to_string(),31000000000000u64,31000u64,market::now_timestamp().as_secs()).encode(), 10),
+                (400, Action::Close, [].into(), 12),
+            ].into_iter().map(|x| (x.0, test::get_log_at(x.1, Bytes::from(x.2), job_num, x.3, 0))).collect(),
+            heads: vec![(0, 10), (100, 11), (200, 12), (450, 13), (600, 14)],
+        };
+
+        let start_time = Instant::now();
+        let job_stream = std::pin::pin!(logger.job_stream(job_num, start_time));
+        let head_stream = std::pin::pin!(logger.head_stream(start_time));
+        let mut aws: TestAws = Default::default();
+        let res = market::job_manager_once(
+            job_stream,
+            head_stream,
             &mut aws,
+            TestStore::default(),
             market::JobId {
                 id: job_num.encode_hex(),
                 operator: "abc".into(),
@@ -2808,16 +4446,330 @@ mod tests {
             },
             &["ap-south-1".into()],
             300,
-            &test::get_rates(),
-            &test::get_gb_rates(),
-            &Vec::new(),
-            &Vec::new(),
+            2,
+            &test::get_inputs(Vec::new(), Vec::new()),
+            &admission::Admissions::default(),
+            &capacity::Capacity::default(),
         )
         .await;
 
         // job manager should have finished successfully
-        assert_eq!(res, 0);
+        assert_eq!(res, market::JobOutcome::Terminated);
+        println!("{:?}", aws.outcomes);
+
+        // open is applied once block 12 arrives, followed by the launch delay
+        if let TestAwsOutcome::SpinUp(out) = &aws.outcomes[0] {
+            assert_eq!((out.time - start_time).as_secs(), 500);
+            assert!(H256::from_str(&out.job).unwrap() == job_num && out.region == *"ap-south-1")
+        } else {
+            panic!();
+        };
+
+        // close is applied once block 14 arrives
+        if let TestAwsOutcome::SpinDown(out) = &aws.outcomes[2] {
+            assert_eq!((out.time - start_time).as_secs(), 600);
+            assert!(H256::from_str(&out.job).unwrap() == job_num && out.region == *"ap-south-1")
+        } else {
+            panic!();
+        };
+
+        assert!(!aws.instances.contains_key(&job_num.to_string()))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_removed_log_before_confirmations() {
+        let _ = market::START.set(Instant::now());
+
+        let job_num = H256::from_low_u64_be(1);
+        let opened = test::get_log_at(Action::Open, Bytes::from(("{\"region\":\"ap-south-1\",\"url\":\"https://example.com/enclave.eif\",\"instance\":\"c6a.xlarge\",\"memory\":4096,\"vcpu\":2}".to_string(),31000000000000u64,31000u64,market::now_timestamp().as_secs()).encode()), job_num, 10, 0);
+        let mut removed = opened.clone();
+        removed.removed = Some(true);
+        let logger = TestLogger {
+            logs: vec![(0, opened), (50, removed)],
+            heads: vec![(0, 10), (100, 11), (200, 12), (300, 13)],
+        };
+
+        let start_time = Instant::now();
+        let job_stream = std::pin::pin!(logger.job_stream(job_num, start_time));
+        let head_stream = std::pin::pin!(logger.head_stream(start_time));
+        let mut aws: TestAws = Default::default();
+        let res = tokio::time::timeout(
+            Duration::from_secs(1000),
+            market::job_manager_once(
+                job_stream,
+                head_stream,
+                &mut aws,
+                TestStore::default(),
+                market::JobId {
+                    id: job_num.encode_hex(),
+                    operator: "abc".into(),
+                    contract: "xyz".into(),
+                    chain: "123".into(),
+                },
+                &["ap-south-1".into()],
+                300,
+                2,
+                &test::get_inputs(Vec::new(), Vec::new()),
+                &admission::Admissions::default(),
+                &capacity::Capacity::default(),
+            ),
+        )
+        .await;
+
+        // job never opened on the canonical chain, nothing should be launched
+        assert!(res.is_err());
         assert!(aws.outcomes.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_removed_log_after_launch() {
+        let _ = market::START.set(Instant::now());
+
+        let job_num = H256::from_low_u64_be(1);
+        let opened = test::get_log_at(Action::Open, Bytes::from(("{\"region\":\"ap-south-1\",\"url\":\"https://example.com/enclave.eif\",\"instance\":\"c6a.xlarge\",\"memory\":4096,\"vcpu\":2}".to_string(),31000000000000u64,31000u64,market::now_timestamp().as_secs()).encode()), job_num, 10, 0);
+        let mut removed = opened.clone();
+        removed.removed = Some(true);
+        let logger = TestLogger {
+            logs: vec![(0, opened), (400, removed)],
+            heads: Vec::new(),
+        };
+
+        let start_time = Instant::now();
+        let job_stream = std::pin::pin!(logger.job_stream(job_num, start_time));
+        let head_stream = std::pin::pin!(logger.head_stream(start_time));
+        let mut aws: TestAws = Default::default();
+        let res = market::job_manager_once(
+            job_stream,
+            head_stream,
+            &mut aws,
+            TestStore::default(),
+            market::JobId {
+                id: job_num.encode_hex(),
+                operator: "abc".into(),
+                contract: "xyz".into(),
+                chain: "123".into(),
+            },
+            &["ap-south-1".into()],
+            300,
+            0,
+            &test::get_inputs(Vec::new(), Vec::new()),
+            &admission::Admissions::default(),
+            &capacity::Capacity::default(),
+        )
+        .await;
+
+        // job manager should have finished successfully
+        assert_eq!(res, market::JobOutcome::Terminated);
+        println!("{:?}", aws.outcomes);
+
+        if let TestAwsOutcome::SpinUp(out) = &aws.outcomes[0] {
+            assert_eq!((out.time - start_time).as_secs(), 300);
+        } else {
+            panic!();
+        };
+
+        // removal of the applied open reverts the job and terminates the instance
+        if let TestAwsOutcome::SpinDown(out) = &aws.outcomes[2] {
+            assert_eq!((out.time - start_time).as_secs(), 400);
+            assert!(H256::from_str(&out.job).unwrap() == job_num && out.region == *"ap-south-1")
+        } else {
+            panic!();
+        };
+
         assert!(!aws.instances.contains_key(&job_num.to_string()))
     }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_removed_log_after_restart() {
+        let _ = market::START.set(Instant::now());
+
+        let job_num = H256::from_low_u64_be(1);
+        let job_id = market::JobId {
+            id: job_num.encode_hex(),
+            operator: "abc".into(),
+            contract: "xyz".into(),
+            chain: "123".into(),
+        };
+        let deposited =
+            test::get_log_at(Action::Deposit, Bytes::from((500).encode()), job_num, 11, 0);
+        let logger = TestLogger {
+            logs: vec![
+                (0, test::get_log_at(Action::Open, Bytes::from(("{\"region\":\"ap-south-1\",\"url\":\"https://example.com/enclave.eif\",\"instance\":\"c6a.xlarge\",\"memory\":4096,\"vcpu\":2}".to_string(),31000000000000u64,31000u64,market::now_timestamp().as_secs()).encode()), job_num, 10, 0)),
+                (50, deposited.clone()),
+            ],
+            heads: Vec::new(),
+        };
+
+        let start_time = Instant::now();
+        let job_stream = std::pin::pin!(logger.job_stream(job_num, start_time));
+        let store = TestStore::default();
+        let mut aws: TestAws = Default::default();
+        // stopped before the logs are final
+        let res = tokio::time::timeout(
+            Duration::from_secs(100),
+            market::job_manager_once(
+                job_stream,
+                tokio_stream::pending(),
+                &mut aws,
+                store.clone(),
+                job_id.clone(),
+                &["ap-south-1".into()],
+                300,
+                0,
+                &test::get_inputs(Vec::new(), Vec::new()),
+                &admission::Admissions::default(),
+                &capacity::Capacity::default(),
+            ),
+        )
+        .await;
+        assert!(res.is_err());
+        let checkpoint = store.checkpoints.lock().unwrap()[&job_id.id].clone();
+        assert_eq!(checkpoint.balance, U256::from(31500u64));
+        assert_eq!(checkpoint.applied.len(), 2);
+
+        // the deposit is removed after the restart
+        let mut removed = deposited;
+        removed.removed = Some(true);
+        let logger = TestLogger {
+            logs: vec![(0, removed)],
+            heads: Vec::new(),
+        };
+        let start_time = Instant::now();
+        let job_stream = std::pin::pin!(logger.job_stream(job_num, start_time));
+        let res = tokio::time::timeout(
+            Duration::from_secs(100),
+            market::job_manager_once(
+                job_stream,
+                tokio_stream::pending(),
+                &mut aws,
+                store.clone(),
+                job_id.clone(),
+                &["ap-south-1".into()],
+                300,
+                0,
+                &test::get_inputs(Vec::new(), Vec::new()),
+                &admission::Admissions::default(),
+                &capacity::Capacity::default(),
+            ),
+        )
+        .await;
+        assert!(res.is_err());
+
+        // state is rebuilt without the deposit
+        let checkpoint = store.checkpoints.lock().unwrap()[&job_id.id].clone();
+        assert_eq!(checkpoint.balance, U256::from(31000u64));
+        assert_eq!(checkpoint.applied.len(), 1);
+        assert_eq!(checkpoint.watermark, Some((10, 0)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reorg_after_policy_reload() {
+        let _ = market::START.set(Instant::now());
+
+        let job_num = H256::from_low_u64_be(1);
+        let job_id = market::JobId {
+            id: job_num.encode_hex(),
+            operator: "abc".into(),
+            contract: "xyz".into(),
+            chain: "123".into(),
+        };
+        let deposited =
+            test::get_log_at(Action::Deposit, Bytes::from((500).encode()), job_num, 80, 0);
+        let mut removed = deposited.clone();
+        removed.removed = Some(true);
+        let logger = TestLogger {
+            logs: vec![
+                (0, test::get_log_at(Action::Open, Bytes::from(("{\"region\":\"ap-south-1\",\"url\":\"https://example.com/enclave.eif\",\"instance\":\"c6a.xlarge\",\"memory\":4096,\"vcpu\":2}".to_string(),31000000000000u64,31000u64,market::now_timestamp().as_secs()).encode()), job_num, 10, 0)),
+                (50, deposited),
+                (400, removed),
+                (500, test::get_log_at(Action::Close, Bytes::new(), job_num, 81, 0)),
+            ],
+            // the open is final once block 80 arrives
+            heads: vec![(20, 80)],
+        };
+
+        let inputs = test::get_inputs(Vec::new(), Vec::new());
+        let admissions = admission::Admissions::default();
+        let start_time = Instant::now();
+        let job_stream = std::pin::pin!(logger.job_stream(job_num, start_time));
+        let head_stream = std::pin::pin!(logger.head_stream(start_time));
+        let mut aws: TestAws = Default::default();
+
+        // policy denying every job is loaded after the job was admitted
+        let reload = async {
+            sleep(Duration::from_secs(10)).await;
+            let mut strict = (*inputs.load()).clone();
+            strict.admission = serde_json::from_str(r#"{"default": "deny"}"#).unwrap();
+            inputs.store(strict);
+        };
+        let (res, _) = tokio::join!(
+            market::job_manager_once(
+                job_stream,
+                head_stream,
+                &mut aws,
+                TestStore::default(),
+                job_id.clone(),
+                &["ap-south-1".into()],
+                300,
+                0,
+                &inputs,
+                &admissions,
+                &capacity::Capacity::default(),
+            ),
+            reload
+        );
+
+        // the job keeps its decision, the reorg does not terminate the instance
+        assert_eq!(res, market::JobOutcome::Terminated);
+        assert!(admissions.get(&job_id).unwrap().decision.allowed);
+
+        if let TestAwsOutcome::SpinUp(out) = &aws.outcomes[0] {
+            assert_eq!((out.time - start_time).as_secs(), 300);
+        } else {
+            panic!();
+        };
+
+        if let TestAwsOutcome::SpinDown(out) = &aws.outcomes[2] {
+            assert_eq!((out.time - start_time).as_secs(), 500);
+        } else {
+            panic!();
+        };
+        assert_eq!(aws.outcomes.len(), 3);
+    }
+
+    #[test]
+    fn test_log_buffer_finalize() {
+        let job = H256::from_low_u64_be(1);
+        let opened = test::get_log_at(Action::Open, Bytes::new(), job, 10, 0);
+        let deposited = test::get_log_at(Action::Deposit, Bytes::new(), job, 20, 0);
+
+        let mut buffer = market::LogBuffer::new(0);
+        assert_eq!(buffer.push(opened.clone()), vec![opened.clone()]);
+        assert_eq!(buffer.push(deposited.clone()), vec![deposited.clone()]);
+
+        // applied logs are kept until they are deeper than the reorg depth
+        buffer.set_head(10 + market::REORG_DEPTH - 1);
+        assert!(buffer.finalize().is_empty());
+        buffer.set_head(10 + market::REORG_DEPTH);
+        assert_eq!(buffer.finalize(), vec![opened.clone()]);
+        assert_eq!(buffer.applied, vec![deposited]);
+
+        // final logs are not rebuilt on anymore
+        let mut removed = opened;
+        removed.removed = Some(true);
+        assert!(!buffer.remove(&removed));
+    }
+
+    #[test]
+    fn test_job_events() {
+        let events = market::job_events();
+
+        assert_eq!(events.len(), 9);
+        assert!(events.contains(&H256::from(ethers::utils::keccak256(
+            "JobReviseRateFinalized(bytes32,uint256)"
+        ))));
+        assert!(events.contains(&H256::from(ethers::utils::keccak256(
+            "JobMetadataUpdated(bytes32,string)"
+        ))));
+    }
 }
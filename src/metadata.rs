@@ -0,0 +1,218 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+// latest metadata schema version understood by the control plane
+pub const METADATA_VERSION: u32 = 1;
+
+// Job metadata as submitted on chain in JobOpened and JobMetadataUpdated
+// Fields are optional so that every missing field can be reported at once during validation
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct JobMetadata {
+    // metadata without a version predates versioning and is treated as version 1
+    #[serde(default = "default_version")]
+    pub version: u32,
+    pub instance: Option<String>,
+    pub region: Option<String>,
+    pub memory: Option<i64>,
+    pub vcpu: Option<i64>,
+    pub url: Option<String>,
+    // salmon is used if not specified for backward compatibility
+    pub family: Option<String>,
+}
+
+fn default_version() -> u32 {
+    1
+}
+
+// Validated job requirements
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobSpec {
+    pub instance_type: String,
+    pub region: String,
+    pub req_mem: i64,
+    pub req_vcpus: i32,
+    pub eif_url: String,
+    pub family: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetadataProblem {
+    // not valid json or a field has the wrong type
+    Malformed(String),
+    UnsupportedVersion(u32),
+    Missing(&'static str),
+    Invalid { field: &'static str, reason: String },
+}
+
+impl fmt::Display for MetadataProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MetadataProblem::Malformed(err) => write!(f, "malformed metadata: {err}"),
+            MetadataProblem::UnsupportedVersion(version) => {
+                write!(f, "unsupported metadata version: {version}")
+            }
+            MetadataProblem::Missing(field) => write!(f, "{field} not set"),
+            MetadataProblem::Invalid { field, reason } => write!(f, "invalid {field}: {reason}"),
+        }
+    }
+}
+
+impl JobMetadata {
+    pub fn parse(metadata: &str) -> Result<JobSpec, Vec<MetadataProblem>> {
+        serde_json::from_str::<JobMetadata>(metadata)
+            .map_err(|err| vec![MetadataProblem::Malformed(err.to_string())])?
+            .validate()
+    }
+
+    pub fn validate(&self) -> Result<JobSpec, Vec<MetadataProblem>> {
+        if self.version == 0 || self.version > METADATA_VERSION {
+            return Err(vec![MetadataProblem::UnsupportedVersion(self.version)]);
+        }
+
+        let mut problems = Vec::new();
+
+        let instance_type = non_empty(&self.instance, "instance", &mut problems);
+        let region = non_empty(&self.region, "region", &mut problems);
+        let eif_url = non_empty(&self.url, "url", &mut problems);
+
+        let req_mem = match self.memory {
+            Some(memory) if memory > 0 => memory,
+            Some(memory) => {
+                problems.push(MetadataProblem::Invalid {
+                    field: "memory",
+                    reason: format!("{memory} is not positive"),
+                });
+                0
+            }
+            None => {
+                problems.push(MetadataProblem::Missing("memory"));
+                0
+            }
+        };
+
+        let req_vcpus = match self.vcpu {
+            Some(vcpu) if vcpu > 0 && vcpu <= i32::MAX.into() => vcpu as i32,
+            Some(vcpu) => {
+                problems.push(MetadataProblem::Invalid {
+                    field: "vcpu",
+                    reason: format!("{vcpu} is out of range"),
+                });
+                0
+            }
+            None => {
+                problems.push(MetadataProblem::Missing("vcpu"));
+                0
+            }
+        };
+
+        if let Some(family) = &self.family {
+            if family.is_empty() {
+                problems.push(MetadataProblem::Invalid {
+                    field: "family",
+                    reason: "empty".to_owned(),
+                });
+            }
+        }
+
+        if !problems.is_empty() {
+            return Err(problems);
+        }
+
+        Ok(JobSpec {
+            instance_type,
+            region,
+            req_mem,
+            req_vcpus,
+            eif_url,
+            family: self.family.clone(),
+        })
+    }
+}
+
+pub fn format_problems(problems: &[MetadataProblem]) -> String {
+    problems
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn non_empty(
+    value: &Option<String>,
+    field: &'static str,
+    problems: &mut Vec<MetadataProblem>,
+) -> String {
+    match value {
+        Some(value) if !value.is_empty() => value.clone(),
+        Some(_) => {
+            problems.push(MetadataProblem::Invalid {
+                field,
+                reason: "empty".to_owned(),
+            });
+            String::new()
+        }
+        None => {
+            problems.push(MetadataProblem::Missing(field));
+            String::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{JobMetadata, JobSpec, MetadataProblem};
+
+    #[test]
+    fn test_parse_valid() {
+        let spec = JobMetadata::parse("{\"region\":\"ap-south-1\",\"url\":\"https://example.com/enclave.eif\",\"instance\":\"c6a.xlarge\",\"memory\":4096,\"vcpu\":2,\"family\":\"tuna\"}");
+
+        assert_eq!(
+            spec,
+            Ok(JobSpec {
+                instance_type: "c6a.xlarge".to_owned(),
+                region: "ap-south-1".to_owned(),
+                req_mem: 4096,
+                req_vcpus: 2,
+                eif_url: "https://example.com/enclave.eif".to_owned(),
+                family: Some("tuna".to_owned()),
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_reports_all_problems() {
+        let problems = JobMetadata::parse("{\"region\":\"\",\"memory\":4096,\"vcpu\":4294967296}");
+
+        assert_eq!(
+            problems,
+            Err(vec![
+                MetadataProblem::Missing("instance"),
+                MetadataProblem::Invalid {
+                    field: "region",
+                    reason: "empty".to_owned()
+                },
+                MetadataProblem::Missing("url"),
+                MetadataProblem::Invalid {
+                    field: "vcpu",
+                    reason: "4294967296 is out of range".to_owned()
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_unsupported_version() {
+        let problems = JobMetadata::parse("{\"version\":2,\"region\":\"ap-south-1\",\"url\":\"https://example.com/enclave.eif\",\"instance\":\"c6a.xlarge\",\"memory\":4096,\"vcpu\":2}");
+
+        assert_eq!(problems, Err(vec![MetadataProblem::UnsupportedVersion(2)]));
+    }
+
+    #[test]
+    fn test_parse_malformed() {
+        let problems = JobMetadata::parse("{\"memory\":\"4096\"}").unwrap_err();
+
+        assert_eq!(problems.len(), 1);
+        assert!(matches!(problems[0], MetadataProblem::Malformed(_)));
+    }
+}
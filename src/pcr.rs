@@ -0,0 +1,334 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+use crate::reload::{Reloadable, Snapshot};
+
+// Nitro enclave image measurements, lowercase hex encoded sha384 digests
+// PCR0 covers the whole image, PCR1 the kernel and boot ramfs, PCR2 the application
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Pcrs {
+    pub pcr0: String,
+    pub pcr1: String,
+    pub pcr2: String,
+}
+
+// measurements section of `nitro-cli describe-eif`
+#[derive(Deserialize)]
+struct DescribeEif {
+    #[serde(rename = "Measurements")]
+    measurements: Measurements,
+}
+
+#[derive(Deserialize)]
+struct Measurements {
+    #[serde(rename = "PCR0")]
+    pcr0: String,
+    #[serde(rename = "PCR1")]
+    pcr1: String,
+    #[serde(rename = "PCR2")]
+    pcr2: String,
+}
+
+pub fn parse_describe_eif(output: &str) -> Result<Pcrs> {
+    let description: DescribeEif =
+        serde_json::from_str(output).context("failed to parse eif description")?;
+    let pcrs = Pcrs {
+        pcr0: description.measurements.pcr0.to_lowercase(),
+        pcr1: description.measurements.pcr1.to_lowercase(),
+        pcr2: description.measurements.pcr2.to_lowercase(),
+    };
+
+    for pcr in [&pcrs.pcr0, &pcrs.pcr1, &pcrs.pcr2] {
+        validate_pcr(pcr)?;
+    }
+
+    Ok(pcrs)
+}
+
+fn validate_pcr(pcr: &str) -> Result<()> {
+    if pcr.len() != 96 || !pcr.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(anyhow!("invalid pcr: {pcr}"));
+    }
+
+    Ok(())
+}
+
+// An allow or deny list entry, every pcr set has to match exactly
+// Leaving pcrs out matches any value, e.g. only pcr2 allows any build of an application
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PcrRule {
+    #[serde(default)]
+    pub pcr0: Option<String>,
+    #[serde(default)]
+    pub pcr1: Option<String>,
+    #[serde(default)]
+    pub pcr2: Option<String>,
+}
+
+impl PcrRule {
+    fn matches(&self, pcrs: &Pcrs) -> bool {
+        [
+            (&self.pcr0, &pcrs.pcr0),
+            (&self.pcr1, &pcrs.pcr1),
+            (&self.pcr2, &pcrs.pcr2),
+        ]
+        .iter()
+        .all(|(expected, actual)| expected.as_ref().is_none_or(|pcr| pcr == *actual))
+    }
+}
+
+// Images are denied if they match any deny rule
+// If there are allow rules, images also have to match one of them
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PcrPolicy {
+    pub allow: Vec<PcrRule>,
+    pub deny: Vec<PcrRule>,
+}
+
+impl PcrPolicy {
+    // empty locations leave the list empty
+    pub fn load(allowlist: &str, denylist: &str) -> Result<PcrPolicy> {
+        Ok(PcrPolicy {
+            allow: load_rules(allowlist).context("failed to load pcr allowlist")?,
+            deny: load_rules(denylist).context("failed to load pcr denylist")?,
+        })
+    }
+
+    pub fn check(&self, pcrs: &Pcrs) -> Result<()> {
+        if self.deny.iter().any(|rule| rule.matches(pcrs)) {
+            return Err(anyhow!("EIF NOT ALLOWED: pcrs are denylisted"));
+        }
+
+        if !self.allow.is_empty() && !self.allow.iter().any(|rule| rule.matches(pcrs)) {
+            return Err(anyhow!("EIF NOT ALLOWED: pcrs are not allowlisted"));
+        }
+
+        Ok(())
+    }
+}
+
+// Pcr policy along with the lists it is reloaded from
+#[derive(Clone)]
+pub struct SharedPcrPolicy {
+    allowlist: String,
+    denylist: String,
+    policy: Snapshot<PcrPolicy>,
+}
+
+impl SharedPcrPolicy {
+    pub fn load(allowlist: &str, denylist: &str) -> Result<SharedPcrPolicy> {
+        Ok(SharedPcrPolicy {
+            allowlist: allowlist.to_owned(),
+            denylist: denylist.to_owned(),
+            policy: Snapshot::new(PcrPolicy::load(allowlist, denylist)?),
+        })
+    }
+
+    pub fn check(&self, pcrs: &Pcrs) -> Result<()> {
+        self.policy.load().check(pcrs)
+    }
+}
+
+impl Reloadable for SharedPcrPolicy {
+    fn name(&self) -> String {
+        "pcr policy".to_owned()
+    }
+
+    fn files(&self) -> Vec<String> {
+        vec![self.allowlist.clone(), self.denylist.clone()]
+    }
+
+    fn reload(&self) -> Result<()> {
+        self.policy
+            .store(PcrPolicy::load(&self.allowlist, &self.denylist)?);
+        Ok(())
+    }
+}
+
+fn load_rules(location: &str) -> Result<Vec<PcrRule>> {
+    if location.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let contents = fs::read_to_string(location).context("Error reading file")?;
+    if is_legacy_list(&contents) {
+        return Err(anyhow!(
+            "{location} lists sha256 image hashes, which are no longer supported, \
+            list the pcrs of the images as json rules instead"
+        ));
+    }
+    let rules: Vec<PcrRule> = serde_json::from_str(&contents).context("failed to parse rules")?;
+
+    rules.into_iter().map(normalize_rule).collect()
+}
+
+// lists used to have one sha256 hash of an image per line
+fn is_legacy_list(contents: &str) -> bool {
+    let mut lines = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty());
+    lines.clone().next().is_some()
+        && lines.all(|line| {
+            let hash = line.trim_start_matches("0x");
+            hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit())
+        })
+}
+
+fn normalize_rule(rule: PcrRule) -> Result<PcrRule> {
+    if rule.pcr0.is_none() && rule.pcr1.is_none() && rule.pcr2.is_none() {
+        return Err(anyhow!("rules need at least one pcr"));
+    }
+
+    let normalize = |pcr: Option<String>| -> Result<Option<String>> {
+        pcr.map(|pcr| {
+            let pcr = pcr.trim_start_matches("0x").to_lowercase();
+            validate_pcr(&pcr)?;
+            Ok(pcr)
+        })
+        .transpose()
+    };
+
+    Ok(PcrRule {
+        pcr0: normalize(rule.pcr0)?,
+        pcr1: normalize(rule.pcr1)?,
+        pcr2: normalize(rule.pcr2)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load_rules, normalize_rule, parse_describe_eif, PcrPolicy, PcrRule, Pcrs};
+
+    fn pcrs(pcr0: char, pcr1: char, pcr2: char) -> Pcrs {
+        Pcrs {
+            pcr0: pcr0.to_string().repeat(96),
+            pcr1: pcr1.to_string().repeat(96),
+            pcr2: pcr2.to_string().repeat(96),
+        }
+    }
+
+    fn rule(pcr0: Option<char>, pcr1: Option<char>, pcr2: Option<char>) -> PcrRule {
+        PcrRule {
+            pcr0: pcr0.map(|c| c.to_string().repeat(96)),
+            pcr1: pcr1.map(|c| c.to_string().repeat(96)),
+            pcr2: pcr2.map(|c| c.to_string().repeat(96)),
+        }
+    }
+
+    #[test]
+    fn test_parse_describe_eif() -> anyhow::Result<()> {
+        let output = format!(
+            r#"{{
+                "EifVersion": 4,
+                "Measurements": {{
+                    "HashAlgorithm": "Sha384 {{ ... }}",
+                    "PCR0": "{}",
+                    "PCR1": "{}",
+                    "PCR2": "{}"
+                }},
+                "IsSigned": false
+            }}"#,
+            "A".repeat(96),
+            "b".repeat(96),
+            "c".repeat(96)
+        );
+
+        assert_eq!(parse_describe_eif(&output)?, pcrs('a', 'b', 'c'));
+
+        assert!(parse_describe_eif("Error: file not found").is_err());
+        assert!(parse_describe_eif(
+            r#"{"Measurements": {"PCR0": "abc", "PCR1": "abc", "PCR2": "abc"}}"#
+        )
+        .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_policy() {
+        let image = pcrs('a', 'b', 'c');
+
+        // no rules allow everything
+        assert!(PcrPolicy::default().check(&image).is_ok());
+
+        let policy = PcrPolicy {
+            allow: vec![rule(Some('a'), Some('b'), Some('c'))],
+            deny: vec![],
+        };
+        assert!(policy.check(&image).is_ok());
+        assert!(policy.check(&pcrs('a', 'b', 'd')).is_err());
+
+        // missing pcrs match anything
+        let policy = PcrPolicy {
+            allow: vec![rule(None, None, Some('c'))],
+            deny: vec![],
+        };
+        assert!(policy.check(&image).is_ok());
+        assert!(policy.check(&pcrs('d', 'd', 'c')).is_ok());
+        assert!(policy.check(&pcrs('a', 'b', 'd')).is_err());
+
+        // deny wins over allow
+        let policy = PcrPolicy {
+            allow: vec![rule(None, None, Some('c'))],
+            deny: vec![rule(Some('a'), None, None)],
+        };
+        assert!(policy.check(&image).is_err());
+        assert!(policy.check(&pcrs('d', 'b', 'c')).is_ok());
+    }
+
+    #[test]
+    fn test_exact_matching() {
+        // prefixes or substrings of a pcr do not match
+        let mut partial = rule(Some('a'), None, None);
+        partial.pcr0 = Some("a".repeat(48));
+        let policy = PcrPolicy {
+            allow: vec![partial],
+            deny: vec![],
+        };
+        assert!(policy.check(&pcrs('a', 'b', 'c')).is_err());
+    }
+
+    #[test]
+    fn test_normalize_rule() {
+        let rule = normalize_rule(PcrRule {
+            pcr0: Some("0x".to_owned() + &"A".repeat(96)),
+            pcr1: None,
+            pcr2: None,
+        })
+        .unwrap();
+        assert_eq!(rule.pcr0, Some("a".repeat(96)));
+
+        assert!(normalize_rule(PcrRule {
+            pcr0: None,
+            pcr1: None,
+            pcr2: None,
+        })
+        .is_err());
+        assert!(normalize_rule(PcrRule {
+            pcr0: Some("abc".to_owned()),
+            pcr1: None,
+            pcr2: None,
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_load_rules_legacy() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!("cp-pcr-rules-{}.json", std::process::id()));
+        let location = path.to_string_lossy().into_owned();
+
+        // sha256 lines of the old format fail with a migration hint
+        std::fs::write(&path, format!("{}\n0x{}\n", "a".repeat(64), "b".repeat(64)))?;
+        let err = load_rules(&location).unwrap_err();
+        assert!(err.to_string().contains("no longer supported"));
+
+        std::fs::write(&path, format!(r#"[{{"pcr2": "{}"}}]"#, "c".repeat(96)))?;
+        assert_eq!(load_rules(&location)?, vec![rule(None, None, Some('c'))]);
+
+        std::fs::remove_file(path)?;
+
+        Ok(())
+    }
+}
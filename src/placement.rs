@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::market::JobId;
+
+// Subnet tagged for oyster and the availability zone it is in
+#[derive(Debug, Clone, PartialEq)]
+pub struct Subnet {
+    pub id: String,
+    pub zone: String,
+}
+
+// Only subnets in zones offering the instance type can be launched into
+// Subnets in the zone the job was placed in before are tried first, the rest keep their order
+pub fn candidate_subnets(
+    subnets: &[Subnet],
+    offered_zones: &[String],
+    preferred_zone: Option<&str>,
+) -> Vec<Subnet> {
+    let (mut preferred, rest): (Vec<Subnet>, Vec<Subnet>) = subnets
+        .iter()
+        .filter(|subnet| offered_zones.contains(&subnet.zone))
+        .cloned()
+        .partition(|subnet| Some(subnet.zone.as_str()) == preferred_zone);
+
+    preferred.extend(rest);
+    preferred
+}
+
+// errors on which the launch is retried in the next zone
+const CAPACITY_ERRORS: [&str; 4] = [
+    "InsufficientInstanceCapacity",
+    "InsufficientHostCapacity",
+    "InsufficientCapacity",
+    // instance type not supported in the zone
+    "Unsupported",
+];
+
+pub fn is_capacity_error(code: Option<&str>) -> bool {
+    code.is_some_and(|code| CAPACITY_ERRORS.contains(&code))
+}
+
+// Zone each job was last placed in, so relaunches land in the same zone
+#[derive(Clone, Default)]
+pub struct JobZones(Arc<Mutex<HashMap<JobId, String>>>);
+
+impl JobZones {
+    pub fn get(&self, job: &JobId) -> Option<String> {
+        self.0.lock().unwrap().get(job).cloned()
+    }
+
+    pub fn set(&self, job: &JobId, zone: &str) {
+        self.0.lock().unwrap().insert(job.clone(), zone.to_owned());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{candidate_subnets, is_capacity_error, Subnet};
+
+    fn subnet(id: &str, zone: &str) -> Subnet {
+        Subnet {
+            id: id.to_owned(),
+            zone: zone.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_candidate_subnets() {
+        let subnets = vec![
+            subnet("subnet-a", "ap-south-1a"),
+            subnet("subnet-b", "ap-south-1b"),
+            subnet("subnet-c", "ap-south-1c"),
+            subnet("subnet-c2", "ap-south-1c"),
+        ];
+        let offered = vec!["ap-south-1b".to_owned(), "ap-south-1c".to_owned()];
+
+        // zones not offering the instance type are skipped
+        assert_eq!(
+            candidate_subnets(&subnets, &offered, None),
+            vec![
+                subnet("subnet-b", "ap-south-1b"),
+                subnet("subnet-c", "ap-south-1c"),
+                subnet("subnet-c2", "ap-south-1c"),
+            ]
+        );
+
+        // the previous zone of the job goes first
+        assert_eq!(
+            candidate_subnets(&subnets, &offered, Some("ap-south-1c")),
+            vec![
+                subnet("subnet-c", "ap-south-1c"),
+                subnet("subnet-c2", "ap-south-1c"),
+                subnet("subnet-b", "ap-south-1b"),
+            ]
+        );
+
+        // a previous zone no longer offering the instance type is not used
+        assert_eq!(
+            candidate_subnets(&subnets, &offered, Some("ap-south-1a")),
+            vec![
+                subnet("subnet-b", "ap-south-1b"),
+                subnet("subnet-c", "ap-south-1c"),
+                subnet("subnet-c2", "ap-south-1c"),
+            ]
+        );
+
+        assert!(candidate_subnets(&subnets, &[], None).is_empty());
+    }
+
+    #[test]
+    fn test_is_capacity_error() {
+        assert!(is_capacity_error(Some("InsufficientInstanceCapacity")));
+        assert!(is_capacity_error(Some("Unsupported")));
+        assert!(!is_capacity_error(Some("UnauthorizedOperation")));
+        assert!(!is_capacity_error(None));
+    }
+}
@@ -0,0 +1,364 @@
+use anyhow::{Context, Result};
+use ethers::prelude::*;
+use ethers::providers::{Http, Provider};
+use ethers::types::Log;
+use tokio::sync::{mpsc, watch};
+use tokio::time::{sleep, Duration};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tokio_stream::Stream;
+
+use crate::market::{is_provider_log, market_filter, LogsProvider, LOGS_PAGE_SIZE};
+
+// LogsProvider for rpc endpoints without websocket support
+// Logs are fetched with eth_getLogs and heads with eth_blockNumber, both once every poll interval
+// eth_getLogs never reports logs removed by reorgs, so logs are only fetched once they have
+// enough confirmations instead of being held back by the job managers
+#[derive(Clone)]
+pub struct PollingProvider {
+    pub contract: Address,
+    pub provider: Address,
+    pub poll_interval: Duration,
+    // blocks behind the head logs are fetched up to
+    pub confirmations: u64,
+}
+
+impl LogsProvider for PollingProvider {
+    type Client = Provider<Http>;
+
+    async fn connect<'a>(&'a self, url: &'a str) -> Result<Provider<Http>> {
+        Provider::<Http>::try_from(url).context("failed to parse rpc url")
+    }
+
+    async fn market_logs<'a>(
+        &'a self,
+        client: &'a Provider<Http>,
+        from_block: u64,
+        caught_up: watch::Sender<bool>,
+    ) -> Result<impl Stream<Item = Log> + Send + 'a> {
+        // fail early if the endpoint is not reachable, later errors end the stream
+        client
+            .get_block_number()
+            .await
+            .context("failed to fetch head block")?;
+
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(poll_logs(
+            client.clone(),
+            self.contract,
+            self.provider,
+            from_block,
+            self.poll_interval,
+            self.confirmations,
+            sender,
+        ));
+
+        // None marks the end of the first poll, set once every log before it was taken
+        Ok(
+            UnboundedReceiverStream::new(receiver).filter_map(move |log| {
+                if log.is_none() {
+                    caught_up.send_replace(true);
+                }
+                std::future::ready(log)
+            }),
+        )
+    }
+
+    async fn new_heads<'a>(
+        &'a self,
+        client: &'a Provider<Http>,
+    ) -> Result<impl Stream<Item = u64> + Send + 'a> {
+        client
+            .get_block_number()
+            .await
+            .context("failed to fetch head block")?;
+
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(poll_heads(client.clone(), self.poll_interval, sender));
+
+        Ok(UnboundedReceiverStream::new(receiver))
+    }
+}
+
+// polls until the stream is dropped or a request fails
+// dropping the sender ends the stream which makes the caller reconnect
+async fn poll_logs(
+    client: Provider<Http>,
+    contract: Address,
+    provider: Address,
+    from_block: u64,
+    poll_interval: Duration,
+    confirmations: u64,
+    sender: mpsc::UnboundedSender<Option<Log>>,
+) {
+    let event_filter = market_filter(contract);
+    let mut start = from_block;
+    let mut caught_up = false;
+
+    loop {
+        let head = match client.get_block_number().await {
+            Ok(head) => head.as_u64().saturating_sub(confirmations),
+            Err(err) => {
+                println!("main: Failed to fetch head block: {err:?}");
+                return;
+            }
+        };
+
+        // page through everything confirmed since the last poll
+        while start <= head {
+            let end = head.min(start + LOGS_PAGE_SIZE - 1);
+            let page = match client
+                .get_logs(&event_filter.clone().from_block(start).to_block(end))
+                .await
+            {
+                Ok(page) => page,
+                Err(err) => {
+                    println!("main: Failed to fetch logs: {err:?}");
+                    return;
+                }
+            };
+
+            for log in page {
+                if is_provider_log(&log, provider) && sender.send(Some(log)).is_err() {
+                    return;
+                }
+            }
+            start = end + 1;
+        }
+
+        if !caught_up {
+            caught_up = true;
+            if sender.send(None).is_err() {
+                return;
+            }
+        }
+
+        tokio::select! {
+            () = sender.closed() => return,
+            () = sleep(poll_interval) => {}
+        }
+    }
+}
+
+async fn poll_heads(
+    client: Provider<Http>,
+    poll_interval: Duration,
+    sender: mpsc::UnboundedSender<u64>,
+) {
+    let mut last = 0;
+
+    loop {
+        let head = match client.get_block_number().await {
+            Ok(head) => head.as_u64(),
+            Err(err) => {
+                println!("main: Failed to fetch head block: {err:?}");
+                return;
+            }
+        };
+
+        if head > last {
+            last = head;
+            if sender.send(head).is_err() {
+                return;
+            }
+        }
+
+        tokio::select! {
+            () = sender.closed() => return,
+            () = sleep(poll_interval) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{extract::State, routing::post, Json, Router};
+    use ethers::prelude::*;
+    use serde_json::{json, Value};
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+    use tokio::sync::watch;
+    use tokio::time::{timeout, Duration};
+
+    use super::PollingProvider;
+    use crate::market::LogsProvider;
+    use crate::test::{self, Action};
+
+    // Minimal JSON-RPC endpoint serving eth_blockNumber and eth_getLogs
+    #[derive(Clone, Default)]
+    struct MockChain {
+        head: Arc<Mutex<u64>>,
+        logs: Arc<Mutex<Vec<Log>>>,
+        // (from block, to block) of every eth_getLogs call
+        ranges: Arc<Mutex<Vec<(u64, u64)>>>,
+    }
+
+    fn parse_block(value: &Value) -> u64 {
+        u64::from_str_radix(value.as_str().unwrap().trim_start_matches("0x"), 16).unwrap()
+    }
+
+    async fn handle_rpc(State(chain): State<MockChain>, Json(request): Json<Value>) -> Json<Value> {
+        let result = match request["method"].as_str().unwrap() {
+            "eth_blockNumber" => json!(format!("{:#x}", *chain.head.lock().unwrap())),
+            "eth_getLogs" => {
+                let filter = &request["params"][0];
+                let from = parse_block(&filter["fromBlock"]);
+                let to = parse_block(&filter["toBlock"]);
+                chain.ranges.lock().unwrap().push((from, to));
+
+                let logs: Vec<Log> = chain
+                    .logs
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .filter(|log| (from..=to).contains(&log.block_number.unwrap().as_u64()))
+                    .cloned()
+                    .collect();
+                json!(logs)
+            }
+            method => panic!("unexpected method: {method}"),
+        };
+
+        Json(json!({"jsonrpc": "2.0", "id": request["id"], "result": result}))
+    }
+
+    // returns the url of the endpoint, bound to a free port so parallel tests never collide
+    fn serve(chain: MockChain) -> String {
+        let router = Router::new().route("/", post(handle_rpc)).with_state(chain);
+        let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .serve(router.into_make_service());
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+
+        url
+    }
+
+    fn polling_provider(provider: Address, confirmations: u64) -> PollingProvider {
+        PollingProvider {
+            contract: Address::zero(),
+            provider,
+            poll_interval: Duration::from_millis(10),
+            confirmations,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_market_logs_paging() -> anyhow::Result<()> {
+        let job_1 = H256::from_low_u64_be(1);
+        let job_2 = H256::from_low_u64_be(2);
+        let job_3 = H256::from_low_u64_be(3);
+
+        let open_1 = test::get_log_at(Action::Open, Bytes::new(), job_1, 10, 0);
+        let deposit_1 = test::get_log_at(Action::Deposit, Bytes::new(), job_1, 2500, 0);
+        let open_2 = test::get_log_at(Action::Open, Bytes::new(), job_2, 4200, 0);
+        let close_1 = test::get_log_at(Action::Close, Bytes::new(), job_1, 4600, 0);
+
+        // job opened for another provider
+        let mut open_3 = test::get_log_at(Action::Open, Bytes::new(), job_3, 4300, 0);
+        open_3.topics[3] = H256::from_low_u64_be(0xdead);
+
+        // indexed provider topic of the test logs
+        let provider = Address::from_low_u64_be(open_1.address.to_low_u64_be());
+
+        let chain = MockChain::default();
+        *chain.head.lock().unwrap() = 4500;
+        *chain.logs.lock().unwrap() =
+            vec![open_1.clone(), deposit_1.clone(), open_2.clone(), open_3];
+        let url = serve(chain.clone());
+
+        let logs_provider = polling_provider(provider, 0);
+        let client = logs_provider.connect(&url).await?;
+        let (caught_up, caught_up_rx) = watch::channel(false);
+        let mut stream = Box::pin(logs_provider.market_logs(&client, 0, caught_up).await?);
+
+        for log in [open_1, deposit_1, open_2] {
+            let next = timeout(Duration::from_secs(5), stream.next()).await?;
+            assert_eq!(next, Some(log));
+        }
+        // set once the stream moves past the first poll
+        assert!(!*caught_up_rx.borrow());
+        assert_eq!(
+            chain.ranges.lock().unwrap()[..],
+            [(0, 1999), (2000, 3999), (4000, 4500)]
+        );
+
+        // new logs are picked up on the next poll without refetching old blocks
+        chain.logs.lock().unwrap().push(close_1.clone());
+        *chain.head.lock().unwrap() = 4600;
+
+        let next = timeout(Duration::from_secs(5), stream.next()).await?;
+        assert_eq!(next, Some(close_1));
+        assert!(*caught_up_rx.borrow());
+        assert_eq!(chain.ranges.lock().unwrap()[3..], [(4501, 4600)]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_market_logs_confirmations() -> anyhow::Result<()> {
+        let job = H256::from_low_u64_be(1);
+        let open = test::get_log_at(Action::Open, Bytes::new(), job, 10, 0);
+        let deposit = test::get_log_at(Action::Deposit, Bytes::new(), job, 95, 0);
+        let provider = Address::from_low_u64_be(open.address.to_low_u64_be());
+
+        let chain = MockChain::default();
+        *chain.head.lock().unwrap() = 100;
+        *chain.logs.lock().unwrap() = vec![open.clone(), deposit.clone()];
+        let url = serve(chain.clone());
+
+        let logs_provider = polling_provider(provider, 10);
+        let client = logs_provider.connect(&url).await?;
+        let (caught_up, _) = watch::channel(false);
+        let mut stream = Box::pin(logs_provider.market_logs(&client, 0, caught_up).await?);
+
+        // logs are only fetched once they have enough confirmations
+        let next = timeout(Duration::from_secs(5), stream.next()).await?;
+        assert_eq!(next, Some(open));
+        assert!(timeout(Duration::from_millis(100), stream.next())
+            .await
+            .is_err());
+        assert_eq!(chain.ranges.lock().unwrap()[0], (0, 90));
+
+        *chain.head.lock().unwrap() = 105;
+        let next = timeout(Duration::from_secs(5), stream.next()).await?;
+        assert_eq!(next, Some(deposit));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_new_heads() -> anyhow::Result<()> {
+        let chain = MockChain::default();
+        *chain.head.lock().unwrap() = 100;
+        let url = serve(chain.clone());
+
+        let logs_provider = polling_provider(Address::zero(), 0);
+        let client = logs_provider.connect(&url).await?;
+        let mut stream = Box::pin(logs_provider.new_heads(&client).await?);
+
+        let next = timeout(Duration::from_secs(5), stream.next()).await?;
+        assert_eq!(next, Some(100));
+
+        *chain.head.lock().unwrap() = 102;
+        let next = timeout(Duration::from_secs(5), stream.next()).await?;
+        assert_eq!(next, Some(102));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_endpoint() -> anyhow::Result<()> {
+        // port that was free a moment ago, nothing listens on it
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+        let logs_provider = polling_provider(Address::zero(), 0);
+        let client = logs_provider.connect(&format!("http://{addr}")).await?;
+
+        let (caught_up, _) = watch::channel(false);
+        assert!(logs_provider
+            .market_logs(&client, 0, caught_up)
+            .await
+            .is_err());
+        assert!(logs_provider.new_heads(&client).await.is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,404 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use ethers::providers::StreamExt;
+use ethers::types::{Address, Bytes, Log, H256, U256, U64};
+use tokio_stream::{Stream, StreamMap};
+
+use crate::market::REORG_DEPTH;
+
+// Everything a provider reports about a log, providers only agree if all of it matches
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct LogKey {
+    address: Address,
+    topics: Vec<H256>,
+    data: Bytes,
+    block_hash: Option<H256>,
+    block_number: Option<U64>,
+    transaction_hash: Option<H256>,
+    log_index: Option<U256>,
+    removed: Option<bool>,
+}
+
+impl From<&Log> for LogKey {
+    fn from(log: &Log) -> Self {
+        LogKey {
+            address: log.address,
+            topics: log.topics.clone(),
+            data: log.data.clone(),
+            block_hash: log.block_hash,
+            block_number: log.block_number,
+            transaction_hash: log.transaction_hash,
+            log_index: log.log_index,
+            removed: log.removed,
+        }
+    }
+}
+
+impl LogKey {
+    fn position(&self) -> Option<(u64, u64)> {
+        Some((self.block_number?.as_u64(), self.log_index?.as_u64()))
+    }
+
+    fn removed(&self) -> bool {
+        self.removed.unwrap_or(false)
+    }
+}
+
+// whether the logs only differ in being removed
+fn same_log(a: &Log, b: &Log) -> bool {
+    LogKey {
+        removed: None,
+        ..a.into()
+    } == LogKey {
+        removed: None,
+        ..b.into()
+    }
+}
+
+// position right before the given one, none if it is the very first
+fn before((block, index): (u64, u64)) -> Option<(u64, u64)> {
+    match (block, index) {
+        (0, 0) => None,
+        (block, 0) => Some((block - 1, u64::MAX)),
+        (block, index) => Some((block, index - 1)),
+    }
+}
+
+// Counts the providers reporting each log
+// Logs reaching the threshold are held back while an earlier log can still reach it, so they are
+// emitted in chain order even if providers report at a different pace
+// Votes are dropped once the log can no longer reach the threshold or is REORG_DEPTH blocks
+// behind the logs agreed on
+pub struct LogQuorum {
+    threshold: usize,
+    providers: usize,
+    votes: HashMap<LogKey, HashSet<usize>>,
+    // last position reported by each provider, providers report logs in order
+    reported: Vec<Option<(u64, u64)>>,
+    // logs that reached the threshold, waiting for earlier logs
+    ready: BTreeMap<(u64, u64), Log>,
+    // highest block of the logs that reached the threshold
+    agreed: u64,
+}
+
+impl LogQuorum {
+    pub fn new(threshold: usize, providers: usize) -> LogQuorum {
+        LogQuorum {
+            threshold,
+            providers,
+            votes: HashMap::new(),
+            reported: vec![None; providers],
+            ready: BTreeMap::new(),
+            agreed: 0,
+        }
+    }
+
+    // returns the logs that can be emitted now, every log is only returned once it is reported by
+    // exactly threshold providers
+    // removals and logs without a position are returned right away
+    pub fn vote(&mut self, provider: usize, log: Log) -> Vec<Log> {
+        let key = LogKey::from(&log);
+        let position = key.position();
+        let removed = key.removed();
+        if let Some(position) = position {
+            let reported = &mut self.reported[provider];
+            if !removed {
+                *reported = (*reported).max(Some(position));
+            } else if reported.is_some_and(|reported| position <= reported) {
+                // the new canonical logs after the reorg are reported from here again
+                *reported = before(position);
+            }
+        }
+
+        let voters = self.votes.entry(key).or_default();
+        if !voters.insert(provider) {
+            // same provider repeating itself
+            return Vec::new();
+        }
+
+        let count = voters.len();
+        if count == self.providers {
+            // nobody left to vote
+            self.votes.remove(&LogKey::from(&log));
+        }
+
+        let mut logs = Vec::new();
+        if count == self.threshold {
+            match position {
+                Some(position) if !removed => {
+                    self.agreed = self.agreed.max(position.0);
+                    self.ready.insert(position, log);
+                }
+                Some(position)
+                    if self
+                        .ready
+                        .get(&position)
+                        .is_some_and(|ready| same_log(ready, &log)) =>
+                {
+                    // removed before it was emitted, neither is emitted then
+                    self.ready.remove(&position);
+                }
+                _ => logs.push(log),
+            }
+        }
+
+        self.evict();
+        logs.extend(self.release());
+
+        logs
+    }
+
+    // whether enough providers are left that have not voted for the log and are not past it yet
+    fn can_reach_threshold(&self, position: (u64, u64), voters: &HashSet<usize>) -> bool {
+        let left = self
+            .reported
+            .iter()
+            .enumerate()
+            .filter(|(provider, reported)| {
+                !voters.contains(provider) && !reported.is_some_and(|reported| reported >= position)
+            })
+            .count();
+
+        voters.len() + left >= self.threshold
+    }
+
+    fn evict(&mut self) {
+        let finalized = self.agreed.saturating_sub(REORG_DEPTH);
+        let dead: Vec<LogKey> = self
+            .votes
+            .iter()
+            .filter(|(key, voters)| match key.position() {
+                Some(position) if position.0 < finalized => true,
+                // reorgs report removals out of order, so only finality drops them
+                Some(position) if !key.removed() && voters.len() < self.threshold => {
+                    !self.can_reach_threshold(position, voters)
+                }
+                _ => false,
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in dead {
+            self.votes.remove(&key);
+        }
+    }
+
+    // ready logs before the first log still waiting for votes, in order
+    fn release(&mut self) -> Vec<Log> {
+        let waiting = self
+            .votes
+            .iter()
+            .filter(|(key, voters)| !key.removed() && voters.len() < self.threshold)
+            .filter_map(|(key, _)| key.position())
+            .min();
+
+        let mut logs = Vec::new();
+        while let Some(entry) = self.ready.first_entry() {
+            if waiting.is_some_and(|waiting| waiting < *entry.key()) {
+                break;
+            }
+            logs.push(entry.remove());
+        }
+
+        logs
+    }
+}
+
+// Tracks the latest head of each provider
+pub struct HeadQuorum {
+    threshold: usize,
+    heads: Vec<u64>,
+    last: u64,
+}
+
+impl HeadQuorum {
+    pub fn new(threshold: usize, providers: usize) -> HeadQuorum {
+        HeadQuorum {
+            threshold,
+            heads: vec![0; providers],
+            last: 0,
+        }
+    }
+
+    // returns the highest head reached by at least threshold providers if it moved forward
+    pub fn vote(&mut self, provider: usize, head: u64) -> Option<u64> {
+        self.heads[provider] = self.heads[provider].max(head);
+
+        let mut heads = self.heads.clone();
+        heads.sort_unstable_by(|a, b| b.cmp(a));
+        let head = heads[self.threshold - 1];
+        if head <= self.last {
+            return None;
+        }
+        self.last = head;
+
+        Some(head)
+    }
+}
+
+// merges the log streams of all providers, emitting logs in chain order once threshold of them agree
+pub fn quorum_logs<'a>(
+    streams: Vec<impl Stream<Item = Log> + Send + 'a>,
+    threshold: usize,
+) -> impl Stream<Item = Log> + Send + 'a {
+    let mut quorum = LogQuorum::new(threshold, streams.len());
+    let mut merged = StreamMap::new();
+    for (provider, stream) in streams.into_iter().enumerate() {
+        merged.insert(provider, Box::pin(stream));
+    }
+
+    merged.flat_map(move |(provider, log)| tokio_stream::iter(quorum.vote(provider, log)))
+}
+
+pub fn quorum_heads<'a>(
+    streams: Vec<impl Stream<Item = u64> + Send + 'a>,
+    threshold: usize,
+) -> impl Stream<Item = u64> + Send + 'a {
+    let mut quorum = HeadQuorum::new(threshold, streams.len());
+    let mut merged = StreamMap::new();
+    for (provider, stream) in streams.into_iter().enumerate() {
+        merged.insert(provider, Box::pin(stream));
+    }
+
+    merged.filter_map(move |(provider, head)| std::future::ready(quorum.vote(provider, head)))
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::{Bytes, H256};
+    use tokio_stream::StreamExt;
+
+    use super::{quorum_heads, quorum_logs, HeadQuorum, LogQuorum};
+    use crate::test::{self, Action};
+
+    #[test]
+    fn test_log_quorum() {
+        let mut quorum = LogQuorum::new(2, 3);
+
+        let job = H256::from_low_u64_be(1);
+        let log = test::get_log_at(Action::Close, Bytes::new(), job, 10, 0);
+
+        assert_eq!(quorum.vote(0, log.clone()), vec![]);
+        // repeated reports from the same provider do not count
+        assert_eq!(quorum.vote(0, log.clone()), vec![]);
+        assert_eq!(quorum.vote(1, log.clone()), vec![log.clone()]);
+        // already emitted
+        assert_eq!(quorum.vote(2, log.clone()), vec![]);
+
+        // a log reported by a single provider is never applied
+        let forged = test::get_log_at(Action::Close, Bytes::new(), job, 10, 1);
+        assert_eq!(quorum.vote(2, forged.clone()), vec![]);
+        assert_eq!(quorum.vote(2, forged), vec![]);
+
+        // providers disagreeing on any field do not agree on the log
+        let mut removed = log.clone();
+        removed.removed = Some(true);
+        assert_eq!(quorum.vote(0, removed.clone()), vec![]);
+        assert_eq!(quorum.vote(1, log), vec![]);
+        assert_eq!(quorum.vote(2, removed.clone()), vec![removed]);
+    }
+
+    #[test]
+    fn test_log_quorum_order() {
+        let mut quorum = LogQuorum::new(2, 3);
+
+        let job = H256::from_low_u64_be(1);
+        let open = test::get_log_at(Action::Open, Bytes::new(), job, 20, 0);
+        let close = test::get_log_at(Action::Close, Bytes::new(), job, 21, 0);
+
+        assert_eq!(quorum.vote(0, open.clone()), vec![]);
+        assert_eq!(quorum.vote(0, close.clone()), vec![]);
+        // held back while the earlier log can still reach the threshold
+        assert_eq!(quorum.vote(1, close.clone()), vec![]);
+        assert_eq!(
+            quorum.vote(2, open.clone()),
+            vec![open.clone(), close.clone()]
+        );
+        assert_eq!(quorum.vote(2, close), vec![]);
+        assert!(quorum.votes.is_empty());
+
+        // a log that can no longer reach the threshold stops holding back later logs
+        let forged = test::get_log_at(Action::Close, Bytes::new(), job, 22, 0);
+        let settle = test::get_log_at(Action::Settle, Bytes::new(), job, 23, 0);
+        assert_eq!(quorum.vote(0, forged), vec![]);
+        assert_eq!(quorum.vote(1, settle.clone()), vec![]);
+        assert_eq!(quorum.vote(2, settle.clone()), vec![settle]);
+        assert_eq!(quorum.votes.len(), 0);
+
+        // a log removed before it is emitted is not emitted at all
+        let deposit = test::get_log_at(Action::Deposit, Bytes::new(), job, 30, 0);
+        let mut removed = deposit.clone();
+        removed.removed = Some(true);
+        let withdraw = test::get_log_at(Action::Withdraw, Bytes::new(), job, 31, 0);
+        assert_eq!(quorum.vote(0, withdraw.clone()), vec![]);
+        assert_eq!(quorum.vote(1, deposit.clone()), vec![]);
+        assert_eq!(quorum.vote(2, deposit), vec![]);
+        assert_eq!(quorum.vote(1, removed.clone()), vec![]);
+        assert_eq!(quorum.vote(2, removed), vec![]);
+        assert_eq!(quorum.vote(1, withdraw.clone()), vec![withdraw]);
+    }
+
+    #[test]
+    fn test_log_quorum_eviction() {
+        let mut quorum = LogQuorum::new(2, 3);
+
+        let job = H256::from_low_u64_be(1);
+        let forged = test::get_log_at(Action::Close, Bytes::new(), job, 10, 0);
+        assert_eq!(quorum.vote(0, forged), vec![]);
+        assert_eq!(quorum.votes.len(), 1);
+
+        // votes are dropped once they are final behind the agreed logs
+        let open = test::get_log_at(Action::Open, Bytes::new(), job, 100, 0);
+        assert_eq!(quorum.vote(0, open.clone()), vec![]);
+        // the forged log was alive, other providers had not reported anything yet
+        assert_eq!(quorum.votes.len(), 2);
+        assert_eq!(quorum.vote(1, open.clone()), vec![open]);
+        assert_eq!(quorum.votes.len(), 1);
+    }
+
+    #[test]
+    fn test_head_quorum() {
+        let mut quorum = HeadQuorum::new(2, 3);
+
+        assert_eq!(quorum.vote(0, 10), None);
+        assert_eq!(quorum.vote(1, 12), Some(10));
+        // a provider running ahead alone does not move the head
+        assert_eq!(quorum.vote(2, 100), Some(12));
+        assert_eq!(quorum.vote(0, 11), None);
+        assert_eq!(quorum.vote(0, 20), Some(20));
+        // heads never go back
+        assert_eq!(quorum.vote(1, 5), None);
+    }
+
+    #[tokio::test]
+    async fn test_quorum_streams() {
+        let job = H256::from_low_u64_be(1);
+        let open = test::get_log_at(Action::Open, Bytes::new(), job, 10, 0);
+        let close = test::get_log_at(Action::Close, Bytes::new(), job, 11, 0);
+        let forged = test::get_log_at(Action::Close, Bytes::new(), job, 10, 1);
+
+        let logs = quorum_logs(
+            vec![
+                tokio_stream::iter(vec![open.clone(), close.clone()]),
+                tokio_stream::iter(vec![open.clone(), forged]),
+                tokio_stream::iter(vec![close.clone()]),
+            ],
+            2,
+        )
+        .collect::<Vec<_>>()
+        .await;
+        // streams are interleaved arbitrarily, logs still come out in chain order
+        assert_eq!(logs, vec![open, close]);
+
+        let heads = quorum_heads(
+            vec![
+                tokio_stream::iter(vec![10, 11, 12]),
+                tokio_stream::iter(vec![10, 11]),
+                tokio_stream::iter(vec![1000]),
+            ],
+            2,
+        )
+        .collect::<Vec<_>>()
+        .await;
+        assert_eq!(heads.last(), Some(&12));
+    }
+}
@@ -0,0 +1,322 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Context, Result};
+use tokio::time::{sleep, Duration, Instant};
+
+// How long and how often instances are checked after launch
+#[derive(Clone, Copy, Debug)]
+pub struct ReadinessConfig {
+    // from launch until ssh is reachable
+    pub deadline: Duration,
+    // between instance state and status checks
+    pub poll_interval: Duration,
+    // first wait after a failed ssh probe, doubled on every failure up to the max
+    pub ssh_backoff: Duration,
+    pub ssh_backoff_max: Duration,
+}
+
+impl Default for ReadinessConfig {
+    fn default() -> Self {
+        ReadinessConfig {
+            deadline: Duration::from_secs(600),
+            poll_interval: Duration::from_secs(5),
+            ssh_backoff: Duration::from_secs(2),
+            ssh_backoff_max: Duration::from_secs(30),
+        }
+    }
+}
+
+// Summary of the describe_instance_status reachability checks
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Reachability {
+    Ok,
+    Initializing,
+    Impaired,
+}
+
+pub trait ReadinessProbe {
+    fn instance_state(&self) -> impl Future<Output = Result<String>> + Send;
+    fn reachability(&self) -> impl Future<Output = Result<Reachability>> + Send;
+    // succeeds once ssh accepts connections
+    fn ssh(&self) -> impl Future<Output = Result<()>> + Send;
+}
+
+// Time taken by a launch to get through each stage
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BootTiming {
+    pub running: Duration,
+    pub ready: Duration,
+}
+
+// Waits for a launched instance to become usable, all stages share a single deadline
+pub struct Readiness {
+    config: ReadinessConfig,
+    launched_at: Instant,
+    running: Option<Duration>,
+}
+
+impl Readiness {
+    pub fn new(config: ReadinessConfig) -> Readiness {
+        Readiness {
+            config,
+            launched_at: Instant::now(),
+            running: None,
+        }
+    }
+
+    fn check_deadline(&self, stage: &str) -> Result<()> {
+        if self.launched_at.elapsed() >= self.config.deadline {
+            return Err(anyhow!(
+                "instance not {stage} after {:?}",
+                self.config.deadline
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub async fn wait_running(&mut self, probe: &impl ReadinessProbe) -> Result<Duration> {
+        loop {
+            let state = probe
+                .instance_state()
+                .await
+                .context("could not get instance state")?;
+            match state.as_str() {
+                "running" => break,
+                "pending" => {}
+                _ => return Err(anyhow!("instance is {state} instead of running")),
+            }
+
+            self.check_deadline("running")?;
+            sleep(self.config.poll_interval).await;
+        }
+
+        let running = self.launched_at.elapsed();
+        self.running = Some(running);
+        Ok(running)
+    }
+
+    // ssh is probed while the status checks are still initializing since sshd is
+    // usually up well before they pass, impaired instances fail right away
+    pub async fn wait_reachable(&mut self, probe: &impl ReadinessProbe) -> Result<BootTiming> {
+        let mut backoff = self.config.ssh_backoff;
+        loop {
+            let reachability = probe
+                .reachability()
+                .await
+                .context("could not get instance status")?;
+            if reachability == Reachability::Impaired {
+                return Err(anyhow!("instance reachability check failed"));
+            }
+
+            match probe.ssh().await {
+                Ok(()) => break,
+                Err(err) => println!("ssh not ready yet: {err:?}"),
+            }
+
+            self.check_deadline("reachable over ssh")?;
+            sleep(backoff).await;
+            backoff = (backoff * 2).min(self.config.ssh_backoff_max);
+        }
+
+        let ready = self.launched_at.elapsed();
+        Ok(BootTiming {
+            running: self.running.unwrap_or(ready),
+            ready,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BootStats {
+    pub boots: u64,
+    pub failures: u64,
+    pub last: Option<BootTiming>,
+    pub total_ready: Duration,
+    pub max_ready: Duration,
+}
+
+impl BootStats {
+    pub fn mean_ready(&self) -> Duration {
+        if self.boots == 0 {
+            return Duration::ZERO;
+        }
+
+        self.total_ready / self.boots as u32
+    }
+}
+
+// Boot timings per instance type and region
+#[derive(Clone, Default)]
+pub struct BootMetrics(Arc<Mutex<HashMap<(String, String), BootStats>>>);
+
+impl BootMetrics {
+    pub fn record(&self, instance_type: &str, region: &str, timing: Option<BootTiming>) {
+        let mut metrics = self.0.lock().unwrap();
+        let stats = metrics
+            .entry((instance_type.to_owned(), region.to_owned()))
+            .or_default();
+
+        match timing {
+            Some(timing) => {
+                stats.boots += 1;
+                stats.last = Some(timing);
+                stats.total_ready += timing.ready;
+                stats.max_ready = stats.max_ready.max(timing.ready);
+            }
+            None => stats.failures += 1,
+        }
+
+        println!(
+            "boot metrics: {instance_type} in {region}: {} boots, {} failures, mean {:?}, max {:?}, last {:?}",
+            stats.boots,
+            stats.failures,
+            stats.mean_ready(),
+            stats.max_ready,
+            stats.last
+        );
+    }
+
+    pub fn get(&self, instance_type: &str, region: &str) -> Option<BootStats> {
+        self.0
+            .lock()
+            .unwrap()
+            .get(&(instance_type.to_owned(), region.to_owned()))
+            .copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use anyhow::{anyhow, Result};
+    use tokio::time::{Duration, Instant};
+
+    use super::{
+        BootMetrics, BootTiming, Reachability, Readiness, ReadinessConfig, ReadinessProbe,
+    };
+
+    // instance that goes running, then gets ssh up at fixed times after creation
+    struct FakeInstance {
+        created: Instant,
+        running_after: Duration,
+        ssh_after: Duration,
+        reachability: Reachability,
+        ssh_probes: Mutex<u32>,
+    }
+
+    impl FakeInstance {
+        fn new(running_after: u64, ssh_after: u64) -> FakeInstance {
+            FakeInstance {
+                created: Instant::now(),
+                running_after: Duration::from_secs(running_after),
+                ssh_after: Duration::from_secs(ssh_after),
+                reachability: Reachability::Initializing,
+                ssh_probes: Mutex::new(0),
+            }
+        }
+    }
+
+    impl ReadinessProbe for FakeInstance {
+        async fn instance_state(&self) -> Result<String> {
+            Ok(if self.created.elapsed() >= self.running_after {
+                "running"
+            } else {
+                "pending"
+            }
+            .to_owned())
+        }
+
+        async fn reachability(&self) -> Result<Reachability> {
+            Ok(self.reachability)
+        }
+
+        async fn ssh(&self) -> Result<()> {
+            *self.ssh_probes.lock().unwrap() += 1;
+            if self.created.elapsed() >= self.ssh_after {
+                Ok(())
+            } else {
+                Err(anyhow!("connection refused"))
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_ready_before_fixed_sleep() -> Result<()> {
+        let instance = FakeInstance::new(12, 25);
+        let mut readiness = Readiness::new(ReadinessConfig::default());
+
+        let running = readiness.wait_running(&instance).await?;
+        assert_eq!(running, Duration::from_secs(15));
+
+        let timing = readiness.wait_reachable(&instance).await?;
+        // probes at 15, 17, 21 and 29 seconds
+        assert_eq!(timing.ready, Duration::from_secs(29));
+        assert_eq!(timing.running, Duration::from_secs(15));
+        assert_eq!(*instance.ssh_probes.lock().unwrap(), 4);
+
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_deadline() {
+        let instance = FakeInstance::new(10, 1000);
+        let mut readiness = Readiness::new(ReadinessConfig {
+            deadline: Duration::from_secs(120),
+            ..Default::default()
+        });
+
+        assert!(readiness.wait_running(&instance).await.is_ok());
+        let start = Instant::now();
+        let err = readiness.wait_reachable(&instance).await.unwrap_err();
+        assert!(err.to_string().contains("reachable over ssh"));
+        assert!(instance.created.elapsed() >= Duration::from_secs(120));
+        assert!(start.elapsed() < Duration::from_secs(150));
+
+        // never running
+        let instance = FakeInstance::new(1000, 1000);
+        let mut readiness = Readiness::new(ReadinessConfig {
+            deadline: Duration::from_secs(120),
+            ..Default::default()
+        });
+        assert!(readiness.wait_running(&instance).await.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_impaired_fails_fast() {
+        let mut instance = FakeInstance::new(0, 0);
+        instance.reachability = Reachability::Impaired;
+        let mut readiness = Readiness::new(ReadinessConfig::default());
+
+        assert!(readiness.wait_running(&instance).await.is_ok());
+        assert!(readiness.wait_reachable(&instance).await.is_err());
+        assert_eq!(*instance.ssh_probes.lock().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_boot_metrics() {
+        let metrics = BootMetrics::default();
+        let timing = |ready| BootTiming {
+            running: Duration::from_secs(10),
+            ready: Duration::from_secs(ready),
+        };
+
+        metrics.record("c6a.xlarge", "ap-south-1", Some(timing(30)));
+        metrics.record("c6a.xlarge", "ap-south-1", Some(timing(50)));
+        metrics.record("c6a.xlarge", "ap-south-1", None);
+        metrics.record("c6a.xlarge", "us-east-1", Some(timing(20)));
+
+        let stats = metrics.get("c6a.xlarge", "ap-south-1").unwrap();
+        assert_eq!(stats.boots, 2);
+        assert_eq!(stats.failures, 1);
+        assert_eq!(stats.mean_ready(), Duration::from_secs(40));
+        assert_eq!(stats.max_ready, Duration::from_secs(50));
+        assert_eq!(stats.last, Some(timing(50)));
+
+        assert_eq!(metrics.get("c6a.xlarge", "us-east-1").unwrap().boots, 1);
+        assert!(metrics.get("c6a.2xlarge", "ap-south-1").is_none());
+    }
+}
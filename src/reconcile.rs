@@ -0,0 +1,497 @@
+use std::collections::HashMap;
+use std::future::Future;
+
+use anyhow::{anyhow, Result};
+use tokio::time::{sleep, Duration, Instant};
+
+use crate::market::{JobId, LiveJobs};
+
+// What the reconciler does with orphaned resources
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReconcileMode {
+    Off,
+    // only log orphaned resources
+    Report,
+    // terminate and release orphaned resources
+    Enforce,
+}
+
+impl ReconcileMode {
+    pub fn parse(mode: &str) -> Result<ReconcileMode> {
+        match mode {
+            "off" => Ok(ReconcileMode::Off),
+            "report" => Ok(ReconcileMode::Report),
+            "enforce" => Ok(ReconcileMode::Enforce),
+            _ => Err(anyhow!("unsupported reconcile mode: {mode}")),
+        }
+    }
+}
+
+// Instance tagged with the operator, contract and chain of a deployment
+#[derive(Clone, Debug, PartialEq)]
+pub struct TaggedInstance {
+    pub instance_id: String,
+    pub job: JobId,
+    pub state: String,
+}
+
+// Elastic ip tagged with the operator, contract and chain of a deployment
+#[derive(Clone, Debug, PartialEq)]
+pub struct TaggedAddress {
+    pub allocation_id: String,
+    pub public_ip: String,
+    pub association_id: Option<String>,
+    pub job: JobId,
+}
+
+pub trait TaggedResources {
+    // instances that are not terminated or shutting down, deployment is a job id without the id
+    fn tagged_instances(
+        &self,
+        deployment: &JobId,
+        region: &str,
+    ) -> impl Future<Output = Result<Vec<TaggedInstance>>> + Send;
+
+    fn tagged_addresses(
+        &self,
+        deployment: &JobId,
+        region: &str,
+    ) -> impl Future<Output = Result<Vec<TaggedAddress>>> + Send;
+
+    fn terminate_orphan(
+        &self,
+        instance: &TaggedInstance,
+        region: &str,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    // disassociates the address first if needed
+    fn release_orphan(
+        &self,
+        address: &TaggedAddress,
+        region: &str,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    // a launch of the job is in progress and may still attach the elastic ip of the job
+    fn launch_pending(&self, job: &JobId) -> bool;
+}
+
+// Resources found orphaned and cleaned up in a single pass
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReconcileReport {
+    // orphaned for longer than the grace period
+    pub orphans: Vec<String>,
+    // terminated or released, only in enforce mode
+    pub cleaned: Vec<String>,
+}
+
+// Compares tagged instances and elastic ips with the jobs that are still being managed
+// and cleans up the ones left behind by crashes or failed operations
+//
+// Job managers of live jobs are only spawned once the market logs are processed after startup,
+// so nothing is looked at before job discovery of every deployment has caught up with the head
+// and resources have to stay orphaned for a grace period before they are touched
+pub struct Reconciler<R> {
+    resources: R,
+    regions: &'static [String],
+    deployments: Vec<JobId>,
+    live_jobs: LiveJobs,
+    mode: ReconcileMode,
+    grace: Duration,
+    // resource id to when it was first seen orphaned
+    orphaned_since: HashMap<String, Instant>,
+}
+
+impl<R: TaggedResources> Reconciler<R> {
+    pub fn new(
+        resources: R,
+        regions: &'static [String],
+        deployments: Vec<JobId>,
+        live_jobs: LiveJobs,
+        mode: ReconcileMode,
+        grace: Duration,
+    ) -> Reconciler<R> {
+        Reconciler {
+            resources,
+            regions,
+            deployments,
+            live_jobs,
+            mode,
+            grace,
+            orphaned_since: HashMap::new(),
+        }
+    }
+
+    pub async fn run(mut self, interval: Duration) {
+        loop {
+            let report = self.reconcile_once().await;
+            println!(
+                "reconcile: {} orphans, {} cleaned",
+                report.orphans.len(),
+                report.cleaned.len()
+            );
+            sleep(interval).await;
+        }
+    }
+
+    pub async fn reconcile_once(&mut self) -> ReconcileReport {
+        let mut report = ReconcileReport::default();
+        let mut orphaned = Vec::new();
+
+        // jobs of a deployment still being discovered have no job manager yet and look orphaned
+        // this covers rpc outages at startup as well as long backfills
+        let syncing = self
+            .deployments
+            .iter()
+            .filter(|deployment| !self.live_jobs.is_synced(deployment))
+            .count();
+        if syncing > 0 {
+            println!("reconcile: waiting for job discovery of {syncing} deployments to catch up");
+            return report;
+        }
+
+        for deployment in self.deployments.clone() {
+            for region in self.regions {
+                // instances first so addresses of orphaned instances are free to be released
+                match self.resources.tagged_instances(&deployment, region).await {
+                    Ok(instances) => {
+                        for instance in instances {
+                            if !self.is_orphaned(
+                                &instance.instance_id,
+                                &instance.job,
+                                &mut orphaned,
+                            ) {
+                                continue;
+                            }
+                            report.orphans.push(instance.instance_id.clone());
+                            self.terminate(&instance, region, &mut report).await;
+                        }
+                    }
+                    Err(err) => {
+                        println!("reconcile: failed to list instances in {region}: {err:?}")
+                    }
+                }
+
+                match self.resources.tagged_addresses(&deployment, region).await {
+                    Ok(addresses) => {
+                        for address in addresses {
+                            if !self.is_orphaned(
+                                &address.allocation_id,
+                                &address.job,
+                                &mut orphaned,
+                            ) {
+                                continue;
+                            }
+                            report.orphans.push(address.allocation_id.clone());
+                            self.release(&address, region, &mut report).await;
+                        }
+                    }
+                    Err(err) => {
+                        println!("reconcile: failed to list elastic ips in {region}: {err:?}")
+                    }
+                }
+            }
+        }
+
+        // resources that are gone or belong to a live job again start over
+        self.orphaned_since.retain(|id, _| orphaned.contains(id));
+
+        report
+    }
+
+    // true once the resource has been orphaned for longer than the grace period
+    fn is_orphaned(&mut self, id: &str, job: &JobId, orphaned: &mut Vec<String>) -> bool {
+        if self.live_jobs.contains(job) {
+            return false;
+        }
+
+        orphaned.push(id.to_owned());
+        let since = *self.orphaned_since.entry(id.to_owned()).or_insert_with(|| {
+            println!("reconcile: job {}: {id} is orphaned", job.id);
+            Instant::now()
+        });
+
+        since.elapsed() >= self.grace
+    }
+
+    async fn terminate(
+        &self,
+        instance: &TaggedInstance,
+        region: &str,
+        report: &mut ReconcileReport,
+    ) {
+        let job = &instance.job.id;
+        let id = &instance.instance_id;
+        if self.mode != ReconcileMode::Enforce {
+            println!(
+                "reconcile: job {job}: would terminate {id} ({}) in {region}",
+                instance.state
+            );
+            return;
+        }
+
+        match self.resources.terminate_orphan(instance, region).await {
+            Ok(()) => {
+                println!("reconcile: job {job}: terminated {id} in {region}");
+                report.cleaned.push(id.clone());
+            }
+            Err(err) => println!("reconcile: job {job}: failed to terminate {id}: {err:?}"),
+        }
+    }
+
+    async fn release(&self, address: &TaggedAddress, region: &str, report: &mut ReconcileReport) {
+        let job = &address.job.id;
+        let id = &address.allocation_id;
+        if self.mode != ReconcileMode::Enforce {
+            println!(
+                "reconcile: job {job}: would release {id} ({}) in {region}",
+                address.public_ip
+            );
+            return;
+        }
+
+        match self.resources.release_orphan(address, region).await {
+            Ok(()) => {
+                println!(
+                    "reconcile: job {job}: released {id} ({}) in {region}",
+                    address.public_ip
+                );
+                report.cleaned.push(id.clone());
+            }
+            Err(err) => println!("reconcile: job {job}: failed to release {id}: {err:?}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use anyhow::Result;
+    use tokio::time::{sleep, Duration};
+
+    use super::{
+        ReconcileMode, ReconcileReport, Reconciler, TaggedAddress, TaggedInstance, TaggedResources,
+    };
+    use crate::market::{JobId, LiveJobs};
+
+    #[derive(Clone, Default)]
+    struct TestResources {
+        instances: Arc<Mutex<Vec<TaggedInstance>>>,
+        addresses: Arc<Mutex<Vec<TaggedAddress>>>,
+    }
+
+    impl TaggedResources for TestResources {
+        async fn tagged_instances(
+            &self,
+            deployment: &JobId,
+            _region: &str,
+        ) -> Result<Vec<TaggedInstance>> {
+            Ok(self
+                .instances
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|instance| instance.job.contract == deployment.contract)
+                .cloned()
+                .collect())
+        }
+
+        async fn tagged_addresses(
+            &self,
+            deployment: &JobId,
+            _region: &str,
+        ) -> Result<Vec<TaggedAddress>> {
+            Ok(self
+                .addresses
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|address| address.job.contract == deployment.contract)
+                .cloned()
+                .collect())
+        }
+
+        async fn terminate_orphan(&self, instance: &TaggedInstance, _region: &str) -> Result<()> {
+            self.instances
+                .lock()
+                .unwrap()
+                .retain(|i| i.instance_id != instance.instance_id);
+            Ok(())
+        }
+
+        async fn release_orphan(&self, address: &TaggedAddress, _region: &str) -> Result<()> {
+            self.addresses
+                .lock()
+                .unwrap()
+                .retain(|a| a.allocation_id != address.allocation_id);
+            Ok(())
+        }
+
+        fn launch_pending(&self, _job: &JobId) -> bool {
+            false
+        }
+    }
+
+    fn job(id: &str) -> JobId {
+        JobId {
+            id: id.to_owned(),
+            operator: "abc".into(),
+            contract: "xyz".into(),
+            chain: "123".into(),
+        }
+    }
+
+    fn resources() -> TestResources {
+        let resources = TestResources::default();
+        *resources.instances.lock().unwrap() = vec![
+            TaggedInstance {
+                instance_id: "i-live".into(),
+                job: job("0x01"),
+                state: "running".into(),
+            },
+            TaggedInstance {
+                instance_id: "i-orphan".into(),
+                job: job("0x02"),
+                state: "running".into(),
+            },
+        ];
+        *resources.addresses.lock().unwrap() = vec![
+            TaggedAddress {
+                allocation_id: "eipalloc-live".into(),
+                public_ip: "1.1.1.1".into(),
+                association_id: Some("eipassoc-live".into()),
+                job: job("0x01"),
+            },
+            TaggedAddress {
+                allocation_id: "eipalloc-orphan".into(),
+                public_ip: "2.2.2.2".into(),
+                association_id: None,
+                job: job("0x03"),
+            },
+        ];
+        resources
+    }
+
+    fn reconciler(
+        resources: TestResources,
+        live_jobs: LiveJobs,
+        mode: ReconcileMode,
+    ) -> Reconciler<TestResources> {
+        let regions: &'static [String] = Box::leak(vec!["ap-south-1".into()].into_boxed_slice());
+        live_jobs.set_synced(&job(""));
+        Reconciler::new(
+            resources,
+            regions,
+            vec![job("")],
+            live_jobs,
+            mode,
+            Duration::from_secs(600),
+        )
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cleanup_after_grace() {
+        let resources = resources();
+        let live_jobs = LiveJobs::default();
+        live_jobs.insert(&job("0x01"));
+        let mut reconciler = reconciler(resources.clone(), live_jobs, ReconcileMode::Enforce);
+
+        // nothing is touched right after startup
+        assert_eq!(
+            reconciler.reconcile_once().await,
+            ReconcileReport::default()
+        );
+        assert_eq!(resources.instances.lock().unwrap().len(), 2);
+
+        sleep(Duration::from_secs(600)).await;
+        let report = reconciler.reconcile_once().await;
+        assert_eq!(report.orphans, vec!["i-orphan", "eipalloc-orphan"]);
+        assert_eq!(report.cleaned, vec!["i-orphan", "eipalloc-orphan"]);
+
+        let instances = resources.instances.lock().unwrap().clone();
+        assert_eq!(instances.len(), 1);
+        assert_eq!(instances[0].instance_id, "i-live");
+        let addresses = resources.addresses.lock().unwrap().clone();
+        assert_eq!(addresses.len(), 1);
+        assert_eq!(addresses[0].allocation_id, "eipalloc-live");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_report_only() {
+        let resources = resources();
+        let mut reconciler = reconciler(
+            resources.clone(),
+            LiveJobs::default(),
+            ReconcileMode::Report,
+        );
+
+        reconciler.reconcile_once().await;
+        sleep(Duration::from_secs(600)).await;
+        let report = reconciler.reconcile_once().await;
+
+        assert_eq!(report.orphans.len(), 4);
+        assert!(report.cleaned.is_empty());
+        assert_eq!(resources.instances.lock().unwrap().len(), 2);
+        assert_eq!(resources.addresses.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_job_started_during_grace() {
+        let resources = resources();
+        let live_jobs = LiveJobs::default();
+        let mut reconciler =
+            reconciler(resources.clone(), live_jobs.clone(), ReconcileMode::Enforce);
+
+        reconciler.reconcile_once().await;
+
+        // job managers of live jobs get spawned while the market logs are processed
+        sleep(Duration::from_secs(300)).await;
+        live_jobs.insert(&job("0x01"));
+        live_jobs.insert(&job("0x02"));
+
+        sleep(Duration::from_secs(300)).await;
+        let report = reconciler.reconcile_once().await;
+        assert_eq!(report.cleaned, vec!["eipalloc-orphan"]);
+
+        // jobs ending restart the grace period
+        live_jobs.remove(&job("0x02"));
+        assert!(reconciler.reconcile_once().await.cleaned.is_empty());
+        sleep(Duration::from_secs(600)).await;
+        assert_eq!(reconciler.reconcile_once().await.cleaned, vec!["i-orphan"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_discovery_not_caught_up() {
+        let resources = resources();
+        let regions: &'static [String] = Box::leak(vec!["ap-south-1".into()].into_boxed_slice());
+        let live_jobs = LiveJobs::default();
+        let mut reconciler = Reconciler::new(
+            resources.clone(),
+            regions,
+            vec![job("")],
+            live_jobs.clone(),
+            ReconcileMode::Enforce,
+            Duration::from_secs(600),
+        );
+
+        // the rpc endpoints are down at startup, no job manager was spawned
+        reconciler.reconcile_once().await;
+        sleep(Duration::from_secs(600)).await;
+        assert_eq!(
+            reconciler.reconcile_once().await,
+            ReconcileReport::default()
+        );
+        assert_eq!(resources.instances.lock().unwrap().len(), 2);
+        assert_eq!(resources.addresses.lock().unwrap().len(), 2);
+
+        // the grace period only starts once discovery caught up
+        live_jobs.insert(&job("0x01"));
+        live_jobs.set_synced(&job(""));
+        assert!(reconciler.reconcile_once().await.cleaned.is_empty());
+        sleep(Duration::from_secs(600)).await;
+        assert_eq!(
+            reconciler.reconcile_once().await.cleaned,
+            vec!["i-orphan", "eipalloc-orphan"]
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+use std::fs;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::time::{sleep, Duration};
+
+// Value shared by readers and swapped as a whole on reloads
+// Readers keep the snapshot they loaded even if a new one is stored meanwhile
+pub struct Snapshot<T>(Arc<RwLock<Arc<T>>>);
+
+impl<T> Clone for Snapshot<T> {
+    fn clone(&self) -> Self {
+        Snapshot(self.0.clone())
+    }
+}
+
+impl<T: Default> Default for Snapshot<T> {
+    fn default() -> Self {
+        Snapshot::new(T::default())
+    }
+}
+
+impl<T> Snapshot<T> {
+    pub fn new(value: T) -> Snapshot<T> {
+        Snapshot(Arc::new(RwLock::new(Arc::new(value))))
+    }
+
+    pub fn load(&self) -> Arc<T> {
+        self.0.read().unwrap().clone()
+    }
+
+    pub fn store(&self, value: T) {
+        *self.0.write().unwrap() = Arc::new(value);
+    }
+}
+
+// Inputs that can be reloaded from their files without a restart
+pub trait Reloadable: Send + Sync {
+    fn name(&self) -> String;
+
+    // empty locations are skipped
+    fn files(&self) -> Vec<String>;
+
+    // loads and validates the files and swaps the new value in, the current value is kept on errors
+    fn reload(&self) -> Result<()>;
+}
+
+// modification times of the files, missing files are left out
+fn modified(files: &[String]) -> HashMap<String, SystemTime> {
+    files
+        .iter()
+        .filter(|file| !file.is_empty())
+        .filter_map(|file| {
+            let modified = fs::metadata(file).and_then(|meta| meta.modified()).ok()?;
+            Some((file.clone(), modified))
+        })
+        .collect()
+}
+
+fn reload(reloadable: &dyn Reloadable, reason: &str) {
+    match reloadable.reload() {
+        Ok(()) => println!("reload: {} reloaded on {reason}", reloadable.name()),
+        Err(err) => println!(
+            "reload: {}: keeping current values, failed to reload on {reason}: {err:?}",
+            reloadable.name()
+        ),
+    }
+}
+
+// Reloads everything on SIGHUP, and whatever uses a file once it has been modified
+pub async fn run(reloadables: Vec<Box<dyn Reloadable>>, poll_interval: Duration) -> Result<()> {
+    let mut hangup = signal(SignalKind::hangup()).context("failed to listen for SIGHUP")?;
+    let mut seen: Vec<HashMap<String, SystemTime>> = reloadables
+        .iter()
+        .map(|reloadable| modified(&reloadable.files()))
+        .collect();
+
+    loop {
+        tokio::select! {
+            _ = hangup.recv() => {
+                for reloadable in &reloadables {
+                    reload(reloadable.as_ref(), "SIGHUP");
+                }
+            }
+            () = sleep(poll_interval) => {
+                for (reloadable, seen) in reloadables.iter().zip(seen.iter_mut()) {
+                    let current = modified(&reloadable.files());
+                    if current == *seen {
+                        continue;
+                    }
+
+                    // failed reloads are not retried until the files change again
+                    *seen = current;
+                    reload(reloadable.as_ref(), "file change");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::Snapshot;
+
+    #[test]
+    fn test_snapshot() {
+        let snapshot = Snapshot::new(vec![1, 2]);
+        let shared = snapshot.clone();
+
+        let before = snapshot.load();
+        shared.store(vec![3]);
+
+        // readers holding a snapshot keep it, new loads see the new value
+        assert_eq!(*before, vec![1, 2]);
+        assert_eq!(*snapshot.load(), vec![3]);
+        assert!(!Arc::ptr_eq(&before, &snapshot.load()));
+    }
+}
@@ -0,0 +1,351 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use ethers::core::rand::{thread_rng, Rng};
+use tokio::time::{sleep, Duration, Instant};
+
+// How aws api errors are handled
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorClass {
+    // rate limited, the whole region backs off
+    Throttling,
+    // network errors and server side failures, worth trying again
+    Transient,
+    Permanent,
+}
+
+const THROTTLING_ERRORS: [&str; 8] = [
+    "RequestLimitExceeded",
+    "Throttling",
+    "ThrottlingException",
+    "ThrottledException",
+    "RequestThrottled",
+    "RequestThrottledException",
+    "TooManyRequestsException",
+    "EC2ThrottledException",
+];
+
+const TRANSIENT_ERRORS: [&str; 6] = [
+    "InternalError",
+    "InternalFailure",
+    "ServiceUnavailable",
+    "Unavailable",
+    "RequestTimeout",
+    "RequestTimeoutException",
+];
+
+// classifies the error code returned by the service
+pub fn classify_code(code: Option<&str>) -> ErrorClass {
+    match code {
+        Some(code) if THROTTLING_ERRORS.contains(&code) => ErrorClass::Throttling,
+        Some(code) if TRANSIENT_ERRORS.contains(&code) => ErrorClass::Transient,
+        _ => ErrorClass::Permanent,
+    }
+}
+
+// Whether a request can be sent again when it is unknown if the previous attempt took effect
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Idempotency {
+    Idempotent,
+    // only retried when throttled since throttled requests are rejected before being processed
+    NonIdempotent,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RetryConfig {
+    // including the first attempt
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    // requests that can be sent in a burst per region
+    pub bucket_capacity: f64,
+    // requests per second per region once the burst is used up
+    pub refill_rate: f64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            max_attempts: 6,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            bucket_capacity: 50.0,
+            refill_rate: 10.0,
+        }
+    }
+}
+
+impl RetryConfig {
+    // exponential backoff with the upper half jittered, jitter is in [0, 1)
+    pub fn backoff(&self, attempt: u32, jitter: f64) -> Duration {
+        let delay = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay);
+
+        delay / 2 + (delay / 2).mul_f64(jitter)
+    }
+}
+
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn refill(&mut self, config: &RetryConfig) {
+        let now = Instant::now();
+        self.tokens = (self.tokens
+            + now.duration_since(self.updated).as_secs_f64() * config.refill_rate)
+            .min(config.bucket_capacity);
+        self.updated = now;
+    }
+
+    // takes a token or returns how long to wait for one
+    fn take(&mut self, config: &RetryConfig) -> Option<Duration> {
+        self.refill(config);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return None;
+        }
+
+        Some(Duration::from_secs_f64(
+            (1.0 - self.tokens) / config.refill_rate,
+        ))
+    }
+}
+
+// Retry layer for aws api requests, shared by all job tasks so requests of every job
+// draw from the same token bucket of the region
+//
+// Throttling empties the bucket, so every task slows down instead of only the one throttled
+#[derive(Clone)]
+pub struct AwsRetry {
+    config: RetryConfig,
+    buckets: Arc<Mutex<HashMap<String, TokenBucket>>>,
+}
+
+impl AwsRetry {
+    pub fn new(config: RetryConfig) -> AwsRetry {
+        AwsRetry {
+            config,
+            buckets: Default::default(),
+        }
+    }
+
+    // waits until a request can be sent in the region
+    pub async fn acquire(&self, region: &str) {
+        loop {
+            let wait = self
+                .buckets
+                .lock()
+                .unwrap()
+                .entry(region.to_owned())
+                .or_insert_with(|| TokenBucket {
+                    tokens: self.config.bucket_capacity,
+                    updated: Instant::now(),
+                })
+                .take(&self.config);
+
+            match wait {
+                Some(wait) => sleep(wait).await,
+                None => return,
+            }
+        }
+    }
+
+    fn throttled(&self, region: &str) {
+        if let Some(bucket) = self.buckets.lock().unwrap().get_mut(region) {
+            bucket.refill(&self.config);
+            bucket.tokens = 0.0;
+        }
+    }
+
+    pub async fn run<T, E, Fut>(
+        &self,
+        region: &str,
+        operation: &str,
+        idempotency: Idempotency,
+        classify: impl Fn(&E) -> ErrorClass,
+        mut request: impl FnMut() -> Fut,
+    ) -> Result<T, E>
+    where
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let mut attempt = 0;
+        loop {
+            self.acquire(region).await;
+            attempt += 1;
+
+            let err = match request().await {
+                Ok(res) => return Ok(res),
+                Err(err) => err,
+            };
+
+            let class = classify(&err);
+            let retry = match class {
+                ErrorClass::Throttling => {
+                    self.throttled(region);
+                    true
+                }
+                ErrorClass::Transient => idempotency == Idempotency::Idempotent,
+                ErrorClass::Permanent => false,
+            };
+            if !retry || attempt >= self.config.max_attempts {
+                return Err(err);
+            }
+
+            let delay = self.config.backoff(attempt, thread_rng().gen());
+            println!(
+                "aws: {operation} in {region}: {class:?} error on attempt {attempt}, retrying in {delay:?}"
+            );
+            sleep(delay).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use tokio::time::{Duration, Instant};
+
+    use super::{classify_code, AwsRetry, ErrorClass, Idempotency, RetryConfig};
+
+    #[test]
+    fn test_classify_code() {
+        assert_eq!(
+            classify_code(Some("RequestLimitExceeded")),
+            ErrorClass::Throttling
+        );
+        assert_eq!(classify_code(Some("InternalError")), ErrorClass::Transient);
+        assert_eq!(
+            classify_code(Some("InsufficientInstanceCapacity")),
+            ErrorClass::Permanent
+        );
+        assert_eq!(classify_code(None), ErrorClass::Permanent);
+    }
+
+    #[test]
+    fn test_backoff() {
+        let config = RetryConfig::default();
+
+        assert_eq!(config.backoff(1, 0.0), Duration::from_millis(250));
+        assert_eq!(config.backoff(1, 0.5), Duration::from_millis(375));
+        assert_eq!(config.backoff(3, 0.0), Duration::from_secs(1));
+        // capped
+        assert_eq!(config.backoff(10, 0.0), Duration::from_secs(15));
+        assert!(config.backoff(100, 0.99) < Duration::from_secs(30));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retry_classes() {
+        let retry = AwsRetry::new(RetryConfig::default());
+        let attempts = Mutex::new(0);
+        let request = |code: &'static str, fail: u32| {
+            *attempts.lock().unwrap() = 0;
+            let attempts = &attempts;
+            move || async move {
+                let mut attempts = attempts.lock().unwrap();
+                *attempts += 1;
+                if *attempts <= fail {
+                    Err(code)
+                } else {
+                    Ok(*attempts)
+                }
+            }
+        };
+        let classify = |code: &&str| classify_code(Some(code));
+
+        // throttled requests are retried even if not idempotent
+        let res = retry
+            .run(
+                "ap-south-1",
+                "RunInstances",
+                Idempotency::NonIdempotent,
+                classify,
+                request("RequestLimitExceeded", 2),
+            )
+            .await;
+        assert_eq!(res, Ok(3));
+
+        let res = retry
+            .run(
+                "ap-south-1",
+                "RunInstances",
+                Idempotency::NonIdempotent,
+                classify,
+                request("InternalError", 2),
+            )
+            .await;
+        assert_eq!(res, Err("InternalError"));
+        assert_eq!(*attempts.lock().unwrap(), 1);
+
+        let res = retry
+            .run(
+                "ap-south-1",
+                "DescribeInstances",
+                Idempotency::Idempotent,
+                classify,
+                request("InternalError", 2),
+            )
+            .await;
+        assert_eq!(res, Ok(3));
+
+        let res = retry
+            .run(
+                "ap-south-1",
+                "DescribeInstances",
+                Idempotency::Idempotent,
+                classify,
+                request("InvalidInstanceID.NotFound", 2),
+            )
+            .await;
+        assert_eq!(res, Err("InvalidInstanceID.NotFound"));
+        assert_eq!(*attempts.lock().unwrap(), 1);
+
+        // gives up after the max attempts
+        let res = retry
+            .run(
+                "ap-south-1",
+                "DescribeInstances",
+                Idempotency::Idempotent,
+                classify,
+                request("RequestLimitExceeded", 100),
+            )
+            .await;
+        assert_eq!(res, Err("RequestLimitExceeded"));
+        assert_eq!(*attempts.lock().unwrap(), 6);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_token_bucket() {
+        let retry = AwsRetry::new(RetryConfig {
+            bucket_capacity: 5.0,
+            refill_rate: 1.0,
+            ..Default::default()
+        });
+
+        // burst, then one request per second
+        let start = Instant::now();
+        for _ in 0..5 {
+            retry.acquire("ap-south-1").await;
+        }
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        retry.acquire("ap-south-1").await;
+        retry.acquire("ap-south-1").await;
+        assert_eq!(start.elapsed(), Duration::from_secs(2));
+
+        // regions do not share buckets
+        retry.acquire("us-east-1").await;
+        assert_eq!(start.elapsed(), Duration::from_secs(2));
+
+        // throttling drains the bucket for every task in the region
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        retry.throttled("ap-south-1");
+        let start = Instant::now();
+        retry.acquire("ap-south-1").await;
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+    }
+}
@@ -8,10 +8,31 @@ use axum::{
 use serde::{Deserialize, Serialize};
 use std::net::SocketAddr;
 
-use crate::market::{GBRateCard, InfraProvider, JobId, RegionalRates};
+use crate::admission::{AdmissionRecord, Admissions};
+use crate::capacity::{Capacity, QueueStatus};
+use crate::inputs::SharedInputs;
+use crate::market::{
+    GBRateCard, InfraProvider, JobId, JobOutcome, JobOutcomes, OutcomeCounts, RegionalRates,
+};
+use crate::pcr::Pcrs;
+use crate::shadow::{IntendedAction, ShadowActions};
+
+// A marketplace contract served by this control plane along with its rate cards
+#[derive(Clone, Debug)]
+pub struct Deployment {
+    // without job_id.id set
+    pub job_id: JobId,
+    // rate cards and admission policy, swapped on reloads
+    pub inputs: SharedInputs,
+}
 
 enum Error {
     GetIPFail,
+    GetOutcomeFail,
+    GetAdmissionFail,
+    GetQueueFail,
+    GetPcrsFail,
+    DeploymentNotFound,
 }
 
 impl IntoResponse for Error {
@@ -26,6 +47,8 @@ type HandlerResult<T> = core::result::Result<T, Error>;
 struct GetIPRequest {
     id: Option<String>,
     region: Option<String>,
+    chain: Option<String>,
+    contract: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -33,10 +56,28 @@ struct GetIPResponse {
     ip: String,
 }
 
+#[derive(Debug, Deserialize)]
+struct GetOutcomeRequest {
+    id: Option<String>,
+    chain: Option<String>,
+    contract: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ShadowActionsRequest {
+    id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeploymentRequest {
+    chain: Option<String>,
+    contract: Option<String>,
+}
+
 #[derive(Debug, Serialize)]
 struct SpecResponse {
     allowed_regions: &'static [String],
-    min_rates: &'static [RegionalRates],
+    min_rates: Vec<RegionalRates>,
 }
 
 #[derive(Debug, Serialize)]
@@ -44,13 +85,31 @@ struct BandwidthResponse {
     rates: Vec<GBRateCard>,
 }
 
+// requests without chain and contract are only accepted if there is a single deployment
+fn find_deployment(
+    deployments: &'static [Deployment],
+    chain: Option<String>,
+    contract: Option<String>,
+) -> HandlerResult<&'static Deployment> {
+    match (chain, contract) {
+        (None, None) if deployments.len() == 1 => Ok(&deployments[0]),
+        (Some(chain), Some(contract)) => deployments
+            .iter()
+            .find(|deployment| {
+                deployment.job_id.chain == chain
+                    && deployment.job_id.contract.eq_ignore_ascii_case(&contract)
+            })
+            .ok_or(Error::DeploymentNotFound),
+        _ => Err(Error::DeploymentNotFound),
+    }
+}
+
 async fn handle_ip_request(
     State(state): State<(
         impl InfraProvider + Send + Sync + Clone,
         &'static [String],
-        &'static [RegionalRates],
-        &'static [GBRateCard],
-        JobId,
+        &'static [Deployment],
+        JobOutcomes,
     )>,
     Query(query): Query<GetIPRequest>,
 ) -> HandlerResult<Json<GetIPResponse>> {
@@ -58,15 +117,14 @@ async fn handle_ip_request(
         return Err(Error::GetIPFail);
     }
 
+    let deployment = find_deployment(state.2, query.chain, query.contract)?;
     let client = &state.0;
 
     let ip = client
         .get_job_ip(
             &JobId {
                 id: query.id.unwrap(),
-                operator: state.4.operator,
-                contract: state.4.contract,
-                chain: state.4.chain,
+                ..deployment.job_id.clone()
             },
             &query.region.unwrap(),
         )
@@ -81,21 +139,50 @@ async fn handle_ip_request(
     Ok(Json(ip))
 }
 
+async fn handle_pcrs_request(
+    State(state): State<(
+        impl InfraProvider + Send + Sync + Clone,
+        &'static [String],
+        &'static [Deployment],
+        JobOutcomes,
+    )>,
+    Query(query): Query<GetIPRequest>,
+) -> HandlerResult<Json<Pcrs>> {
+    let (Some(id), Some(region)) = (query.id, query.region) else {
+        return Err(Error::GetPcrsFail);
+    };
+
+    let deployment = find_deployment(state.2, query.chain, query.contract)?;
+    let pcrs = state
+        .0
+        .get_job_pcrs(
+            &JobId {
+                id,
+                ..deployment.job_id.clone()
+            },
+            &region,
+        )
+        .await
+        .map_err(|_| Error::GetPcrsFail)?;
+
+    Ok(Json(pcrs))
+}
+
 async fn handle_spec_request(
     State(state): State<(
         impl InfraProvider + Send + Sync + Clone,
         &'static [String],
-        &'static [RegionalRates],
-        &'static [GBRateCard],
-        JobId,
+        &'static [Deployment],
+        JobOutcomes,
     )>,
+    Query(query): Query<DeploymentRequest>,
 ) -> HandlerResult<Json<SpecResponse>> {
     let regions = state.1;
-    let rates = state.2;
+    let deployment = find_deployment(state.2, query.chain, query.contract)?;
 
     let res = SpecResponse {
         allowed_regions: regions,
-        min_rates: rates,
+        min_rates: deployment.inputs.load().rates.clone(),
     };
 
     Ok(Json(res))
@@ -105,46 +192,158 @@ async fn handle_bandwidth_request(
     State(state): State<(
         impl InfraProvider + Send + Sync + Clone,
         &'static [String],
-        &'static [RegionalRates],
-        &'static [GBRateCard],
-        JobId,
+        &'static [Deployment],
+        JobOutcomes,
     )>,
+    Query(query): Query<DeploymentRequest>,
 ) -> HandlerResult<Json<BandwidthResponse>> {
-    let bandwidth = state.3;
+    let deployment = find_deployment(state.2, query.chain, query.contract)?;
     let res = BandwidthResponse {
-        rates: bandwidth.to_owned(),
+        rates: deployment.inputs.load().gb_rates.clone(),
     };
 
     Ok(Json(res))
 }
 
+async fn handle_outcome_request(
+    State(state): State<(
+        impl InfraProvider + Send + Sync + Clone,
+        &'static [String],
+        &'static [Deployment],
+        JobOutcomes,
+    )>,
+    Query(query): Query<GetOutcomeRequest>,
+) -> HandlerResult<Json<JobOutcome>> {
+    let Some(id) = query.id else {
+        return Err(Error::GetOutcomeFail);
+    };
+    let deployment = find_deployment(state.2, query.chain, query.contract)?;
+
+    // only jobs whose manager has exited have an outcome
+    let outcome = state
+        .3
+        .get(&JobId {
+            id,
+            ..deployment.job_id.clone()
+        })
+        .ok_or(Error::GetOutcomeFail)?;
+
+    Ok(Json(outcome))
+}
+
+// counts of all deployments together
+async fn handle_outcomes_request(
+    State(state): State<(
+        impl InfraProvider + Send + Sync + Clone,
+        &'static [String],
+        &'static [Deployment],
+        JobOutcomes,
+    )>,
+) -> Json<OutcomeCounts> {
+    Json(state.3.counts())
+}
+
+async fn handle_admission_request(
+    State(state): State<(&'static [Deployment], Admissions, Capacity)>,
+    Query(query): Query<GetOutcomeRequest>,
+) -> HandlerResult<Json<AdmissionRecord>> {
+    let Some(id) = query.id else {
+        return Err(Error::GetAdmissionFail);
+    };
+    let deployment = find_deployment(state.0, query.chain, query.contract)?;
+
+    // only jobs that have been opened have a decision
+    let record = state
+        .1
+        .get(&JobId {
+            id,
+            ..deployment.job_id.clone()
+        })
+        .ok_or(Error::GetAdmissionFail)?;
+
+    Ok(Json(record))
+}
+
+async fn handle_queue_request(
+    State(state): State<(&'static [Deployment], Admissions, Capacity)>,
+    Query(query): Query<GetOutcomeRequest>,
+) -> HandlerResult<Json<QueueStatus>> {
+    let Some(id) = query.id else {
+        return Err(Error::GetQueueFail);
+    };
+    let deployment = find_deployment(state.0, query.chain, query.contract)?;
+
+    // only jobs waiting for capacity or holding it have a status
+    let status = state
+        .2
+        .status(&JobId {
+            id,
+            ..deployment.job_id.clone()
+        })
+        .ok_or(Error::GetQueueFail)?;
+
+    Ok(Json(status))
+}
+
+async fn handle_shadow_actions_request(
+    State(actions): State<ShadowActions>,
+    Query(query): Query<ShadowActionsRequest>,
+) -> Json<Vec<IntendedAction>> {
+    let actions = actions
+        .list()
+        .into_iter()
+        .filter(|action| {
+            query.id.is_none() || action.job.as_ref().map(|job| &job.id) == query.id.as_ref()
+        })
+        .collect();
+
+    Json(actions)
+}
+
 fn all_routes(
     state: (
         impl InfraProvider + Send + Sync + Clone + 'static,
         &'static [String],
-        &'static [RegionalRates],
-        &'static [GBRateCard],
-        JobId,
+        &'static [Deployment],
+        JobOutcomes,
     ),
 ) -> Router {
     Router::new()
         .route("/ip", get(handle_ip_request))
         .route("/spec", get(handle_spec_request))
         .route("/bandwidth", get(handle_bandwidth_request))
+        .route("/outcome", get(handle_outcome_request))
+        .route("/outcomes", get(handle_outcomes_request))
+        .route("/pcrs", get(handle_pcrs_request))
         .with_state(state)
 }
 
 pub async fn serve(
     client: impl InfraProvider + Send + Sync + Clone + 'static,
     regions: &'static [String],
-    rates: &'static [RegionalRates],
-    bandwidth: &'static [GBRateCard],
+    deployments: &'static [Deployment],
     addr: SocketAddr,
-    job_id: JobId,
+    outcomes: JobOutcomes,
+    admissions: Admissions,
+    capacity: Capacity,
+    // intended actions of shadow mode, only served if set
+    shadow: Option<ShadowActions>,
 ) {
-    let state = (client, regions, rates, bandwidth, job_id);
-
-    let router = Router::new().merge(all_routes(state));
+    let state = (client, regions, deployments, outcomes);
+
+    let mut router = Router::new().merge(all_routes(state)).merge(
+        Router::new()
+            .route("/admission", get(handle_admission_request))
+            .route("/queue", get(handle_queue_request))
+            .with_state((deployments, admissions, capacity)),
+    );
+    if let Some(actions) = shadow {
+        router = router.merge(
+            Router::new()
+                .route("/shadow/actions", get(handle_shadow_actions_request))
+                .with_state(actions),
+        );
+    }
     println!("Listening for connections on {}", addr);
     axum::Server::bind(&addr)
         .serve(router.into_make_service())
@@ -154,16 +353,51 @@ pub async fn serve(
 
 #[cfg(test)]
 mod tests {
-    use super::serve;
+    use super::{serve, Deployment};
 
     use anyhow;
     use ethers::{abi::AbiEncode, prelude::*};
     use serde_json::json;
     use std::net::SocketAddr;
 
-    use crate::market::{GBRateCard, JobId, RateCard, RegionalRates};
+    use crate::admission::{AdmissionDecision, Admissions};
+    use crate::capacity::{Capacity, CapacityLimits, Slot};
+    use crate::inputs::{MarketInputs, SharedInputs};
+    use crate::market::{
+        GBRateCard, InfraProvider, JobId, JobOutcome, JobOutcomes, RateCard, RegionalRates,
+    };
+    use crate::pcr::Pcrs;
+    use crate::shadow::{ShadowActions, ShadowInfra};
     use crate::test::{InstanceMetadata, TestAws};
 
+    fn deployment(
+        chain: &str,
+        contract: &str,
+        rates: &'static [RegionalRates],
+        gb_rates: &'static [GBRateCard],
+    ) -> Deployment {
+        Deployment {
+            job_id: JobId {
+                id: H256::zero().encode_hex(),
+                operator: "abc".into(),
+                contract: contract.into(),
+                chain: chain.into(),
+            },
+            inputs: SharedInputs::new(MarketInputs {
+                rates: rates.to_vec(),
+                gb_rates: gb_rates.to_vec(),
+                ..Default::default()
+            }),
+        }
+    }
+
+    fn deployments(
+        rates: &'static [RegionalRates],
+        gb_rates: &'static [GBRateCard],
+    ) -> &'static [Deployment] {
+        Box::leak(vec![deployment("123", "xyz", rates, gb_rates)].into_boxed_slice())
+    }
+
     #[tokio::test]
     async fn test_get_ip_happy_case() -> anyhow::Result<()> {
         let mut aws: TestAws = Default::default();
@@ -187,15 +421,12 @@ mod tests {
         tokio::spawn(serve(
             aws.clone(),
             regions,
-            compute_rates,
-            bandwidth_rates,
+            deployments(compute_rates, bandwidth_rates),
             SocketAddr::from(([0, 0, 0, 0], port)),
-            JobId {
-                id: job_id.clone(),
-                operator: "abc".into(),
-                contract: "xyz".into(),
-                chain: "123".into(),
-            },
+            JobOutcomes::default(),
+            Admissions::default(),
+            Capacity::default(),
+            None,
         ));
 
         let hc = httpc_test::new_client(format!("http://localhost:{}", port))?;
@@ -243,15 +474,12 @@ mod tests {
         tokio::spawn(serve(
             aws.clone(),
             regions,
-            compute_rates,
-            bandwidth_rates,
+            deployments(compute_rates, bandwidth_rates),
             SocketAddr::from(([0, 0, 0, 0], port)),
-            JobId {
-                id: job_id.clone(),
-                operator: "abc".into(),
-                contract: "xyz".into(),
-                chain: "123".into(),
-            },
+            JobOutcomes::default(),
+            Admissions::default(),
+            Capacity::default(),
+            None,
         ));
 
         let hc = httpc_test::new_client(format!("http://localhost:{}", port))?;
@@ -287,15 +515,12 @@ mod tests {
         tokio::spawn(serve(
             aws.clone(),
             regions,
-            compute_rates,
-            bandwidth_rates,
+            deployments(compute_rates, bandwidth_rates),
             SocketAddr::from(([0, 0, 0, 0], port)),
-            JobId {
-                id: job_id.clone(),
-                operator: "abc".into(),
-                contract: "xyz".into(),
-                chain: "123".into(),
-            },
+            JobOutcomes::default(),
+            Admissions::default(),
+            Capacity::default(),
+            None,
         ));
 
         let hc = httpc_test::new_client(format!("http://localhost:{}", port))?;
@@ -361,20 +586,15 @@ mod tests {
         let bandwidth_rates: &'static [GBRateCard] = Box::leak(vec![].into_boxed_slice());
         let port = 8084;
 
-        let job_id = H256::from_low_u64_be(1).encode_hex();
-
         tokio::spawn(serve(
             aws.clone(),
             regions,
-            compute_rates,
-            bandwidth_rates,
+            deployments(compute_rates, bandwidth_rates),
             SocketAddr::from(([0, 0, 0, 0], port)),
-            JobId {
-                id: job_id,
-                operator: "abc".into(),
-                contract: "xyz".into(),
-                chain: "123".into(),
-            },
+            JobOutcomes::default(),
+            Admissions::default(),
+            Capacity::default(),
+            None,
         ));
 
         let hc = httpc_test::new_client(format!("http://localhost:{}", port))?;
@@ -422,21 +642,17 @@ mod tests {
             .into_boxed_slice(),
         );
         let port = 8085;
-
-        let job_id = H256::from_low_u64_be(1).encode_hex();
+        let deployments = deployments(compute_rates, bandwidth_rates);
 
         tokio::spawn(serve(
             aws.clone(),
             regions,
-            compute_rates,
-            bandwidth_rates,
+            deployments,
             SocketAddr::from(([0, 0, 0, 0], port)),
-            JobId {
-                id: job_id,
-                operator: "abc".into(),
-                contract: "xyz".into(),
-                chain: "123".into(),
-            },
+            JobOutcomes::default(),
+            Admissions::default(),
+            Capacity::default(),
+            None,
         ));
 
         let hc = httpc_test::new_client(format!("http://localhost:{}", port))?;
@@ -453,6 +669,422 @@ mod tests {
 
         assert_eq!(body, bandwidth_rates);
 
+        // reloaded rates are served right away
+        deployments[0].inputs.store(MarketInputs {
+            gb_rates: bandwidth_rates[..1].to_vec(),
+            ..Default::default()
+        });
+        let body = json!(hc.do_get("/bandwidth").await?.json_body()?);
+        let body: Vec<GBRateCard> =
+            serde_json::from_value(body.get("rates").unwrap().clone()).unwrap_or_default();
+
+        assert_eq!(body, bandwidth_rates[..1]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_outcome_request() -> anyhow::Result<()> {
+        let aws: TestAws = Default::default();
+        let regions: &'static [String] =
+            Box::leak(vec![String::from("ap-south-1")].into_boxed_slice());
+        let compute_rates: &'static [RegionalRates] = Box::leak(vec![].into_boxed_slice());
+        let bandwidth_rates: &'static [GBRateCard] = Box::leak(vec![].into_boxed_slice());
+        let port = 8086;
+
+        let job_id = H256::from_low_u64_be(1).encode_hex();
+        let outcomes = JobOutcomes::default();
+        outcomes.record(
+            &JobId {
+                id: job_id.clone(),
+                operator: "abc".into(),
+                contract: "xyz".into(),
+                chain: "123".into(),
+            },
+            JobOutcome::PolicyRejected("owner address not allowed".to_owned()),
+        );
+
+        tokio::spawn(serve(
+            aws.clone(),
+            regions,
+            deployments(compute_rates, bandwidth_rates),
+            SocketAddr::from(([0, 0, 0, 0], port)),
+            outcomes,
+            Admissions::default(),
+            Capacity::default(),
+            None,
+        ));
+
+        let hc = httpc_test::new_client(format!("http://localhost:{}", port))?;
+
+        let res = hc.do_get(&format!("/outcome?id={}", job_id)).await?;
+        assert_eq!(res.status(), 200);
+
+        let body = res.json_body();
+        assert!(body.is_ok());
+        assert_eq!(
+            body.unwrap(),
+            json!({"outcome": "policy_rejected", "reason": "owner address not allowed"})
+        );
+
+        let res = hc
+            .do_get(&format!(
+                "/outcome?id={}",
+                H256::from_low_u64_be(2).encode_hex()
+            ))
+            .await?;
+        assert_eq!(res.status(), 400);
+
+        let res = hc.do_get("/outcomes").await?;
+        assert_eq!(res.status(), 200);
+        assert_eq!(
+            res.json_body()?,
+            json!({"terminated": 0, "recoverable": 0, "unrecoverable": 0, "policy_rejected": 1})
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_admission_request() -> anyhow::Result<()> {
+        let aws: TestAws = Default::default();
+        let regions: &'static [String] =
+            Box::leak(vec![String::from("ap-south-1")].into_boxed_slice());
+        let compute_rates: &'static [RegionalRates] = Box::leak(vec![].into_boxed_slice());
+        let bandwidth_rates: &'static [GBRateCard] = Box::leak(vec![].into_boxed_slice());
+        let port = 8091;
+
+        let job_id = H256::from_low_u64_be(1).encode_hex();
+        let owner = H256::from_low_u64_be(7).encode_hex();
+        let admissions = Admissions::default();
+        admissions.restore(
+            &JobId {
+                id: job_id.clone(),
+                operator: "abc".into(),
+                contract: "xyz".into(),
+                chain: "123".into(),
+            },
+            AdmissionDecision {
+                owner: owner.clone(),
+                allowed: false,
+                rule: "address-blacklist".to_owned(),
+                reason: "owner address not allowed".to_owned(),
+                priority: 0,
+            },
+        );
+
+        tokio::spawn(serve(
+            aws.clone(),
+            regions,
+            deployments(compute_rates, bandwidth_rates),
+            SocketAddr::from(([0, 0, 0, 0], port)),
+            JobOutcomes::default(),
+            admissions,
+            Capacity::default(),
+            None,
+        ));
+
+        let hc = httpc_test::new_client(format!("http://localhost:{}", port))?;
+
+        let res = hc
+            .do_get(&format!("/admission?id={}&chain=123&contract=xyz", job_id))
+            .await?;
+        assert_eq!(res.status(), 200);
+        assert_eq!(
+            res.json_body()?,
+            json!({
+                "owner": owner,
+                "allowed": false,
+                "rule": "address-blacklist",
+                "reason": "owner address not allowed",
+                "priority": 0,
+                "active": true,
+            })
+        );
+
+        // job not opened yet
+        let res = hc
+            .do_get(&format!(
+                "/admission?id={}",
+                H256::from_low_u64_be(2).encode_hex()
+            ))
+            .await?;
+        assert_eq!(res.status(), 400);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_queue_request() -> anyhow::Result<()> {
+        let aws: TestAws = Default::default();
+        let regions: &'static [String] =
+            Box::leak(vec![String::from("ap-south-1")].into_boxed_slice());
+        let compute_rates: &'static [RegionalRates] = Box::leak(vec![].into_boxed_slice());
+        let bandwidth_rates: &'static [GBRateCard] = Box::leak(vec![].into_boxed_slice());
+        let port = 8092;
+
+        let job_id = |id: u64| JobId {
+            id: H256::from_low_u64_be(id).encode_hex(),
+            operator: "abc".into(),
+            contract: "xyz".into(),
+            chain: "123".into(),
+        };
+        let slot = Slot {
+            region: "ap-south-1".into(),
+            instance_type: "c6a.xlarge".into(),
+            vcpus: 4,
+        };
+        let capacity = Capacity::new(CapacityLimits {
+            max_vcpus: Some(4),
+            ..Default::default()
+        });
+        assert_eq!(capacity.acquire(&job_id(1), &slot, 0), Ok(()));
+        assert_eq!(capacity.acquire(&job_id(2), &slot, 0), Err(1));
+
+        tokio::spawn(serve(
+            aws.clone(),
+            regions,
+            deployments(compute_rates, bandwidth_rates),
+            SocketAddr::from(([0, 0, 0, 0], port)),
+            JobOutcomes::default(),
+            Admissions::default(),
+            capacity,
+            None,
+        ));
+
+        let hc = httpc_test::new_client(format!("http://localhost:{}", port))?;
+
+        let res = hc.do_get(&format!("/queue?id={}", job_id(2).id)).await?;
+        assert_eq!(res.status(), 200);
+        assert_eq!(
+            res.json_body()?,
+            json!({
+                "status": "queued",
+                "position": 1,
+                "length": 1,
+                "slot": {"region": "ap-south-1", "instance_type": "c6a.xlarge", "vcpus": 4},
+            })
+        );
+
+        let res = hc.do_get(&format!("/queue?id={}", job_id(1).id)).await?;
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.json_body()?["status"], "launched");
+
+        // not launching
+        let res = hc.do_get(&format!("/queue?id={}", job_id(3).id)).await?;
+        assert_eq!(res.status(), 400);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_route_by_deployment() -> anyhow::Result<()> {
+        let mut aws: TestAws = Default::default();
+        let job_id = H256::from_low_u64_be(1).encode_hex();
+        let instance_metadata = InstanceMetadata::new(None, None).await;
+        aws.instances.insert(job_id.clone(), instance_metadata);
+
+        let regions: &'static [String] =
+            Box::leak(vec![String::from("ap-south-1")].into_boxed_slice());
+        let rates_1: &'static [RegionalRates] = Box::leak(
+            vec![RegionalRates {
+                region: String::from("ap-south-1"),
+                rate_cards: vec![],
+            }]
+            .into_boxed_slice(),
+        );
+        let rates_2: &'static [RegionalRates] = Box::leak(
+            vec![RegionalRates {
+                region: String::from("us-east-1"),
+                rate_cards: vec![],
+            }]
+            .into_boxed_slice(),
+        );
+        let bandwidth_rates: &'static [GBRateCard] = Box::leak(vec![].into_boxed_slice());
+        let deployments: &'static [Deployment] = Box::leak(
+            vec![
+                deployment("123", "0xAbC", rates_1, bandwidth_rates),
+                deployment("456", "0xdef", rates_2, bandwidth_rates),
+            ]
+            .into_boxed_slice(),
+        );
+        let port = 8087;
+
+        tokio::spawn(serve(
+            aws.clone(),
+            regions,
+            deployments,
+            SocketAddr::from(([0, 0, 0, 0], port)),
+            JobOutcomes::default(),
+            Admissions::default(),
+            Capacity::default(),
+            None,
+        ));
+
+        let hc = httpc_test::new_client(format!("http://localhost:{}", port))?;
+
+        // contract addresses are matched case insensitively
+        let res = hc.do_get("/spec?chain=123&contract=0xabc").await?;
+        assert_eq!(res.status(), 200);
+        let body = json!(res.json_body()?);
+        let body: Vec<RegionalRates> =
+            serde_json::from_value(body.get("min_rates").unwrap().clone()).unwrap();
+        assert_eq!(body, rates_1);
+
+        let res = hc.do_get("/spec?chain=456&contract=0xdef").await?;
+        assert_eq!(res.status(), 200);
+        let body = json!(res.json_body()?);
+        let body: Vec<RegionalRates> =
+            serde_json::from_value(body.get("min_rates").unwrap().clone()).unwrap();
+        assert_eq!(body, rates_2);
+
+        // ambiguous without chain and contract
+        let res = hc.do_get("/spec").await?;
+        assert_eq!(res.status(), 400);
+
+        let res = hc.do_get("/spec?chain=456&contract=0xabc").await?;
+        assert_eq!(res.status(), 400);
+
+        let res = hc
+            .do_get(&format!(
+                "/ip?id={}&region=ap-south-1&chain=123&contract=0xabc",
+                job_id
+            ))
+            .await?;
+        assert_eq!(res.status(), 200);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_shadow_actions() -> anyhow::Result<()> {
+        let actions = ShadowActions::default();
+        let mut shadow = ShadowInfra::new(TestAws::default(), actions.clone());
+        let job_1 = JobId {
+            id: H256::from_low_u64_be(1).encode_hex(),
+            operator: "abc".into(),
+            contract: "xyz".into(),
+            chain: "123".into(),
+        };
+        let job_2 = JobId {
+            id: H256::from_low_u64_be(2).encode_hex(),
+            ..job_1.clone()
+        };
+        shadow.spin_down("i-1", &job_1, "ap-south-1").await?;
+        shadow.spin_down("i-2", &job_2, "ap-south-1").await?;
+
+        let regions: &'static [String] =
+            Box::leak(vec![String::from("ap-south-1")].into_boxed_slice());
+        let compute_rates: &'static [RegionalRates] = Box::leak(vec![].into_boxed_slice());
+        let bandwidth_rates: &'static [GBRateCard] = Box::leak(vec![].into_boxed_slice());
+        let port = 8088;
+
+        tokio::spawn(serve(
+            shadow,
+            regions,
+            deployments(compute_rates, bandwidth_rates),
+            SocketAddr::from(([0, 0, 0, 0], port)),
+            JobOutcomes::default(),
+            Admissions::default(),
+            Capacity::default(),
+            Some(actions),
+        ));
+
+        let hc = httpc_test::new_client(format!("http://localhost:{}", port))?;
+
+        let res = hc.do_get("/shadow/actions").await?;
+        assert_eq!(res.status(), 200);
+        let body = res.json_body()?;
+        assert_eq!(body.as_array().unwrap().len(), 2);
+
+        let res = hc
+            .do_get(&format!("/shadow/actions?id={}", job_2.id))
+            .await?;
+        assert_eq!(res.status(), 200);
+        let body = res.json_body()?;
+        assert_eq!(body.as_array().unwrap().len(), 1);
+        assert_eq!(body[0]["instance_id"], "i-2");
+        assert_eq!(body[0]["action"], "spin_down");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_shadow_actions_disabled() -> anyhow::Result<()> {
+        let regions: &'static [String] =
+            Box::leak(vec![String::from("ap-south-1")].into_boxed_slice());
+        let compute_rates: &'static [RegionalRates] = Box::leak(vec![].into_boxed_slice());
+        let bandwidth_rates: &'static [GBRateCard] = Box::leak(vec![].into_boxed_slice());
+        let port = 8089;
+
+        tokio::spawn(serve(
+            TestAws::default(),
+            regions,
+            deployments(compute_rates, bandwidth_rates),
+            SocketAddr::from(([0, 0, 0, 0], port)),
+            JobOutcomes::default(),
+            Admissions::default(),
+            Capacity::default(),
+            None,
+        ));
+
+        let hc = httpc_test::new_client(format!("http://localhost:{}", port))?;
+
+        let res = hc.do_get("/shadow/actions").await?;
+        assert_eq!(res.status(), 404);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_pcrs() -> anyhow::Result<()> {
+        let mut aws: TestAws = Default::default();
+        let job_id = H256::from_low_u64_be(1).encode_hex();
+        let pcrs = Pcrs {
+            pcr0: "a".repeat(96),
+            pcr1: "b".repeat(96),
+            pcr2: "c".repeat(96),
+        };
+        aws.pcrs.insert(job_id.clone(), pcrs.clone());
+
+        let regions: &'static [String] =
+            Box::leak(vec![String::from("ap-south-1")].into_boxed_slice());
+        let compute_rates: &'static [RegionalRates] = Box::leak(vec![].into_boxed_slice());
+        let bandwidth_rates: &'static [GBRateCard] = Box::leak(vec![].into_boxed_slice());
+        let port = 8090;
+
+        tokio::spawn(serve(
+            aws.clone(),
+            regions,
+            deployments(compute_rates, bandwidth_rates),
+            SocketAddr::from(([0, 0, 0, 0], port)),
+            JobOutcomes::default(),
+            Admissions::default(),
+            Capacity::default(),
+            None,
+        ));
+
+        let hc = httpc_test::new_client(format!("http://localhost:{}", port))?;
+
+        let res = hc
+            .do_get(&format!("/pcrs?id={}&region=ap-south-1", job_id))
+            .await?;
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.json_body()?, json!(pcrs));
+
+        // unknown job
+        let res = hc
+            .do_get(&format!(
+                "/pcrs?id={}&region=ap-south-1",
+                H256::from_low_u64_be(2).encode_hex()
+            ))
+            .await?;
+        assert_eq!(res.status(), 400);
+
+        // missing region
+        let res = hc.do_get(&format!("/pcrs?id={}", job_id)).await?;
+        assert_eq!(res.status(), 400);
+
         Ok(())
     }
 }
@@ -0,0 +1,484 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::market::{InfraProvider, JobId};
+use crate::pcr::Pcrs;
+
+// Number of intended actions kept in memory, older ones are only in the log
+pub const SHADOW_ACTIONS_LIMIT: usize = 10000;
+
+// A mutation the control plane would have made to the infrastructure
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    SpinUp {
+        instance_type: String,
+        family: String,
+        eif_url: String,
+        req_mem: i64,
+        req_vcpu: i32,
+        bandwidth: u64,
+    },
+    SpinDown,
+    RunEnclave {
+        family: String,
+        eif_url: String,
+        req_mem: i64,
+        req_vcpu: i32,
+        bandwidth: u64,
+    },
+    UpdateEnclaveImage {
+        eif_url: String,
+        req_mem: i64,
+        req_vcpu: i32,
+    },
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct IntendedAction {
+    // unix timestamp in seconds
+    pub time: u64,
+    // not known for enclave image updates of instances not launched in shadow mode
+    pub job: Option<JobId>,
+    pub region: String,
+    pub instance_id: String,
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+// Intended actions in the order they were taken, shared with the http server
+#[derive(Clone, Default)]
+pub struct ShadowActions(Arc<Mutex<VecDeque<IntendedAction>>>);
+
+impl ShadowActions {
+    pub fn record(&self, action: IntendedAction) {
+        let job = action.job.as_ref().map_or("-", |job| job.id.as_str());
+        println!(
+            "shadow: job {job}: would {} {} in {}",
+            action_name(&action.action),
+            action.instance_id,
+            action.region
+        );
+
+        let mut actions = self.0.lock().unwrap();
+        if actions.len() == SHADOW_ACTIONS_LIMIT {
+            actions.pop_front();
+        }
+        actions.push_back(action);
+    }
+
+    pub fn list(&self) -> Vec<IntendedAction> {
+        self.0.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+fn action_name(action: &Action) -> &'static str {
+    match action {
+        Action::SpinUp { .. } => "spin up",
+        Action::SpinDown => "spin down",
+        Action::RunEnclave { .. } => "run enclave on",
+        Action::UpdateEnclaveImage { .. } => "update enclave image on",
+    }
+}
+
+// What the infrastructure would look like if the intended actions had been taken
+#[derive(Default)]
+struct Overlay {
+    // job to (instance id, region) of instances launched in shadow mode
+    launched: HashMap<JobId, (String, String)>,
+    // instances terminated in shadow mode, real or not
+    terminated: HashSet<String>,
+    count: u64,
+}
+
+// InfraProvider that never touches the infrastructure
+// Reads pass through to the wrapped provider while mutations are only recorded,
+// so a new version can run next to production and be diffed against it
+// Instances launched in shadow mode get placeholder ids which are reported as running
+#[derive(Clone)]
+pub struct ShadowInfra<I> {
+    inner: I,
+    actions: ShadowActions,
+    overlay: Arc<Mutex<Overlay>>,
+}
+
+impl<I> ShadowInfra<I> {
+    pub fn new(inner: I, actions: ShadowActions) -> ShadowInfra<I> {
+        ShadowInfra {
+            inner,
+            actions,
+            overlay: Default::default(),
+        }
+    }
+
+    fn record(&self, job: Option<&JobId>, region: &str, instance_id: &str, action: Action) {
+        self.actions.record(IntendedAction {
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs()),
+            job: job.cloned(),
+            region: region.to_owned(),
+            instance_id: instance_id.to_owned(),
+            action,
+        });
+    }
+
+    fn is_shadow_instance(&self, instance_id: &str) -> bool {
+        self.overlay
+            .lock()
+            .unwrap()
+            .launched
+            .values()
+            .any(|(id, _)| id == instance_id)
+    }
+
+    fn is_terminated(&self, instance_id: &str) -> bool {
+        self.overlay
+            .lock()
+            .unwrap()
+            .terminated
+            .contains(instance_id)
+    }
+}
+
+impl<I: InfraProvider + Send + Sync> InfraProvider for ShadowInfra<I> {
+    async fn spin_up(
+        &mut self,
+        eif_url: &str,
+        job: &JobId,
+        instance_type: &str,
+        family: &str,
+        region: &str,
+        req_mem: i64,
+        req_vcpu: i32,
+        bandwidth: u64,
+    ) -> Result<String> {
+        let instance_id = {
+            let mut overlay = self.overlay.lock().unwrap();
+            overlay.count += 1;
+            let instance_id = format!("shadow-{}", overlay.count);
+            overlay
+                .launched
+                .insert(job.clone(), (instance_id.clone(), region.to_owned()));
+            instance_id
+        };
+
+        self.record(
+            Some(job),
+            region,
+            &instance_id,
+            Action::SpinUp {
+                instance_type: instance_type.to_owned(),
+                family: family.to_owned(),
+                eif_url: eif_url.to_owned(),
+                req_mem,
+                req_vcpu,
+                bandwidth,
+            },
+        );
+
+        Ok(instance_id)
+    }
+
+    async fn spin_down(&mut self, instance_id: &str, job: &JobId, region: &str) -> Result<()> {
+        {
+            let mut overlay = self.overlay.lock().unwrap();
+            if overlay
+                .launched
+                .get(job)
+                .is_some_and(|(id, _)| id == instance_id)
+            {
+                overlay.launched.remove(job);
+            }
+            overlay.terminated.insert(instance_id.to_owned());
+        }
+
+        self.record(Some(job), region, instance_id, Action::SpinDown);
+
+        Ok(())
+    }
+
+    async fn get_job_instance(&self, job: &JobId, region: &str) -> Result<(bool, String, String)> {
+        let launched = self.overlay.lock().unwrap().launched.get(job).cloned();
+        if let Some((instance_id, launched_region)) = launched {
+            if launched_region == region {
+                return Ok((true, instance_id, "running".to_owned()));
+            }
+        }
+
+        let (exists, instance_id, state) = self.inner.get_job_instance(job, region).await?;
+        if exists && self.is_terminated(&instance_id) {
+            return Ok((exists, instance_id, "terminated".to_owned()));
+        }
+
+        Ok((exists, instance_id, state))
+    }
+
+    async fn get_job_ip(&self, job: &JobId, region: &str) -> Result<String> {
+        self.inner.get_job_ip(job, region).await
+    }
+
+    async fn get_job_pcrs(&self, job: &JobId, region: &str) -> Result<Pcrs> {
+        self.inner.get_job_pcrs(job, region).await
+    }
+
+    fn restore_job_pcrs(&mut self, job: &JobId, instance_id: &str, pcrs: Pcrs) {
+        self.inner.restore_job_pcrs(job, instance_id, pcrs)
+    }
+
+    async fn check_instance_running(&mut self, instance_id: &str, region: &str) -> Result<bool> {
+        if self.is_terminated(instance_id) {
+            return Ok(false);
+        }
+        if self.is_shadow_instance(instance_id) {
+            return Ok(true);
+        }
+
+        self.inner.check_instance_running(instance_id, region).await
+    }
+
+    async fn check_enclave_running(&mut self, instance_id: &str, region: &str) -> Result<bool> {
+        if self.is_shadow_instance(instance_id) {
+            return Ok(true);
+        }
+
+        self.inner.check_enclave_running(instance_id, region).await
+    }
+
+    async fn run_enclave(
+        &mut self,
+        job: &JobId,
+        instance_id: &str,
+        family: &str,
+        region: &str,
+        image_url: &str,
+        req_vcpu: i32,
+        req_mem: i64,
+        bandwidth: u64,
+    ) -> Result<()> {
+        self.record(
+            Some(job),
+            region,
+            instance_id,
+            Action::RunEnclave {
+                family: family.to_owned(),
+                eif_url: image_url.to_owned(),
+                req_mem,
+                req_vcpu,
+                bandwidth,
+            },
+        );
+
+        Ok(())
+    }
+
+    async fn update_enclave_image(
+        &mut self,
+        instance_id: &str,
+        region: &str,
+        eif_url: &str,
+        req_vcpu: i32,
+        req_mem: i64,
+    ) -> Result<()> {
+        let job = self
+            .overlay
+            .lock()
+            .unwrap()
+            .launched
+            .iter()
+            .find(|(_, (id, _))| id == instance_id)
+            .map(|(job, _)| job.clone());
+
+        self.record(
+            job.as_ref(),
+            region,
+            instance_id,
+            Action::UpdateEnclaveImage {
+                eif_url: eif_url.to_owned(),
+                req_mem,
+                req_vcpu,
+            },
+        );
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::abi::AbiEncode;
+    use ethers::types::H256;
+
+    use super::{Action, ShadowActions, ShadowInfra};
+    use crate::market::{InfraProvider, JobId};
+    use crate::test::{InstanceMetadata, TestAws};
+
+    fn job_id(id: u64) -> JobId {
+        JobId {
+            id: H256::from_low_u64_be(id).encode_hex(),
+            operator: "abc".into(),
+            contract: "xyz".into(),
+            chain: "123".into(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mutations_are_recorded() -> anyhow::Result<()> {
+        let actions = ShadowActions::default();
+        let mut shadow = ShadowInfra::new(TestAws::default(), actions.clone());
+        let job = job_id(1);
+
+        let instance_id = shadow
+            .spin_up(
+                "https://example.com/enclave.eif",
+                &job,
+                "c6a.xlarge",
+                "salmon",
+                "ap-south-1",
+                4096,
+                2,
+                76,
+            )
+            .await?;
+        shadow
+            .run_enclave(
+                &job,
+                &instance_id,
+                "salmon",
+                "ap-south-1",
+                "https://example.com/enclave.eif",
+                2,
+                4096,
+                76,
+            )
+            .await?;
+        shadow
+            .update_enclave_image(
+                &instance_id,
+                "ap-south-1",
+                "https://example.com/updated.eif",
+                2,
+                4096,
+            )
+            .await?;
+
+        // the shadow instance is visible to later reads
+        assert_eq!(
+            shadow.get_job_instance(&job, "ap-south-1").await?,
+            (true, instance_id.clone(), "running".to_owned())
+        );
+        assert!(
+            shadow
+                .check_instance_running(&instance_id, "ap-south-1")
+                .await?
+        );
+        assert!(
+            shadow
+                .check_enclave_running(&instance_id, "ap-south-1")
+                .await?
+        );
+
+        shadow.spin_down(&instance_id, &job, "ap-south-1").await?;
+        assert!(!shadow.get_job_instance(&job, "ap-south-1").await?.0);
+        assert!(
+            !shadow
+                .check_instance_running(&instance_id, "ap-south-1")
+                .await?
+        );
+
+        // nothing reached the wrapped provider
+        assert!(shadow.inner.outcomes.is_empty());
+        assert!(shadow.inner.instances.is_empty());
+
+        let actions = actions.list();
+        assert_eq!(actions.len(), 4);
+        assert!(actions.iter().all(|action| action.job == Some(job.clone())
+            && action.instance_id == instance_id
+            && action.region == "ap-south-1"));
+        assert!(matches!(actions[0].action, Action::SpinUp { .. }));
+        assert!(matches!(actions[1].action, Action::RunEnclave { .. }));
+        assert_eq!(
+            actions[2].action,
+            Action::UpdateEnclaveImage {
+                eif_url: "https://example.com/updated.eif".into(),
+                req_mem: 4096,
+                req_vcpu: 2,
+            }
+        );
+        assert_eq!(actions[3].action, Action::SpinDown);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reads_pass_through() -> anyhow::Result<()> {
+        let mut aws = TestAws::default();
+        let job = job_id(1);
+        let metadata = InstanceMetadata::new(None, None).await;
+        aws.instances.insert(job.id.clone(), metadata.clone());
+
+        let actions = ShadowActions::default();
+        let mut shadow = ShadowInfra::new(aws, actions.clone());
+
+        assert_eq!(
+            shadow.get_job_instance(&job, "ap-south-1").await?,
+            (true, metadata.instance_id.clone(), "running".to_owned())
+        );
+        assert_eq!(
+            shadow.get_job_ip(&job, "ap-south-1").await?,
+            metadata.ip_address
+        );
+
+        // terminating the real instance only terminates it in the shadow view
+        shadow
+            .spin_down(&metadata.instance_id, &job, "ap-south-1")
+            .await?;
+        assert_eq!(
+            shadow.get_job_instance(&job, "ap-south-1").await?.2,
+            "terminated"
+        );
+        assert!(
+            !shadow
+                .check_instance_running(&metadata.instance_id, "ap-south-1")
+                .await?
+        );
+        assert!(shadow.inner.outcomes.is_empty());
+        assert_eq!(shadow.inner.instances.len(), 1);
+        assert_eq!(actions.list().len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_action_serialization() -> anyhow::Result<()> {
+        let actions = ShadowActions::default();
+        let mut shadow = ShadowInfra::new(TestAws::default(), actions.clone());
+        let job = job_id(1);
+
+        shadow.spin_down("i-123", &job, "ap-south-1").await?;
+
+        let mut value = serde_json::to_value(actions.list())?;
+        value[0]["time"] = 0.into();
+        assert_eq!(
+            value,
+            serde_json::json!([{
+                "time": 0,
+                "job": {
+                    "id": job.id,
+                    "operator": "abc",
+                    "contract": "xyz",
+                    "chain": "123",
+                },
+                "region": "ap-south-1",
+                "instance_id": "i-123",
+                "action": "spin_down",
+            }])
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,542 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Result};
+use ethers::core::rand::{thread_rng, Rng};
+use ethers::utils::{hex, keccak256};
+use tokio::time::{sleep_until, Duration, Instant};
+
+use crate::market::{InfraProvider, JobId};
+use crate::pcr::Pcrs;
+use crate::reconcile::{TaggedAddress, TaggedInstance, TaggedResources};
+
+// Knobs of the simulated infrastructure
+#[derive(Clone, Debug)]
+pub struct SimulatedConfig {
+    // time spent in pending before running
+    pub boot_latency: Duration,
+    // time spent in shutting-down before terminated
+    pub shutdown_latency: Duration,
+    // probability of a launch failing
+    pub launch_failure_rate: f64,
+    // probability of an enclave failing to start
+    pub enclave_failure_rate: f64,
+}
+
+impl Default for SimulatedConfig {
+    fn default() -> Self {
+        SimulatedConfig {
+            boot_latency: Duration::from_secs(30),
+            shutdown_latency: Duration::from_secs(10),
+            launch_failure_rate: 0.0,
+            enclave_failure_rate: 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct SimulatedInstance {
+    instance_id: String,
+    job: JobId,
+    region: String,
+    ip: String,
+    req_vcpu: i32,
+    req_mem: i64,
+    eif_url: Option<String>,
+    pcrs: Option<Pcrs>,
+    launched_at: Instant,
+    terminated_at: Option<Instant>,
+}
+
+impl SimulatedInstance {
+    // follows the ec2 lifecycle, pending -> running -> shutting-down -> terminated
+    fn state(&self, config: &SimulatedConfig) -> &'static str {
+        let now = Instant::now();
+        match self.terminated_at {
+            Some(terminated_at) if now >= terminated_at + config.shutdown_latency => "terminated",
+            Some(_) => "shutting-down",
+            None if now >= self.launched_at + config.boot_latency => "running",
+            None => "pending",
+        }
+    }
+
+    fn is_alive(&self, config: &SimulatedConfig) -> bool {
+        matches!(self.state(config), "pending" | "running")
+    }
+}
+
+#[derive(Default)]
+struct SimulatedState {
+    // instance id to instance, terminated instances are kept like ec2 does for a while
+    instances: HashMap<String, SimulatedInstance>,
+    launched: u64,
+}
+
+// InfraProvider that keeps instances in memory, for running the control plane without aws
+// Clones share the same instances so job managers and the http server see the same state
+#[derive(Clone, Default)]
+pub struct SimulatedInfra {
+    config: SimulatedConfig,
+    state: Arc<Mutex<SimulatedState>>,
+}
+
+impl SimulatedInfra {
+    pub fn new(config: SimulatedConfig) -> SimulatedInfra {
+        SimulatedInfra {
+            config,
+            state: Default::default(),
+        }
+    }
+
+    fn fails(rate: f64) -> bool {
+        rate > 0.0 && thread_rng().gen_bool(rate.min(1.0))
+    }
+
+    // latest live instance of the job, or the latest one if none are alive
+    fn job_instance(&self, job: &JobId) -> Option<SimulatedInstance> {
+        let state = self.state.lock().unwrap();
+        state
+            .instances
+            .values()
+            .filter(|instance| instance.job == *job)
+            .max_by_key(|instance| (instance.is_alive(&self.config), instance.launched_at))
+            .cloned()
+    }
+
+    fn instance(&self, instance_id: &str, region: &str) -> Result<SimulatedInstance> {
+        let state = self.state.lock().unwrap();
+        state
+            .instances
+            .get(instance_id)
+            .filter(|instance| instance.region == region)
+            .cloned()
+            .ok_or(anyhow!("instance not found: {instance_id}"))
+    }
+
+    // enclaves can only be started once the instance is up, like over ssh
+    async fn start_enclave(
+        &mut self,
+        instance_id: &str,
+        region: &str,
+        eif_url: &str,
+        req_vcpu: i32,
+        req_mem: i64,
+    ) -> Result<()> {
+        let instance = self.instance(instance_id, region)?;
+        if !instance.is_alive(&self.config) {
+            return Err(anyhow!("instance is not running: {instance_id}"));
+        }
+        sleep_until(instance.launched_at + self.config.boot_latency).await;
+
+        if Self::fails(self.config.enclave_failure_rate) {
+            return Err(anyhow!("simulated enclave failure"));
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let instance = state
+            .instances
+            .get_mut(instance_id)
+            .ok_or(anyhow!("instance not found: {instance_id}"))?;
+        instance.eif_url = Some(eif_url.to_owned());
+        instance.pcrs = Some(simulated_pcrs(eif_url));
+        instance.req_vcpu = req_vcpu;
+        instance.req_mem = req_mem;
+
+        Ok(())
+    }
+}
+
+// stable per image url so the same image always reports the same measurements
+fn simulated_pcrs(eif_url: &str) -> Pcrs {
+    let pcr = |index: u8| {
+        format!(
+            "{:0>96}",
+            hex::encode(keccak256(format!("{index}{eif_url}")))
+        )
+    };
+
+    Pcrs {
+        pcr0: pcr(0),
+        pcr1: pcr(1),
+        pcr2: pcr(2),
+    }
+}
+
+impl InfraProvider for SimulatedInfra {
+    async fn spin_up(
+        &mut self,
+        _eif_url: &str,
+        job: &JobId,
+        instance_type: &str,
+        family: &str,
+        region: &str,
+        req_mem: i64,
+        req_vcpu: i32,
+        _bandwidth: u64,
+    ) -> Result<String> {
+        if Self::fails(self.config.launch_failure_rate) {
+            return Err(anyhow!("simulated launch failure"));
+        }
+
+        let mut state = self.state.lock().unwrap();
+        state.launched += 1;
+        let launched = state.launched;
+
+        // fake ips from the private range, unique per launch
+        let instance = SimulatedInstance {
+            instance_id: format!("i-sim{launched:013x}"),
+            job: job.clone(),
+            region: region.to_owned(),
+            ip: format!(
+                "10.{}.{}.{}",
+                (launched >> 16) & 255,
+                (launched >> 8) & 255,
+                launched & 255
+            ),
+            req_vcpu,
+            req_mem,
+            eif_url: None,
+            pcrs: None,
+            launched_at: Instant::now(),
+            terminated_at: None,
+        };
+        println!(
+            "simulated: launched {} ({instance_type}, {family}) in {region} for job {}",
+            instance.instance_id, job.id
+        );
+        let instance_id = instance.instance_id.clone();
+        state.instances.insert(instance_id.clone(), instance);
+
+        Ok(instance_id)
+    }
+
+    async fn spin_down(&mut self, instance_id: &str, _job: &JobId, region: &str) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let instance = state
+            .instances
+            .get_mut(instance_id)
+            .filter(|instance| instance.region == region)
+            .ok_or(anyhow!("instance not found: {instance_id}"))?;
+
+        if instance.terminated_at.is_none() {
+            println!("simulated: terminating {instance_id}");
+            instance.terminated_at = Some(Instant::now());
+            instance.eif_url = None;
+        }
+
+        Ok(())
+    }
+
+    async fn get_job_instance(&self, job: &JobId, region: &str) -> Result<(bool, String, String)> {
+        match self.job_instance(job) {
+            Some(instance) if instance.region == region => Ok((
+                true,
+                instance.instance_id.clone(),
+                instance.state(&self.config).to_owned(),
+            )),
+            _ => Ok((false, String::new(), String::new())),
+        }
+    }
+
+    async fn get_job_ip(&self, job: &JobId, region: &str) -> Result<String> {
+        match self.job_instance(job) {
+            Some(instance) if instance.region == region && instance.is_alive(&self.config) => {
+                Ok(instance.ip)
+            }
+            _ => Err(anyhow!("Instance not found for job - {}", job.id)),
+        }
+    }
+
+    async fn get_job_pcrs(&self, job: &JobId, region: &str) -> Result<Pcrs> {
+        match self.job_instance(job) {
+            Some(instance) if instance.region == region && instance.is_alive(&self.config) => {
+                instance
+                    .pcrs
+                    .ok_or(anyhow!("no enclave running for job - {}", job.id))
+            }
+            _ => Err(anyhow!("Instance not found for job - {}", job.id)),
+        }
+    }
+
+    fn restore_job_pcrs(&mut self, _job: &JobId, instance_id: &str, pcrs: Pcrs) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(instance) = state.instances.get_mut(instance_id) {
+            instance.pcrs.get_or_insert(pcrs);
+        }
+    }
+
+    async fn check_instance_running(&mut self, instance_id: &str, region: &str) -> Result<bool> {
+        let instance = self.instance(instance_id, region)?;
+        Ok(instance.is_alive(&self.config))
+    }
+
+    async fn check_enclave_running(&mut self, instance_id: &str, region: &str) -> Result<bool> {
+        let instance = self.instance(instance_id, region)?;
+        Ok(instance.state(&self.config) == "running" && instance.eif_url.is_some())
+    }
+
+    async fn run_enclave(
+        &mut self,
+        _job: &JobId,
+        instance_id: &str,
+        _family: &str,
+        region: &str,
+        image_url: &str,
+        req_vcpu: i32,
+        req_mem: i64,
+        _bandwidth: u64,
+    ) -> Result<()> {
+        self.start_enclave(instance_id, region, image_url, req_vcpu, req_mem)
+            .await
+    }
+
+    async fn update_enclave_image(
+        &mut self,
+        instance_id: &str,
+        region: &str,
+        eif_url: &str,
+        req_vcpu: i32,
+        req_mem: i64,
+    ) -> Result<()> {
+        let instance = self.instance(instance_id, region)?;
+        if instance.req_vcpu != req_vcpu || instance.req_mem != req_mem {
+            return Err(anyhow!("Can only change EIF URL"));
+        }
+
+        self.start_enclave(instance_id, region, eif_url, req_vcpu, req_mem)
+            .await
+    }
+}
+
+// simulated instances have no elastic ips
+impl TaggedResources for SimulatedInfra {
+    async fn tagged_instances(
+        &self,
+        deployment: &JobId,
+        region: &str,
+    ) -> Result<Vec<TaggedInstance>> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .instances
+            .values()
+            .filter(|instance| {
+                instance.region == region
+                    && instance.is_alive(&self.config)
+                    && instance.job.operator == deployment.operator
+                    && instance.job.contract == deployment.contract
+                    && instance.job.chain == deployment.chain
+            })
+            .map(|instance| TaggedInstance {
+                instance_id: instance.instance_id.clone(),
+                job: instance.job.clone(),
+                state: instance.state(&self.config).to_owned(),
+            })
+            .collect())
+    }
+
+    async fn tagged_addresses(
+        &self,
+        _deployment: &JobId,
+        _region: &str,
+    ) -> Result<Vec<TaggedAddress>> {
+        Ok(Vec::new())
+    }
+
+    async fn terminate_orphan(&self, instance: &TaggedInstance, region: &str) -> Result<()> {
+        self.clone()
+            .spin_down(&instance.instance_id, &instance.job, region)
+            .await
+    }
+
+    async fn release_orphan(&self, address: &TaggedAddress, _region: &str) -> Result<()> {
+        Err(anyhow!(
+            "no elastic ips in simulated infra: {}",
+            address.allocation_id
+        ))
+    }
+
+    // spin ups are not split into steps that could leak anything
+    fn launch_pending(&self, _job: &JobId) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::{sleep, Duration};
+
+    use super::{SimulatedConfig, SimulatedInfra};
+    use crate::market::{InfraProvider, JobId};
+
+    fn job_id(id: &str) -> JobId {
+        JobId {
+            id: id.into(),
+            operator: "abc".into(),
+            contract: "xyz".into(),
+            chain: "123".into(),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_instance_lifecycle() -> anyhow::Result<()> {
+        let mut infra = SimulatedInfra::new(SimulatedConfig {
+            boot_latency: Duration::from_secs(30),
+            shutdown_latency: Duration::from_secs(10),
+            ..Default::default()
+        });
+        let job = job_id("1");
+
+        assert_eq!(
+            infra.get_job_instance(&job, "ap-south-1").await?,
+            (false, String::new(), String::new())
+        );
+
+        let instance_id = infra
+            .spin_up(
+                "https://example.com/enclave.eif",
+                &job,
+                "c6a.xlarge",
+                "salmon",
+                "ap-south-1",
+                4096,
+                2,
+                76,
+            )
+            .await?;
+        let (exists, id, state) = infra.get_job_instance(&job, "ap-south-1").await?;
+        assert!(exists);
+        assert_eq!(id, instance_id);
+        assert_eq!(state, "pending");
+        assert!(
+            infra
+                .check_instance_running(&instance_id, "ap-south-1")
+                .await?
+        );
+        assert!(
+            !infra
+                .check_enclave_running(&instance_id, "ap-south-1")
+                .await?
+        );
+        let ip = infra.get_job_ip(&job, "ap-south-1").await?;
+        assert!(infra.get_job_pcrs(&job, "ap-south-1").await.is_err());
+
+        // enclave starts once the instance has booted
+        infra
+            .run_enclave(
+                &job,
+                &instance_id,
+                "salmon",
+                "ap-south-1",
+                "https://example.com/enclave.eif",
+                2,
+                4096,
+                76,
+            )
+            .await?;
+        assert_eq!(
+            infra.get_job_instance(&job, "ap-south-1").await?.2,
+            "running"
+        );
+        assert!(
+            infra
+                .check_enclave_running(&instance_id, "ap-south-1")
+                .await?
+        );
+        let pcrs = infra.get_job_pcrs(&job, "ap-south-1").await?;
+        assert_eq!(pcrs.pcr0.len(), 96);
+        assert_ne!(pcrs.pcr0, pcrs.pcr2);
+
+        // clones share instances
+        let server_view = infra.clone();
+        assert_eq!(server_view.get_job_ip(&job, "ap-south-1").await?, ip);
+
+        infra.spin_down(&instance_id, &job, "ap-south-1").await?;
+        assert_eq!(
+            infra.get_job_instance(&job, "ap-south-1").await?.2,
+            "shutting-down"
+        );
+        assert!(
+            !infra
+                .check_instance_running(&instance_id, "ap-south-1")
+                .await?
+        );
+        assert!(server_view.get_job_ip(&job, "ap-south-1").await.is_err());
+
+        sleep(Duration::from_secs(10)).await;
+        assert_eq!(
+            infra.get_job_instance(&job, "ap-south-1").await?.2,
+            "terminated"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_relaunch_gets_new_instance() -> anyhow::Result<()> {
+        let mut infra = SimulatedInfra::default();
+        let job = job_id("1");
+
+        let first = infra
+            .spin_up("", &job, "c6a.xlarge", "salmon", "ap-south-1", 4096, 2, 76)
+            .await?;
+        let first_ip = infra.get_job_ip(&job, "ap-south-1").await?;
+        infra.spin_down(&first, &job, "ap-south-1").await?;
+
+        let second = infra
+            .spin_up("", &job, "c6a.xlarge", "salmon", "ap-south-1", 4096, 2, 76)
+            .await?;
+        assert_ne!(first, second);
+
+        // live instance wins over the terminated one
+        assert_eq!(infra.get_job_instance(&job, "ap-south-1").await?.1, second);
+        assert_ne!(infra.get_job_ip(&job, "ap-south-1").await?, first_ip);
+
+        // other regions do not see the instance
+        assert!(!infra.get_job_instance(&job, "us-east-1").await?.0);
+
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failures() -> anyhow::Result<()> {
+        let mut infra = SimulatedInfra::new(SimulatedConfig {
+            launch_failure_rate: 1.0,
+            ..Default::default()
+        });
+        let job = job_id("1");
+
+        assert!(infra
+            .spin_up("", &job, "c6a.xlarge", "salmon", "ap-south-1", 4096, 2, 76)
+            .await
+            .is_err());
+        assert!(!infra.get_job_instance(&job, "ap-south-1").await?.0);
+
+        let mut infra = SimulatedInfra::new(SimulatedConfig {
+            enclave_failure_rate: 1.0,
+            ..Default::default()
+        });
+        let instance_id = infra
+            .spin_up("", &job, "c6a.xlarge", "salmon", "ap-south-1", 4096, 2, 76)
+            .await?;
+        assert!(infra
+            .run_enclave(
+                &job,
+                &instance_id,
+                "salmon",
+                "ap-south-1",
+                "https://example.com/enclave.eif",
+                2,
+                4096,
+                76
+            )
+            .await
+            .is_err());
+        assert!(
+            !infra
+                .check_enclave_running(&instance_id, "ap-south-1")
+                .await?
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,243 @@
+use anyhow::{anyhow, Context, Result};
+use base64::engine::general_purpose::STANDARD_NO_PAD;
+use base64::Engine;
+use ssh2::{HashType, Session};
+use std::io::Read;
+use std::net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::task::spawn_blocking;
+use tokio::time::{timeout, Duration};
+
+use crate::command::RemoteCommand;
+
+// Deadlines of the different ssh stages
+#[derive(Clone, Copy, Debug)]
+pub struct SshTimeouts {
+    pub connect: Duration,
+    pub handshake: Duration,
+    // enclave images are downloaded over ssh, so this has to allow for that
+    pub command: Duration,
+}
+
+impl Default for SshTimeouts {
+    fn default() -> Self {
+        SshTimeouts {
+            connect: Duration::from_secs(10),
+            handshake: Duration::from_secs(10),
+            command: Duration::from_secs(300),
+        }
+    }
+}
+
+// ssh2 only has a blocking api, so all of it runs on the blocking pool to keep
+// slow or unresponsive instances from stalling the runtime workers
+// Dropping the session or hitting a deadline shuts the socket down,
+// which makes any blocking call still running on the pool fail right away
+pub struct SshSession {
+    session: Arc<Mutex<Session>>,
+    stream: TcpStream,
+    timeouts: SshTimeouts,
+}
+
+impl SshSession {
+    // connects and performs the handshake, the host key has to be checked before authenticating
+    pub async fn connect(addr: &str, timeouts: SshTimeouts) -> Result<SshSession> {
+        let addr = addr.to_owned();
+        let stream = run_blocking(timeouts.connect, "connect", move || {
+            let addr: SocketAddr = addr
+                .to_socket_addrs()
+                .context("Failed to resolve address")?
+                .next()
+                .ok_or(anyhow!("no address found for {addr}"))?;
+            TcpStream::connect_timeout(&addr, timeouts.connect).context("Failed to connect")
+        })
+        .await?;
+
+        let mut session = Session::new()?;
+        session.set_tcp_stream(stream.try_clone()?);
+
+        let handshake = run_blocking(timeouts.handshake, "handshake", move || {
+            // bounds every libssh2 call, the async deadlines are what callers rely on
+            session.set_timeout(timeouts.handshake.as_millis() as u32);
+            session.handshake().context("Failed to perform handshake")?;
+            Ok(session)
+        })
+        .await;
+        let session = match handshake {
+            Ok(session) => session,
+            Err(err) => {
+                let _ = stream.shutdown(Shutdown::Both);
+                return Err(err);
+            }
+        };
+
+        Ok(SshSession {
+            session: Arc::new(Mutex::new(session)),
+            stream,
+            timeouts,
+        })
+    }
+
+    // SHA256 fingerprint in the format printed by ssh-keygen -l
+    pub fn host_key_fingerprint(&self) -> Result<String> {
+        let session = self.session.lock().unwrap();
+        let hash = session
+            .host_key_hash(HashType::Sha256)
+            .ok_or(anyhow!("no host key presented"))?;
+
+        Ok("SHA256:".to_owned() + &STANDARD_NO_PAD.encode(hash))
+    }
+
+    pub async fn authenticate(&self, user: &str, key_location: &str) -> Result<()> {
+        let session = self.session.clone();
+        let user = user.to_owned();
+        let key_location = PathBuf::from(key_location);
+        let timeouts = self.timeouts;
+        let res = run_blocking(timeouts.handshake, "authentication", move || {
+            let session = session.lock().unwrap();
+            session
+                .userauth_pubkey_file(&user, None, &key_location, None)
+                .context("Failed to authenticate")?;
+            session.set_timeout(timeouts.command.as_millis() as u32);
+            Ok(())
+        })
+        .await;
+
+        if res.is_err() {
+            let _ = self.stream.shutdown(Shutdown::Both);
+            return res;
+        }
+
+        println!("SSH connection established");
+        Ok(())
+    }
+
+    // returns stdout and stderr of the command
+    pub async fn exec(&self, command: impl Into<RemoteCommand>) -> Result<(String, String)> {
+        let session = self.session.clone();
+        let command = command.into().as_str().to_owned();
+        let res = run_blocking(self.timeouts.command, "command", move || {
+            exec_blocking(&session.lock().unwrap(), &command)
+        })
+        .await;
+
+        if res.is_err() {
+            // the session is in an unknown state after a failed command
+            let _ = self.stream.shutdown(Shutdown::Both);
+        }
+
+        res
+    }
+}
+
+impl Drop for SshSession {
+    fn drop(&mut self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+fn exec_blocking(session: &Session, command: &str) -> Result<(String, String)> {
+    let mut channel = session
+        .channel_session()
+        .context("Failed to get channel session")?;
+    let mut stdout = String::new();
+    let mut stderr = String::new();
+    channel
+        .exec(command)
+        .with_context(|| format!("Failed to execute command: {command}"))?;
+    channel
+        .read_to_string(&mut stdout)
+        .context("Failed to read stdout")?;
+    channel
+        .stderr()
+        .read_to_string(&mut stderr)
+        .context("Failed to read stderr")?;
+    channel.wait_close().context("Failed to wait for close")?;
+
+    Ok((stdout, stderr))
+}
+
+async fn run_blocking<T: Send + 'static>(
+    deadline: Duration,
+    stage: &str,
+    f: impl FnOnce() -> Result<T> + Send + 'static,
+) -> Result<T> {
+    timeout(deadline, spawn_blocking(f))
+        .await
+        .map_err(|_| anyhow!("ssh {stage} timed out after {deadline:?}"))?
+        .with_context(|| format!("ssh {stage} task failed"))?
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use tokio::time::{Duration, Instant};
+
+    use super::{SshSession, SshTimeouts};
+
+    #[tokio::test]
+    async fn test_handshake_timeout() -> anyhow::Result<()> {
+        // accepts connections but never speaks ssh
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?.to_string();
+        std::thread::spawn(move || {
+            let _streams: Vec<_> = listener.incoming().collect();
+        });
+
+        let start = Instant::now();
+        let res = SshSession::connect(
+            &addr,
+            SshTimeouts {
+                handshake: Duration::from_millis(200),
+                ..Default::default()
+            },
+        )
+        .await;
+
+        assert!(res.is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_connect_refused() -> anyhow::Result<()> {
+        // bind and drop to get a port nobody listens on
+        let addr = TcpListener::bind("127.0.0.1:0")?.local_addr()?.to_string();
+
+        let res = SshSession::connect(&addr, Default::default()).await;
+        assert!(res.is_err());
+
+        Ok(())
+    }
+
+    // the runtime keeps making progress while a handshake hangs
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_does_not_block_runtime() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?.to_string();
+        std::thread::spawn(move || {
+            let _streams: Vec<_> = listener.incoming().collect();
+        });
+
+        let connect = tokio::spawn(async move {
+            SshSession::connect(
+                &addr,
+                SshTimeouts {
+                    handshake: Duration::from_secs(2),
+                    ..Default::default()
+                },
+            )
+            .await
+        });
+
+        let start = Instant::now();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        assert!(connect.await?.is_err());
+
+        Ok(())
+    }
+}
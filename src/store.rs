@@ -0,0 +1,302 @@
+use std::future::Future;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use ethers::types::{Log, H256, U256};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::admission::AdmissionDecision;
+use crate::market::JobId;
+use crate::pcr::Pcrs;
+
+// Persisted snapshot of a job along with the position of the last log applied to it
+// Lets job managers resume from the watermark instead of replaying every event from genesis
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct JobCheckpoint {
+    // (block number, log index) of the last processed log
+    pub watermark: Option<(u64, u64)>,
+
+    pub balance: U256,
+    // seconds since epoch
+    pub last_settled: u64,
+    pub rate: U256,
+    pub original_rate: U256,
+    pub instance_id: String,
+    pub family: String,
+    pub min_rate: U256,
+    pub bandwidth: u64,
+    pub eif_url: String,
+    pub instance_type: String,
+    pub region: String,
+    pub req_vcpus: i32,
+    pub req_mem: i64,
+    // vcpus of the instance type, checkpoints without it predate capacity limits
+    #[serde(default)]
+    pub instance_vcpus: u32,
+
+    pub infra_state: bool,
+    pub infra_change_scheduled: bool,
+    pub eif_update: bool,
+    // reason the job was rejected by policy, checkpoints without it predate rejections
+    #[serde(default)]
+    pub rejection: Option<String>,
+    // reason the job can never be served, checkpoints without it predate failures being saved
+    #[serde(default)]
+    pub failure: Option<String>,
+    // decision of the admission policy, restored so that resumed jobs count towards quotas
+    #[serde(default)]
+    pub admission: Option<AdmissionDecision>,
+    // measurements approved when the enclave was run, served again after a restart
+    #[serde(default)]
+    pub pcrs: Option<Pcrs>,
+
+    // applied logs that are not final yet and the state before them, so that reorgs removing
+    // them after a restart still rebuild the state, checkpoints without them predate reorg handling
+    #[serde(default)]
+    pub applied: Vec<Log>,
+    #[serde(default)]
+    pub base: Option<Box<JobCheckpoint>>,
+}
+
+// Persisted state of the log dispatcher of a deployment
+// Lets restarts resume job discovery from the watermark instead of backfilling the whole history
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct DispatcherCheckpoint {
+    // (block number, log index) of the last dispatched log
+    pub watermark: Option<(u64, u64)>,
+    // every job opened for the provider, their managers are respawned on restart
+    pub jobs: Vec<H256>,
+}
+
+pub trait JobStore {
+    fn load(&self, job: &JobId) -> impl Future<Output = Result<Option<JobCheckpoint>>> + Send;
+
+    fn save(
+        &self,
+        job: &JobId,
+        checkpoint: &JobCheckpoint,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    // deployment is the job id without the id set
+    fn load_dispatcher(
+        &self,
+        deployment: &JobId,
+    ) -> impl Future<Output = Result<Option<DispatcherCheckpoint>>> + Send;
+
+    fn save_dispatcher(
+        &self,
+        deployment: &JobId,
+        checkpoint: &DispatcherCheckpoint,
+    ) -> impl Future<Output = Result<()>> + Send;
+}
+
+impl<'a, T> JobStore for &'a T
+where
+    T: JobStore + Send + Sync,
+{
+    async fn load(&self, job: &JobId) -> Result<Option<JobCheckpoint>> {
+        (**self).load(job).await
+    }
+
+    async fn save(&self, job: &JobId, checkpoint: &JobCheckpoint) -> Result<()> {
+        (**self).save(job, checkpoint).await
+    }
+
+    async fn load_dispatcher(&self, deployment: &JobId) -> Result<Option<DispatcherCheckpoint>> {
+        (**self).load_dispatcher(deployment).await
+    }
+
+    async fn save_dispatcher(
+        &self,
+        deployment: &JobId,
+        checkpoint: &DispatcherCheckpoint,
+    ) -> Result<()> {
+        (**self).save_dispatcher(deployment, checkpoint).await
+    }
+}
+
+// Stores one json file per job inside a directory
+// An empty directory disables checkpointing, jobs are then always replayed from the start block
+#[derive(Clone)]
+pub struct FileStore {
+    dir: String,
+}
+
+impl FileStore {
+    pub fn new(dir: String) -> Result<FileStore> {
+        if !dir.is_empty() {
+            std::fs::create_dir_all(&dir).context("failed to create state directory")?;
+        }
+
+        Ok(FileStore { dir })
+    }
+
+    fn path(&self, job: &JobId) -> PathBuf {
+        Path::new(&self.dir).join(format!(
+            "{}-{}-{}-{}.json",
+            job.chain, job.contract, job.operator, job.id
+        ))
+    }
+
+    // job ids are hashes, so the name never collides with a job
+    fn dispatcher_path(&self, deployment: &JobId) -> PathBuf {
+        Path::new(&self.dir).join(format!(
+            "{}-{}-{}-dispatcher.json",
+            deployment.chain, deployment.contract, deployment.operator
+        ))
+    }
+
+    async fn read<T: DeserializeOwned>(&self, path: PathBuf) -> Result<Option<T>> {
+        if self.dir.is_empty() {
+            return Ok(None);
+        }
+
+        let contents = match fs::read_to_string(path).await {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err).context("failed to read checkpoint file"),
+        };
+        let checkpoint =
+            serde_json::from_str(&contents).context("failed to parse checkpoint file")?;
+
+        Ok(Some(checkpoint))
+    }
+
+    async fn write(&self, path: PathBuf, checkpoint: &impl Serialize) -> Result<()> {
+        if self.dir.is_empty() {
+            return Ok(());
+        }
+
+        let contents =
+            serde_json::to_string(checkpoint).context("failed to serialize checkpoint")?;
+
+        // write to a temporary file and rename so a crash never leaves a partial checkpoint
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, contents)
+            .await
+            .context("failed to write checkpoint file")?;
+        fs::rename(&tmp_path, &path)
+            .await
+            .context("failed to rename checkpoint file")?;
+
+        Ok(())
+    }
+}
+
+impl JobStore for FileStore {
+    async fn load(&self, job: &JobId) -> Result<Option<JobCheckpoint>> {
+        self.read(self.path(job)).await
+    }
+
+    async fn save(&self, job: &JobId, checkpoint: &JobCheckpoint) -> Result<()> {
+        self.write(self.path(job), checkpoint).await
+    }
+
+    async fn load_dispatcher(&self, deployment: &JobId) -> Result<Option<DispatcherCheckpoint>> {
+        self.read(self.dispatcher_path(deployment)).await
+    }
+
+    async fn save_dispatcher(
+        &self,
+        deployment: &JobId,
+        checkpoint: &DispatcherCheckpoint,
+    ) -> Result<()> {
+        self.write(self.dispatcher_path(deployment), checkpoint)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::{H256, U256};
+
+    use super::{DispatcherCheckpoint, FileStore, JobCheckpoint, JobStore};
+    use crate::admission::AdmissionDecision;
+    use crate::market::JobId;
+    use crate::pcr::Pcrs;
+
+    #[tokio::test]
+    async fn test_file_store_roundtrip() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("cp-store-{}", std::process::id()));
+        let store = FileStore::new(dir.to_string_lossy().into_owned())?;
+        let job = JobId {
+            id: "0x01".into(),
+            operator: "abc".into(),
+            contract: "xyz".into(),
+            chain: "123".into(),
+        };
+
+        assert_eq!(store.load(&job).await?, None);
+
+        let checkpoint = JobCheckpoint {
+            watermark: Some((10, 2)),
+            balance: U256::from(31000u64),
+            last_settled: 1700000000,
+            rate: U256::from(31000000000000u64),
+            original_rate: U256::from(31000000000000u64),
+            instance_id: "i-0123456789abcdef0".into(),
+            family: "salmon".into(),
+            min_rate: U256::from(29997916666666u64),
+            bandwidth: 76,
+            eif_url: "https://example.com/enclave.eif".into(),
+            instance_type: "c6a.xlarge".into(),
+            region: "ap-south-1".into(),
+            req_vcpus: 2,
+            req_mem: 4096,
+            instance_vcpus: 4,
+            infra_state: true,
+            infra_change_scheduled: false,
+            eif_update: false,
+            rejection: None,
+            failure: None,
+            admission: Some(AdmissionDecision {
+                owner: "0x0000000000000000000000000f5f91ba30a00bd43bd19466f020b3e5fc7a49ec".into(),
+                allowed: true,
+                rule: "default".into(),
+                reason: "allowed by default".into(),
+                priority: 0,
+            }),
+            pcrs: None,
+            applied: Vec::new(),
+            base: None,
+        };
+        store.save(&job, &checkpoint).await?;
+        assert_eq!(store.load(&job).await?, Some(checkpoint));
+
+        // dispatcher checkpoints are kept apart from the jobs of the deployment
+        let deployment = JobId {
+            id: String::new(),
+            ..job.clone()
+        };
+        assert_eq!(store.load_dispatcher(&deployment).await?, None);
+        let dispatcher = DispatcherCheckpoint {
+            watermark: Some((12, 0)),
+            jobs: vec![H256::from_low_u64_be(1)],
+        };
+        store.save_dispatcher(&deployment, &dispatcher).await?;
+        assert_eq!(store.load_dispatcher(&deployment).await?, Some(dispatcher));
+        assert!(store.load(&job).await?.is_some());
+
+        std::fs::remove_dir_all(dir)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_file_store_disabled() -> anyhow::Result<()> {
+        let store = FileStore::new(String::new())?;
+        let job = JobId {
+            id: "0x01".into(),
+            operator: "abc".into(),
+            contract: "xyz".into(),
+            chain: "123".into(),
+        };
+
+        assert_eq!(store.load(&job).await?, None);
+        assert_eq!(store.load_dispatcher(&job).await?, None);
+
+        Ok(())
+    }
+}
@@ -1,15 +1,26 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use ethers::prelude::rand::Rng;
 use ethers::prelude::*;
 use ethers::types::Log;
-use ethers::utils::keccak256;
 use std::collections::HashMap;
 use std::iter;
 use std::str::FromStr;
-use tokio::time::{Duration, Instant};
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+use tokio::time::{sleep_until, Duration, Instant};
 use tokio_stream::{Stream, StreamExt};
 
-use crate::market::{GBRateCard, InfraProvider, JobId, LogsProvider, RateCard, RegionalRates};
+use crate::admission::AdmissionPolicy;
+use crate::host_keys::HostKeyMismatch;
+use crate::inputs::{MarketInputs, SharedInputs};
+use crate::market::{
+    GBRateCard, InfraProvider, JobClosedFilter, JobDepositedFilter, JobId,
+    JobMetadataUpdatedFilter, JobOpenedFilter, JobReviseRateCancelledFilter,
+    JobReviseRateFinalizedFilter, JobReviseRateInitiatedFilter, JobSettledFilter,
+    JobWithdrewFilter, LogsProvider, RateCard, RegionalRates,
+};
+use crate::pcr::Pcrs;
+use crate::store::{DispatcherCheckpoint, JobCheckpoint, JobStore};
 
 #[cfg(test)]
 #[derive(Clone, Debug)]
@@ -107,6 +118,12 @@ pub struct TestAws {
 
     // HashMap format - (Job, InstanceMetadata)
     pub instances: HashMap<String, InstanceMetadata>,
+
+    // HashMap format - (Job, Pcrs)
+    pub pcrs: HashMap<String, Pcrs>,
+
+    // instances presenting a host key other than their pinned one
+    pub host_key_mismatches: Vec<String>,
 }
 
 #[cfg(test)]
@@ -195,12 +212,32 @@ impl InfraProvider for TestAws {
         return Err(anyhow!("Instance not found for job - {}", job.id));
     }
 
+    async fn get_job_pcrs(&self, job: &JobId, _region: &str) -> Result<Pcrs> {
+        self.pcrs
+            .get(&job.id)
+            .cloned()
+            .ok_or(anyhow!("Pcrs not found for job - {}", job.id))
+    }
+
+    fn restore_job_pcrs(&mut self, job: &JobId, _instance_id: &str, pcrs: Pcrs) {
+        self.pcrs.insert(job.id.clone(), pcrs);
+    }
+
     async fn check_instance_running(&mut self, _instance_id: &str, _region: &str) -> Result<bool> {
         // println!("TEST: check_instance_running | instance_id: {}, region: {}", instance_id, region);
         Ok(true)
     }
 
-    async fn check_enclave_running(&mut self, _instance_id: &str, _region: &str) -> Result<bool> {
+    async fn check_enclave_running(&mut self, instance_id: &str, _region: &str) -> Result<bool> {
+        if self.host_key_mismatches.iter().any(|id| id == instance_id) {
+            return Err(HostKeyMismatch {
+                instance_id: instance_id.to_owned(),
+                expected: vec!["SHA256:pinned".to_owned()],
+                presented: "SHA256:presented".to_owned(),
+            })
+            .context("failed to connect to the instance");
+        }
+
         Ok(true)
     }
 
@@ -290,36 +327,127 @@ impl InfraProvider for TestAws {
 }
 
 #[cfg(test)]
-#[derive(Clone)]
-pub struct TestLogger {}
+#[derive(Clone, Default)]
+pub struct TestLogger {
+    // (moment in seconds, log)
+    pub logs: Vec<(u64, Log)>,
+    // (moment in seconds, block number)
+    pub heads: Vec<(u64, u64)>,
+}
+
+#[cfg(test)]
+impl TestLogger {
+    pub fn job_stream(&self, job: H256, start_time: Instant) -> impl Stream<Item = Log> + Send {
+        let logs = self
+            .logs
+            .iter()
+            .filter(|(_, log)| log.topics[1] == job)
+            .cloned()
+            .collect();
+        timed_stream(logs, start_time)
+    }
+
+    pub fn market_stream(&self, start_time: Instant) -> impl Stream<Item = Log> + Send {
+        timed_stream(self.logs.clone(), start_time)
+    }
+
+    pub fn head_stream(&self, start_time: Instant) -> impl Stream<Item = u64> + Send {
+        timed_stream(self.heads.clone(), start_time)
+    }
+}
+
+// emits every item at its moment after start_time
+// pending stream appended so the stream never ends
+#[cfg(test)]
+fn timed_stream<T: Send + 'static>(
+    items: Vec<(u64, T)>,
+    start_time: Instant,
+) -> impl Stream<Item = T> + Send {
+    tokio_stream::iter(items)
+        .then(move |(moment, item)| async move {
+            sleep_until(start_time + Duration::from_secs(moment)).await;
+            item
+        })
+        .chain(tokio_stream::pending())
+}
 
 #[cfg(test)]
 impl LogsProvider for TestLogger {
-    async fn new_jobs<'a>(
-        &'a self,
-        _client: &'a Provider<Ws>,
-    ) -> Result<impl Stream<Item = (H256, bool)> + 'a> {
-        let logs: Vec<Log> = Vec::new();
-        Ok(tokio_stream::iter(
-            logs.iter()
-                .map(|job| (job.topics[1], false))
-                .collect::<Vec<_>>(),
-        )
-        .throttle(Duration::from_secs(2)))
+    type Client = ();
+
+    async fn connect<'a>(&'a self, _url: &'a str) -> Result<()> {
+        Ok(())
     }
 
-    async fn job_logs<'a>(
+    async fn market_logs<'a>(
         &'a self,
-        _client: &'a Provider<Ws>,
-        job: H256,
+        _client: &'a (),
+        _from_block: u64,
+        caught_up: watch::Sender<bool>,
     ) -> Result<impl Stream<Item = Log> + Send + 'a> {
-        let logs: Vec<Log> = Vec::new();
-        Ok(tokio_stream::iter(
-            logs.into_iter()
-                .filter(|log| log.topics[1] == job)
-                .collect::<Vec<_>>(),
-        )
-        .throttle(Duration::from_secs(2)))
+        // logs are timed, there is no backfill to wait for
+        caught_up.send_replace(true);
+        Ok(self.market_stream(Instant::now()))
+    }
+
+    async fn new_heads<'a>(
+        &'a self,
+        _client: &'a (),
+    ) -> Result<impl Stream<Item = u64> + Send + 'a> {
+        Ok(self.head_stream(Instant::now()))
+    }
+}
+
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct TestStore {
+    // HashMap format - (Job, JobCheckpoint)
+    pub checkpoints: Arc<Mutex<HashMap<String, JobCheckpoint>>>,
+    // number of loads that fail before checkpoints are returned
+    pub failing_loads: Arc<Mutex<usize>>,
+    // HashMap format - (Contract, DispatcherCheckpoint)
+    pub dispatchers: Arc<Mutex<HashMap<String, DispatcherCheckpoint>>>,
+}
+
+#[cfg(test)]
+impl JobStore for TestStore {
+    async fn load(&self, job: &JobId) -> Result<Option<JobCheckpoint>> {
+        let mut failing_loads = self.failing_loads.lock().unwrap();
+        if *failing_loads > 0 {
+            *failing_loads -= 1;
+            return Err(anyhow!("store unavailable"));
+        }
+
+        Ok(self.checkpoints.lock().unwrap().get(&job.id).cloned())
+    }
+
+    async fn save(&self, job: &JobId, checkpoint: &JobCheckpoint) -> Result<()> {
+        self.checkpoints
+            .lock()
+            .unwrap()
+            .insert(job.id.clone(), checkpoint.clone());
+        Ok(())
+    }
+
+    async fn load_dispatcher(&self, deployment: &JobId) -> Result<Option<DispatcherCheckpoint>> {
+        Ok(self
+            .dispatchers
+            .lock()
+            .unwrap()
+            .get(&deployment.contract)
+            .cloned())
+    }
+
+    async fn save_dispatcher(
+        &self,
+        deployment: &JobId,
+        checkpoint: &DispatcherCheckpoint,
+    ) -> Result<()> {
+        self.dispatchers
+            .lock()
+            .unwrap()
+            .insert(deployment.contract.clone(), checkpoint.clone());
+        Ok(())
     }
 }
 
@@ -360,6 +488,16 @@ pub fn get_gb_rates() -> Vec<GBRateCard> {
     }]
 }
 
+#[cfg(test)]
+pub fn get_inputs(address_whitelist: Vec<String>, address_blacklist: Vec<String>) -> SharedInputs {
+    SharedInputs::new(MarketInputs {
+        rates: get_rates(),
+        gb_rates: get_gb_rates(),
+        admission: AdmissionPolicy::default()
+            .with_address_lists(address_whitelist, address_blacklist),
+    })
+}
+
 #[cfg(test)]
 pub fn get_log(topic: Action, data: Bytes, idx: H256) -> Log {
     let mut log = Log {
@@ -368,62 +506,28 @@ pub fn get_log(topic: Action, data: Bytes, idx: H256) -> Log {
         data,
         ..Default::default()
     };
-    match topic {
-        Action::Open => {
-            log.topics = vec![
-                H256::from(keccak256(
-                    "JobOpened(bytes32,string,address,address,uint256,uint256,uint256)",
-                )),
-                idx,
-                H256::from_low_u64_be(log.address.to_low_u64_be()),
-            ];
-        }
-        Action::Close => {
-            log.topics = vec![H256::from(keccak256("JobClosed(bytes32)")), idx];
-        }
-        Action::Settle => {
-            log.topics = vec![
-                H256::from(keccak256("JobSettled(bytes32,uint256,uint256)")),
-                idx,
-            ];
-        }
-        Action::Deposit => {
-            log.topics = vec![
-                H256::from(keccak256("JobDeposited(bytes32,address,uint256)")),
-                idx,
-            ];
-        }
-        Action::Withdraw => {
-            log.topics = vec![
-                H256::from(keccak256("JobWithdrew(bytes32,address,uint256)")),
-                idx,
-            ];
-        }
-        Action::ReviseRateInitiated => {
-            log.topics = vec![
-                H256::from(keccak256("JobReviseRateInitiated(bytes32,uint256)")),
-                idx,
-            ];
-        }
-        Action::ReviseRateCancelled => {
-            log.topics = vec![
-                H256::from(keccak256("JobReviseRateCancelled(bytes32)")),
-                idx,
-            ];
-        }
-        Action::ReviseRateFinalized => {
-            log.topics = vec![
-                H256::from(keccak256("JobReviseRateFinalized(bytes32, uint256)")),
-                idx,
-            ];
-        }
-        Action::MetadataUpdated => {
-            log.topics = vec![
-                H256::from(keccak256("JobMetadataUpdated(bytes32,string)")),
-                idx,
-            ];
-        }
-    }
+    // indexed addresses only need to be present for decoding, reuse the same one everywhere
+    let address = H256::from_low_u64_be(log.address.to_low_u64_be());
+    log.topics = match topic {
+        Action::Open => vec![JobOpenedFilter::signature(), idx, address, address],
+        Action::Close => vec![JobClosedFilter::signature(), idx],
+        Action::Settle => vec![JobSettledFilter::signature(), idx],
+        Action::Deposit => vec![JobDepositedFilter::signature(), idx, address],
+        Action::Withdraw => vec![JobWithdrewFilter::signature(), idx, address],
+        Action::ReviseRateInitiated => vec![JobReviseRateInitiatedFilter::signature(), idx],
+        Action::ReviseRateCancelled => vec![JobReviseRateCancelledFilter::signature(), idx],
+        Action::ReviseRateFinalized => vec![JobReviseRateFinalizedFilter::signature(), idx],
+        Action::MetadataUpdated => vec![JobMetadataUpdatedFilter::signature(), idx],
+    };
+
+    log
+}
+
+#[cfg(test)]
+pub fn get_log_at(topic: Action, data: Bytes, idx: H256, block: u64, log_index: u64) -> Log {
+    let mut log = get_log(topic, data, idx);
+    log.block_number = Some(block.into());
+    log.log_index = Some(log_index.into());
 
     log
 }